@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN deleted_at TEXT;
@@ -0,0 +1,15 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN status TEXT NOT NULL DEFAULT 'active';
+
+UPDATE user SET status = 'deleted' WHERE deleted_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS user_status_changes
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    email TEXT NOT NULL REFERENCES user(email) ON DELETE CASCADE,
+    from_status TEXT NOT NULL,
+    to_status TEXT NOT NULL,
+    reason TEXT,
+    actor TEXT NOT NULL,
+    changed_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN created_at TEXT;
+
+UPDATE user SET created_at = datetime('now') WHERE created_at IS NULL;
+
+ALTER TABLE user ADD COLUMN email_domain TEXT
+    GENERATED ALWAYS AS (lower(substr(email, instr(email, '@') + 1))) VIRTUAL;
+
+CREATE INDEX IF NOT EXISTS user_status_idx ON user (status, created_at);
+CREATE INDEX IF NOT EXISTS user_created_at_idx ON user (created_at, email);
+CREATE INDEX IF NOT EXISTS user_email_domain_idx ON user (email_domain, email);
+CREATE INDEX IF NOT EXISTS user_name_idx ON user (name COLLATE NOCASE, email);
+CREATE INDEX IF NOT EXISTS user_email_nocase_idx ON user (email COLLATE NOCASE);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE user ADD COLUMN user_metadata TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE user ADD COLUMN app_metadata TEXT NOT NULL DEFAULT '{}';
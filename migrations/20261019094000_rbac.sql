@@ -0,0 +1,44 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS roles
+(
+    name TEXT PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS permissions
+(
+    name TEXT PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions
+(
+    role TEXT NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    permission TEXT NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+    PRIMARY KEY (role, permission)
+);
+
+CREATE TABLE IF NOT EXISTS user_roles
+(
+    email TEXT NOT NULL REFERENCES user(email) ON DELETE CASCADE,
+    role TEXT NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    PRIMARY KEY (email, role)
+);
+
+CREATE INDEX IF NOT EXISTS user_roles_role_idx ON user_roles (role);
+
+-- keys issued to a user act with that user's permissions
+ALTER TABLE api_keys ADD COLUMN owner TEXT REFERENCES user(email) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS api_keys_owner_idx ON api_keys (owner);
+
+INSERT OR IGNORE INTO permissions (name, description) VALUES
+    ('users:read', 'list and view users'),
+    ('users:write', 'change, disable and delete users'),
+    ('roles:read', 'view roles and permissions'),
+    ('roles:write', 'manage roles, permissions and role grants');
+
+INSERT OR IGNORE INTO roles (name, description) VALUES ('admin', 'full access to Authy');
+
+INSERT OR IGNORE INTO role_permissions (role, permission)
+    SELECT 'admin', name FROM permissions;
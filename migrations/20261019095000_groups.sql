@@ -0,0 +1,33 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS groups
+(
+    name TEXT PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS group_users
+(
+    group_name TEXT NOT NULL REFERENCES groups(name) ON DELETE CASCADE,
+    email TEXT NOT NULL REFERENCES user(email) ON DELETE CASCADE,
+    PRIMARY KEY (group_name, email)
+);
+
+CREATE INDEX IF NOT EXISTS group_users_email_idx ON group_users (email);
+
+CREATE TABLE IF NOT EXISTS group_groups
+(
+    parent TEXT NOT NULL REFERENCES groups(name) ON DELETE CASCADE,
+    child TEXT NOT NULL REFERENCES groups(name) ON DELETE CASCADE,
+    PRIMARY KEY (parent, child),
+    CHECK (parent <> child)
+);
+
+CREATE INDEX IF NOT EXISTS group_groups_child_idx ON group_groups (child);
+
+INSERT OR IGNORE INTO permissions (name, description) VALUES
+    ('groups:read', 'view groups and memberships'),
+    ('groups:write', 'manage groups and memberships');
+
+INSERT OR IGNORE INTO role_permissions (role, permission) VALUES
+    ('admin', 'groups:read'),
+    ('admin', 'groups:write');
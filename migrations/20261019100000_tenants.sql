@@ -0,0 +1,187 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS tenants
+(
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+-- everything that existed before tenants belongs to the default tenant
+INSERT OR IGNORE INTO tenants (id, name) VALUES ('default', 'Default');
+
+INSERT OR IGNORE INTO permissions (name, description) VALUES
+    ('tenants:read', 'view tenants'),
+    ('tenants:write', 'create and rename tenants');
+
+-- Tables referencing user, roles or groups are set aside and recreated afterwards, since
+-- dropping a parent table would otherwise cascade into them.
+CREATE TABLE api_keys_old AS SELECT * FROM api_keys;
+CREATE TABLE user_status_changes_old AS SELECT * FROM user_status_changes;
+CREATE TABLE role_permissions_old AS SELECT * FROM role_permissions;
+CREATE TABLE user_roles_old AS SELECT * FROM user_roles;
+CREATE TABLE group_users_old AS SELECT * FROM group_users;
+CREATE TABLE group_groups_old AS SELECT * FROM group_groups;
+
+DROP TABLE api_keys;
+DROP TABLE user_status_changes;
+DROP TABLE role_permissions;
+DROP TABLE user_roles;
+DROP TABLE group_users;
+DROP TABLE group_groups;
+
+CREATE TABLE user_new
+(
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    password TEXT NOT NULL,
+    deleted_at TEXT,
+    status TEXT NOT NULL DEFAULT 'active',
+    created_at TEXT,
+    email_domain TEXT
+        GENERATED ALWAYS AS (lower(substr(email, instr(email, '@') + 1))) VIRTUAL,
+    user_metadata TEXT NOT NULL DEFAULT '{}',
+    app_metadata TEXT NOT NULL DEFAULT '{}',
+    PRIMARY KEY (tenant, email)
+);
+
+INSERT INTO user_new (
+    tenant, name, email, password, deleted_at, status, created_at, user_metadata, app_metadata
+)
+SELECT 'default', name, email, password, deleted_at, status, created_at, user_metadata, app_metadata
+FROM user;
+
+DROP TABLE user;
+ALTER TABLE user_new RENAME TO user;
+
+CREATE INDEX IF NOT EXISTS user_status_idx ON user (tenant, status, created_at);
+CREATE INDEX IF NOT EXISTS user_created_at_idx ON user (tenant, created_at, email);
+CREATE INDEX IF NOT EXISTS user_email_domain_idx ON user (tenant, email_domain, email);
+CREATE INDEX IF NOT EXISTS user_name_idx ON user (tenant, name COLLATE NOCASE, email);
+CREATE INDEX IF NOT EXISTS user_email_nocase_idx ON user (tenant, email COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS user_deleted_at_idx ON user (deleted_at) WHERE deleted_at IS NOT NULL;
+
+CREATE TABLE roles_new
+(
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (tenant, name)
+);
+
+INSERT INTO roles_new (tenant, name, description) SELECT 'default', name, description FROM roles;
+
+DROP TABLE roles;
+ALTER TABLE roles_new RENAME TO roles;
+
+CREATE TABLE groups_new
+(
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (tenant, name)
+);
+
+INSERT INTO groups_new (tenant, name, description) SELECT 'default', name, description FROM groups;
+
+DROP TABLE groups;
+ALTER TABLE groups_new RENAME TO groups;
+
+CREATE TABLE api_keys
+(
+    api_key BLOB PRIMARY KEY,
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    -- keys issued to a user act with that user's permissions
+    owner TEXT,
+    FOREIGN KEY (tenant, owner) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_owner_idx ON api_keys (tenant, owner);
+
+INSERT INTO api_keys (api_key, tenant, owner) SELECT api_key, 'default', owner FROM api_keys_old;
+
+CREATE TABLE user_status_changes
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    from_status TEXT NOT NULL,
+    to_status TEXT NOT NULL,
+    reason TEXT,
+    actor TEXT NOT NULL,
+    changed_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+INSERT INTO user_status_changes (
+    id, tenant, email, from_status, to_status, reason, actor, changed_at
+)
+SELECT id, 'default', email, from_status, to_status, reason, actor, changed_at
+FROM user_status_changes_old;
+
+CREATE TABLE role_permissions
+(
+    tenant TEXT NOT NULL,
+    role TEXT NOT NULL,
+    permission TEXT NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+    PRIMARY KEY (tenant, role, permission),
+    FOREIGN KEY (tenant, role) REFERENCES roles(tenant, name) ON DELETE CASCADE
+);
+
+INSERT INTO role_permissions (tenant, role, permission)
+SELECT 'default', role, permission FROM role_permissions_old;
+
+INSERT OR IGNORE INTO role_permissions (tenant, role, permission) VALUES
+    ('default', 'admin', 'tenants:read'),
+    ('default', 'admin', 'tenants:write');
+
+CREATE TABLE user_roles
+(
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL,
+    PRIMARY KEY (tenant, email, role),
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, role) REFERENCES roles(tenant, name) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS user_roles_role_idx ON user_roles (tenant, role);
+
+INSERT INTO user_roles (tenant, email, role) SELECT 'default', email, role FROM user_roles_old;
+
+CREATE TABLE group_users
+(
+    tenant TEXT NOT NULL,
+    group_name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    PRIMARY KEY (tenant, group_name, email),
+    FOREIGN KEY (tenant, group_name) REFERENCES groups(tenant, name) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS group_users_email_idx ON group_users (tenant, email);
+
+INSERT INTO group_users (tenant, group_name, email)
+SELECT 'default', group_name, email FROM group_users_old;
+
+CREATE TABLE group_groups
+(
+    tenant TEXT NOT NULL,
+    parent TEXT NOT NULL,
+    child TEXT NOT NULL,
+    PRIMARY KEY (tenant, parent, child),
+    FOREIGN KEY (tenant, parent) REFERENCES groups(tenant, name) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, child) REFERENCES groups(tenant, name) ON DELETE CASCADE,
+    CHECK (parent <> child)
+);
+
+CREATE INDEX IF NOT EXISTS group_groups_child_idx ON group_groups (tenant, child);
+
+INSERT INTO group_groups (tenant, parent, child) SELECT 'default', parent, child FROM group_groups_old;
+
+DROP TABLE api_keys_old;
+DROP TABLE user_status_changes_old;
+DROP TABLE role_permissions_old;
+DROP TABLE user_roles_old;
+DROP TABLE group_users_old;
+DROP TABLE group_groups_old;
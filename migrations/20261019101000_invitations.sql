@@ -0,0 +1,14 @@
+-- Single-use invitations; only a hash of the emailed token is stored
+CREATE TABLE invitations (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL,
+    invited_by TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    accepted_at TEXT,
+    FOREIGN KEY (tenant, role) REFERENCES roles(tenant, name) ON DELETE CASCADE
+);
+
+CREATE INDEX invitations_tenant_email ON invitations(tenant, email);
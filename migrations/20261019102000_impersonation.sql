@@ -0,0 +1,23 @@
+-- Keys issued for impersonation act as `owner` but remember who is behind them,
+-- and stop working after `expires_at`
+ALTER TABLE api_keys ADD COLUMN impersonator TEXT;
+ALTER TABLE api_keys ADD COLUMN expires_at TEXT;
+
+CREATE TABLE audit_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant TEXT NOT NULL,
+    occurred_at TEXT NOT NULL DEFAULT (datetime('now')),
+    -- email of the acting user, NULL for operator keys
+    actor TEXT,
+    action TEXT NOT NULL,
+    target TEXT,
+    detail TEXT NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX audit_events_tenant_idx ON audit_events (tenant, occurred_at);
+
+INSERT INTO permissions (name, description) VALUES
+    ('users:impersonate', 'act as another user for support purposes');
+
+INSERT INTO role_permissions (tenant, role, permission)
+    SELECT tenant, name, 'users:impersonate' FROM roles WHERE name = 'admin';
@@ -0,0 +1,30 @@
+-- Request context and outcome for every audit event
+ALTER TABLE audit_events ADD COLUMN impersonator TEXT;
+ALTER TABLE audit_events ADD COLUMN outcome TEXT NOT NULL DEFAULT 'success';
+ALTER TABLE audit_events ADD COLUMN ip TEXT;
+ALTER TABLE audit_events ADD COLUMN user_agent TEXT;
+ALTER TABLE audit_events ADD COLUMN request_id TEXT;
+
+-- Per-tenant hash chain: hash = sha256(prev_hash + event). Events recorded before the
+-- chain existed keep a NULL hash.
+ALTER TABLE audit_events ADD COLUMN prev_hash TEXT;
+ALTER TABLE audit_events ADD COLUMN hash TEXT;
+
+CREATE INDEX audit_events_actor_idx ON audit_events (tenant, actor);
+CREATE INDEX audit_events_target_idx ON audit_events (tenant, target);
+
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
+
+CREATE TRIGGER audit_events_no_delete BEFORE DELETE ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
+
+INSERT INTO permissions (name, description) VALUES
+    ('audit:read', 'query and export the audit log');
+
+INSERT INTO role_permissions (tenant, role, permission)
+    SELECT tenant, name, 'audit:read' FROM roles WHERE name = 'admin';
@@ -0,0 +1,39 @@
+-- Set by a "this wasn't me" report; sign-in is refused until the password is changed
+ALTER TABLE user ADD COLUMN password_reset_required INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE login_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    occurred_at TEXT NOT NULL DEFAULT (datetime('now')),
+    ip TEXT,
+    user_agent TEXT,
+    device_id TEXT,
+    success INTEGER NOT NULL,
+    new_device INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX login_history_user_idx ON login_history (tenant, email, id);
+
+-- Devices (identified by the device cookie) a user has signed in from
+CREATE TABLE known_devices (
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    device_id TEXT NOT NULL,
+    first_seen TEXT NOT NULL DEFAULT (datetime('now')),
+    last_seen TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (tenant, email, device_id),
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+-- Single-use "this wasn't me" tokens sent with new-device notifications
+CREATE TABLE login_alerts (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    login_id INTEGER NOT NULL REFERENCES login_history(id) ON DELETE CASCADE,
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
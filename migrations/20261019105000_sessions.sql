@@ -0,0 +1,12 @@
+-- User-bound API keys double as sign-in sessions. `id` names a session without exposing
+-- the key itself.
+ALTER TABLE api_keys ADD COLUMN id TEXT;
+ALTER TABLE api_keys ADD COLUMN created_at TEXT;
+ALTER TABLE api_keys ADD COLUMN last_seen_at TEXT;
+ALTER TABLE api_keys ADD COLUMN ip TEXT;
+ALTER TABLE api_keys ADD COLUMN user_agent TEXT;
+ALTER TABLE api_keys ADD COLUMN device_id TEXT;
+
+UPDATE api_keys SET id = lower(hex(randomblob(16))), created_at = datetime('now');
+
+CREATE UNIQUE INDEX api_keys_id_idx ON api_keys (id);
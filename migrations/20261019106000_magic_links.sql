@@ -0,0 +1,14 @@
+-- Single-use email sign-in links. Each is bound to the browser that requested it through a
+-- nonce kept in a private cookie; only hashes of the token and nonce are stored.
+CREATE TABLE magic_links (
+    token_hash TEXT PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    nonce_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX magic_links_user_idx ON magic_links (tenant, email);
@@ -0,0 +1,18 @@
+-- E.164 number one-time codes can be sent to by SMS
+ALTER TABLE user ADD COLUMN phone TEXT;
+
+-- One-time sign-in codes; only the latest unused one per user is accepted
+CREATE TABLE otp_codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    used_at TEXT,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX otp_codes_user_idx ON otp_codes (tenant, email, id);
@@ -0,0 +1,27 @@
+-- Passkeys; `id` is the base64url credential id and `public_key` an uncompressed P-256 point
+CREATE TABLE webauthn_credentials (
+    tenant TEXT NOT NULL,
+    id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    public_key BLOB NOT NULL,
+    sign_count INTEGER NOT NULL DEFAULT 0,
+    transports TEXT NOT NULL DEFAULT '[]',
+    name TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    last_used_at TEXT,
+    PRIMARY KEY (tenant, id),
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX webauthn_credentials_user_idx ON webauthn_credentials (tenant, email);
+
+-- Outstanding ceremony challenges. `email` is unset for passkey-first sign-in, where the
+-- user is only known once the authenticator picks a credential.
+CREATE TABLE webauthn_challenges (
+    challenge TEXT PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    email TEXT,
+    purpose TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
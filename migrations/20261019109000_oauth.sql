@@ -0,0 +1,69 @@
+-- OAuth 2.0 clients of the authorization server. Confidential clients authenticate with a
+-- secret, of which only a hash is stored; public clients (SPAs, native apps) have none.
+CREATE TABLE oauth_clients (
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    client_type TEXT NOT NULL,
+    secret_hash TEXT,
+    -- JSON arrays
+    redirect_uris TEXT NOT NULL,
+    scopes TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (tenant, id)
+);
+
+-- Scopes a user has consented to give a client; asking for nothing more skips consent.
+CREATE TABLE oauth_grants (
+    tenant TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (tenant, client_id, email),
+    FOREIGN KEY (tenant, client_id) REFERENCES oauth_clients(tenant, id) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+-- Single-use authorization codes, bound to the redirect URI and PKCE challenge they were
+-- requested with.
+CREATE TABLE oauth_codes (
+    code_hash TEXT PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    redirect_uri TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    code_challenge TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (tenant, client_id) REFERENCES oauth_clients(tenant, id) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+-- Access and refresh tokens handed out at the token endpoint; only hashes are stored.
+CREATE TABLE oauth_tokens (
+    token_hash TEXT PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (tenant, client_id) REFERENCES oauth_clients(tenant, id) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX oauth_tokens_user_idx ON oauth_tokens (tenant, email);
+
+INSERT INTO permissions (name, description) VALUES
+    ('clients:read', 'view OAuth clients'),
+    ('clients:write', 'register and remove OAuth clients');
+
+INSERT INTO role_permissions (tenant, role, permission)
+    SELECT tenant, name, permission FROM roles
+    CROSS JOIN (SELECT 'clients:read' AS permission UNION SELECT 'clients:write')
+    WHERE name = 'admin';
@@ -0,0 +1,14 @@
+-- Keys ID tokens are signed with, shared by every tenant. The newest signs; all of them
+-- are published so tokens signed before a rotation can still be checked.
+CREATE TABLE signing_keys (
+    id TEXT PRIMARY KEY,
+    algorithm TEXT NOT NULL,
+    private_key BLOB NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+-- Echoed in the ID token issued for the code
+ALTER TABLE oauth_codes ADD COLUMN nonce TEXT;
+
+-- Where RP-initiated logout may send the user afterwards (JSON array)
+ALTER TABLE oauth_clients ADD COLUMN post_logout_redirect_uris TEXT NOT NULL DEFAULT '[]';
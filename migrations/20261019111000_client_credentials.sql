@@ -0,0 +1,21 @@
+-- Tokens from the client_credentials grant act for a client rather than a user, so
+-- `email` becomes optional. SQLite cannot relax a column in place; the table is rebuilt.
+CREATE TABLE oauth_tokens_new (
+    token_hash TEXT PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    -- NULL for client credentials
+    email TEXT,
+    scope TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (tenant, client_id) REFERENCES oauth_clients(tenant, id) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+INSERT INTO oauth_tokens_new SELECT * FROM oauth_tokens;
+DROP TABLE oauth_tokens;
+ALTER TABLE oauth_tokens_new RENAME TO oauth_tokens;
+
+CREATE INDEX oauth_tokens_user_idx ON oauth_tokens (tenant, email);
@@ -0,0 +1,21 @@
+-- Device authorization grant (RFC 8628): a device without a browser polls with its device
+-- code while the user approves the matching user code on another device.
+CREATE TABLE oauth_device_codes (
+    device_code_hash TEXT PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    -- without the dash shown to users
+    user_code TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    -- set once the user has decided
+    email TEXT,
+    approved INTEGER,
+    -- seconds the device must wait between polls; raised when it polls too fast
+    poll_interval INTEGER NOT NULL,
+    polled_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    UNIQUE (tenant, user_code),
+    FOREIGN KEY (tenant, client_id) REFERENCES oauth_clients(tenant, id) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
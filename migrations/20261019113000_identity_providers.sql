@@ -0,0 +1,43 @@
+-- Upstream OpenID Connect providers users may sign in with. Authy is registered with each
+-- as a confidential client; the secret has to be sent to the provider, so it is stored
+-- as is.
+CREATE TABLE identity_providers (
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    -- used in URLs
+    id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    issuer TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    client_secret TEXT NOT NULL,
+    -- JSON array
+    scopes TEXT NOT NULL DEFAULT '["openid","email","profile"]',
+    -- whether a verified email from the provider may be linked to the account with that
+    -- address on first sign-in
+    link_by_email INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (tenant, id)
+);
+
+-- Subjects at a provider that sign in as a local user.
+CREATE TABLE federated_identities (
+    tenant TEXT NOT NULL,
+    provider_id TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (tenant, provider_id, subject),
+    FOREIGN KEY (tenant, provider_id) REFERENCES identity_providers(tenant, id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE
+);
+
+CREATE INDEX federated_identities_user_idx ON federated_identities (tenant, email);
+
+INSERT INTO permissions (name, description) VALUES
+    ('providers:read', 'view identity providers'),
+    ('providers:write', 'add and remove identity providers');
+
+INSERT INTO role_permissions (tenant, role, permission)
+    SELECT tenant, name, permission FROM roles
+    CROSS JOIN (SELECT 'providers:read' AS permission UNION SELECT 'providers:write')
+    WHERE name = 'admin';
@@ -0,0 +1,56 @@
+-- Ways a user can sign in: their password, each of their passkeys and each identity at an
+-- upstream provider. Users always keep at least one.
+CREATE TABLE identities (
+    tenant TEXT NOT NULL,
+    id TEXT NOT NULL DEFAULT (lower(hex(randomblob(16)))),
+    email TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('password', 'passkey', 'federated')),
+    -- passkeys
+    credential_id TEXT,
+    -- identities at a provider
+    provider_id TEXT,
+    subject TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (tenant, id),
+    CHECK ((kind = 'passkey') = (credential_id IS NOT NULL)),
+    CHECK ((kind = 'federated') = (provider_id IS NOT NULL AND subject IS NOT NULL)),
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, credential_id) REFERENCES webauthn_credentials(tenant, id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (tenant, provider_id) REFERENCES identity_providers(tenant, id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX identities_user_idx ON identities (tenant, email);
+CREATE UNIQUE INDEX identities_password_idx ON identities (tenant, email)
+    WHERE kind = 'password';
+CREATE UNIQUE INDEX identities_passkey_idx ON identities (tenant, credential_id)
+    WHERE kind = 'passkey';
+CREATE UNIQUE INDEX identities_federated_idx ON identities (tenant, provider_id, subject)
+    WHERE kind = 'federated';
+
+INSERT INTO identities (tenant, email, kind, created_at)
+    SELECT tenant, email, 'password', COALESCE(created_at, datetime('now')) FROM user;
+
+INSERT INTO identities (tenant, email, kind, credential_id, created_at)
+    SELECT tenant, email, 'passkey', id, created_at FROM webauthn_credentials;
+
+INSERT INTO identities (tenant, email, kind, provider_id, subject, created_at)
+    SELECT tenant, email, 'federated', provider_id, subject, created_at
+    FROM federated_identities;
+
+DROP TABLE federated_identities;
+
+-- Requests to link an identity at a provider, made by a user who has just re-authenticated
+-- and redeemed once by the browser that goes on to sign in at the provider.
+CREATE TABLE identity_link_requests (
+    token_hash TEXT PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    email TEXT NOT NULL,
+    provider_id TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (tenant, email) REFERENCES user(tenant, email) ON DELETE CASCADE,
+    FOREIGN KEY (tenant, provider_id) REFERENCES identity_providers(tenant, id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,23 @@
+-- Hosts behind a reverse proxy that asks `/auth/verify` whether to let a request through,
+-- and who may reach them. A host is either a name or a wildcard (`*.example.com`) for its
+-- subdomains; hosts without a rule are closed to everyone.
+CREATE TABLE access_rules (
+    tenant TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    host TEXT NOT NULL,
+    -- JSON arrays; users need one of the roles or to belong to one of the groups, and any
+    -- signed-in user is let through when both are empty
+    roles TEXT NOT NULL DEFAULT '[]',
+    groups TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (tenant, host)
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('access:read', 'view access rules of proxied hosts'),
+    ('access:write', 'change access rules of proxied hosts');
+
+INSERT INTO role_permissions (tenant, role, permission)
+    SELECT tenant, name, permission FROM roles
+    CROSS JOIN (SELECT 'access:read' AS permission UNION SELECT 'access:write')
+    WHERE name = 'admin';
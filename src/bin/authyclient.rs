@@ -1,9 +1,20 @@
-use authy::domain::user::field::{Email, Name, Password};
-use authy::service::ask::{GetUser, NewUser, UpdateUser};
+use authy::domain::oauth::DEVICE_CODE_GRANT_TYPE;
+use authy::domain::role::RoleName;
+use authy::domain::user::field::{Email, Metadata, Name, Password, Phone, Status};
+use authy::service::ask::{
+    DeleteUser, DeviceAuthorization, GetUser, NewUser, TokenResponse, UpdateStatus, UpdateUser,
+};
 use authy::web::api::{ApiKey, API_KEY_HEADER};
-use authy::{ServiceError, User};
+use reqwest::blocking::{RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 // use std::process::Command;
 use structopt::StructOpt;
 // use strum::EnumString;
@@ -23,6 +34,8 @@ enum Command {
         email: Email,
         #[structopt(short, long, help = "password")]
         password: Password,
+        #[structopt(long, help = "phone number in E.164 form")]
+        phone: Option<Phone>,
     },
     Update {
         #[structopt(short, long, help = "name")]
@@ -31,9 +44,37 @@ enum Command {
         email: Email,
         #[structopt(short, long, help = "password")]
         password: Password,
+        #[structopt(long, help = "phone number in E.164 form")]
+        phone: Option<Phone>,
+    },
+    Delete {
+        #[structopt(short, long, help = "email")]
+        email: Email,
+        #[structopt(long, help = "erase the user permanently instead of deactivating")]
+        permanent: bool,
+    },
+    SetStatus {
+        #[structopt(short, long, help = "email")]
+        email: Email,
+        #[structopt(short, long, help = "new status")]
+        status: Status,
+        #[structopt(short, long, help = "reason for the change")]
+        reason: Option<String>,
     },
     GetApiKey {},
     RevokeApiKey {},
+    /// Signs in through the browser and caches the tokens for later commands.
+    Login {
+        #[structopt(long, env = "AUTHY_CLIENT_ID", help = "OAuth client to sign in with")]
+        client_id: String,
+        #[structopt(
+            long,
+            help = "scopes to ask for; defaults to those the client was registered with"
+        )]
+        scope: Option<String>,
+    },
+    /// Revokes and forgets the cached tokens.
+    Logout {},
 }
 
 #[derive(StructOpt, Debug)]
@@ -45,103 +86,360 @@ struct Opt {
     #[structopt(default_value = "http://127.0.0.1:8000", env = "AUTHY_ADDR")]
     addr: String,
 
-    #[structopt(long)]
-    api_key: String,
+    #[structopt(long, help = "API key; defaults to the tokens cached by `login`")]
+    api_key: Option<String>,
+
+    #[structopt(long, env = "AUTHY_TENANT", help = "tenant to act in")]
+    tenant: Option<String>,
+}
+
+/// How requests to the API are authenticated.
+enum Credentials {
+    ApiKey(ApiKey),
+    /// Access token cached by `login`.
+    Bearer(String),
+}
+
+impl Credentials {
+    fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Credentials::ApiKey(api_key) => request.header(API_KEY_HEADER, api_key.to_base64()),
+            Credentials::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+/// Tokens from `login`, keyed by the OAuth endpoint (server and tenant) they came from.
+#[derive(Serialize, Deserialize, Default)]
+struct TokenCache(HashMap<String, CachedToken>);
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedToken {
+    client_id: String,
+    access_token: String,
+    refresh_token: Option<String>,
+    /// Unix time.
+    expires_at: u64,
 }
 
-fn get_user(addr: &str, ask_scv: GetUser, api_key: ApiKey) -> Result<User, Box<dyn Error>> {
+impl CachedToken {
+    fn new(client_id: &str, tokens: TokenResponse) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            access_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            expires_at: unix_time() + tokens.expires_in,
+        }
+    }
+}
+
+impl TokenCache {
+    /// `$AUTHY_TOKEN_CACHE`, or `~/.authy/tokens.json`.
+    fn path() -> Result<PathBuf, Box<dyn Error>> {
+        if let Some(path) = std::env::var_os("AUTHY_TOKEN_CACHE") {
+            return Ok(path.into());
+        }
+        let home = std::env::var_os("HOME").ok_or("set HOME or AUTHY_TOKEN_CACHE")?;
+        Ok(PathBuf::from(home).join(".authy").join("tokens.json"))
+    }
+
+    fn load() -> Result<Self, Box<dyn Error>> {
+        match fs::read(Self::path()?) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the cache so that only the current user can read it.
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            if path.exists() {
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+            }
+        }
+        serde_json::to_writer_pretty(options.open(path)?, self)?;
+
+        Ok(())
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// A user as returned by the API, which never includes their password.
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct User {
+    name: Name,
+    email: Email,
+    phone: Option<Phone>,
+    status: Status,
+    user_metadata: Metadata,
+    app_metadata: Metadata,
+    roles: Vec<RoleName>,
+}
+
+/// Error response of the OAuth endpoints.
+#[derive(Debug, Deserialize)]
+struct OAuthErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl std::fmt::Display for OAuthErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error_description {
+            Some(description) => write!(f, "{}: {description}", self.error),
+            None => f.write_str(&self.error),
+        }
+    }
+}
+
+impl Error for OAuthErrorResponse {}
+
+/// Reads the body of an OAuth endpoint response; any status but 200 carries an error.
+fn oauth_response<T: DeserializeOwned>(
+    response: Response,
+) -> Result<Result<T, OAuthErrorResponse>, Box<dyn Error>> {
+    match response.status().is_success() {
+        true => Ok(Ok(response.json()?)),
+        false => Ok(Err(response.json()?)),
+    }
+}
+
+/// Runs the device authorization grant: the user approves the request in a browser while
+/// the token endpoint is polled for the outcome.
+fn login(
+    oauth: &str,
+    client_id: &str,
+    scope: Option<String>,
+) -> Result<CachedToken, Box<dyn Error>> {
     let client = reqwest::blocking::Client::builder().build()?;
-    let addr = format!("{}/api/user/login", addr);
-    let mut request = client.post(addr);
+    let mut form = vec![("client_id", client_id.to_string())];
+    if let Some(scope) = scope {
+        form.push(("scope", scope));
+    }
+    let response = client
+        .post(format!("{oauth}/device_authorization"))
+        .form(&form)
+        .send()?;
+    let authorization: DeviceAuthorization = oauth_response(response)??;
+
+    println!(
+        "To sign in, open {} and enter the code {}",
+        authorization.verification_uri, authorization.user_code
+    );
+    println!("or open {}", authorization.verification_uri_complete);
+
+    let mut interval = authorization.interval;
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        let response = client
+            .post(format!("{oauth}/token"))
+            .form(&[
+                ("grant_type", DEVICE_CODE_GRANT_TYPE),
+                ("device_code", &authorization.device_code),
+                ("client_id", client_id),
+            ])
+            .send()?;
+
+        match oauth_response::<TokenResponse>(response)? {
+            Ok(tokens) => return Ok(CachedToken::new(client_id, tokens)),
+            Err(e) if e.error == "authorization_pending" => {}
+            Err(e) if e.error == "slow_down" => interval += 5,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
 
-    request = request.header(API_KEY_HEADER, api_key.to_base64());
-
-    let user: User = request.json(&ask_scv).send()?.json()?;
-
-    if ask_scv.password.is_none() {
-        Err(Box::new(ServiceError::PermissionError(
-            "Invalid password".to_string(),
-        )))
-    } else if &ask_scv.password.unwrap().into_inner() != user.clone().password.into_inner().as_str()
-    {
-        Err(Box::new(ServiceError::PermissionError(
-            "Invalid password".to_string(),
-        )))
-    } else {
-        Ok(user)
+/// The cached access token for `oauth`, refreshed first if it has expired.
+fn cached_token(oauth: &str) -> Result<String, Box<dyn Error>> {
+    let mut cache = TokenCache::load()?;
+    let token = cache
+        .0
+        .get(oauth)
+        .cloned()
+        .ok_or("not signed in: pass --api-key or run `authyclient login`")?;
+    // leave some time for the request to arrive
+    if token.expires_at > unix_time() + 30 {
+        return Ok(token.access_token);
     }
 
-    // if ask_scv.password.is_some() && user.password.into_inner() == ask_scv.password.unwrap().into_inner()() {
-    //     Ok(request.json(&ask_scv).send()?.json()?)
-    // } else {
-    //     Err(ServiceError::PermissionError("Invalid password".to_string()))
-    // }
+    let refresh_token = token
+        .refresh_token
+        .ok_or("session expired: run `authyclient login` again")?;
+    let client = reqwest::blocking::Client::builder().build()?;
+    let response = client
+        .post(format!("{oauth}/token"))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", &refresh_token),
+            ("client_id", &token.client_id),
+        ])
+        .send()?;
+
+    match oauth_response::<TokenResponse>(response)? {
+        Ok(tokens) => {
+            let token = CachedToken::new(&token.client_id, tokens);
+            let access_token = token.access_token.clone();
+            cache.0.insert(oauth.to_string(), token);
+            cache.save()?;
+            Ok(access_token)
+        }
+        Err(e) => {
+            cache.0.remove(oauth);
+            cache.save()?;
+            Err(format!("session expired ({e}): run `authyclient login` again").into())
+        }
+    }
+}
+
+/// Forgets the cached tokens for `oauth` and revokes them; revoking the refresh token also
+/// revokes the access token. `false` if there were none.
+fn logout(oauth: &str) -> Result<bool, Box<dyn Error>> {
+    let mut cache = TokenCache::load()?;
+    let token = match cache.0.remove(oauth) {
+        Some(token) => token,
+        None => return Ok(false),
+    };
+    cache.save()?;
+
+    let client = reqwest::blocking::Client::builder().build()?;
+    let revoked = token.refresh_token.unwrap_or(token.access_token);
+    let response = client
+        .post(format!("{oauth}/revoke"))
+        .form(&[("token", &revoked), ("client_id", &token.client_id)])
+        .send()?;
+    if !response.status().is_success() {
+        return Err(response.json::<OAuthErrorResponse>()?.into());
+    }
+
+    Ok(true)
 }
 
-fn new_user(addr: &str, ask_scv: NewUser, api_key: ApiKey) -> Result<User, Box<dyn Error>> {
+fn credentials(api_key: Option<&str>, oauth: &str) -> Result<Credentials, Box<dyn Error>> {
+    match api_key {
+        Some(api_key) => Ok(Credentials::ApiKey(ApiKey::from_str(api_key)?)),
+        None => Ok(Credentials::Bearer(cached_token(oauth)?)),
+    }
+}
+
+fn get_user(
+    addr: &str,
+    ask_scv: GetUser,
+    credentials: &Credentials,
+) -> Result<User, Box<dyn Error>> {
     let client = reqwest::blocking::Client::builder().build()?;
-    let addr = format!("{}/api/user", addr);
+    let addr = format!("{}/user/login", addr);
     let mut request = client.post(addr);
 
-    request = request.header(API_KEY_HEADER, api_key.to_base64());
+    request = credentials.apply(request);
 
+    // the server checks the password and refuses to return the user otherwise
     Ok(request.json(&ask_scv).send()?.json()?)
 }
 
-fn update_user(addr: &str, ask_scv: UpdateUser, api_key: ApiKey) -> Result<User, Box<dyn Error>> {
+fn new_user(
+    addr: &str,
+    ask_scv: NewUser,
+    credentials: &Credentials,
+) -> Result<User, Box<dyn Error>> {
     let client = reqwest::blocking::Client::builder().build()?;
-    let addr = format!("{}/api/user/login", addr);
-    let mut request = client.post(addr.clone());
+    let addr = format!("{}/user", addr);
+    let mut request = client.post(addr);
 
-    request = request.header(API_KEY_HEADER, api_key.clone().to_base64());
+    request = credentials.apply(request);
 
-    let get_user_req = GetUser {
-        email: ask_scv.email,
-        password: None,
-    };
-
-    let user: User = request.json(&get_user_req).send()?.json()?;
+    Ok(request.json(&ask_scv).send()?.json()?)
+}
 
+fn update_user(
+    addr: &str,
+    ask_scv: UpdateUser,
+    credentials: &Credentials,
+) -> Result<User, Box<dyn Error>> {
     let client = reqwest::blocking::Client::builder().build()?;
-    let addr = format!("{}/api/user", addr);
+    let addr = format!("{}/user", addr);
     let mut request = client.patch(addr);
 
-    request = request.header(API_KEY_HEADER, api_key.to_base64());
-
-    let update_user_req = UpdateUser {
-        email: user.email,
-        name: match ask_scv.name {
-            Some(value) => Some(value),
-            None => Some(user.name),
-        },
-        password: match ask_scv.password {
-            Some(value) => Some(value),
-            None => Some(user.password),
-        },
-    };
+    request = credentials.apply(request);
+
+    // fields left out are kept by the server
+    Ok(request.json(&ask_scv).send()?.json()?)
+}
+
+fn delete_user(
+    addr: &str,
+    ask_scv: DeleteUser,
+    credentials: &Credentials,
+) -> Result<String, Box<dyn Error>> {
+    let client = reqwest::blocking::Client::builder().build()?;
+    let addr = format!("{}/user", addr);
+    let mut request = client.delete(addr);
+
+    request = credentials.apply(request);
+
+    Ok(request.json(&ask_scv).send()?.json()?)
+}
 
-    Ok(request.json(&update_user_req).send()?.json()?)
+fn set_user_status(
+    addr: &str,
+    ask_scv: UpdateStatus,
+    credentials: &Credentials,
+) -> Result<User, Box<dyn Error>> {
+    let client = reqwest::blocking::Client::builder().build()?;
+    let addr = format!("{}/user/status", addr);
+    let mut request = client.put(addr);
+
+    request = credentials.apply(request);
+
+    Ok(request.json(&ask_scv).send()?.json()?)
 }
 
 fn get_api_key(addr: &str) -> Result<ApiKey, Box<dyn Error>> {
     let client = reqwest::blocking::Client::builder().build()?;
-    let addr = format!("{}/api/user", addr);
+    let addr = format!("{}/user", addr);
     let request = client.get(addr);
 
     Ok(request.send()?.json()?)
 }
 
-fn revoke_api_key(addr: &str, api_key: ApiKey) -> Result<bool, Box<dyn Error>> {
+fn revoke_api_key(addr: &str, credentials: &Credentials) -> Result<bool, Box<dyn Error>> {
     let client = reqwest::blocking::Client::builder().build()?;
-    let addr = format!("{}/api/user", addr);
+    let addr = format!("{}/user", addr);
     let mut request = client.get(addr);
 
-    request = request.header(API_KEY_HEADER, api_key.to_base64());
+    request = credentials.apply(request);
 
     Ok(request.send()?.json()?)
 }
 
-fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+fn run(mut opt: Opt) -> Result<(), Box<dyn Error>> {
+    let oauth = match &opt.tenant {
+        Some(tenant) => format!("{}/oauth/t/{}", opt.addr, tenant),
+        None => format!("{}/oauth", opt.addr),
+    };
+    opt.addr = match &opt.tenant {
+        Some(tenant) => format!("{}/api/t/{}", opt.addr, tenant),
+        None => format!("{}/api", opt.addr),
+    };
+    let api_key = opt.api_key.as_deref();
+
     match opt.command {
         Command::Get { email, password } => {
             let req = GetUser {
@@ -149,7 +447,7 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
                 password: Some(password),
             };
 
-            let user = get_user(&opt.addr.as_str(), req, ApiKey::from_str(&opt.api_key)?)?;
+            let user = get_user(opt.addr.as_str(), req, &credentials(api_key, &oauth)?)?;
             println!("{:#?}", user);
 
             Ok(())
@@ -158,14 +456,16 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
             name,
             email,
             password,
+            phone,
         } => {
             let req = NewUser {
                 email,
                 password,
                 name,
+                phone,
             };
 
-            let user = new_user(&opt.addr, req, ApiKey::from_str(&opt.api_key)?)?;
+            let user = new_user(&opt.addr, req, &credentials(api_key, &oauth)?)?;
 
             println!("{user:#?}");
             Ok(())
@@ -174,14 +474,41 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
             name,
             email,
             password,
+            phone,
         } => {
             let req = UpdateUser {
                 email,
                 name: Some(name),
                 password: Some(password),
+                phone,
+                user_metadata: None,
             };
 
-            let user = update_user(&opt.addr, req, ApiKey::from_str(&opt.api_key)?)?;
+            let user = update_user(&opt.addr, req, &credentials(api_key, &oauth)?)?;
+
+            println!("{user:#?}");
+            Ok(())
+        }
+        Command::Delete { email, permanent } => {
+            let req = DeleteUser { email, permanent };
+
+            let status = delete_user(&opt.addr, req, &credentials(api_key, &oauth)?)?;
+
+            println!("{status}");
+            Ok(())
+        }
+        Command::SetStatus {
+            email,
+            status,
+            reason,
+        } => {
+            let req = UpdateStatus {
+                email,
+                status,
+                reason,
+            };
+
+            let user = set_user_status(&opt.addr, req, &credentials(api_key, &oauth)?)?;
 
             println!("{user:#?}");
             Ok(())
@@ -193,7 +520,7 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
             Ok(())
         }
         Command::RevokeApiKey {} => {
-            let status = revoke_api_key(&opt.addr, ApiKey::from_str(&opt.api_key)?)?;
+            let status = revoke_api_key(&opt.addr, &credentials(api_key, &oauth)?)?;
 
             if status {
                 println!("logout successful");
@@ -201,6 +528,23 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
                 println!("logout not successful");
             }
 
+            Ok(())
+        }
+        Command::Login { client_id, scope } => {
+            let token = login(&oauth, &client_id, scope)?;
+
+            let mut cache = TokenCache::load()?;
+            cache.0.insert(oauth, token);
+            cache.save()?;
+            println!("login successful");
+            Ok(())
+        }
+        Command::Logout {} => {
+            match logout(&oauth)? {
+                true => println!("logout successful"),
+                false => println!("not logged in"),
+            }
+
             Ok(())
         }
     }
@@ -1,6 +1,10 @@
 use authy::data::AppDatabase;
+use authy::domain::tenant::TenantId;
+use authy::{LdapServer, Links, LocalSender, Mailer, Maintenance, RelyingParty, SessionCookie};
 use dotenv::dotenv;
-// use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -8,6 +12,60 @@ use structopt::StructOpt;
 struct Opt {
     #[structopt(default_value = "sqlite:data.db")]
     connection_string: String,
+
+    #[structopt(
+        long,
+        default_value = "30",
+        help = "days a soft-deleted user is kept before being purged"
+    )]
+    retention_days: u64,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "append outgoing mail to this file as JSON lines instead of printing it"
+    )]
+    outbox: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "append one-time codes to this file as JSON lines instead of printing them"
+    )]
+    message_outbox: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "http://localhost:8000",
+        help = "base URL of this server, used for links in outgoing mail and as the passkey origin"
+    )]
+    public_url: String,
+
+    #[structopt(
+        long,
+        help = "domain to share the sign-in session with, for hosts behind /auth/verify"
+    )]
+    cookie_domain: Option<String>,
+
+    #[structopt(
+        long,
+        help = "address to serve LDAP simple bind and search on, e.g. 127.0.0.1:3389; binds are only accepted from this host, as there is no TLS"
+    )]
+    ldap_address: Option<SocketAddr>,
+
+    #[structopt(
+        long,
+        default_value = "dc=authy",
+        help = "base DN of the user entries served over LDAP"
+    )]
+    ldap_base_dn: String,
+
+    #[structopt(
+        long,
+        default_value = "default",
+        help = "tenant whose users are served over LDAP"
+    )]
+    ldap_tenant: String,
 }
 
 fn main() {
@@ -18,7 +76,42 @@ fn main() {
     // let handle = rt.handle().clone();
     let database = rt.block_on(async move { AppDatabase::new(&opt.connection_string).await });
 
-    let config = authy::RocketConfig { database };
+    let maintenance = Maintenance {
+        retention: Duration::from_secs(opt.retention_days * 24 * 60 * 60),
+        ..Default::default()
+    };
+
+    let mailer = match opt.outbox {
+        Some(path) => Mailer::Outbox(path),
+        None => Mailer::Stdout,
+    };
+
+    let sender = match opt.message_outbox {
+        Some(path) => LocalSender::File(path),
+        None => LocalSender::Stdout,
+    };
+
+    let relying_party =
+        RelyingParty::from_url(&opt.public_url, "Authy").expect("invalid public URL");
+
+    let ldap = opt.ldap_address.map(|address| LdapServer {
+        address,
+        base_dn: opt.ldap_base_dn,
+        tenant: TenantId::new(&opt.ldap_tenant).expect("invalid LDAP tenant"),
+    });
+
+    let config = authy::RocketConfig {
+        database,
+        maintenance,
+        mailer,
+        links: Links::new(&opt.public_url),
+        sender: Box::new(sender),
+        relying_party,
+        session_cookie: SessionCookie {
+            domain: opt.cookie_domain,
+        },
+        ldap,
+    };
 
     let _ = rt.block_on(async move {
         authy::rocket(config)
@@ -9,6 +9,10 @@ use sqlx::Sqlite;
 pub enum DataError {
     #[error("database error: {0}")]
     Database(#[from] sqlx::Error),
+    #[error("invalid cursor")]
+    InvalidCursor,
+    #[error("invalid record: {0}")]
+    InvalidRecord(String),
 }
 
 pub type AppDatabase = Database<Sqlite>;
@@ -1,4 +1,20 @@
-use crate::{domain::user::field::Email, UserError};
+use crate::domain::access;
+use crate::domain::audit::{self, AuditAction, AuditOutcome};
+use crate::domain::federation;
+use crate::domain::group::{self, GroupError, GroupMember, GroupName};
+use crate::domain::identity;
+use crate::domain::invitation;
+use crate::domain::login;
+use crate::domain::oauth;
+use crate::domain::oidc;
+use crate::domain::role::{self, PermissionName, RoleError, RoleName};
+use crate::domain::session;
+use crate::domain::tenant::{self, TenantError, TenantId};
+use crate::domain::token::Token;
+use crate::domain::webauthn;
+use crate::web::api::ApiKey;
+use crate::{domain::user::field::Email, DataError, UserError};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, sqlx::FromRow)]
 pub struct User {
@@ -6,6 +22,13 @@ pub struct User {
     pub(in crate::data) name: String,
     pub(in crate::data) email: String,
     pub(in crate::data) password: String,
+    pub(in crate::data) phone: Option<String>,
+    pub(in crate::data) status: String,
+    pub(in crate::data) created_at: Option<String>,
+    pub(in crate::data) user_metadata: String,
+    pub(in crate::data) app_metadata: String,
+    /// JSON array of role names.
+    pub(in crate::data) roles: String,
 }
 
 #[derive(Debug)]
@@ -13,6 +36,7 @@ pub struct NewUser {
     pub(in crate::data) name: String,
     pub(in crate::data) email: String,
     pub(in crate::data) password: String,
+    pub(in crate::data) phone: Option<String>,
 }
 
 impl From<crate::service::ask::NewUser> for NewUser {
@@ -21,6 +45,7 @@ impl From<crate::service::ask::NewUser> for NewUser {
             name: user.name.into_inner(),
             email: user.email.into_inner(),
             password: user.password.into_inner(),
+            phone: user.phone.map(|value| value.into_inner()),
         }
     }
 }
@@ -56,6 +81,8 @@ pub struct UpdateUser {
     pub(in crate::data) email: String,
     pub(in crate::data) name: Option<String>,
     pub(in crate::data) password: Option<String>,
+    pub(in crate::data) phone: Option<String>,
+    pub(in crate::data) user_metadata: Option<String>,
 }
 
 // impl Into<GetUser> for
@@ -71,6 +98,15 @@ impl TryFrom<User> for crate::domain::user::User {
             name: field::Name::new(&user.name)?,
             email: field::Email::new(&user.email)?,
             password: field::Password::new(&user.password)?,
+            phone: user.phone.as_deref().map(field::Phone::new).transpose()?,
+            status: field::Status::new(&user.status)?,
+            user_metadata: field::Metadata::new(&user.user_metadata)?,
+            app_metadata: field::Metadata::new(&user.app_metadata)?,
+            roles: serde_json::from_str::<Vec<String>>(&user.roles)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|role| RoleName::new(role).ok())
+                .collect(),
         })
     }
 }
@@ -79,14 +115,957 @@ impl From<crate::service::ask::UpdateUser> for UpdateUser {
     fn from(user: crate::service::ask::UpdateUser) -> Self {
         Self {
             email: user.email.into_inner(),
-            name: match user.name {
-                Some(value) => Some(value.into_inner()),
-                None => None,
-            },
-            password: match user.password {
-                Some(value) => Some(value.into_inner()),
-                None => None,
-            },
+            name: user.name.map(|value| value.into_inner()),
+            password: user.password.map(|value| value.into_inner()),
+            phone: user.phone.map(|value| value.into_inner()),
+            user_metadata: user.user_metadata.map(|value| value.to_string()),
         }
     }
 }
+
+pub struct DeleteUser {
+    pub(in crate::data) email: String,
+}
+
+impl From<crate::service::ask::DeleteUser> for DeleteUser {
+    fn from(req: crate::service::ask::DeleteUser) -> Self {
+        Self {
+            email: req.email.into_inner(),
+        }
+    }
+}
+
+pub struct UpdateStatus {
+    pub(in crate::data) email: String,
+    pub(in crate::data) from_status: String,
+    pub(in crate::data) to_status: String,
+    pub(in crate::data) reason: Option<String>,
+    pub(in crate::data) actor: String,
+}
+
+impl UpdateStatus {
+    pub fn new(
+        req: crate::service::ask::UpdateStatus,
+        from: crate::domain::user::field::Status,
+        actor: String,
+    ) -> Self {
+        Self {
+            email: req.email.into_inner(),
+            from_status: from.to_string(),
+            to_status: req.status.to_string(),
+            reason: req.reason,
+            actor,
+        }
+    }
+}
+
+/// Position after the last user of a page, encoded as `<sort value>\0<email>`.
+pub struct Cursor {
+    pub(in crate::data) value: String,
+    pub(in crate::data) email: String,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(format!("{}\0{}", self.value, self.email))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, DataError> {
+        use base64::Engine;
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| DataError::InvalidCursor)?;
+        let raw = String::from_utf8(bytes).map_err(|_| DataError::InvalidCursor)?;
+        let (value, email) = raw.split_once('\0').ok_or(DataError::InvalidCursor)?;
+
+        Ok(Self {
+            value: value.to_owned(),
+            email: email.to_owned(),
+        })
+    }
+}
+
+pub struct ListUsers {
+    pub(in crate::data) status: Option<String>,
+    pub(in crate::data) created_after: Option<String>,
+    pub(in crate::data) created_before: Option<String>,
+    pub(in crate::data) email_domain: Option<String>,
+    pub(in crate::data) search: Option<String>,
+    pub(in crate::data) sort: crate::service::ask::SortBy,
+    pub(in crate::data) descending: bool,
+    pub(in crate::data) after: Option<Cursor>,
+    pub(in crate::data) limit: u32,
+}
+
+impl TryFrom<crate::service::ask::ListUsers> for ListUsers {
+    type Error = DataError;
+
+    fn try_from(req: crate::service::ask::ListUsers) -> Result<Self, Self::Error> {
+        use crate::service::ask::SortOrder;
+
+        Ok(Self {
+            status: req.status.map(|status| status.to_string()),
+            created_after: req.created_after,
+            created_before: req.created_before,
+            email_domain: req.email_domain.map(|domain| domain.to_lowercase()),
+            search: req.search,
+            sort: req.sort,
+            descending: matches!(req.order, SortOrder::Desc),
+            after: req.cursor.as_deref().map(Cursor::decode).transpose()?,
+            limit: req.limit.unwrap_or(50).clamp(1, 200),
+        })
+    }
+}
+
+pub struct FindUsers {
+    pub(in crate::data) email: Option<String>,
+    pub(in crate::data) name: Option<String>,
+    pub(in crate::data) active: Option<bool>,
+    pub(in crate::data) offset: u32,
+    pub(in crate::data) limit: u32,
+}
+
+impl From<crate::service::ask::FindUsers> for FindUsers {
+    fn from(req: crate::service::ask::FindUsers) -> Self {
+        Self {
+            email: req.email.map(Email::into_inner),
+            name: req.name,
+            active: req.active,
+            offset: req.offset,
+            limit: req.limit,
+        }
+    }
+}
+
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Role {
+    pub(in crate::data) name: String,
+    pub(in crate::data) description: String,
+    /// JSON array of permission names.
+    pub(in crate::data) permissions: String,
+}
+
+impl TryFrom<Role> for role::Role {
+    type Error = RoleError;
+
+    fn try_from(role: Role) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: RoleName::new(&role.name)?,
+            description: role.description,
+            permissions: serde_json::from_str::<Vec<String>>(&role.permissions)
+                .unwrap_or_default()
+                .iter()
+                .map(|permission| PermissionName::new(permission))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Permission {
+    pub(in crate::data) name: String,
+    pub(in crate::data) description: String,
+}
+
+impl TryFrom<Permission> for role::Permission {
+    type Error = RoleError;
+
+    fn try_from(permission: Permission) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: PermissionName::new(&permission.name)?,
+            description: permission.description,
+        })
+    }
+}
+
+impl From<role::Permission> for Permission {
+    fn from(permission: role::Permission) -> Self {
+        Self {
+            name: permission.name.into_inner(),
+            description: permission.description,
+        }
+    }
+}
+
+pub struct NewRole {
+    pub(in crate::data) name: String,
+    pub(in crate::data) description: String,
+    pub(in crate::data) permissions: Vec<String>,
+}
+
+impl From<role::Role> for NewRole {
+    fn from(role: role::Role) -> Self {
+        Self {
+            name: role.name.into_inner(),
+            description: role.description,
+            permissions: role
+                .permissions
+                .into_iter()
+                .map(PermissionName::into_inner)
+                .collect(),
+        }
+    }
+}
+
+pub struct RoleGrant {
+    pub(in crate::data) email: String,
+    pub(in crate::data) role: String,
+}
+
+impl From<crate::service::ask::RoleGrant> for RoleGrant {
+    fn from(req: crate::service::ask::RoleGrant) -> Self {
+        Self {
+            email: req.email.into_inner(),
+            role: req.role.into_inner(),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Group {
+    pub(in crate::data) name: String,
+    pub(in crate::data) description: String,
+}
+
+impl TryFrom<Group> for group::Group {
+    type Error = GroupError;
+
+    fn try_from(group: Group) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: GroupName::new(&group.name)?,
+            description: group.description,
+        })
+    }
+}
+
+impl From<group::Group> for Group {
+    fn from(group: group::Group) -> Self {
+        Self {
+            name: group.name.into_inner(),
+            description: group.description,
+        }
+    }
+}
+
+pub struct GroupMembership {
+    pub(in crate::data) group: String,
+    pub(in crate::data) member: GroupMember,
+}
+
+impl From<crate::service::ask::GroupMembership> for GroupMembership {
+    fn from(req: crate::service::ask::GroupMembership) -> Self {
+        Self {
+            group: req.group.into_inner(),
+            member: req.member,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Tenant {
+    pub(in crate::data) id: String,
+    pub(in crate::data) name: String,
+}
+
+impl TryFrom<Tenant> for tenant::Tenant {
+    type Error = TenantError;
+
+    fn try_from(tenant: Tenant) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: TenantId::new(&tenant.id)?,
+            name: tenant.name,
+        })
+    }
+}
+
+impl From<tenant::Tenant> for Tenant {
+    fn from(tenant: tenant::Tenant) -> Self {
+        Self {
+            id: tenant.id.into_inner(),
+            name: tenant.name,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Invitation {
+    pub(in crate::data) email: String,
+    pub(in crate::data) role: String,
+    pub(in crate::data) invited_by: Option<String>,
+    pub(in crate::data) created_at: String,
+    pub(in crate::data) expires_at: String,
+    pub(in crate::data) accepted_at: Option<String>,
+}
+
+impl TryFrom<Invitation> for invitation::Invitation {
+    type Error = RoleError;
+
+    fn try_from(invitation: Invitation) -> Result<Self, Self::Error> {
+        Ok(Self {
+            email: invitation.email.as_str().into(),
+            role: RoleName::new(&invitation.role)?,
+            invited_by: invitation.invited_by.as_deref().map(Email::from),
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+            accepted_at: invitation.accepted_at,
+        })
+    }
+}
+
+pub struct NewInvitation {
+    pub(in crate::data) token_hash: String,
+    pub(in crate::data) email: String,
+    pub(in crate::data) role: String,
+    pub(in crate::data) invited_by: Option<String>,
+    /// SQLite datetime modifier, e.g. `+7 days`.
+    pub(in crate::data) expires_in: String,
+}
+
+impl NewInvitation {
+    pub fn new(
+        req: crate::service::ask::NewInvitation,
+        token: &Token,
+        invited_by: Option<Email>,
+    ) -> Self {
+        Self {
+            token_hash: token.hash(),
+            email: req.email.into_inner(),
+            role: req.role.into_inner(),
+            invited_by: invited_by.map(Email::into_inner),
+            expires_in: format!("+{} days", req.expires_in_days),
+        }
+    }
+}
+
+pub struct AcceptInvitation {
+    pub(in crate::data) token_hash: String,
+    pub(in crate::data) name: Option<String>,
+    pub(in crate::data) password: Option<String>,
+}
+
+impl From<crate::service::ask::AcceptInvitation> for AcceptInvitation {
+    fn from(req: crate::service::ask::AcceptInvitation) -> Self {
+        Self {
+            token_hash: req.token.hash(),
+            name: req.name.map(|name| name.into_inner()),
+            password: req.password.map(|password| password.into_inner()),
+        }
+    }
+}
+
+pub struct NewAuditEvent {
+    pub(in crate::data) actor: Option<String>,
+    pub(in crate::data) impersonator: Option<String>,
+    pub(in crate::data) action: String,
+    pub(in crate::data) target: Option<String>,
+    pub(in crate::data) outcome: String,
+    pub(in crate::data) ip: Option<String>,
+    pub(in crate::data) user_agent: Option<String>,
+    pub(in crate::data) request_id: Option<String>,
+    /// JSON object with action specific details.
+    pub(in crate::data) detail: String,
+}
+
+impl NewAuditEvent {
+    pub fn new(
+        ctx: &crate::service::audit::Context,
+        action: AuditAction,
+        target: Option<String>,
+        outcome: AuditOutcome,
+        detail: serde_json::Value,
+    ) -> Self {
+        Self {
+            actor: ctx.actor.clone().map(Email::into_inner),
+            impersonator: ctx.impersonator.clone().map(Email::into_inner),
+            action: action.to_string(),
+            target,
+            outcome: outcome.to_string(),
+            ip: ctx.ip.clone(),
+            user_agent: ctx.user_agent.clone(),
+            request_id: ctx.request_id.clone(),
+            detail: detail.to_string(),
+        }
+    }
+
+    /// Links the event to `prev_hash`; any change to a chained event, or to the order of
+    /// events, changes every hash after it.
+    pub(in crate::data) fn chain_hash(
+        &self,
+        tenant: &str,
+        occurred_at: &str,
+        prev_hash: Option<&str>,
+    ) -> String {
+        let fields = serde_json::json!([
+            prev_hash,
+            tenant,
+            occurred_at,
+            self.actor,
+            self.impersonator,
+            self.action,
+            self.target,
+            self.outcome,
+            self.ip,
+            self.user_agent,
+            self.request_id,
+            self.detail,
+        ]);
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(fields.to_string().as_bytes()))
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuditEvent {
+    pub(in crate::data) id: i64,
+    pub(in crate::data) occurred_at: String,
+    pub(in crate::data) actor: Option<String>,
+    pub(in crate::data) impersonator: Option<String>,
+    pub(in crate::data) action: String,
+    pub(in crate::data) target: Option<String>,
+    pub(in crate::data) outcome: String,
+    pub(in crate::data) ip: Option<String>,
+    pub(in crate::data) user_agent: Option<String>,
+    pub(in crate::data) request_id: Option<String>,
+    pub(in crate::data) detail: String,
+    pub(in crate::data) prev_hash: Option<String>,
+    pub(in crate::data) hash: Option<String>,
+}
+
+impl AuditEvent {
+    pub(in crate::data) fn recompute_hash(&self, tenant: &str) -> String {
+        NewAuditEvent {
+            actor: self.actor.clone(),
+            impersonator: self.impersonator.clone(),
+            action: self.action.clone(),
+            target: self.target.clone(),
+            outcome: self.outcome.clone(),
+            ip: self.ip.clone(),
+            user_agent: self.user_agent.clone(),
+            request_id: self.request_id.clone(),
+            detail: self.detail.clone(),
+        }
+        .chain_hash(tenant, &self.occurred_at, self.prev_hash.as_deref())
+    }
+}
+
+impl TryFrom<AuditEvent> for audit::AuditEvent {
+    type Error = DataError;
+
+    fn try_from(event: AuditEvent) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: event.id,
+            occurred_at: event.occurred_at,
+            actor: event.actor.as_deref().map(Email::from),
+            impersonator: event.impersonator.as_deref().map(Email::from),
+            action: event.action,
+            target: event.target,
+            outcome: event
+                .outcome
+                .parse()
+                .map_err(|_| DataError::InvalidRecord(format!("outcome: {}", event.outcome)))?,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            request_id: event.request_id,
+            detail: serde_json::from_str(&event.detail)
+                .map_err(|_| DataError::InvalidRecord(format!("detail: {}", event.detail)))?,
+            hash: event.hash,
+        })
+    }
+}
+
+pub struct ListAuditEvents {
+    pub(in crate::data) actor: Option<String>,
+    pub(in crate::data) action: Option<String>,
+    pub(in crate::data) target: Option<String>,
+    pub(in crate::data) outcome: Option<String>,
+    pub(in crate::data) since: Option<String>,
+    pub(in crate::data) until: Option<String>,
+    pub(in crate::data) before_id: Option<i64>,
+    /// `None` exports every matching event.
+    pub(in crate::data) limit: Option<u32>,
+}
+
+impl ListAuditEvents {
+    pub fn page(req: crate::service::ask::ListAuditEvents) -> Self {
+        Self {
+            limit: Some(req.limit.unwrap_or(50).clamp(1, 200)),
+            ..Self::export(req)
+        }
+    }
+
+    pub fn export(req: crate::service::ask::ListAuditEvents) -> Self {
+        Self {
+            actor: req.actor.map(Email::into_inner),
+            action: req.action,
+            target: req.target,
+            outcome: req.outcome.map(|outcome| outcome.to_string()),
+            since: req.since,
+            until: req.until,
+            before_id: req.cursor,
+            limit: None,
+        }
+    }
+}
+
+pub struct NewImpersonation {
+    pub(in crate::data) api_key: Vec<u8>,
+    pub(in crate::data) email: String,
+    pub(in crate::data) impersonator: String,
+    /// SQLite datetime modifier, e.g. `+60 minutes`.
+    pub(in crate::data) expires_in: String,
+}
+
+impl NewImpersonation {
+    pub fn new(api_key: ApiKey, email: &Email, impersonator: &Email, expires_in: &str) -> Self {
+        Self {
+            api_key: api_key.into_inner(),
+            email: email.clone().into_inner(),
+            impersonator: impersonator.clone().into_inner(),
+            expires_in: expires_in.to_string(),
+        }
+    }
+}
+
+pub struct NewSession {
+    pub(in crate::data) api_key: Vec<u8>,
+    pub(in crate::data) email: String,
+    pub(in crate::data) ip: Option<String>,
+    pub(in crate::data) user_agent: Option<String>,
+    pub(in crate::data) device_id: Option<String>,
+}
+
+impl NewSession {
+    pub fn new(api_key: ApiKey, ctx: &crate::service::audit::Context, email: &Email) -> Self {
+        Self {
+            api_key: api_key.into_inner(),
+            email: email.clone().into_inner(),
+            ip: ctx.ip.clone(),
+            user_agent: ctx.user_agent.clone(),
+            device_id: ctx.device_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Session {
+    pub(in crate::data) id: String,
+    pub(in crate::data) created_at: String,
+    pub(in crate::data) last_seen_at: Option<String>,
+    pub(in crate::data) ip: Option<String>,
+    pub(in crate::data) user_agent: Option<String>,
+    pub(in crate::data) device_id: Option<String>,
+    pub(in crate::data) impersonator: Option<String>,
+    pub(in crate::data) current: bool,
+}
+
+impl From<Session> for session::Session {
+    fn from(session: Session) -> Self {
+        Self {
+            id: session.id,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            device_id: session.device_id,
+            impersonator: session.impersonator.as_deref().map(Email::from),
+            current: session.current,
+        }
+    }
+}
+
+pub struct NewLogin {
+    pub(in crate::data) email: String,
+    pub(in crate::data) ip: Option<String>,
+    pub(in crate::data) user_agent: Option<String>,
+    pub(in crate::data) device_id: Option<String>,
+    pub(in crate::data) success: bool,
+}
+
+impl NewLogin {
+    pub fn new(ctx: &crate::service::audit::Context, email: &Email, success: bool) -> Self {
+        Self {
+            email: email.clone().into_inner(),
+            ip: ctx.ip.clone(),
+            user_agent: ctx.user_agent.clone(),
+            device_id: ctx.device_id.clone(),
+            success,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Login {
+    pub(in crate::data) occurred_at: String,
+    pub(in crate::data) ip: Option<String>,
+    pub(in crate::data) user_agent: Option<String>,
+    pub(in crate::data) device_id: Option<String>,
+    pub(in crate::data) success: bool,
+    pub(in crate::data) new_device: bool,
+}
+
+impl From<Login> for login::Login {
+    fn from(login: Login) -> Self {
+        Self {
+            occurred_at: login.occurred_at,
+            ip: login.ip,
+            user_agent: login.user_agent,
+            device_id: login.device_id,
+            success: login.success,
+            new_device: login.new_device,
+        }
+    }
+}
+
+pub struct NewCredential {
+    pub(in crate::data) id: String,
+    pub(in crate::data) email: String,
+    pub(in crate::data) public_key: Vec<u8>,
+    pub(in crate::data) sign_count: i64,
+    pub(in crate::data) transports: String,
+    pub(in crate::data) name: Option<String>,
+}
+
+impl NewCredential {
+    pub fn new(
+        email: &Email,
+        credential: &webauthn::AttestedCredential,
+        sign_count: u32,
+        transports: &[String],
+        name: Option<String>,
+    ) -> Self {
+        Self {
+            id: webauthn::encode(&credential.id),
+            email: email.clone().into_inner(),
+            public_key: credential.public_key.as_bytes().to_vec(),
+            sign_count: sign_count.into(),
+            transports: serde_json::to_string(transports).unwrap_or_else(|_| "[]".to_string()),
+            name,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Credential {
+    pub(in crate::data) id: String,
+    pub(in crate::data) name: Option<String>,
+    pub(in crate::data) transports: String,
+    pub(in crate::data) created_at: String,
+    pub(in crate::data) last_used_at: Option<String>,
+}
+
+impl From<Credential> for webauthn::Credential {
+    fn from(credential: Credential) -> Self {
+        Self {
+            id: credential.id,
+            name: credential.name,
+            transports: serde_json::from_str(&credential.transports).unwrap_or_default(),
+            created_at: credential.created_at,
+            last_used_at: credential.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewClient {
+    pub(in crate::data) id: String,
+    pub(in crate::data) name: String,
+    pub(in crate::data) client_type: String,
+    pub(in crate::data) secret_hash: Option<String>,
+    pub(in crate::data) redirect_uris: String,
+    pub(in crate::data) scopes: String,
+    pub(in crate::data) post_logout_redirect_uris: String,
+}
+
+impl NewClient {
+    pub fn new(id: &str, req: &crate::service::ask::NewClient, secret: Option<&Token>) -> Self {
+        Self {
+            id: id.to_string(),
+            name: req.name.clone(),
+            client_type: req.client_type.to_string(),
+            secret_hash: secret.map(Token::hash),
+            redirect_uris: serde_json::to_string(&req.redirect_uris)
+                .unwrap_or_else(|_| "[]".to_string()),
+            scopes: serde_json::to_string(&req.scopes).unwrap_or_else(|_| "[]".to_string()),
+            post_logout_redirect_uris: serde_json::to_string(&req.post_logout_redirect_uris)
+                .unwrap_or_else(|_| "[]".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Client {
+    pub(in crate::data) id: String,
+    pub(in crate::data) name: String,
+    pub(in crate::data) client_type: String,
+    pub(in crate::data) redirect_uris: String,
+    pub(in crate::data) scopes: String,
+    pub(in crate::data) post_logout_redirect_uris: String,
+    pub(in crate::data) created_at: String,
+}
+
+impl TryFrom<Client> for oauth::Client {
+    type Error = DataError;
+
+    fn try_from(client: Client) -> Result<Self, Self::Error> {
+        Ok(Self {
+            client_type: client.client_type.parse().map_err(|_| {
+                DataError::InvalidRecord(format!("client type: {}", client.client_type))
+            })?,
+            redirect_uris: serde_json::from_str(&client.redirect_uris).map_err(|_| {
+                DataError::InvalidRecord(format!("redirect URIs: {}", client.redirect_uris))
+            })?,
+            scopes: serde_json::from_str(&client.scopes)
+                .map_err(|_| DataError::InvalidRecord(format!("scopes: {}", client.scopes)))?,
+            post_logout_redirect_uris: serde_json::from_str(&client.post_logout_redirect_uris)
+                .map_err(|_| {
+                    DataError::InvalidRecord(format!(
+                        "post-logout redirect URIs: {}",
+                        client.post_logout_redirect_uris
+                    ))
+                })?,
+            id: client.id,
+            name: client.name,
+            created_at: client.created_at,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct NewAuthorizationCode {
+    pub(in crate::data) code_hash: String,
+    pub(in crate::data) client_id: String,
+    pub(in crate::data) email: String,
+    pub(in crate::data) redirect_uri: String,
+    pub(in crate::data) scope: String,
+    pub(in crate::data) code_challenge: String,
+    pub(in crate::data) nonce: Option<String>,
+}
+
+impl NewAuthorizationCode {
+    pub fn new(
+        code: &Token,
+        client: &oauth::Client,
+        email: &Email,
+        scope: &oauth::Scope,
+        req: &crate::service::ask::AuthorizeRequest,
+    ) -> Self {
+        Self {
+            code_hash: code.hash(),
+            client_id: client.id.clone(),
+            email: email.clone().into_inner(),
+            redirect_uri: req.redirect_uri.clone(),
+            scope: scope.to_string(),
+            code_challenge: req.code_challenge.clone().unwrap_or_default(),
+            nonce: req.nonce.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewOAuthToken {
+    pub(in crate::data) token_hash: String,
+    pub(in crate::data) kind: String,
+    pub(in crate::data) client_id: String,
+    /// `None` for tokens acting for the client itself.
+    pub(in crate::data) email: Option<String>,
+    pub(in crate::data) scope: String,
+}
+
+impl NewOAuthToken {
+    pub fn new(
+        token: &Token,
+        kind: oauth::TokenKind,
+        client_id: &str,
+        email: Option<&str>,
+        scope: &oauth::Scope,
+    ) -> Self {
+        Self {
+            token_hash: token.hash(),
+            kind: kind.to_string(),
+            client_id: client_id.to_string(),
+            email: email.map(str::to_string),
+            scope: scope.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewDeviceCode {
+    pub(in crate::data) device_code_hash: String,
+    pub(in crate::data) client_id: String,
+    pub(in crate::data) user_code: String,
+    pub(in crate::data) scope: String,
+    pub(in crate::data) poll_interval: i64,
+}
+
+impl NewDeviceCode {
+    pub fn new(
+        device_code: &Token,
+        user_code: &oauth::UserCode,
+        client: &oauth::Client,
+        scope: &oauth::Scope,
+        poll_interval: u64,
+    ) -> Self {
+        Self {
+            device_code_hash: device_code.hash(),
+            client_id: client.id.clone(),
+            user_code: user_code.as_str().to_string(),
+            scope: scope.to_string(),
+            poll_interval: poll_interval as i64,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SigningKey {
+    pub(in crate::data) id: String,
+    pub(in crate::data) private_key: Vec<u8>,
+}
+
+impl SigningKey {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl TryFrom<SigningKey> for oidc::SigningKey {
+    type Error = DataError;
+
+    fn try_from(key: SigningKey) -> Result<Self, Self::Error> {
+        let id = key.id.clone();
+        oidc::SigningKey::from_pkcs8(key.id, key.private_key)
+            .map_err(|_| DataError::InvalidRecord(format!("signing key: {id}")))
+    }
+}
+
+#[derive(Debug)]
+pub struct NewIdentityProvider {
+    pub(in crate::data) id: String,
+    pub(in crate::data) name: String,
+    pub(in crate::data) issuer: String,
+    pub(in crate::data) client_id: String,
+    pub(in crate::data) client_secret: String,
+    pub(in crate::data) scopes: String,
+    pub(in crate::data) link_by_email: bool,
+}
+
+impl NewIdentityProvider {
+    pub fn new(req: &crate::service::ask::NewIdentityProvider, scopes: &[String]) -> Self {
+        Self {
+            id: req.id.clone(),
+            name: req.name.clone(),
+            issuer: req.issuer.trim_end_matches('/').to_string(),
+            client_id: req.client_id.clone(),
+            client_secret: req.client_secret.clone(),
+            scopes: serde_json::to_string(scopes).unwrap_or_else(|_| "[]".to_string()),
+            link_by_email: req.link_by_email,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct IdentityProvider {
+    pub(in crate::data) id: String,
+    pub(in crate::data) name: String,
+    pub(in crate::data) issuer: String,
+    pub(in crate::data) client_id: String,
+    pub(in crate::data) scopes: String,
+    pub(in crate::data) link_by_email: bool,
+    pub(in crate::data) created_at: String,
+}
+
+impl TryFrom<IdentityProvider> for federation::IdentityProvider {
+    type Error = DataError;
+
+    fn try_from(provider: IdentityProvider) -> Result<Self, Self::Error> {
+        Ok(Self {
+            scopes: serde_json::from_str(&provider.scopes)
+                .map_err(|_| DataError::InvalidRecord(format!("scopes: {}", provider.scopes)))?,
+            id: provider.id,
+            name: provider.name,
+            issuer: provider.issuer,
+            client_id: provider.client_id,
+            link_by_email: provider.link_by_email,
+            created_at: provider.created_at,
+        })
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Identity {
+    pub(in crate::data) id: String,
+    pub(in crate::data) kind: String,
+    pub(in crate::data) credential_id: Option<String>,
+    pub(in crate::data) provider_id: Option<String>,
+    pub(in crate::data) subject: Option<String>,
+    pub(in crate::data) name: Option<String>,
+    pub(in crate::data) created_at: String,
+}
+
+impl TryFrom<Identity> for identity::Identity {
+    type Error = DataError;
+
+    fn try_from(identity: Identity) -> Result<Self, Self::Error> {
+        Ok(Self {
+            kind: identity.kind.parse().map_err(|_| {
+                DataError::InvalidRecord(format!("identity kind: {}", identity.kind))
+            })?,
+            id: identity.id,
+            credential_id: identity.credential_id,
+            provider: identity.provider_id,
+            subject: identity.subject,
+            name: identity.name,
+            created_at: identity.created_at,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct NewAccessRule {
+    pub(in crate::data) host: String,
+    pub(in crate::data) roles: String,
+    pub(in crate::data) groups: String,
+}
+
+impl NewAccessRule {
+    pub fn new(host: &str, req: &crate::service::ask::SetAccessRule) -> Self {
+        Self {
+            host: host.to_string(),
+            roles: serde_json::to_string(&req.roles).unwrap_or_else(|_| "[]".to_string()),
+            groups: serde_json::to_string(&req.groups).unwrap_or_else(|_| "[]".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AccessRule {
+    pub(in crate::data) host: String,
+    pub(in crate::data) roles: String,
+    pub(in crate::data) groups: String,
+    pub(in crate::data) created_at: String,
+    pub(in crate::data) updated_at: String,
+}
+
+impl TryFrom<AccessRule> for access::AccessRule {
+    type Error = DataError;
+
+    fn try_from(rule: AccessRule) -> Result<Self, Self::Error> {
+        Ok(Self {
+            roles: serde_json::from_str(&rule.roles)
+                .map_err(|_| DataError::InvalidRecord(format!("roles: {}", rule.roles)))?,
+            groups: serde_json::from_str(&rule.groups)
+                .map_err(|_| DataError::InvalidRecord(format!("groups: {}", rule.groups)))?,
+            host: rule.host,
+            created_at: rule.created_at,
+            updated_at: rule.updated_at,
+        })
+    }
+}
@@ -1,106 +1,3229 @@
 use super::model;
+use super::Transaction;
+use crate::domain::tenant::TenantId;
 use crate::{web::api::ApiKey, DataError, DatabasePool};
-use sqlx::Row;
 
 type Result<T> = std::result::Result<T, DataError>;
 
 pub async fn get_user<M: Into<model::GetUser>>(
+    tenant: &TenantId,
     model: M,
     pool: &DatabasePool,
 ) -> Result<model::User> {
     let model = model.into();
+    let tenant = tenant.as_str();
     let email = model.email.as_str();
 
-    Ok(
-        sqlx::query_as!(model::User, "SELECT * FROM user WHERE email = ?", email)
-            .fetch_one(pool)
-            .await?,
+    Ok(sqlx::query_as!(
+        model::User,
+        r#"SELECT name, email, password, phone, status, created_at, user_metadata, app_metadata,
+                (SELECT json_group_array(role) FROM user_roles
+                    WHERE user_roles.tenant = user.tenant
+                    AND user_roles.email = user.email) AS "roles!: String"
+            FROM user WHERE tenant = ? AND email = ? AND deleted_at IS NULL"#,
+        tenant,
+        email
     )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn list_users<M: TryInto<model::ListUsers, Error = DataError>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<model::UserPage> {
+    use crate::service::ask::SortBy;
+
+    let model = model.try_into()?;
+    let column = match model.sort {
+        SortBy::CreatedAt => "created_at",
+        SortBy::Name => "name",
+        SortBy::Email => "email",
+    };
+
+    let mut builder = sqlx::QueryBuilder::new(
+        r#"SELECT name, email, password, phone, status, created_at, user_metadata, app_metadata,
+                (SELECT json_group_array(role) FROM user_roles
+                    WHERE user_roles.tenant = user.tenant
+                    AND user_roles.email = user.email) AS roles
+            FROM user WHERE deleted_at IS NULL AND tenant = "#,
+    );
+    builder.push_bind(tenant.as_str());
+
+    if let Some(status) = model.status {
+        builder.push(" AND status = ").push_bind(status);
+    }
+    if let Some(after) = model.created_after {
+        builder
+            .push(" AND created_at >= datetime(")
+            .push_bind(after)
+            .push(")");
+    }
+    if let Some(before) = model.created_before {
+        builder
+            .push(" AND created_at < datetime(")
+            .push_bind(before)
+            .push(")");
+    }
+    if let Some(domain) = model.email_domain {
+        builder.push(" AND email_domain = ").push_bind(domain);
+    }
+    if let Some(search) = model.search {
+        let pattern = format!(
+            "{}%",
+            search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        builder
+            .push(" AND (name LIKE ")
+            .push_bind(pattern.clone())
+            .push(" ESCAPE '\\' OR email LIKE ")
+            .push_bind(pattern)
+            .push(" ESCAPE '\\')");
+    }
+
+    let (cmp, order) = match model.descending {
+        true => ("<", "DESC"),
+        false => (">", "ASC"),
+    };
+    if let Some(after) = model.after {
+        builder
+            .push(format!(" AND ({column}, email) {cmp} ("))
+            .push_bind(after.value)
+            .push(", ")
+            .push_bind(after.email)
+            .push(")");
+    }
+    builder
+        .push(format!(" ORDER BY {column} {order}, email {order} LIMIT "))
+        .push_bind(model.limit + 1);
+
+    let mut users: Vec<model::User> = builder.build_query_as().fetch_all(pool).await?;
+
+    let next_cursor = match users.len() > model.limit as usize {
+        true => {
+            users.truncate(model.limit as usize);
+            users.last().map(|user| {
+                let value = match model.sort {
+                    SortBy::CreatedAt => user.created_at.clone().unwrap_or_default(),
+                    SortBy::Name => user.name.clone(),
+                    SortBy::Email => user.email.clone(),
+                };
+                model::Cursor {
+                    value,
+                    email: user.email.clone(),
+                }
+                .encode()
+            })
+        }
+        false => None,
+    };
+
+    Ok(model::UserPage { users, next_cursor })
+}
+
+/// Users with exactly the given email or name (compared case-insensitively) and status:
+/// the page at `offset`, and how many there are in all.
+pub async fn find_users<M: Into<model::FindUsers>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<(Vec<model::User>, i64)> {
+    let model = model.into();
+    let filter = |builder: &mut sqlx::QueryBuilder<'_, sqlx::Sqlite>| {
+        builder
+            .push(" FROM user WHERE deleted_at IS NULL AND tenant = ")
+            .push_bind(tenant.as_str().to_string());
+        if let Some(email) = model.email.clone() {
+            builder
+                .push(" AND email = ")
+                .push_bind(email)
+                .push(" COLLATE NOCASE");
+        }
+        if let Some(name) = model.name.clone() {
+            builder
+                .push(" AND name = ")
+                .push_bind(name)
+                .push(" COLLATE NOCASE");
+        }
+        match model.active {
+            Some(true) => builder.push(" AND status = 'active'"),
+            Some(false) => builder.push(" AND status != 'active'"),
+            None => builder,
+        };
+    };
+
+    let mut builder = sqlx::QueryBuilder::new("SELECT COUNT(*)");
+    filter(&mut builder);
+    let (total,): (i64,) = builder.build_query_as().fetch_one(pool).await?;
+
+    let mut builder = sqlx::QueryBuilder::new(
+        r#"SELECT name, email, password, phone, status, created_at, user_metadata, app_metadata,
+                (SELECT json_group_array(role) FROM user_roles
+                    WHERE user_roles.tenant = user.tenant
+                    AND user_roles.email = user.email) AS roles"#,
+    );
+    filter(&mut builder);
+    builder
+        .push(" ORDER BY created_at, email LIMIT ")
+        .push_bind(model.limit)
+        .push(" OFFSET ")
+        .push_bind(model.offset);
+    let users = builder.build_query_as().fetch_all(pool).await?;
+
+    Ok((users, total))
 }
 
 pub async fn new_user<M: Into<model::NewUser>>(
+    tenant: &TenantId,
     model: M,
     pool: &DatabasePool,
 ) -> Result<model::User> {
     let model = model.into();
+    let tenant_id = tenant.as_str();
+    let mut tx = pool.begin().await?;
 
     let _ = sqlx::query!(
         r#"INSERT INTO user (
-            name, email, password
+            tenant, name, email, password, phone, created_at
         ) 
-        VALUES (?, ?, ?)"#,
+        VALUES (?, ?, ?, ?, ?, datetime('now'))"#,
+        tenant_id,
         model.name,
         model.email,
-        model.password
+        model.password,
+        model.phone
     )
-    .execute(pool)
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO identities (tenant, email, kind) VALUES (?, ?, 'password')",
+        tenant_id,
+        model.email
+    )
+    .execute(&mut tx)
     .await?;
 
-    get_user(model.email, pool).await
+    tx.commit().await?;
+
+    get_user(tenant, model.email, pool).await
 }
 
 pub async fn update_user<M: Into<model::UpdateUser>>(
+    tenant: &TenantId,
     model: M,
     pool: &DatabasePool,
 ) -> std::result::Result<model::User, DataError> {
     let model = model.into();
+    let tenant_id = tenant.as_str();
 
     println!("{:#?}", model);
 
     let _ = sqlx::query!(
         r#"UPDATE user SET
-                name = ?,
-                password = ?
-            WHERE email = ?
+                name = COALESCE(?, name),
+                password = COALESCE(?, password),
+                phone = COALESCE(?, phone),
+                user_metadata = COALESCE(?, user_metadata),
+                password_reset_required = password_reset_required
+                    AND password = COALESCE(?, password)
+            WHERE tenant = ? AND email = ? AND deleted_at IS NULL
         "#,
         model.name,
         model.password,
+        model.phone,
+        model.user_metadata,
+        model.password,
+        tenant_id,
         model.email
     )
     .execute(pool)
     .await?;
 
-    get_user(model.email, pool).await
+    // setting a password lets a user who had removed theirs sign in with one again
+    if model.password.is_some() {
+        sqlx::query!(
+            r#"INSERT OR IGNORE INTO identities (tenant, email, kind)
+                SELECT tenant, email, 'password' FROM user
+                WHERE tenant = ? AND email = ? AND deleted_at IS NULL"#,
+            tenant_id,
+            model.email
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    get_user(tenant, model.email, pool).await
 }
 
-pub async fn save_api_key(api_key: ApiKey, pool: &DatabasePool) -> Result<ApiKey> {
-    let bytes = api_key.clone().into_inner();
+/// Which of a user's metadata objects to change.
+#[derive(Debug, Clone, Copy)]
+pub enum MetadataKind {
+    User,
+    App,
+}
 
-    sqlx::query!("INSERT INTO api_keys (api_key) VALUES (?)", bytes)
-        .execute(pool)
-        .await
-        .map(|_| ())?;
+/// Replaces a user's metadata with what `merge` makes of it, reading and writing it in one
+/// transaction so that concurrent changes cannot overwrite each other.
+pub async fn merge_metadata<E: From<DataError>>(
+    tenant: &TenantId,
+    email: &str,
+    kind: MetadataKind,
+    merge: impl FnOnce(&str) -> std::result::Result<String, E>,
+    pool: &DatabasePool,
+) -> std::result::Result<model::User, E> {
+    let tenant_id = tenant.as_str();
+    let mut tx = pool.begin().await.map_err(DataError::from)?;
 
-    Ok(api_key)
+    // reading through a write takes the write lock first, so a concurrent change waits for
+    // this one to commit instead of merging into the same value
+    let current = match kind {
+        MetadataKind::User => {
+            sqlx::query_scalar!(
+                r#"UPDATE user SET user_metadata = user_metadata
+                    WHERE tenant = ? AND email = ? AND deleted_at IS NULL
+                    RETURNING user_metadata AS "user_metadata!""#,
+                tenant_id,
+                email
+            )
+            .fetch_one(&mut tx)
+            .await
+        }
+        MetadataKind::App => {
+            sqlx::query_scalar!(
+                r#"UPDATE user SET app_metadata = app_metadata
+                    WHERE tenant = ? AND email = ? AND deleted_at IS NULL
+                    RETURNING app_metadata AS "app_metadata!""#,
+                tenant_id,
+                email
+            )
+            .fetch_one(&mut tx)
+            .await
+        }
+    }
+    .map_err(DataError::from)?;
+
+    let merged = merge(&current)?;
+    match kind {
+        MetadataKind::User => {
+            sqlx::query!(
+                "UPDATE user SET user_metadata = ? WHERE tenant = ? AND email = ?",
+                merged,
+                tenant_id,
+                email
+            )
+            .execute(&mut tx)
+            .await
+        }
+        MetadataKind::App => {
+            sqlx::query!(
+                "UPDATE user SET app_metadata = ? WHERE tenant = ? AND email = ?",
+                merged,
+                tenant_id,
+                email
+            )
+            .execute(&mut tx)
+            .await
+        }
+    }
+    .map_err(DataError::from)?;
+    tx.commit().await.map_err(DataError::from)?;
+
+    Ok(get_user(tenant, email.to_string(), pool).await?)
 }
 
-pub enum RevocationStatus {
-    Revoked,
+pub enum DeletionStatus {
+    Deleted,
     NotFound,
 }
 
-pub async fn revoke_api_key(api_key: ApiKey, pool: &DatabasePool) -> Result<RevocationStatus> {
-    let bytes = api_key.into_inner();
+pub async fn delete_user<M: Into<model::DeleteUser>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let model = model.into();
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
 
-    Ok(
-        sqlx::query!("DELETE FROM api_keys WHERE api_key = ?", bytes)
+    remove_credentials(tenant, &model.email, &mut tx).await?;
+    let deleted = sqlx::query!(
+        "DELETE FROM user WHERE tenant = ? AND email = ?",
+        tenant,
+        model.email
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    match deleted {
+        0 => Ok(DeletionStatus::NotFound),
+        _ => {
+            tx.commit().await?;
+            Ok(DeletionStatus::Deleted)
+        }
+    }
+}
+
+pub async fn deactivate_user<M: Into<model::DeleteUser>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let model = model.into();
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let deactivated = sqlx::query!(
+        r#"UPDATE user SET
+                deleted_at = datetime('now'),
+                status = 'deleted'
+            WHERE tenant = ? AND email = ? AND deleted_at IS NULL
+        "#,
+        tenant,
+        model.email
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    match deactivated {
+        0 => Ok(DeletionStatus::NotFound),
+        _ => {
+            remove_credentials(tenant, &model.email, &mut tx).await?;
+            tx.commit().await?;
+            Ok(DeletionStatus::Deleted)
+        }
+    }
+}
+
+/// Permanently removes users that were soft-deleted more than `retention_secs` ago.
+pub async fn purge_deleted_users(retention_secs: i64, pool: &DatabasePool) -> Result<u64> {
+    let cutoff = format!("-{} seconds", retention_secs);
+    let mut tx = pool.begin().await?;
+
+    // everything else bound to the user goes with it through foreign keys
+    sqlx::query!(
+        r#"DELETE FROM api_keys WHERE (tenant, impersonator) IN (
+                SELECT tenant, email FROM user
+                    WHERE deleted_at IS NOT NULL AND deleted_at <= datetime('now', ?)
+            )"#,
+        cutoff
+    )
+    .execute(&mut tx)
+    .await?;
+    let purged = sqlx::query!(
+        r#"DELETE FROM user
+            WHERE deleted_at IS NOT NULL
+            AND deleted_at <= datetime('now', ?)
+        "#,
+        cutoff
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(purged)
+}
+
+/// Removes the sessions, keys and tokens a user could still sign in or act with, including
+/// keys others hold to impersonate them or that they hold to impersonate others.
+async fn remove_credentials(tenant: &str, email: &str, tx: &mut Transaction<'_>) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM api_keys WHERE tenant = ? AND (owner = ? OR impersonator = ?)",
+        tenant,
+        email,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM oauth_tokens WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM oauth_codes WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM oauth_device_codes WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM magic_links WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM otp_codes WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM login_alerts WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM identity_link_requests WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+pub enum StatusChange {
+    Applied,
+    Conflict,
+}
+
+/// Moves a user from `from_status` to `to_status` and records the change.
+///
+/// Returns `Conflict` if the user's status changed in the meantime.
+pub async fn set_user_status(
+    tenant: &TenantId,
+    model: model::UpdateStatus,
+    pool: &DatabasePool,
+) -> Result<StatusChange> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let updated = sqlx::query!(
+        r#"UPDATE user SET
+                status = ?,
+                deleted_at = CASE WHEN ? = 'deleted' THEN datetime('now') ELSE deleted_at END
+            WHERE tenant = ? AND email = ? AND status = ? AND deleted_at IS NULL
+        "#,
+        model.to_status,
+        model.to_status,
+        tenant,
+        model.email,
+        model.from_status
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        return Ok(StatusChange::Conflict);
+    }
+    if model.to_status == "deleted" {
+        remove_credentials(tenant, &model.email, &mut tx).await?;
+    }
+
+    sqlx::query!(
+        r#"INSERT INTO user_status_changes (
+            tenant, email, from_status, to_status, reason, actor
+        )
+        VALUES (?, ?, ?, ?, ?, ?)"#,
+        tenant,
+        model.email,
+        model.from_status,
+        model.to_status,
+        model.reason,
+        model.actor
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(StatusChange::Applied)
+}
+
+pub async fn list_roles(tenant: &TenantId, pool: &DatabasePool) -> Result<Vec<model::Role>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Role,
+        r#"SELECT name, description,
+                (SELECT json_group_array(permission) FROM role_permissions
+                    WHERE role_permissions.tenant = roles.tenant
+                    AND role_permissions.role = roles.name) AS "permissions!: String"
+            FROM roles WHERE tenant = ? ORDER BY name"#,
+        tenant
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn get_role(tenant: &TenantId, name: &str, pool: &DatabasePool) -> Result<model::Role> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Role,
+        r#"SELECT name, description,
+                (SELECT json_group_array(permission) FROM role_permissions
+                    WHERE role_permissions.tenant = roles.tenant
+                    AND role_permissions.role = roles.name) AS "permissions!: String"
+            FROM roles WHERE tenant = ? AND name = ?"#,
+        tenant,
+        name
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// Creates a role, or replaces the description and permissions of an existing one.
+pub async fn save_role<M: Into<model::NewRole>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<model::Role> {
+    let model = model.into();
+    let tenant_id = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"INSERT INTO roles (tenant, name, description) VALUES (?, ?, ?)
+            ON CONFLICT (tenant, name) DO UPDATE SET description = excluded.description"#,
+        tenant_id,
+        model.name,
+        model.description
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM role_permissions WHERE tenant = ? AND role = ?",
+        tenant_id,
+        model.name
+    )
+    .execute(&mut tx)
+    .await?;
+
+    for permission in &model.permissions {
+        sqlx::query!(
+            "INSERT INTO role_permissions (tenant, role, permission) VALUES (?, ?, ?)",
+            tenant_id,
+            model.name,
+            permission
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    get_role(tenant, &model.name, pool).await
+}
+
+pub async fn delete_role(
+    tenant: &TenantId,
+    name: &str,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query!(
+        "DELETE FROM roles WHERE tenant = ? AND name = ?",
+        tenant,
+        name
+    )
+    .execute(pool)
+    .await
+    .map(|result| match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })?)
+}
+
+pub async fn list_permissions(pool: &DatabasePool) -> Result<Vec<model::Permission>> {
+    Ok(sqlx::query_as!(
+        model::Permission,
+        "SELECT name, description FROM permissions ORDER BY name"
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn save_permission<M: Into<model::Permission>>(
+    model: M,
+    pool: &DatabasePool,
+) -> Result<model::Permission> {
+    let model = model.into();
+
+    sqlx::query!(
+        r#"INSERT INTO permissions (name, description) VALUES (?, ?)
+            ON CONFLICT (name) DO UPDATE SET description = excluded.description"#,
+        model.name,
+        model.description
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(model)
+}
+
+pub async fn grant_role<M: Into<model::RoleGrant>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let model = model.into();
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        "INSERT OR IGNORE INTO user_roles (tenant, email, role) VALUES (?, ?, ?)",
+        tenant,
+        model.email,
+        model.role
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn revoke_role<M: Into<model::RoleGrant>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let model = model.into();
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query!(
+        "DELETE FROM user_roles WHERE tenant = ? AND email = ? AND role = ?",
+        tenant,
+        model.email,
+        model.role
+    )
+    .execute(pool)
+    .await
+    .map(|result| match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })?)
+}
+
+pub async fn user_permissions(
+    tenant: &TenantId,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<Vec<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"SELECT DISTINCT role_permissions.permission
+            FROM user_roles
+            JOIN role_permissions ON role_permissions.tenant = user_roles.tenant
+                AND role_permissions.role = user_roles.role
+            WHERE user_roles.tenant = ? AND user_roles.email = ?"#,
+        tenant,
+        email
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn list_groups(tenant: &TenantId, pool: &DatabasePool) -> Result<Vec<model::Group>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Group,
+        "SELECT name, description FROM groups WHERE tenant = ? ORDER BY name",
+        tenant
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn save_group<M: Into<model::Group>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<model::Group> {
+    let model = model.into();
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO groups (tenant, name, description) VALUES (?, ?, ?)
+            ON CONFLICT (tenant, name) DO UPDATE SET description = excluded.description"#,
+        tenant,
+        model.name,
+        model.description
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(model)
+}
+
+pub async fn delete_group(
+    tenant: &TenantId,
+    name: &str,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query!(
+        "DELETE FROM groups WHERE tenant = ? AND name = ?",
+        tenant,
+        name
+    )
+    .execute(pool)
+    .await
+    .map(|result| match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })?)
+}
+
+/// Direct user and group members of a group.
+pub async fn group_members(
+    tenant: &TenantId,
+    name: &str,
+    pool: &DatabasePool,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let tenant = tenant.as_str();
+
+    // make sure the group exists so an unknown group is not reported as empty
+    sqlx::query_scalar!(
+        "SELECT name FROM groups WHERE tenant = ? AND name = ?",
+        tenant,
+        name
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let users = sqlx::query_scalar!(
+        "SELECT email FROM group_users WHERE tenant = ? AND group_name = ? ORDER BY email",
+        tenant,
+        name
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let groups = sqlx::query_scalar!(
+        "SELECT child FROM group_groups WHERE tenant = ? AND parent = ? ORDER BY child",
+        tenant,
+        name
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok((users, groups))
+}
+
+pub enum MembershipChange {
+    Added,
+    WouldCycle,
+}
+
+pub async fn add_group_member<M: Into<model::GroupMembership>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<MembershipChange> {
+    use crate::domain::group::GroupMember;
+
+    let model = model.into();
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    match model.member {
+        GroupMember::User(email) => {
+            let email = email.into_inner();
+            sqlx::query!(
+                "INSERT OR IGNORE INTO group_users (tenant, group_name, email) VALUES (?, ?, ?)",
+                tenant,
+                model.group,
+                email
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        GroupMember::Group(child) => {
+            let child = child.into_inner();
+
+            // the parent must not already be reachable from the child
+            let cycle = sqlx::query_scalar!(
+                r#"WITH RECURSIVE descendants(name) AS (
+                    SELECT ?
+                    UNION
+                    SELECT group_groups.child FROM group_groups
+                        JOIN descendants ON group_groups.parent = descendants.name
+                        WHERE group_groups.tenant = ?
+                )
+                SELECT COUNT(*) AS "count!: i64" FROM descendants WHERE name = ?"#,
+                child,
+                tenant,
+                model.group
+            )
+            .fetch_one(&mut tx)
+            .await?;
+
+            if cycle > 0 {
+                return Ok(MembershipChange::WouldCycle);
+            }
+
+            sqlx::query!(
+                "INSERT OR IGNORE INTO group_groups (tenant, parent, child) VALUES (?, ?, ?)",
+                tenant,
+                model.group,
+                child
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(MembershipChange::Added)
+}
+
+pub async fn remove_group_member<M: Into<model::GroupMembership>>(
+    tenant: &TenantId,
+    model: M,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    use crate::domain::group::GroupMember;
+
+    let model = model.into();
+    let tenant = tenant.as_str();
+
+    let result = match model.member {
+        GroupMember::User(email) => {
+            let email = email.into_inner();
+            sqlx::query!(
+                "DELETE FROM group_users WHERE tenant = ? AND group_name = ? AND email = ?",
+                tenant,
+                model.group,
+                email
+            )
             .execute(pool)
-            .await
-            .map(|result| match result.rows_affected() {
-                0 => RevocationStatus::NotFound,
-                _ => RevocationStatus::Revoked,
-            })?,
+            .await?
+        }
+        GroupMember::Group(child) => {
+            let child = child.into_inner();
+            sqlx::query!(
+                "DELETE FROM group_groups WHERE tenant = ? AND parent = ? AND child = ?",
+                tenant,
+                model.group,
+                child
+            )
+            .execute(pool)
+            .await?
+        }
+    };
+
+    Ok(match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })
+}
+
+/// Every group a user belongs to, directly or through nested groups.
+pub async fn user_groups(
+    tenant: &TenantId,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<Vec<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"WITH RECURSIVE effective(name) AS (
+            SELECT group_name FROM group_users WHERE tenant = ? AND email = ?
+            UNION
+            SELECT group_groups.parent FROM group_groups
+                JOIN effective ON group_groups.child = effective.name
+                WHERE group_groups.tenant = ?
+        )
+        SELECT name AS "name!: String" FROM effective ORDER BY name"#,
+        tenant,
+        email,
+        tenant
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn save_api_key(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<ApiKey> {
+    let bytes = api_key.clone().into_inner();
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO api_keys (api_key, tenant, id, created_at)
+            VALUES (?, ?, lower(hex(randomblob(16))), datetime('now'))"#,
+        bytes,
+        tenant
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())?;
+
+    Ok(api_key)
+}
+
+/// Stores a key issued to a user at sign-in and returns the id of the session it starts.
+pub async fn save_user_api_key(
+    tenant: &TenantId,
+    model: model::NewSession,
+    pool: &DatabasePool,
+) -> Result<String> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"INSERT INTO api_keys (api_key, tenant, owner, id, created_at, ip, user_agent, device_id)
+            VALUES (?, ?, ?, lower(hex(randomblob(16))), datetime('now'), ?, ?, ?)
+            RETURNING id AS "id!""#,
+        model.api_key,
+        tenant,
+        model.email,
+        model.ip,
+        model.user_agent,
+        model.device_id
     )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub enum KeyOwner {
+    Operator,
+    User {
+        email: String,
+        /// Set for keys issued by [`start_impersonation`].
+        impersonator: Option<String>,
+    },
 }
 
-pub async fn api_key_is_valid(api_key: ApiKey, pool: &DatabasePool) -> Result<bool> {
+/// Returns the user a key was issued to.
+pub async fn api_key_owner(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<KeyOwner> {
     let bytes = api_key.into_inner();
-    Ok(sqlx::query("SELECT api_key FROM api_keys WHERE api_key = ?")
-        .bind(bytes)
+    let tenant = tenant.as_str();
+
+    let key = sqlx::query!(
+        "SELECT owner, impersonator FROM api_keys WHERE api_key = ? AND tenant = ?",
+        bytes,
+        tenant
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(match key.owner {
+        Some(email) => KeyOwner::User {
+            email,
+            impersonator: key.impersonator,
+        },
+        None => KeyOwner::Operator,
+    })
+}
+
+pub enum RevocationStatus {
+    Revoked,
+    NotFound,
+}
+
+pub async fn revoke_api_key(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<RevocationStatus> {
+    let bytes = api_key.into_inner();
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query!(
+        "DELETE FROM api_keys WHERE api_key = ? AND tenant = ?",
+        bytes,
+        tenant
+    )
+    .execute(pool)
+    .await
+    .map(|result| match result.rows_affected() {
+        0 => RevocationStatus::NotFound,
+        _ => RevocationStatus::Revoked,
+    })?)
+}
+
+pub async fn api_key_is_valid(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<bool> {
+    let bytes = api_key.into_inner();
+    Ok(sqlx::query(
+        r#"SELECT api_key FROM api_keys WHERE api_key = ? AND tenant = ?
+                AND (expires_at IS NULL OR expires_at > datetime('now'))"#,
+    )
+    .bind(bytes)
+    .bind(tenant.as_str())
+    .fetch_all(pool)
+    .await
+    .map(|row| {
+        let count = row.len();
+        count > 0
+    })?)
+}
+
+/// Records use of a key, at most once a minute to keep reads from turning into writes.
+pub async fn touch_api_key(tenant: &TenantId, api_key: ApiKey, pool: &DatabasePool) -> Result<()> {
+    let bytes = api_key.into_inner();
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"UPDATE api_keys SET last_seen_at = datetime('now')
+            WHERE api_key = ? AND tenant = ?
+            AND (last_seen_at IS NULL OR last_seen_at < datetime('now', '-1 minute'))"#,
+        bytes,
+        tenant
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Lists a user's unexpired sessions, most recently started first. `current` is the key
+/// the listing was requested with, if any.
+pub async fn list_sessions(
+    tenant: &TenantId,
+    email: &str,
+    current: Option<ApiKey>,
+    pool: &DatabasePool,
+) -> Result<Vec<model::Session>> {
+    let tenant = tenant.as_str();
+    let current = current.map(ApiKey::into_inner);
+
+    Ok(sqlx::query_as!(
+        model::Session,
+        r#"SELECT id AS "id!", created_at AS "created_at!", last_seen_at, ip, user_agent,
+                device_id, impersonator, COALESCE(api_key = ?, 0) AS "current!: bool"
+            FROM api_keys
+            WHERE tenant = ? AND owner = ?
+            AND (expires_at IS NULL OR expires_at > datetime('now'))
+            ORDER BY created_at DESC, rowid DESC"#,
+        current,
+        tenant,
+        email
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Revokes one of a user's sessions; sessions of other users are reported as not found.
+pub async fn revoke_session(
+    tenant: &TenantId,
+    email: &str,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<RevocationStatus> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query!(
+        "DELETE FROM api_keys WHERE tenant = ? AND owner = ? AND id = ?",
+        tenant,
+        email,
+        id
+    )
+    .execute(pool)
+    .await
+    .map(|result| match result.rows_affected() {
+        0 => RevocationStatus::NotFound,
+        _ => RevocationStatus::Revoked,
+    })?)
+}
+
+/// Revokes every session a user holds, along with their OAuth tokens, and returns how many
+/// sessions there were.
+pub async fn revoke_sessions(tenant: &TenantId, email: &str, pool: &DatabasePool) -> Result<u64> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query!(
+        "DELETE FROM api_keys WHERE tenant = ? AND owner = ?",
+        tenant,
+        email
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    // tokens handed to OAuth clients would otherwise outlive the sessions they came from
+    sqlx::query!(
+        "DELETE FROM oauth_tokens WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(revoked)
+}
+
+pub async fn list_tenants(pool: &DatabasePool) -> Result<Vec<model::Tenant>> {
+    Ok(
+        sqlx::query_as!(model::Tenant, "SELECT id, name FROM tenants ORDER BY id")
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
+pub async fn tenant_exists(tenant: &TenantId, pool: &DatabasePool) -> Result<bool> {
+    let tenant = tenant.as_str();
+
+    Ok(
+        sqlx::query_scalar!("SELECT id FROM tenants WHERE id = ?", tenant)
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+    )
+}
+
+/// Creates or renames a tenant. New tenants get an `admin` role with every permission
+/// except the ones for managing tenants.
+pub async fn save_tenant<M: Into<model::Tenant>>(
+    model: M,
+    pool: &DatabasePool,
+) -> Result<model::Tenant> {
+    let model = model.into();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"INSERT INTO tenants (id, name) VALUES (?, ?)
+            ON CONFLICT (id) DO UPDATE SET name = excluded.name"#,
+        model.id,
+        model.name
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"INSERT OR IGNORE INTO roles (tenant, name, description)
+            VALUES (?, 'admin', 'full access to the tenant')"#,
+        model.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"INSERT OR IGNORE INTO role_permissions (tenant, role, permission)
+            SELECT ?, 'admin', name FROM permissions WHERE name NOT LIKE 'tenants:%'"#,
+        model.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(model)
+}
+
+pub async fn new_invitation(
+    tenant: &TenantId,
+    model: model::NewInvitation,
+    pool: &DatabasePool,
+) -> Result<model::Invitation> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO invitations (
+            token_hash, tenant, email, role, invited_by, expires_at
+        )
+        VALUES (?, ?, ?, ?, ?, datetime('now', ?))"#,
+        model.token_hash,
+        tenant,
+        model.email,
+        model.role,
+        model.invited_by,
+        model.expires_in
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(sqlx::query_as!(
+        model::Invitation,
+        r#"SELECT email, role, invited_by, created_at, expires_at, accepted_at
+            FROM invitations WHERE token_hash = ?"#,
+        model.token_hash
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// Invitations that have been neither accepted nor expired.
+pub async fn list_invitations(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<model::Invitation>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Invitation,
+        r#"SELECT email, role, invited_by, created_at, expires_at, accepted_at
+            FROM invitations
+            WHERE tenant = ? AND accepted_at IS NULL AND expires_at > datetime('now')
+            ORDER BY created_at, email"#,
+        tenant
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub enum InvitationAcceptance {
+    /// The invitation was consumed; holds the invitee's email.
+    Accepted(String),
+    /// The token is unknown, expired or already used.
+    Invalid,
+    /// The invitee has no account yet and no name/password was supplied.
+    AccountRequired,
+}
+
+/// Consumes an invitation, creating the invitee's account if needed, and grants its role.
+pub async fn accept_invitation(
+    tenant: &TenantId,
+    model: model::AcceptInvitation,
+    pool: &DatabasePool,
+) -> Result<InvitationAcceptance> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let invitation = sqlx::query!(
+        r#"SELECT email, role FROM invitations
+            WHERE token_hash = ? AND tenant = ?
+            AND accepted_at IS NULL AND expires_at > datetime('now')"#,
+        model.token_hash,
+        tenant
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let invitation = match invitation {
+        Some(invitation) => invitation,
+        None => return Ok(InvitationAcceptance::Invalid),
+    };
+
+    let consumed = sqlx::query!(
+        r#"UPDATE invitations SET accepted_at = datetime('now')
+            WHERE token_hash = ? AND accepted_at IS NULL"#,
+        model.token_hash
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if consumed == 0 {
+        return Ok(InvitationAcceptance::Invalid);
+    }
+
+    let existing = sqlx::query_scalar!(
+        "SELECT email FROM user WHERE tenant = ? AND email = ? AND deleted_at IS NULL",
+        tenant,
+        invitation.email
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    if existing.is_none() {
+        let (name, password) = match (model.name, model.password) {
+            (Some(name), Some(password)) => (name, password),
+            _ => return Ok(InvitationAcceptance::AccountRequired),
+        };
+
+        sqlx::query!(
+            r#"INSERT INTO user (
+                tenant, name, email, password, created_at
+            )
+            VALUES (?, ?, ?, ?, datetime('now'))"#,
+            tenant,
+            name,
+            invitation.email,
+            password
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO identities (tenant, email, kind) VALUES (?, ?, 'password')",
+            tenant,
+            invitation.email
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    sqlx::query!(
+        "INSERT OR IGNORE INTO user_roles (tenant, email, role) VALUES (?, ?, ?)",
+        tenant,
+        invitation.email,
+        invitation.role
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(InvitationAcceptance::Accepted(invitation.email))
+}
+
+/// Appends an event to the tenant's hash chain.
+async fn insert_audit_event(
+    tenant: &str,
+    event: &model::NewAuditEvent,
+    tx: &mut Transaction<'_>,
+) -> Result<()> {
+    // a statement that writes takes the write lock before the head of the chain is read, so
+    // concurrent events queue up behind each other instead of failing to upgrade their read
+    // lock or both extending the same head; no row matches, so the triggers never fire
+    sqlx::query!("DELETE FROM audit_events WHERE 0")
+        .execute(&mut *tx)
+        .await?;
+    let prev_hash = sqlx::query_scalar!(
+        r#"SELECT hash AS "hash!" FROM audit_events
+            WHERE tenant = ? AND hash IS NOT NULL
+            ORDER BY id DESC LIMIT 1"#,
+        tenant
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let occurred_at = sqlx::query_scalar!(r#"SELECT datetime('now') AS "now!: String""#)
+        .fetch_one(&mut *tx)
+        .await?;
+    let hash = event.chain_hash(tenant, &occurred_at, prev_hash.as_deref());
+
+    sqlx::query!(
+        r#"INSERT INTO audit_events (
+            tenant, occurred_at, actor, impersonator, action, target, outcome,
+            ip, user_agent, request_id, detail, prev_hash, hash
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+        tenant,
+        occurred_at,
+        event.actor,
+        event.impersonator,
+        event.action,
+        event.target,
+        event.outcome,
+        event.ip,
+        event.user_agent,
+        event.request_id,
+        event.detail,
+        prev_hash,
+        hash
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn record_audit_event(
+    tenant: &TenantId,
+    event: model::NewAuditEvent,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    insert_audit_event(tenant.as_str(), &event, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+fn audit_events_query<'a>(
+    tenant: &'a TenantId,
+    model: model::ListAuditEvents,
+    descending: bool,
+) -> sqlx::QueryBuilder<'a, sqlx::Sqlite> {
+    let mut builder = sqlx::QueryBuilder::new(
+        r#"SELECT id, occurred_at, actor, impersonator, action, target, outcome,
+                ip, user_agent, request_id, detail, prev_hash, hash
+            FROM audit_events WHERE tenant = "#,
+    );
+    builder.push_bind(tenant.as_str());
+
+    if let Some(actor) = model.actor {
+        builder.push(" AND actor = ").push_bind(actor);
+    }
+    if let Some(action) = model.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+    if let Some(target) = model.target {
+        builder.push(" AND target = ").push_bind(target);
+    }
+    if let Some(outcome) = model.outcome {
+        builder.push(" AND outcome = ").push_bind(outcome);
+    }
+    if let Some(since) = model.since {
+        builder
+            .push(" AND occurred_at >= datetime(")
+            .push_bind(since)
+            .push(")");
+    }
+    if let Some(until) = model.until {
+        builder
+            .push(" AND occurred_at < datetime(")
+            .push_bind(until)
+            .push(")");
+    }
+    if let Some(before_id) = model.before_id {
+        builder.push(" AND id < ").push_bind(before_id);
+    }
+
+    builder.push(match descending {
+        true => " ORDER BY id DESC",
+        false => " ORDER BY id ASC",
+    });
+    if let Some(limit) = model.limit {
+        builder.push(" LIMIT ").push_bind(limit);
+    }
+
+    builder
+}
+
+/// One page of events, newest first, and the cursor for the next page.
+pub async fn list_audit_events(
+    tenant: &TenantId,
+    mut model: model::ListAuditEvents,
+    pool: &DatabasePool,
+) -> Result<(Vec<model::AuditEvent>, Option<i64>)> {
+    let limit = model.limit.unwrap_or(50);
+    model.limit = Some(limit + 1);
+
+    let mut events: Vec<model::AuditEvent> = audit_events_query(tenant, model, true)
+        .build_query_as()
+        .fetch_all(pool)
+        .await?;
+
+    let next_cursor = match events.len() > limit as usize {
+        true => {
+            events.truncate(limit as usize);
+            events.last().map(|event| event.id)
+        }
+        false => None,
+    };
+
+    Ok((events, next_cursor))
+}
+
+/// Every matching event, oldest first.
+pub async fn export_audit_events(
+    tenant: &TenantId,
+    model: model::ListAuditEvents,
+    pool: &DatabasePool,
+) -> Result<Vec<model::AuditEvent>> {
+    Ok(audit_events_query(tenant, model, false)
+        .build_query_as()
         .fetch_all(pool)
-        .await
-        .map(|row| {
-            let count = row.len();
-            count > 0
-        })?)
+        .await?)
+}
+
+pub enum AuditChain {
+    /// Holds the number of chained events checked.
+    Intact(u64),
+    /// The event with id `at` does not fit the chain; `verified` events before it do.
+    Broken { verified: u64, at: i64 },
+}
+
+/// Recomputes the tenant's hash chain from its first chained event.
+pub async fn verify_audit_chain(tenant: &TenantId, pool: &DatabasePool) -> Result<AuditChain> {
+    let tenant = tenant.as_str();
+
+    let events = sqlx::query_as!(
+        model::AuditEvent,
+        r#"SELECT id AS "id!", occurred_at, actor, impersonator, action, target, outcome,
+                ip, user_agent, request_id, detail, prev_hash, hash
+            FROM audit_events
+            WHERE tenant = ? AND id >= (
+                SELECT COALESCE(MIN(id), 0) FROM audit_events
+                WHERE tenant = ? AND hash IS NOT NULL
+            )
+            ORDER BY id"#,
+        tenant,
+        tenant
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut prev_hash: Option<String> = None;
+    let mut verified = 0;
+    for event in events {
+        let intact = event.hash.is_some()
+            && event.prev_hash == prev_hash
+            && event.hash.as_deref() == Some(event.recompute_hash(tenant).as_str());
+        if !intact {
+            return Ok(AuditChain::Broken {
+                verified,
+                at: event.id,
+            });
+        }
+
+        prev_hash = event.hash;
+        verified += 1;
+    }
+
+    Ok(AuditChain::Intact(verified))
+}
+
+/// Issues a short-lived key acting as `model.email` and records who started it.
+pub async fn start_impersonation(
+    tenant: &TenantId,
+    model: model::NewImpersonation,
+    event: model::NewAuditEvent,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"INSERT INTO api_keys (api_key, tenant, owner, impersonator, expires_at, id, created_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?), lower(hex(randomblob(16))), datetime('now'))"#,
+        model.api_key,
+        tenant,
+        model.email,
+        model.impersonator,
+        model.expires_in
+    )
+    .execute(&mut tx)
+    .await?;
+
+    insert_audit_event(tenant, &event, &mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Revokes an impersonation key and records the end of the impersonation.
+pub async fn stop_impersonation(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    event: model::NewAuditEvent,
+    pool: &DatabasePool,
+) -> Result<RevocationStatus> {
+    let bytes = api_key.into_inner();
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query!(
+        r#"DELETE FROM api_keys
+            WHERE api_key = ? AND tenant = ? AND impersonator IS NOT NULL"#,
+        bytes,
+        tenant
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if revoked == 0 {
+        return Ok(RevocationStatus::NotFound);
+    }
+
+    insert_audit_event(tenant, &event, &mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(RevocationStatus::Revoked)
+}
+
+pub struct RecordedLogin {
+    pub id: i64,
+    /// First successful sign-in from the device.
+    pub new_device: bool,
+    /// Devices the user had signed in from before this one.
+    pub known_devices: i64,
+}
+
+/// Adds a sign-in attempt to the user's history, remembering the device on success.
+pub async fn record_login(
+    tenant: &TenantId,
+    model: model::NewLogin,
+    pool: &DatabasePool,
+) -> Result<RecordedLogin> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let known_devices = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM known_devices WHERE tenant = ? AND email = ?",
+        tenant,
+        model.email
+    )
+    .fetch_one(&mut tx)
+    .await? as i64;
+
+    let mut new_device = false;
+    if let (true, Some(device_id)) = (model.success, &model.device_id) {
+        new_device = sqlx::query!(
+            r#"INSERT OR IGNORE INTO known_devices (tenant, email, device_id)
+                VALUES (?, ?, ?)"#,
+            tenant,
+            model.email,
+            device_id
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected()
+            > 0;
+
+        sqlx::query!(
+            r#"UPDATE known_devices SET last_seen = datetime('now')
+                WHERE tenant = ? AND email = ? AND device_id = ?"#,
+            tenant,
+            model.email,
+            device_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let id = sqlx::query!(
+        r#"INSERT INTO login_history (
+            tenant, email, ip, user_agent, device_id, success, new_device
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+        tenant,
+        model.email,
+        model.ip,
+        model.user_agent,
+        model.device_id,
+        model.success,
+        new_device
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid();
+
+    tx.commit().await?;
+
+    Ok(RecordedLogin {
+        id,
+        new_device,
+        known_devices,
+    })
+}
+
+pub async fn login_history(
+    tenant: &TenantId,
+    email: &str,
+    limit: u32,
+    pool: &DatabasePool,
+) -> Result<Vec<model::Login>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Login,
+        r#"SELECT occurred_at, ip, user_agent, device_id,
+                success AS "success: bool", new_device AS "new_device: bool"
+            FROM login_history WHERE tenant = ? AND email = ?
+            ORDER BY id DESC LIMIT ?"#,
+        tenant,
+        email,
+        limit
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn password_reset_required(
+    tenant: &TenantId,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<bool> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"SELECT password_reset_required AS "required: bool" FROM user
+            WHERE tenant = ? AND email = ?"#,
+        tenant,
+        email
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn save_login_alert(
+    tenant: &TenantId,
+    token_hash: &str,
+    email: &str,
+    login_id: i64,
+    expires_in: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO login_alerts (token_hash, tenant, email, login_id, expires_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?))"#,
+        token_hash,
+        tenant,
+        email,
+        login_id,
+        expires_in
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Consumes a "this wasn't me" token: revokes every key the user holds and requires a
+/// password change before the next sign-in. Returns the user's email, or `None` if the
+/// token is unknown, expired or already used.
+pub async fn report_login(
+    tenant: &TenantId,
+    token_hash: &str,
+    pool: &DatabasePool,
+) -> Result<Option<String>> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let email = sqlx::query_scalar!(
+        r#"SELECT email FROM login_alerts
+            WHERE token_hash = ? AND tenant = ?
+            AND used_at IS NULL AND expires_at > datetime('now')"#,
+        token_hash,
+        tenant
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let email = match email {
+        Some(email) => email,
+        None => return Ok(None),
+    };
+
+    sqlx::query!(
+        "UPDATE login_alerts SET used_at = datetime('now') WHERE token_hash = ?",
+        token_hash
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE user SET password_reset_required = 1 WHERE tenant = ? AND email = ?",
+        tenant,
+        email
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM api_keys WHERE tenant = ? AND owner = ?",
+        tenant,
+        email
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(email))
+}
+
+pub async fn save_magic_link(
+    tenant: &TenantId,
+    token_hash: &str,
+    email: &str,
+    nonce_hash: &str,
+    expires_in: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO magic_links (token_hash, tenant, email, nonce_hash, expires_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?))"#,
+        token_hash,
+        tenant,
+        email,
+        nonce_hash,
+        expires_in
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks a sign-in link used and returns the email it was sent to, or `None` if the token
+/// is unknown, expired, already used or presented without the nonce it was issued with.
+pub async fn consume_magic_link(
+    tenant: &TenantId,
+    token_hash: &str,
+    nonce_hash: &str,
+    pool: &DatabasePool,
+) -> Result<Option<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"UPDATE magic_links SET used_at = datetime('now')
+            WHERE token_hash = ? AND tenant = ? AND nonce_hash = ?
+            AND used_at IS NULL AND expires_at > datetime('now')
+            RETURNING email AS "email!""#,
+        token_hash,
+        tenant,
+        nonce_hash
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Stores a one-time code for a user, unless they were already sent `max_requests` codes
+/// since `window` (an SQLite datetime modifier such as `-1 hour`). Codes the user has not
+/// used yet stop working but are kept, so that their failed attempts still count.
+#[allow(clippy::too_many_arguments)]
+pub async fn save_otp_code(
+    tenant: &TenantId,
+    email: &str,
+    channel: &str,
+    code_hash: &str,
+    expires_in: &str,
+    window: &str,
+    max_requests: i64,
+    pool: &DatabasePool,
+) -> Result<bool> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let inserted = sqlx::query!(
+        r#"INSERT INTO otp_codes (tenant, email, channel, code_hash, expires_at)
+            SELECT ?, ?, ?, ?, datetime('now', ?)
+            WHERE (SELECT count(*) FROM otp_codes
+                WHERE tenant = ? AND email = ? AND created_at > datetime('now', ?)) < ?"#,
+        tenant,
+        email,
+        channel,
+        code_hash,
+        expires_in,
+        tenant,
+        email,
+        window,
+        max_requests
+    )
+    .execute(&mut tx)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    let id = inserted.last_insert_rowid();
+    sqlx::query!(
+        r#"UPDATE otp_codes SET expires_at = datetime('now')
+            WHERE tenant = ? AND email = ? AND id < ? AND used_at IS NULL
+            AND expires_at > datetime('now')"#,
+        tenant,
+        email,
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+pub enum OtpCheck {
+    Verified,
+    Rejected,
+}
+
+/// Checks a code against the user's outstanding one. A match uses the code up; a mismatch
+/// counts as an attempt. Once the codes sent since `window` have `max_attempts` failed
+/// attempts between them, no code is accepted until they fall out of the window.
+pub async fn check_otp_code(
+    tenant: &TenantId,
+    email: &str,
+    code_hash: &str,
+    max_attempts: i64,
+    window: &str,
+    pool: &DatabasePool,
+) -> Result<OtpCheck> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let failed = sqlx::query_scalar!(
+        r#"SELECT coalesce(sum(attempts), 0) AS "failed!: i64" FROM otp_codes
+            WHERE tenant = ? AND email = ? AND created_at > datetime('now', ?)"#,
+        tenant,
+        email,
+        window
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    if failed >= max_attempts {
+        return Ok(OtpCheck::Rejected);
+    }
+
+    let code = sqlx::query!(
+        r#"SELECT id AS "id!", code_hash FROM otp_codes
+            WHERE tenant = ? AND email = ? AND used_at IS NULL
+            AND expires_at > datetime('now')
+            ORDER BY id DESC LIMIT 1"#,
+        tenant,
+        email
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let code = match code {
+        Some(code) => code,
+        None => return Ok(OtpCheck::Rejected),
+    };
+
+    let check = match code.code_hash == code_hash {
+        true => {
+            sqlx::query!(
+                "UPDATE otp_codes SET used_at = datetime('now') WHERE id = ?",
+                code.id
+            )
+            .execute(&mut tx)
+            .await?;
+            OtpCheck::Verified
+        }
+        false => {
+            sqlx::query!(
+                "UPDATE otp_codes SET attempts = attempts + 1 WHERE id = ?",
+                code.id
+            )
+            .execute(&mut tx)
+            .await?;
+            OtpCheck::Rejected
+        }
+    };
+
+    tx.commit().await?;
+
+    Ok(check)
+}
+
+pub async fn save_webauthn_challenge(
+    tenant: &TenantId,
+    challenge: &str,
+    email: Option<&str>,
+    purpose: &str,
+    expires_in: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO webauthn_challenges (challenge, tenant, email, purpose, expires_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?))"#,
+        challenge,
+        tenant,
+        email,
+        purpose,
+        expires_in
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub struct PendingChallenge {
+    pub purpose: String,
+    /// User the challenge was issued to, if known when it was issued.
+    pub email: Option<String>,
+}
+
+/// Removes an unexpired challenge and returns what it was issued for.
+pub async fn consume_webauthn_challenge(
+    tenant: &TenantId,
+    challenge: &str,
+    pool: &DatabasePool,
+) -> Result<Option<PendingChallenge>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        PendingChallenge,
+        r#"DELETE FROM webauthn_challenges
+            WHERE challenge = ? AND tenant = ? AND expires_at > datetime('now')
+            RETURNING purpose AS "purpose!", email"#,
+        challenge,
+        tenant
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn save_webauthn_credential(
+    tenant: &TenantId,
+    model: model::NewCredential,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"INSERT INTO webauthn_credentials (
+            tenant, id, email, public_key, sign_count, transports, name
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+        tenant,
+        model.id,
+        model.email,
+        model.public_key,
+        model.sign_count,
+        model.transports,
+        model.name
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"INSERT INTO identities (tenant, email, kind, credential_id)
+            VALUES (?, ?, 'passkey', ?)"#,
+        tenant,
+        model.email,
+        model.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn list_webauthn_credentials(
+    tenant: &TenantId,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<Vec<model::Credential>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Credential,
+        r#"SELECT id, name, transports, created_at, last_used_at FROM webauthn_credentials
+            WHERE tenant = ? AND email = ? ORDER BY created_at, id"#,
+        tenant,
+        email
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub struct StoredCredential {
+    pub email: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+}
+
+/// Looks up what is needed to verify an assertion made with a credential.
+pub async fn webauthn_credential(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<StoredCredential>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        StoredCredential,
+        r#"SELECT email, public_key, sign_count FROM webauthn_credentials
+            WHERE tenant = ? AND id = ?"#,
+        tenant,
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn has_webauthn_credentials(
+    tenant: &TenantId,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<bool> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM webauthn_credentials WHERE tenant = ? AND email = ?
+        ) AS "exists!: bool""#,
+        tenant,
+        email
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn record_webauthn_use(
+    tenant: &TenantId,
+    id: &str,
+    sign_count: i64,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"UPDATE webauthn_credentials SET sign_count = ?, last_used_at = datetime('now')
+            WHERE tenant = ? AND id = ?"#,
+        sign_count,
+        tenant,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_webauthn_credential(
+    tenant: &TenantId,
+    email: &str,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query!(
+        "DELETE FROM webauthn_credentials WHERE tenant = ? AND email = ? AND id = ?",
+        tenant,
+        email,
+        id
+    )
+    .execute(pool)
+    .await
+    .map(|result| match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })?)
+}
+
+pub async fn save_oauth_client(
+    tenant: &TenantId,
+    model: model::NewClient,
+    pool: &DatabasePool,
+) -> Result<model::Client> {
+    let tenant = tenant.as_str();
+    // committed before returning, so the insert does not keep the database locked
+    let mut tx = pool.begin().await?;
+
+    let client = sqlx::query_as!(
+        model::Client,
+        r#"INSERT INTO oauth_clients (
+            tenant, id, name, client_type, secret_hash, redirect_uris, scopes,
+            post_logout_redirect_uris
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id!", name AS "name!", client_type AS "client_type!",
+            redirect_uris AS "redirect_uris!", scopes AS "scopes!",
+            post_logout_redirect_uris AS "post_logout_redirect_uris!",
+            created_at AS "created_at!""#,
+        tenant,
+        model.id,
+        model.name,
+        model.client_type,
+        model.secret_hash,
+        model.redirect_uris,
+        model.scopes,
+        model.post_logout_redirect_uris
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(client)
+}
+
+pub async fn list_oauth_clients(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<model::Client>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Client,
+        r#"SELECT id, name, client_type, redirect_uris, scopes, post_logout_redirect_uris,
+            created_at FROM oauth_clients
+            WHERE tenant = ? ORDER BY created_at, id"#,
+        tenant
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn oauth_client(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<model::Client>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Client,
+        r#"SELECT id, name, client_type, redirect_uris, scopes, post_logout_redirect_uris,
+            created_at FROM oauth_clients
+            WHERE tenant = ? AND id = ?"#,
+        tenant,
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Hash of a client's secret; `None` for unknown and public clients.
+pub async fn oauth_client_secret_hash(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        "SELECT secret_hash FROM oauth_clients WHERE tenant = ? AND id = ?",
+        tenant,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten())
+}
+
+pub async fn delete_oauth_client(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query!(
+        "DELETE FROM oauth_clients WHERE tenant = ? AND id = ?",
+        tenant,
+        id
+    )
+    .execute(pool)
+    .await
+    .map(|result| match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })?)
+}
+
+/// Scope a user has consented to give a client, if any.
+pub async fn oauth_grant_scope(
+    tenant: &TenantId,
+    client_id: &str,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<Option<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        "SELECT scope FROM oauth_grants WHERE tenant = ? AND client_id = ? AND email = ?",
+        tenant,
+        client_id,
+        email
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn save_oauth_grant(
+    tenant: &TenantId,
+    client_id: &str,
+    email: &str,
+    scope: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO oauth_grants (tenant, client_id, email, scope) VALUES (?, ?, ?, ?)
+            ON CONFLICT (tenant, client_id, email)
+            DO UPDATE SET scope = excluded.scope, updated_at = datetime('now')"#,
+        tenant,
+        client_id,
+        email,
+        scope
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn save_oauth_code(
+    tenant: &TenantId,
+    model: model::NewAuthorizationCode,
+    expires_in: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO oauth_codes (
+            code_hash, tenant, client_id, email, redirect_uri, scope, code_challenge, nonce,
+            expires_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, datetime('now', ?))"#,
+        model.code_hash,
+        tenant,
+        model.client_id,
+        model.email,
+        model.redirect_uri,
+        model.scope,
+        model.code_challenge,
+        model.nonce,
+        expires_in
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub email: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+}
+
+/// Marks an unexpired, unused authorization code as used and returns what it grants.
+pub async fn consume_oauth_code(
+    tenant: &TenantId,
+    code_hash: &str,
+    pool: &DatabasePool,
+) -> Result<Option<AuthorizationCode>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        AuthorizationCode,
+        r#"UPDATE oauth_codes SET used_at = datetime('now')
+            WHERE code_hash = ? AND tenant = ? AND used_at IS NULL
+                AND expires_at > datetime('now')
+            RETURNING client_id AS "client_id!", email AS "email!",
+                redirect_uri AS "redirect_uri!", scope AS "scope!",
+                code_challenge AS "code_challenge!", nonce"#,
+        code_hash,
+        tenant
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn save_oauth_token(
+    tenant: &TenantId,
+    model: model::NewOAuthToken,
+    expires_in: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO oauth_tokens (
+            token_hash, tenant, kind, client_id, email, scope, expires_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, datetime('now', ?))"#,
+        model.token_hash,
+        tenant,
+        model.kind,
+        model.client_id,
+        model.email,
+        model.scope,
+        expires_in
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub struct RefreshGrant {
+    pub email: String,
+    pub scope: String,
+}
+
+/// Removes an unexpired refresh token issued to `client_id`; refresh tokens are rotated on
+/// every use.
+pub async fn consume_oauth_refresh_token(
+    tenant: &TenantId,
+    token_hash: &str,
+    client_id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<RefreshGrant>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        RefreshGrant,
+        r#"DELETE FROM oauth_tokens
+            WHERE token_hash = ? AND tenant = ? AND client_id = ? AND kind = 'refresh'
+                AND expires_at > datetime('now')
+            RETURNING email AS "email!", scope AS "scope!""#,
+        token_hash,
+        tenant,
+        client_id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub struct AccessGrant {
+    pub client_id: String,
+    /// `None` for client credentials.
+    pub email: Option<String>,
+    pub scope: String,
+}
+
+/// What an unexpired access token was issued for.
+pub async fn oauth_access_grant(
+    tenant: &TenantId,
+    token_hash: &str,
+    pool: &DatabasePool,
+) -> Result<Option<AccessGrant>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        AccessGrant,
+        r#"SELECT client_id, email, scope FROM oauth_tokens
+            WHERE token_hash = ? AND tenant = ? AND kind = 'access'
+                AND expires_at > datetime('now')"#,
+        token_hash,
+        tenant
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Keys for signing ID tokens, newest first. They are shared by all tenants.
+pub async fn signing_keys(pool: &DatabasePool) -> Result<Vec<model::SigningKey>> {
+    Ok(sqlx::query_as!(
+        model::SigningKey,
+        r#"SELECT id AS "id!", private_key FROM signing_keys
+            ORDER BY created_at DESC, rowid DESC"#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn save_signing_key(
+    id: &str,
+    algorithm: &str,
+    private_key: &[u8],
+    pool: &DatabasePool,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO signing_keys (id, algorithm, private_key) VALUES (?, ?, ?)",
+        id,
+        algorithm,
+        private_key
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Stores a new device code. Expired codes of the tenant are removed first, so their user
+/// codes can be handed out again.
+pub async fn save_oauth_device_code(
+    tenant: &TenantId,
+    model: model::NewDeviceCode,
+    expires_in: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "DELETE FROM oauth_device_codes WHERE tenant = ? AND expires_at <= datetime('now')",
+        tenant
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"INSERT INTO oauth_device_codes (
+            device_code_hash, tenant, client_id, user_code, scope, poll_interval, expires_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, datetime('now', ?))"#,
+        model.device_code_hash,
+        tenant,
+        model.client_id,
+        model.user_code,
+        model.scope,
+        model.poll_interval,
+        expires_in
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub struct DeviceRequest {
+    pub client_id: String,
+    pub scope: String,
+}
+
+/// The request behind a user code that is unexpired and still waiting for the user.
+pub async fn oauth_device_request(
+    tenant: &TenantId,
+    user_code: &str,
+    pool: &DatabasePool,
+) -> Result<Option<DeviceRequest>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        DeviceRequest,
+        r#"SELECT client_id, scope FROM oauth_device_codes
+            WHERE tenant = ? AND user_code = ? AND approved IS NULL
+                AND expires_at > datetime('now')"#,
+        tenant,
+        user_code
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Records the user's decision on a pending device request; `false` if there was none.
+pub async fn decide_oauth_device_request(
+    tenant: &TenantId,
+    user_code: &str,
+    email: Option<&str>,
+    approved: bool,
+    pool: &DatabasePool,
+) -> Result<bool> {
+    let tenant = tenant.as_str();
+
+    let result = sqlx::query!(
+        r#"UPDATE oauth_device_codes SET email = ?, approved = ?
+            WHERE tenant = ? AND user_code = ? AND approved IS NULL
+                AND expires_at > datetime('now')"#,
+        email,
+        approved,
+        tenant,
+        user_code
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// State of a device code when the device polls with it.
+pub struct DevicePoll {
+    pub email: Option<String>,
+    pub approved: Option<bool>,
+    pub scope: String,
+    pub expired: bool,
+    /// The device polled again before its interval was up.
+    pub too_fast: bool,
+}
+
+/// Records a poll with a device code issued to `client_id`. Codes that have expired or have
+/// been decided are removed, so they can only be redeemed once; otherwise a device polling
+/// too fast has its interval raised by `slow_down` seconds.
+pub async fn poll_oauth_device_code(
+    tenant: &TenantId,
+    device_code_hash: &str,
+    client_id: &str,
+    slow_down: i64,
+    pool: &DatabasePool,
+) -> Result<Option<DevicePoll>> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let poll = sqlx::query_as!(
+        DevicePoll,
+        r#"SELECT email, approved AS "approved: bool", scope,
+                expires_at <= datetime('now') AS "expired!: bool",
+                (polled_at IS NOT NULL AND CAST(strftime('%s', 'now') AS INTEGER)
+                    - CAST(strftime('%s', polled_at) AS INTEGER) < poll_interval)
+                    AS "too_fast!: bool"
+            FROM oauth_device_codes
+            WHERE device_code_hash = ? AND tenant = ? AND client_id = ?"#,
+        device_code_hash,
+        tenant,
+        client_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    match &poll {
+        Some(poll) if poll.expired || poll.approved.is_some() => {
+            sqlx::query!(
+                "DELETE FROM oauth_device_codes WHERE device_code_hash = ? AND tenant = ?",
+                device_code_hash,
+                tenant
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        Some(poll) => {
+            let slow_down = if poll.too_fast { slow_down } else { 0 };
+            sqlx::query!(
+                r#"UPDATE oauth_device_codes
+                    SET polled_at = datetime('now'), poll_interval = poll_interval + ?
+                    WHERE device_code_hash = ? AND tenant = ?"#,
+                slow_down,
+                device_code_hash,
+                tenant
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        None => {}
+    }
+
+    tx.commit().await?;
+    Ok(poll)
+}
+
+/// An unexpired token from `oauth_tokens`, with its times as Unix timestamps.
+pub struct OAuthTokenRecord {
+    pub kind: String,
+    pub client_id: String,
+    pub email: Option<String>,
+    pub scope: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+}
+
+pub async fn oauth_token(
+    tenant: &TenantId,
+    token_hash: &str,
+    pool: &DatabasePool,
+) -> Result<Option<OAuthTokenRecord>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        OAuthTokenRecord,
+        r#"SELECT kind, client_id, email, scope,
+                CAST(strftime('%s', created_at) AS INTEGER) AS "issued_at!: i64",
+                CAST(strftime('%s', expires_at) AS INTEGER) AS "expires_at!: i64"
+            FROM oauth_tokens
+            WHERE token_hash = ? AND tenant = ? AND expires_at > datetime('now')"#,
+        token_hash,
+        tenant
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub struct RevokedToken {
+    pub kind: String,
+    pub email: Option<String>,
+}
+
+/// Removes a token issued to `client_id`, along with the access tokens issued with it when
+/// it is a refresh token.
+pub async fn revoke_oauth_token(
+    tenant: &TenantId,
+    token_hash: &str,
+    client_id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<RevokedToken>> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query_as!(
+        RevokedToken,
+        r#"DELETE FROM oauth_tokens WHERE token_hash = ? AND tenant = ? AND client_id = ?
+            RETURNING kind AS "kind!", email"#,
+        token_hash,
+        tenant,
+        client_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    if let Some(RevokedToken {
+        kind,
+        email: Some(email),
+    }) = &revoked
+    {
+        if kind == "refresh" {
+            sqlx::query!(
+                r#"DELETE FROM oauth_tokens
+                    WHERE tenant = ? AND client_id = ? AND email = ? AND kind = 'access'"#,
+                tenant,
+                client_id,
+                email
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(revoked)
+}
+
+/// An unexpired API key, with its times as Unix timestamps.
+pub struct SessionToken {
+    pub owner: Option<String>,
+    pub impersonator: Option<String>,
+    pub issued_at: Option<i64>,
+    pub expires_at: Option<i64>,
+}
+
+pub async fn session_token(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<Option<SessionToken>> {
+    let bytes = api_key.into_inner();
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        SessionToken,
+        r#"SELECT owner, impersonator,
+                CAST(strftime('%s', created_at) AS INTEGER) AS "issued_at: i64",
+                CAST(strftime('%s', expires_at) AS INTEGER) AS "expires_at: i64"
+            FROM api_keys
+            WHERE api_key = ? AND tenant = ?
+                AND (expires_at IS NULL OR expires_at > datetime('now'))"#,
+        bytes,
+        tenant
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn save_identity_provider(
+    tenant: &TenantId,
+    model: model::NewIdentityProvider,
+    pool: &DatabasePool,
+) -> Result<model::IdentityProvider> {
+    let tenant = tenant.as_str();
+    // committed before returning, so the insert does not keep the database locked
+    let mut tx = pool.begin().await?;
+
+    let provider = sqlx::query_as!(
+        model::IdentityProvider,
+        r#"INSERT INTO identity_providers (
+            tenant, id, name, issuer, client_id, client_secret, scopes, link_by_email
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id!", name AS "name!", issuer AS "issuer!",
+            client_id AS "client_id!", scopes AS "scopes!",
+            link_by_email AS "link_by_email!: bool", created_at AS "created_at!""#,
+        tenant,
+        model.id,
+        model.name,
+        model.issuer,
+        model.client_id,
+        model.client_secret,
+        model.scopes,
+        model.link_by_email
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(provider)
+}
+
+pub async fn list_identity_providers(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<model::IdentityProvider>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::IdentityProvider,
+        r#"SELECT id, name, issuer, client_id, scopes,
+                link_by_email AS "link_by_email: bool", created_at
+            FROM identity_providers WHERE tenant = ? ORDER BY name, id"#,
+        tenant
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn identity_provider(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<model::IdentityProvider>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::IdentityProvider,
+        r#"SELECT id, name, issuer, client_id, scopes,
+                link_by_email AS "link_by_email: bool", created_at
+            FROM identity_providers WHERE tenant = ? AND id = ?"#,
+        tenant,
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn identity_provider_secret(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        "SELECT client_secret FROM identity_providers WHERE tenant = ? AND id = ?",
+        tenant,
+        id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Removes a provider along with the identities linked through it.
+pub async fn delete_identity_provider(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let tenant = tenant.as_str();
+
+    let result = sqlx::query!(
+        "DELETE FROM identity_providers WHERE tenant = ? AND id = ?",
+        tenant,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })
+}
+
+/// The user a subject at a provider signs in as, if it has been linked.
+pub async fn federated_identity_email(
+    tenant: &TenantId,
+    provider_id: &str,
+    subject: &str,
+    pool: &DatabasePool,
+) -> Result<Option<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"SELECT email FROM identities
+            WHERE tenant = ? AND kind = 'federated' AND provider_id = ? AND subject = ?"#,
+        tenant,
+        provider_id,
+        subject
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn save_federated_identity(
+    tenant: &TenantId,
+    provider_id: &str,
+    subject: &str,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO identities (tenant, provider_id, subject, email, kind)
+            VALUES (?, ?, ?, ?, 'federated')"#,
+        tenant,
+        provider_id,
+        subject,
+        email
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The user's ways to sign in, with the names of their passkeys and providers.
+pub async fn list_identities(
+    tenant: &TenantId,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<Vec<model::Identity>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::Identity,
+        r#"SELECT identities.id AS "id!", identities.kind AS "kind!",
+                identities.credential_id, identities.provider_id, identities.subject,
+                COALESCE(webauthn_credentials.name, identity_providers.name) AS "name?: String",
+                identities.created_at AS "created_at!"
+            FROM identities
+            LEFT JOIN webauthn_credentials
+                ON webauthn_credentials.tenant = identities.tenant
+                AND webauthn_credentials.id = identities.credential_id
+            LEFT JOIN identity_providers
+                ON identity_providers.tenant = identities.tenant
+                AND identity_providers.id = identities.provider_id
+            WHERE identities.tenant = ? AND identities.email = ?
+            ORDER BY identities.created_at, identities.rowid"#,
+        tenant,
+        email
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The identity standing for a passkey of the user.
+pub async fn passkey_identity(
+    tenant: &TenantId,
+    email: &str,
+    credential_id: &str,
+    pool: &DatabasePool,
+) -> Result<Option<String>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"SELECT id AS "id!" FROM identities
+            WHERE tenant = ? AND email = ? AND kind = 'passkey' AND credential_id = ?"#,
+        tenant,
+        email,
+        credential_id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub enum IdentityDeletion {
+    /// Deleted the identity, of the given kind.
+    Deleted(String),
+    NotFound,
+    /// The identity is the user's only way left to sign in.
+    LastSignInMethod,
+}
+
+/// Removes one of the user's ways to sign in, unless it is the last one. A passkey is
+/// deleted along with its identity; a password is replaced with `unusable_password`.
+pub async fn delete_identity(
+    tenant: &TenantId,
+    email: &str,
+    id: &str,
+    unusable_password: &str,
+    pool: &DatabasePool,
+) -> Result<IdentityDeletion> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let identity = sqlx::query!(
+        r#"SELECT kind, credential_id,
+                (SELECT COUNT(*) FROM identities AS others
+                    WHERE others.tenant = identities.tenant
+                    AND others.email = identities.email) AS "count!: i64"
+            FROM identities WHERE tenant = ? AND email = ? AND id = ?"#,
+        tenant,
+        email,
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let identity = match identity {
+        Some(identity) if identity.count <= 1 => return Ok(IdentityDeletion::LastSignInMethod),
+        Some(identity) => identity,
+        None => return Ok(IdentityDeletion::NotFound),
+    };
+
+    match identity.credential_id {
+        // the identity goes with the credential
+        Some(credential_id) => {
+            sqlx::query!(
+                "DELETE FROM webauthn_credentials WHERE tenant = ? AND id = ?",
+                tenant,
+                credential_id
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        None => {
+            sqlx::query!(
+                "DELETE FROM identities WHERE tenant = ? AND id = ?",
+                tenant,
+                id
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+    if identity.kind == "password" {
+        sqlx::query!(
+            "UPDATE user SET password = ? WHERE tenant = ? AND email = ?",
+            unusable_password,
+            tenant,
+            email
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(IdentityDeletion::Deleted(identity.kind))
+}
+
+pub async fn save_identity_link_request(
+    tenant: &TenantId,
+    token_hash: &str,
+    email: &str,
+    provider_id: &str,
+    expires_in: &str,
+    pool: &DatabasePool,
+) -> Result<()> {
+    let tenant = tenant.as_str();
+
+    sqlx::query!(
+        r#"INSERT INTO identity_link_requests (token_hash, tenant, email, provider_id, expires_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?))"#,
+        token_hash,
+        tenant,
+        email,
+        provider_id,
+        expires_in
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Redeems a request `email` made to link an identity at `provider_id`. False if it is
+/// unknown, expired, for another provider or someone else's, which is left alone.
+pub async fn consume_identity_link_request(
+    tenant: &TenantId,
+    token_hash: &str,
+    provider_id: &str,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<bool> {
+    let tenant = tenant.as_str();
+    let mut tx = pool.begin().await?;
+
+    let request = sqlx::query!(
+        r#"DELETE FROM identity_link_requests WHERE token_hash = ? AND tenant = ? AND email = ?
+            RETURNING provider_id AS "provider_id!",
+                expires_at > datetime('now') AS "valid!: bool""#,
+        token_hash,
+        tenant,
+        email
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(request.is_some_and(|request| request.valid && request.provider_id == provider_id))
+}
+
+/// Seconds since the session signed in with `api_key` was started.
+pub async fn session_age(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<Option<i64>> {
+    let bytes = api_key.into_inner();
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_scalar!(
+        r#"SELECT CAST(strftime('%s', 'now') - strftime('%s', created_at) AS INTEGER)
+                AS "age!: i64"
+            FROM api_keys WHERE api_key = ? AND tenant = ? AND created_at IS NOT NULL"#,
+        bytes,
+        tenant
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn list_access_rules(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<model::AccessRule>> {
+    let tenant = tenant.as_str();
+
+    Ok(sqlx::query_as!(
+        model::AccessRule,
+        r#"SELECT host, roles, groups, created_at, updated_at
+            FROM access_rules WHERE tenant = ? ORDER BY host"#,
+        tenant
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Adds the rule for a host, or replaces the one it has.
+pub async fn save_access_rule(
+    tenant: &TenantId,
+    model: model::NewAccessRule,
+    pool: &DatabasePool,
+) -> Result<model::AccessRule> {
+    let tenant = tenant.as_str();
+    // committed before returning, so the insert does not keep the database locked
+    let mut tx = pool.begin().await?;
+
+    let rule = sqlx::query_as!(
+        model::AccessRule,
+        r#"INSERT INTO access_rules (tenant, host, roles, groups) VALUES (?, ?, ?, ?)
+            ON CONFLICT (tenant, host) DO UPDATE
+                SET roles = excluded.roles, groups = excluded.groups,
+                    updated_at = datetime('now')
+            RETURNING host AS "host!", roles AS "roles!", groups AS "groups!",
+                created_at AS "created_at!", updated_at AS "updated_at!""#,
+        tenant,
+        model.host,
+        model.roles,
+        model.groups
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(rule)
+}
+
+pub async fn delete_access_rule(
+    tenant: &TenantId,
+    host: &str,
+    pool: &DatabasePool,
+) -> Result<DeletionStatus> {
+    let tenant = tenant.as_str();
+
+    let result = sqlx::query!(
+        "DELETE FROM access_rules WHERE tenant = ? AND host = ?",
+        tenant,
+        host
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(match result.rows_affected() {
+        0 => DeletionStatus::NotFound,
+        _ => DeletionStatus::Deleted,
+    })
 }
@@ -0,0 +1,76 @@
+use super::role::RoleName;
+use serde::{Deserialize, Serialize};
+
+/// Errors of deciding whether a request through a reverse proxy may pass.
+#[derive(Debug, thiserror::Error)]
+pub enum AccessError {
+    #[error("invalid host: {0}")]
+    InvalidHost(String),
+    #[error("no access rule for {0}")]
+    UnknownHost(String),
+    #[error("sign in to continue")]
+    Unauthenticated,
+    #[error("not allowed to access {0}")]
+    Denied(String),
+}
+
+/// Who may reach a host behind the proxy. A user needs one of `roles` or to belong to one
+/// of `groups`; with neither, every signed-in user may.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccessRule {
+    /// A host name, or `*.` and a domain for every host under it.
+    pub host: String,
+    pub roles: Vec<RoleName>,
+    pub groups: Vec<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl AccessRule {
+    pub fn allows(&self, roles: &[RoleName], groups: &[String]) -> bool {
+        (self.roles.is_empty() && self.groups.is_empty())
+            || self.roles.iter().any(|role| roles.contains(role))
+            || self.groups.iter().any(|group| groups.contains(group))
+    }
+}
+
+/// Checks the host of a rule: lowercase labels of letters, digits and dashes, the first of
+/// which may be `*`.
+pub fn validate_host(host: &str) -> Result<(), AccessError> {
+    let name = host.strip_prefix("*.").unwrap_or(host);
+    let valid = host.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        });
+
+    match valid {
+        true => Ok(()),
+        false => Err(AccessError::InvalidHost(host.to_string())),
+    }
+}
+
+/// The host name of a `Host` header or URL authority, without its port.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim().to_ascii_lowercase();
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+        _ => host,
+    }
+}
+
+/// The rule for `host`: the one naming it, or else the wildcard for the closest domain
+/// above it.
+pub fn find_rule<'a>(rules: &'a [AccessRule], host: &str) -> Option<&'a AccessRule> {
+    rules.iter().find(|rule| rule.host == host).or_else(|| {
+        rules
+            .iter()
+            .filter_map(|rule| Some((rule, rule.host.strip_prefix('*')?)))
+            .filter(|(_, domain)| host.ends_with(domain))
+            .max_by_key(|(_, domain)| domain.len())
+            .map(|(rule, _)| rule)
+    })
+}
@@ -0,0 +1,168 @@
+use crate::Email;
+use serde::{Deserialize, Serialize};
+
+/// Kinds of events written to the audit log.
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString,
+)]
+pub enum AuditAction {
+    #[serde(rename = "user.create")]
+    #[strum(serialize = "user.create")]
+    UserCreate,
+    #[serde(rename = "user.update")]
+    #[strum(serialize = "user.update")]
+    UserUpdate,
+    #[serde(rename = "user.delete")]
+    #[strum(serialize = "user.delete")]
+    UserDelete,
+    #[serde(rename = "user.status")]
+    #[strum(serialize = "user.status")]
+    UserStatus,
+    #[serde(rename = "user.app_metadata")]
+    #[strum(serialize = "user.app_metadata")]
+    UserAppMetadata,
+    #[serde(rename = "login")]
+    #[strum(serialize = "login")]
+    Login,
+    #[serde(rename = "login.reported")]
+    #[strum(serialize = "login.reported")]
+    LoginReported,
+    #[serde(rename = "magic_link.request")]
+    #[strum(serialize = "magic_link.request")]
+    MagicLinkRequest,
+    #[serde(rename = "otp.request")]
+    #[strum(serialize = "otp.request")]
+    OtpRequest,
+    #[serde(rename = "passkey.register")]
+    #[strum(serialize = "passkey.register")]
+    PasskeyRegister,
+    #[serde(rename = "passkey.delete")]
+    #[strum(serialize = "passkey.delete")]
+    PasskeyDelete,
+    #[serde(rename = "api_key.issue")]
+    #[strum(serialize = "api_key.issue")]
+    ApiKeyIssue,
+    #[serde(rename = "api_key.revoke")]
+    #[strum(serialize = "api_key.revoke")]
+    ApiKeyRevoke,
+    #[serde(rename = "session.revoke")]
+    #[strum(serialize = "session.revoke")]
+    SessionRevoke,
+    #[serde(rename = "session.revoke_all")]
+    #[strum(serialize = "session.revoke_all")]
+    SessionRevokeAll,
+    #[serde(rename = "role.save")]
+    #[strum(serialize = "role.save")]
+    RoleSave,
+    #[serde(rename = "role.delete")]
+    #[strum(serialize = "role.delete")]
+    RoleDelete,
+    #[serde(rename = "role.grant")]
+    #[strum(serialize = "role.grant")]
+    RoleGrant,
+    #[serde(rename = "role.revoke")]
+    #[strum(serialize = "role.revoke")]
+    RoleRevoke,
+    #[serde(rename = "permission.save")]
+    #[strum(serialize = "permission.save")]
+    PermissionSave,
+    #[serde(rename = "group.save")]
+    #[strum(serialize = "group.save")]
+    GroupSave,
+    #[serde(rename = "group.delete")]
+    #[strum(serialize = "group.delete")]
+    GroupDelete,
+    #[serde(rename = "group.member_add")]
+    #[strum(serialize = "group.member_add")]
+    GroupMemberAdd,
+    #[serde(rename = "group.member_remove")]
+    #[strum(serialize = "group.member_remove")]
+    GroupMemberRemove,
+    #[serde(rename = "tenant.save")]
+    #[strum(serialize = "tenant.save")]
+    TenantSave,
+    #[serde(rename = "invitation.create")]
+    #[strum(serialize = "invitation.create")]
+    InvitationCreate,
+    #[serde(rename = "invitation.accept")]
+    #[strum(serialize = "invitation.accept")]
+    InvitationAccept,
+    #[serde(rename = "client.register")]
+    #[strum(serialize = "client.register")]
+    ClientRegister,
+    #[serde(rename = "client.delete")]
+    #[strum(serialize = "client.delete")]
+    ClientDelete,
+    #[serde(rename = "provider.register")]
+    #[strum(serialize = "provider.register")]
+    ProviderRegister,
+    #[serde(rename = "provider.delete")]
+    #[strum(serialize = "provider.delete")]
+    ProviderDelete,
+    #[serde(rename = "identity.link")]
+    #[strum(serialize = "identity.link")]
+    IdentityLink,
+    #[serde(rename = "identity.link_start")]
+    #[strum(serialize = "identity.link_start")]
+    IdentityLinkStart,
+    #[serde(rename = "identity.unlink")]
+    #[strum(serialize = "identity.unlink")]
+    IdentityUnlink,
+    #[serde(rename = "access_rule.set")]
+    #[strum(serialize = "access_rule.set")]
+    AccessRuleSet,
+    #[serde(rename = "access_rule.delete")]
+    #[strum(serialize = "access_rule.delete")]
+    AccessRuleDelete,
+    #[serde(rename = "oauth.authorize")]
+    #[strum(serialize = "oauth.authorize")]
+    OAuthAuthorize,
+    #[serde(rename = "oauth.deny")]
+    #[strum(serialize = "oauth.deny")]
+    OAuthDeny,
+    #[serde(rename = "oauth.token")]
+    #[strum(serialize = "oauth.token")]
+    OAuthToken,
+    #[serde(rename = "oauth.revoke")]
+    #[strum(serialize = "oauth.revoke")]
+    OAuthRevoke,
+    #[serde(rename = "oauth.logout")]
+    #[strum(serialize = "oauth.logout")]
+    Logout,
+    #[serde(rename = "impersonation.start")]
+    #[strum(serialize = "impersonation.start")]
+    ImpersonationStart,
+    #[serde(rename = "impersonation.stop")]
+    #[strum(serialize = "impersonation.stop")]
+    ImpersonationStop,
+}
+
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub occurred_at: String,
+    /// `None` for operator keys and unauthenticated requests.
+    pub actor: Option<Email>,
+    /// Set when `actor` was being impersonated.
+    pub impersonator: Option<Email>,
+    /// Stored as text so events of retired actions can still be read.
+    pub action: String,
+    pub target: Option<String>,
+    pub outcome: AuditOutcome,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub detail: serde_json::Value,
+    /// `None` for events recorded before the log was hash-chained.
+    pub hash: Option<String>,
+}
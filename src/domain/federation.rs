@@ -0,0 +1,295 @@
+use super::token::Token;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{
+    RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED, RSA_PKCS1_2048_8192_SHA256,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Errors of signing in through an upstream identity provider.
+#[derive(Debug, thiserror::Error)]
+pub enum FederationError {
+    #[error("unknown identity provider")]
+    UnknownProvider,
+    #[error("identity provider {0} already exists")]
+    DuplicateProvider(String),
+    #[error("invalid identity provider: {0}")]
+    InvalidProvider(String),
+    #[error("identity provider is unavailable: {0}")]
+    Unavailable(String),
+    #[error("identity provider refused the sign-in: {0}")]
+    Refused(String),
+    #[error("sign-in was not started from this browser or has expired")]
+    InvalidState,
+    #[error("the request to link an identity is invalid or has expired")]
+    InvalidLinkRequest,
+    #[error("sign in in this browser as the user who asked to link the identity")]
+    LinkSession,
+    #[error("invalid ID token: {0}")]
+    InvalidIdToken(String),
+    #[error("no account is linked to this identity")]
+    NotLinked,
+    #[error("this identity is already linked to another account")]
+    AlreadyLinked,
+}
+
+/// An upstream OpenID Connect provider, with Authy as its client.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IdentityProvider {
+    pub id: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    /// Whether a verified email from the provider may be linked to the account with that
+    /// address on first sign-in. Only for providers trusted to verify addresses.
+    pub link_by_email: bool,
+    pub created_at: String,
+}
+
+/// Provider ids appear in URLs: lowercase letters, digits and dashes.
+pub fn validate_provider_id(id: &str) -> Result<(), FederationError> {
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+    match valid {
+        true => Ok(()),
+        false => Err(FederationError::InvalidProvider(format!(
+            "{id}: ids use lowercase letters, digits and dashes"
+        ))),
+    }
+}
+
+/// Issuers must be `https` URLs, except on the loopback interface, without a query or
+/// fragment (OpenID Connect Discovery, section 2).
+pub fn validate_issuer(issuer: &str) -> Result<(), FederationError> {
+    let invalid = |reason: &str| FederationError::InvalidProvider(format!("{issuer}: {reason}"));
+    let url = url::Url::parse(issuer).map_err(|_| invalid("not an absolute URL"))?;
+
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid("must not contain a query or fragment"));
+    }
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(invalid("must use https")),
+    }
+}
+
+/// The parts of a provider's discovery document Authy uses.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// A sign-in through a provider that has been started but not finished. It is kept by the
+/// browser, in a private cookie, until the provider sends the user back.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FederatedLogin {
+    pub provider: String,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    /// Where to continue afterwards, relative to `/oauth`.
+    pub return_to: Option<String>,
+    /// User who asked to link the identity to their account.
+    pub link_to: Option<String>,
+}
+
+impl FederatedLogin {
+    pub fn new(provider: &str, return_to: Option<String>, link_to: Option<String>) -> Self {
+        Self {
+            provider: provider.to_string(),
+            state: Token::generate().to_string(),
+            nonce: Token::generate().to_string(),
+            code_verifier: Token::generate().to_string(),
+            return_to,
+            link_to,
+        }
+    }
+
+    /// Where to send the user to sign in at the provider.
+    pub fn authorization_url(
+        &self,
+        metadata: &ProviderMetadata,
+        provider: &IdentityProvider,
+        redirect_uri: &str,
+    ) -> Result<String, FederationError> {
+        let mut url = url::Url::parse(&metadata.authorization_endpoint).map_err(|_| {
+            FederationError::Unavailable("invalid authorization endpoint".to_string())
+        })?;
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(self.code_verifier.as_bytes()));
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", &provider.scopes.join(" "))
+            .append_pair("state", &self.state)
+            .append_pair("nonce", &self.nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.to_string())
+    }
+}
+
+/// The `aud` claim, which may hold one client or several.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Audience::One(_) => 1,
+            Audience::Many(auds) => auds.len(),
+        }
+    }
+}
+
+/// Claims of an ID token from an upstream provider.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExternalIdToken {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub azp: Option<String>,
+    pub exp: u64,
+    pub iat: u64,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+}
+
+/// Leeway for clocks that are slightly apart.
+const CLOCK_SKEW_SECS: u64 = 60;
+
+impl ExternalIdToken {
+    /// Checks the claims as required by OpenID Connect Core, section 3.1.3.7.
+    pub fn validate(
+        &self,
+        issuer: &str,
+        client_id: &str,
+        nonce: &str,
+        now: u64,
+    ) -> Result<(), FederationError> {
+        let invalid = |reason: &str| Err(FederationError::InvalidIdToken(reason.to_string()));
+
+        if self.iss != issuer {
+            return invalid("issued by another provider");
+        }
+        if !self.aud.contains(client_id) {
+            return invalid("issued to another client");
+        }
+        if self.aud.len() > 1 && self.azp.as_deref() != Some(client_id) {
+            return invalid("issued to another authorized party");
+        }
+        if self.exp + CLOCK_SKEW_SECS <= now {
+            return invalid("expired");
+        }
+        if self.iat > now + CLOCK_SKEW_SECS {
+            return invalid("issued in the future");
+        }
+        if self.nonce.as_deref() != Some(nonce) {
+            return invalid("nonce does not match the sign-in request");
+        }
+
+        Ok(())
+    }
+
+    /// The address the provider vouches for, if any.
+    pub fn verified_email(&self) -> Option<&str> {
+        match self.email_verified {
+            Some(true) => self.email.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// A provider's public keys, as a JSON Web Key Set.
+#[derive(Debug, Deserialize, Clone)]
+pub struct JsonWebKeySet {
+    pub keys: Vec<JsonWebKey>,
+}
+
+/// The members of a JSON Web Key needed for RS256 and ES256 signatures.
+#[derive(Debug, Deserialize, Clone)]
+pub struct JsonWebKey {
+    pub kty: String,
+    pub kid: Option<String>,
+    #[serde(rename = "use")]
+    pub usage: Option<String>,
+    pub crv: Option<String>,
+    pub x: Option<String>,
+    pub y: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+}
+
+impl JsonWebKeySet {
+    /// Checks the signature of `jwt` against the key it names and returns its claims.
+    /// Only RS256 and ES256 are accepted, and never `none`.
+    pub fn verify<T: for<'de> Deserialize<'de>>(&self, jwt: &str) -> Result<T, FederationError> {
+        #[derive(Deserialize)]
+        struct Header {
+            alg: String,
+            kid: Option<String>,
+        }
+
+        let malformed = || FederationError::InvalidIdToken("malformed token".to_string());
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| malformed());
+
+        let (input, signature) = jwt.rsplit_once('.').ok_or_else(malformed)?;
+        let (header, claims) = input.split_once('.').ok_or_else(malformed)?;
+        let header: Header = serde_json::from_slice(&decode(header)?).map_err(|_| malformed())?;
+        let signature = decode(signature)?;
+
+        let key = self
+            .keys
+            .iter()
+            .filter(|key| key.usage.as_deref().unwrap_or("sig") == "sig")
+            .find(|key| header.kid.is_none() || key.kid == header.kid)
+            .ok_or_else(|| FederationError::InvalidIdToken("signed with an unknown key".into()))?;
+        let component = |value: &Option<String>| decode(value.as_deref().unwrap_or_default());
+
+        let verified = match (header.alg.as_str(), key.kty.as_str()) {
+            ("RS256", "RSA") => RsaPublicKeyComponents {
+                n: component(&key.n)?,
+                e: component(&key.e)?,
+            }
+            .verify(&RSA_PKCS1_2048_8192_SHA256, input.as_bytes(), &signature),
+            ("ES256", "EC") if key.crv.as_deref() == Some("P-256") => {
+                let point = [vec![0x04], component(&key.x)?, component(&key.y)?].concat();
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point)
+                    .verify(input.as_bytes(), &signature)
+            }
+            (alg, _) => {
+                return Err(FederationError::InvalidIdToken(format!(
+                    "unsupported algorithm {alg}"
+                )))
+            }
+        };
+        verified.map_err(|_| FederationError::InvalidIdToken("invalid signature".to_string()))?;
+
+        serde_json::from_slice(&decode(claims)?).map_err(|_| malformed())
+    }
+}
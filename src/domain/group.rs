@@ -0,0 +1,63 @@
+use super::role::is_valid_name;
+use crate::Email;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GroupError {
+    #[error("invalid group name: {0}")]
+    InvalidGroup(String),
+
+    #[error("adding {1} to {0} would create a membership cycle")]
+    Cycle(GroupName, GroupName),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct GroupName(String);
+
+impl GroupName {
+    pub fn new(name: &str) -> Result<Self, GroupError> {
+        match is_valid_name(name) {
+            true => Ok(Self(name.to_string())),
+            false => Err(GroupError::InvalidGroup(name.to_string())),
+        }
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl std::fmt::Display for GroupName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for GroupName {
+    type Err = GroupError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::new(value)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Group {
+    pub name: GroupName,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Direct members of a group.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct GroupMembers {
+    pub users: Vec<Email>,
+    pub groups: Vec<GroupName>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupMember {
+    User(Email),
+    Group(GroupName),
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdentityError {
+    #[error("identity not found")]
+    UnknownIdentity,
+    #[error("the last way to sign in cannot be removed")]
+    LastSignInMethod,
+    #[error("sign in again or confirm your password first")]
+    ReauthenticationRequired,
+}
+
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum IdentityKind {
+    Password,
+    Passkey,
+    /// An identity at an upstream provider.
+    Federated,
+}
+
+/// A way a user can sign in.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Identity {
+    pub id: String,
+    pub kind: IdentityKind,
+    /// Base64url credential id of a passkey.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_id: Option<String>,
+    /// Provider of a federated identity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Subject identifier at the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Name of the passkey or provider, for display.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub created_at: String,
+}
@@ -0,0 +1,15 @@
+use super::role::RoleName;
+use crate::Email;
+use serde::{Deserialize, Serialize};
+
+/// An outstanding or accepted invitation to join a tenant with a pre-assigned role.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Invitation {
+    pub email: Email,
+    pub role: RoleName,
+    /// `None` when the invitation was sent with an operator key.
+    pub invited_by: Option<Email>,
+    pub created_at: String,
+    pub expires_at: String,
+    pub accepted_at: Option<String>,
+}
@@ -0,0 +1,622 @@
+//! The subset of LDAPv3 (RFC 4511) that legacy applications need to authenticate users:
+//! simple bind and search, in BER as LDAP restricts it.
+
+/// Largest message accepted from a client.
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Deepest nesting of `and`, `or` and `not` accepted in a search filter.
+pub const MAX_FILTER_DEPTH: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LdapError {
+    #[error("malformed message: {0}")]
+    Malformed(&'static str),
+    #[error("message larger than {MAX_MESSAGE_SIZE} bytes")]
+    TooLarge,
+    #[error("filter nested deeper than {MAX_FILTER_DEPTH} levels")]
+    TooDeep,
+}
+
+/// `resultCode` of an LDAP response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultCode {
+    Success = 0,
+    OperationsError = 1,
+    ProtocolError = 2,
+    SizeLimitExceeded = 4,
+    AuthMethodNotSupported = 7,
+    ConfidentialityRequired = 13,
+    NoSuchObject = 32,
+    InvalidCredentials = 49,
+    InsufficientAccessRights = 50,
+    UnwillingToPerform = 53,
+    Other = 80,
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: i64,
+    pub request: Request,
+}
+
+#[derive(Debug, Clone)]
+pub enum Request {
+    /// `password` is `None` for SASL binds, which are not supported.
+    Bind {
+        version: i64,
+        name: String,
+        password: Option<String>,
+    },
+    Unbind,
+    Search(Search),
+    Abandon,
+    /// Any other operation, by the tag of its request.
+    Unsupported(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub base: String,
+    pub scope: Scope,
+    /// Most entries to return; 0 for no limit.
+    pub size_limit: i64,
+    pub types_only: bool,
+    pub filter: Filter,
+    /// Attributes to return; none or `*` for all of them.
+    pub attributes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Base,
+    OneLevel,
+    Subtree,
+}
+
+/// A search filter. Matching is case-insensitive, like for the attributes entries have.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Equal(String, String),
+    Substrings {
+        attribute: String,
+        initial: Option<String>,
+        any: Vec<String>,
+        last: Option<String>,
+    },
+    Present(String),
+    /// Ordering and extensible matches, which match nothing.
+    Unsupported,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        match self {
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(entry)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(entry)),
+            Filter::Not(filter) => !filter.matches(entry),
+            Filter::Equal(attribute, value) => entry
+                .values(attribute)
+                .iter()
+                .any(|candidate| candidate.to_lowercase() == value.to_lowercase()),
+            Filter::Substrings {
+                attribute,
+                initial,
+                any,
+                last,
+            } => entry.values(attribute).iter().any(|candidate| {
+                substrings_match(
+                    &candidate.to_lowercase(),
+                    initial.as_deref(),
+                    any,
+                    last.as_deref(),
+                )
+            }),
+            Filter::Present(attribute) => !entry.values(attribute).is_empty(),
+            Filter::Unsupported => false,
+        }
+    }
+
+    /// The email a filter is limited to, when it requires `uid` or `mail` to equal one.
+    pub fn email(&self) -> Option<&str> {
+        match self {
+            Filter::Equal(attribute, value)
+                if attribute.eq_ignore_ascii_case("uid")
+                    || attribute.eq_ignore_ascii_case("mail") =>
+            {
+                Some(value)
+            }
+            Filter::And(filters) => filters.iter().find_map(Filter::email),
+            _ => None,
+        }
+    }
+}
+
+fn substrings_match(
+    value: &str,
+    initial: Option<&str>,
+    any: &[String],
+    last: Option<&str>,
+) -> bool {
+    let mut rest = value;
+    if let Some(initial) = initial {
+        match rest.strip_prefix(initial.to_lowercase().as_str()) {
+            Some(after) => rest = after,
+            None => return false,
+        }
+    }
+    for part in any {
+        let part = part.to_lowercase();
+        match rest.find(&part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    match last {
+        Some(last) => rest.ends_with(&last.to_lowercase()),
+        None => true,
+    }
+}
+
+/// A directory entry.
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    pub dn: String,
+    pub attributes: Vec<(String, Vec<String>)>,
+}
+
+impl Entry {
+    pub fn new(dn: String) -> Self {
+        Self {
+            dn,
+            attributes: vec![],
+        }
+    }
+
+    pub fn with(mut self, attribute: &str, values: Vec<String>) -> Self {
+        if !values.is_empty() {
+            self.attributes.push((attribute.to_string(), values));
+        }
+        self
+    }
+
+    pub fn values(&self, attribute: &str) -> &[String] {
+        self.attributes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+            .map(|(_, values)| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The entry with only the attributes a search asked for.
+    pub fn select(mut self, attributes: &[String]) -> Self {
+        let all = attributes.is_empty() || attributes.iter().any(|attribute| attribute == "*");
+        if !all {
+            self.attributes.retain(|(name, _)| {
+                attributes
+                    .iter()
+                    .any(|attribute| attribute.eq_ignore_ascii_case(name))
+            });
+        }
+        self
+    }
+}
+
+/// A response to a request, sent with the request's message ID.
+#[derive(Debug, Clone)]
+pub enum Response {
+    Bind(ResultCode, String),
+    SearchEntry(Entry, bool),
+    SearchDone(ResultCode, String),
+    /// The response to an unsupported request, by the tag of the request.
+    Unsupported(u8),
+    /// Notice of disconnection (RFC 4511, section 4.4.1), sent with message ID 0 before
+    /// closing a connection whose messages cannot be read.
+    Disconnection(ResultCode, String),
+}
+
+// BER tags used by LDAP
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const BOOLEAN: u8 = 0x01;
+const ENUMERATED: u8 = 0x0a;
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const UNBIND_REQUEST: u8 = 0x42;
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_ENTRY: u8 = 0x64;
+const SEARCH_DONE: u8 = 0x65;
+const ABANDON_REQUEST: u8 = 0x50;
+const MODIFY_REQUEST: u8 = 0x66;
+const ADD_REQUEST: u8 = 0x68;
+const DELETE_REQUEST: u8 = 0x4a;
+const MODIFY_DN_REQUEST: u8 = 0x6c;
+const COMPARE_REQUEST: u8 = 0x6e;
+const EXTENDED_REQUEST: u8 = 0x77;
+const EXTENDED_RESPONSE: u8 = 0x78;
+const RESPONSE_NAME: u8 = 0x8a;
+
+const NOTICE_OF_DISCONNECTION: &str = "1.3.6.1.4.1.1466.20036";
+
+struct Element<'a> {
+    tag: u8,
+    contents: &'a [u8],
+}
+
+/// Reads one element from the start of `buf`, along with its length; `None` if `buf` does
+/// not hold all of it yet.
+fn read_element(buf: &[u8]) -> Result<Option<(Element<'_>, usize)>, LdapError> {
+    let (tag, first) = match buf {
+        [tag, first, ..] => (*tag, *first),
+        _ => return Ok(None),
+    };
+    if tag & 0x1f == 0x1f {
+        return Err(LdapError::Malformed("multi-byte tag"));
+    }
+
+    let (length, header) = match first {
+        0..=0x7f => (first as usize, 2),
+        0x81..=0x84 => {
+            let size = (first & 0x7f) as usize;
+            let bytes = match buf.get(2..2 + size) {
+                Some(bytes) => bytes,
+                None => return Ok(None),
+            };
+            let length = bytes
+                .iter()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+            (length, 2 + size)
+        }
+        _ => return Err(LdapError::Malformed("unsupported length")),
+    };
+    if length > MAX_MESSAGE_SIZE {
+        return Err(LdapError::TooLarge);
+    }
+
+    Ok(buf.get(header..header + length).map(|contents| {
+        let element = Element { tag, contents };
+        (element, header + length)
+    }))
+}
+
+fn children(contents: &[u8]) -> Result<Vec<Element<'_>>, LdapError> {
+    let mut elements = vec![];
+    let mut rest = contents;
+    while !rest.is_empty() {
+        let (element, length) =
+            read_element(rest)?.ok_or(LdapError::Malformed("truncated element"))?;
+        elements.push(element);
+        rest = &rest[length..];
+    }
+    Ok(elements)
+}
+
+fn integer(contents: &[u8]) -> Result<i64, LdapError> {
+    if contents.is_empty() || contents.len() > 8 {
+        return Err(LdapError::Malformed("invalid integer"));
+    }
+    let sign = if contents[0] & 0x80 != 0 { -1i64 } else { 0 };
+    Ok(contents
+        .iter()
+        .fold(sign, |value, byte| (value << 8) | *byte as i64))
+}
+
+fn string(contents: &[u8]) -> Result<String, LdapError> {
+    String::from_utf8(contents.to_vec()).map_err(|_| LdapError::Malformed("invalid UTF-8"))
+}
+
+fn expect<'a>(element: Option<&Element<'a>>, tag: u8) -> Result<&'a [u8], LdapError> {
+    match element {
+        Some(element) if element.tag == tag => Ok(element.contents),
+        _ => Err(LdapError::Malformed("unexpected element")),
+    }
+}
+
+impl Message {
+    /// Reads the first message in `buf`, along with its length; `None` if `buf` does not
+    /// hold all of it yet.
+    pub fn parse(buf: &[u8]) -> Result<Option<(Message, usize)>, LdapError> {
+        let (element, length) = match read_element(buf)? {
+            Some(read) => read,
+            None if buf.len() > MAX_MESSAGE_SIZE => return Err(LdapError::TooLarge),
+            None => return Ok(None),
+        };
+        if element.tag != SEQUENCE {
+            return Err(LdapError::Malformed("message is not a sequence"));
+        }
+
+        let parts = children(element.contents)?;
+        let id = integer(expect(parts.first(), INTEGER)?)?;
+        let op = parts
+            .get(1)
+            .ok_or(LdapError::Malformed("missing operation"))?;
+        let request = match op.tag {
+            BIND_REQUEST => {
+                let fields = children(op.contents)?;
+                let version = integer(expect(fields.first(), INTEGER)?)?;
+                let name = string(expect(fields.get(1), OCTET_STRING)?)?;
+                let password = match fields.get(2) {
+                    Some(simple) if simple.tag == 0x80 => Some(string(simple.contents)?),
+                    _ => None,
+                };
+                Request::Bind {
+                    version,
+                    name,
+                    password,
+                }
+            }
+            UNBIND_REQUEST => Request::Unbind,
+            SEARCH_REQUEST => Request::Search(parse_search(op.contents)?),
+            ABANDON_REQUEST => Request::Abandon,
+            tag @ (MODIFY_REQUEST | ADD_REQUEST | DELETE_REQUEST | MODIFY_DN_REQUEST
+            | COMPARE_REQUEST | EXTENDED_REQUEST) => Request::Unsupported(tag),
+            _ => return Err(LdapError::Malformed("unknown operation")),
+        };
+
+        Ok(Some((Message { id, request }, length)))
+    }
+}
+
+fn parse_search(contents: &[u8]) -> Result<Search, LdapError> {
+    let fields = children(contents)?;
+    let scope = match integer(expect(fields.get(1), ENUMERATED)?)? {
+        0 => Scope::Base,
+        1 => Scope::OneLevel,
+        2 => Scope::Subtree,
+        _ => return Err(LdapError::Malformed("invalid scope")),
+    };
+    let filter = fields
+        .get(6)
+        .ok_or(LdapError::Malformed("missing filter"))?;
+
+    Ok(Search {
+        base: string(expect(fields.first(), OCTET_STRING)?)?,
+        scope,
+        size_limit: integer(expect(fields.get(3), INTEGER)?)?,
+        types_only: expect(fields.get(5), BOOLEAN)?
+            .first()
+            .copied()
+            .unwrap_or(0)
+            != 0,
+        filter: parse_filter(filter, 0)?,
+        attributes: children(expect(fields.get(7), SEQUENCE)?)?
+            .iter()
+            .map(|attribute| string(attribute.contents))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// Reads a filter found `depth` levels down in another.
+fn parse_filter(element: &Element<'_>, depth: usize) -> Result<Filter, LdapError> {
+    if depth > MAX_FILTER_DEPTH {
+        return Err(LdapError::TooDeep);
+    }
+    let assertion = |contents| -> Result<(String, String), LdapError> {
+        let parts = children(contents)?;
+        Ok((
+            string(expect(parts.first(), OCTET_STRING)?)?,
+            string(expect(parts.get(1), OCTET_STRING)?)?,
+        ))
+    };
+
+    Ok(match element.tag {
+        0xa0 | 0xa1 => {
+            let filters = children(element.contents)?
+                .iter()
+                .map(|filter| parse_filter(filter, depth + 1))
+                .collect::<Result<_, _>>()?;
+            match element.tag {
+                0xa0 => Filter::And(filters),
+                _ => Filter::Or(filters),
+            }
+        }
+        0xa2 => {
+            let inner = children(element.contents)?;
+            let inner = inner.first().ok_or(LdapError::Malformed("empty not"))?;
+            Filter::Not(Box::new(parse_filter(inner, depth + 1)?))
+        }
+        // approximate matches are taken for equality
+        0xa3 | 0xa8 => {
+            let (attribute, value) = assertion(element.contents)?;
+            Filter::Equal(attribute, value)
+        }
+        0xa4 => {
+            let parts = children(element.contents)?;
+            let mut filter = Filter::Substrings {
+                attribute: string(expect(parts.first(), OCTET_STRING)?)?,
+                initial: None,
+                any: vec![],
+                last: None,
+            };
+            if let Filter::Substrings {
+                initial, any, last, ..
+            } = &mut filter
+            {
+                for part in children(expect(parts.get(1), SEQUENCE)?)? {
+                    let value = string(part.contents)?;
+                    match part.tag {
+                        0x80 => *initial = Some(value),
+                        0x81 => any.push(value),
+                        0x82 => *last = Some(value),
+                        _ => return Err(LdapError::Malformed("invalid substring")),
+                    }
+                }
+            }
+            filter
+        }
+        0x87 => Filter::Present(string(element.contents)?),
+        0xa5 | 0xa6 | 0xa9 => Filter::Unsupported,
+        _ => return Err(LdapError::Malformed("invalid filter")),
+    })
+}
+
+fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let length = contents.len();
+    match length {
+        0..=0x7f => out.push(length as u8),
+        _ => {
+            let bytes: Vec<u8> = length
+                .to_be_bytes()
+                .into_iter()
+                .skip_while(|byte| *byte == 0)
+                .collect();
+            out.push(0x80 | bytes.len() as u8);
+            out.extend(bytes);
+        }
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+fn encode_integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // drop leading bytes that only repeat the sign
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    encode(tag, &bytes[start..])
+}
+
+fn encode_result(tag: u8, code: ResultCode, message: &str) -> Vec<u8> {
+    let contents = [
+        encode_integer(ENUMERATED, code as i64),
+        encode(OCTET_STRING, b""),
+        encode(OCTET_STRING, message.as_bytes()),
+    ]
+    .concat();
+    encode(tag, &contents)
+}
+
+impl Response {
+    pub fn encode(&self, id: i64) -> Vec<u8> {
+        let op = match self {
+            Response::Bind(code, message) => encode_result(BIND_RESPONSE, *code, message),
+            Response::SearchEntry(entry, types_only) => {
+                let attributes: Vec<u8> = entry
+                    .attributes
+                    .iter()
+                    .flat_map(|(name, values)| {
+                        let values: Vec<u8> = match types_only {
+                            true => vec![],
+                            false => values
+                                .iter()
+                                .flat_map(|value| encode(OCTET_STRING, value.as_bytes()))
+                                .collect(),
+                        };
+                        let contents =
+                            [encode(OCTET_STRING, name.as_bytes()), encode(SET, &values)].concat();
+                        encode(SEQUENCE, &contents)
+                    })
+                    .collect();
+                let contents = [
+                    encode(OCTET_STRING, entry.dn.as_bytes()),
+                    encode(SEQUENCE, &attributes),
+                ]
+                .concat();
+                encode(SEARCH_ENTRY, &contents)
+            }
+            Response::SearchDone(code, message) => encode_result(SEARCH_DONE, *code, message),
+            Response::Unsupported(EXTENDED_REQUEST) => encode_result(
+                EXTENDED_RESPONSE,
+                ResultCode::ProtocolError,
+                "extended operations are not supported",
+            ),
+            // the responses to modify, add, delete, modify DN and compare follow their requests
+            Response::Unsupported(tag) => encode_result(
+                tag + 1,
+                ResultCode::UnwillingToPerform,
+                "the directory is read-only",
+            ),
+            Response::Disconnection(code, message) => {
+                let contents = [
+                    encode_integer(ENUMERATED, *code as i64),
+                    encode(OCTET_STRING, b""),
+                    encode(OCTET_STRING, message.as_bytes()),
+                    encode(RESPONSE_NAME, NOTICE_OF_DISCONNECTION.as_bytes()),
+                ]
+                .concat();
+                encode(EXTENDED_RESPONSE, &contents)
+            }
+        };
+
+        encode(SEQUENCE, &[encode_integer(INTEGER, id), op].concat())
+    }
+}
+
+/// Splits a DN into its RDNs, `attribute=value` with the value unescaped, comparing
+/// attribute names and values case-insensitively.
+fn rdns(dn: &str) -> Vec<(String, String)> {
+    let mut rdns = vec![];
+    let mut current = String::new();
+    let mut chars = dn.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            ',' => rdns.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() {
+        rdns.push(current);
+    }
+
+    rdns.iter()
+        .filter_map(|rdn| {
+            let (attribute, value) = rdn.split_once('=')?;
+            Some((
+                attribute.trim().to_ascii_lowercase(),
+                value.trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+fn same_dn(a: &[(String, String)], b: &[(String, String)]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|((a, x), (b, y))| a == b && x.to_lowercase() == y.to_lowercase())
+}
+
+/// Whether two DNs name the same entry.
+pub fn is_same_dn(a: &str, b: &str) -> bool {
+    same_dn(&rdns(a), &rdns(b))
+}
+
+/// The DN of the user with `email` under `base`.
+pub fn user_dn(email: &str, base: &str) -> String {
+    let mut escaped = String::new();
+    for c in email.chars() {
+        if ",+\"\\<>;=".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    format!("uid={escaped},{base}")
+}
+
+/// The value of `attribute` in the RDN of `dn` when it names an entry directly under
+/// `base`, e.g. the email of `uid=alice@example.com,dc=example,dc=com`.
+pub fn rdn_value(dn: &str, attribute: &str, base: &str) -> Option<String> {
+    let names = rdns(dn);
+    let (first, parent) = names.split_first()?;
+    match first.0 == attribute && same_dn(parent, &rdns(base)) {
+        true => Some(first.1.clone()),
+        false => None,
+    }
+}
+
+/// Whether `dn` is `base` or an entry under it.
+pub fn is_within(dn: &str, base: &str) -> bool {
+    let (dn, base) = (rdns(dn), rdns(base));
+    dn.len() >= base.len() && same_dn(&dn[dn.len() - base.len()..], &base)
+}
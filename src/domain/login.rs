@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+/// A sign-in attempt on an existing account.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Login {
+    pub occurred_at: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Value of the device cookie the attempt was made with.
+    pub device_id: Option<String>,
+    pub success: bool,
+    /// First successful sign-in from this device.
+    pub new_device: bool,
+}
@@ -1,3 +1,20 @@
+pub mod access;
+pub mod audit;
+pub mod federation;
+pub mod group;
+pub mod identity;
+pub mod invitation;
+pub mod ldap;
+pub mod login;
+pub mod oauth;
+pub mod oidc;
+pub mod otp;
+pub mod role;
+pub mod scim;
+pub mod session;
+pub mod tenant;
+pub mod token;
 pub mod user;
+pub mod webauthn;
 
 pub use user::User;
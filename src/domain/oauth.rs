@@ -0,0 +1,242 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+
+/// Errors of the authorization and token endpoints; each maps to an RFC 6749 error code.
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("client authentication failed")]
+    InvalidClient,
+    #[error("{0}")]
+    InvalidGrant(String),
+    #[error("client is not allowed to make this request")]
+    UnauthorizedClient,
+    #[error("unsupported grant type")]
+    UnsupportedGrantType,
+    #[error("only the `code` response type is supported")]
+    UnsupportedResponseType,
+    #[error("requested scope exceeds what the client may ask for")]
+    InvalidScope,
+    #[error("the user denied the request")]
+    AccessDenied,
+    #[error("invalid redirect URI: {0}")]
+    InvalidRedirectUri(String),
+    #[error("the user is not signed in")]
+    LoginRequired,
+    #[error("the user has not consented to the requested scope")]
+    ConsentRequired,
+    #[error("access token is invalid or expired")]
+    InvalidToken,
+    #[error("access token lacks the required scope")]
+    InsufficientScope,
+    #[error("the user has not yet approved the device")]
+    AuthorizationPending,
+    #[error("polling too fast; wait longer between requests")]
+    SlowDown,
+    #[error("the device code has expired")]
+    ExpiredToken,
+}
+
+impl OAuthError {
+    /// The `error` code sent to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) | Self::InvalidRedirectUri(_) => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant(_) => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::UnsupportedResponseType => "unsupported_response_type",
+            Self::InvalidScope => "invalid_scope",
+            Self::AccessDenied => "access_denied",
+            Self::LoginRequired => "login_required",
+            Self::ConsentRequired => "consent_required",
+            Self::InvalidToken => "invalid_token",
+            Self::InsufficientScope => "insufficient_scope",
+            Self::AuthorizationPending => "authorization_pending",
+            Self::SlowDown => "slow_down",
+            Self::ExpiredToken => "expired_token",
+        }
+    }
+}
+
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ClientType {
+    /// Can keep a secret, e.g. a server-side web app.
+    Confidential,
+    /// Runs on the user's device and cannot keep a secret; relies on PKCE alone.
+    Public,
+}
+
+/// An application registered to sign users in through Authy.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Client {
+    pub id: String,
+    pub name: String,
+    pub client_type: ClientType,
+    pub redirect_uris: Vec<String>,
+    /// Scopes the client may request, besides the OpenID Connect ones.
+    pub scopes: Vec<String>,
+    /// Where the user may be sent after signing out through the client.
+    #[serde(default)]
+    pub post_logout_redirect_uris: Vec<String>,
+    pub created_at: String,
+}
+
+impl Client {
+    /// Redirect URIs must match a registered one exactly.
+    pub fn allows_redirect(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|uri| uri == redirect_uri)
+    }
+
+    pub fn allows_post_logout_redirect(&self, redirect_uri: &str) -> bool {
+        self.post_logout_redirect_uris
+            .iter()
+            .any(|uri| uri == redirect_uri)
+    }
+}
+
+/// Checks a redirect URI at registration: it must be absolute, carry no fragment and only
+/// use plain `http` on the loopback interface.
+pub fn validate_redirect_uri(uri: &str) -> Result<(), OAuthError> {
+    let invalid = |reason: &str| OAuthError::InvalidRedirectUri(format!("{uri}: {reason}"));
+    let url = url::Url::parse(uri).map_err(|_| invalid("not an absolute URL"))?;
+
+    if url.fragment().is_some() {
+        return Err(invalid("must not contain a fragment"));
+    }
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    match url.scheme() {
+        "http" if !loopback => Err(invalid("http is only allowed for loopback addresses")),
+        _ => Ok(()),
+    }
+}
+
+/// A set of space-delimited scope tokens.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scope(BTreeSet<String>);
+
+impl Scope {
+    /// Whether `token` can be used as a scope: printable ASCII without spaces, quotes or
+    /// backslashes.
+    pub fn is_valid_token(token: &str) -> bool {
+        !token.is_empty()
+            && token
+                .chars()
+                .all(|c| c.is_ascii_graphic() && c != '"' && c != '\\')
+    }
+
+    pub fn parse(scope: &str) -> Self {
+        Self(scope.split_whitespace().map(str::to_string).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, other: &Scope) -> bool {
+        self.0.is_superset(&other.0)
+    }
+
+    pub fn union(&self, other: &Scope) -> Self {
+        Self(self.0.union(&other.0).cloned().collect())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.iter().collect::<Vec<_>>().join(" "))
+    }
+}
+
+impl From<&[String]> for Scope {
+    fn from(scopes: &[String]) -> Self {
+        Self(scopes.iter().cloned().collect())
+    }
+}
+
+/// Checks a PKCE code verifier against the S256 challenge it was derived from.
+pub fn verify_code_challenge(challenge: &str, verifier: &str) -> Result<(), OAuthError> {
+    let well_formed = (43..=128).contains(&verifier.len())
+        && verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+    if !well_formed {
+        return Err(OAuthError::InvalidRequest(
+            "malformed code_verifier".to_string(),
+        ));
+    }
+
+    match URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) == challenge {
+        true => Ok(()),
+        false => Err(OAuthError::InvalidGrant(
+            "code_verifier does not match the code challenge".to_string(),
+        )),
+    }
+}
+
+/// What a token stored in `oauth_tokens` is good for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+/// `grant_type` of token requests redeeming a device code.
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// The code a user enters to approve a device (RFC 8628, section 6.1). It is eight
+/// consonants, which are easy to type and cannot spell words, shown as `XXXX-XXXX`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserCode(String);
+
+impl UserCode {
+    const ALPHABET: &'static [u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+    const LENGTH: usize = 8;
+
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let code = (0..Self::LENGTH)
+            .map(|_| char::from(Self::ALPHABET[rng.gen_range(0..Self::ALPHABET.len())]))
+            .collect();
+        Self(code)
+    }
+
+    /// Reads a code as the user typed it, ignoring case, spaces and dashes.
+    pub fn parse(input: &str) -> Option<Self> {
+        let code: String = input
+            .chars()
+            .filter(|c| !matches!(c, '-' | ' '))
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        let valid = code.len() == Self::LENGTH && code.bytes().all(|c| Self::ALPHABET.contains(&c));
+
+        valid.then_some(Self(code))
+    }
+
+    /// The code as stored, without the dash.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for UserCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (first, second) = self.0.split_at(Self::LENGTH / 2);
+        write!(f, "{first}-{second}")
+    }
+}
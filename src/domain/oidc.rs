@@ -0,0 +1,205 @@
+use super::oauth::Scope;
+use super::tenant::TenantId;
+use super::user::field::Status;
+use super::User;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED,
+    ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    #[error("signing key is unusable")]
+    SigningKey,
+    #[error("malformed token")]
+    MalformedToken,
+    #[error("token was not signed by this provider")]
+    Signature,
+}
+
+/// Scopes defined by OpenID Connect; every client may ask for them.
+pub const STANDARD_SCOPES: [&str; 4] = ["openid", "profile", "email", "phone"];
+
+/// Scope that turns an OAuth request into an OpenID Connect one.
+pub const OPENID: &str = "openid";
+
+/// Claims Authy can release about a user.
+pub const CLAIMS: [&str; 6] = [
+    "sub",
+    "name",
+    "email",
+    "email_verified",
+    "phone_number",
+    "nonce",
+];
+
+/// Stable identifier of a user towards clients, so email addresses are not used as ids.
+pub fn subject(tenant: &TenantId, email: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(format!("{tenant}/{email}").as_bytes()))
+}
+
+/// ES256 key ID tokens are signed with.
+pub struct SigningKey {
+    pub id: String,
+    pkcs8: Vec<u8>,
+    key_pair: EcdsaKeyPair,
+}
+
+impl SigningKey {
+    pub const ALGORITHM: &'static str = "ES256";
+
+    pub fn generate() -> Result<Self, OidcError> {
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+                .map_err(|_| OidcError::SigningKey)?;
+        let id = format!("{:032x}", rand::random::<u128>());
+
+        Self::from_pkcs8(id, pkcs8.as_ref().to_vec())
+    }
+
+    pub fn from_pkcs8(id: String, pkcs8: Vec<u8>) -> Result<Self, OidcError> {
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8)
+            .map_err(|_| OidcError::SigningKey)?;
+
+        Ok(Self {
+            id,
+            pkcs8,
+            key_pair,
+        })
+    }
+
+    pub fn pkcs8(&self) -> &[u8] {
+        &self.pkcs8
+    }
+
+    /// The public half as a JSON Web Key.
+    pub fn jwk(&self) -> serde_json::Value {
+        // uncompressed point: 0x04, then x and y
+        let point = self.key_pair.public_key().as_ref();
+        serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+            "kid": self.id,
+            "use": "sig",
+            "alg": Self::ALGORITHM,
+        })
+    }
+
+    /// Signs `claims` as a compact JWS.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, OidcError> {
+        let header = serde_json::json!({ "alg": Self::ALGORITHM, "typ": "JWT", "kid": self.id });
+        let input = format!("{}.{}", encode_part(&header)?, encode_part(claims)?);
+        let signature = self
+            .key_pair
+            .sign(&SystemRandom::new(), input.as_bytes())
+            .map_err(|_| OidcError::SigningKey)?;
+
+        Ok(format!(
+            "{input}.{}",
+            URL_SAFE_NO_PAD.encode(signature.as_ref())
+        ))
+    }
+
+    /// Checks the signature of a JWT made with this key and returns its claims. Expiry and
+    /// audience are left to the caller.
+    pub fn verify<T: for<'de> Deserialize<'de>>(&self, jwt: &str) -> Result<T, OidcError> {
+        let (input, signature) = jwt.rsplit_once('.').ok_or(OidcError::MalformedToken)?;
+        let (_, claims) = input.split_once('.').ok_or(OidcError::MalformedToken)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| OidcError::MalformedToken)?;
+
+        UnparsedPublicKey::new(
+            &ECDSA_P256_SHA256_FIXED,
+            self.key_pair.public_key().as_ref(),
+        )
+        .verify(input.as_bytes(), &signature)
+        .map_err(|_| OidcError::Signature)?;
+
+        let claims = URL_SAFE_NO_PAD
+            .decode(claims)
+            .map_err(|_| OidcError::MalformedToken)?;
+        serde_json::from_slice(&claims).map_err(|_| OidcError::MalformedToken)
+    }
+}
+
+/// Id of the key a JWT claims to be signed with.
+pub fn key_id(jwt: &str) -> Result<String, OidcError> {
+    #[derive(Deserialize)]
+    struct Header {
+        kid: String,
+    }
+
+    let header = jwt.split('.').next().ok_or(OidcError::MalformedToken)?;
+    let header = URL_SAFE_NO_PAD
+        .decode(header)
+        .map_err(|_| OidcError::MalformedToken)?;
+    serde_json::from_slice::<Header>(&header)
+        .map(|header| header.kid)
+        .map_err(|_| OidcError::MalformedToken)
+}
+
+/// Base64url-encoded JSON, the form of each JWS part.
+fn encode_part<T: Serialize + ?Sized>(value: &T) -> Result<String, OidcError> {
+    serde_json::to_vec(value)
+        .map(|json| URL_SAFE_NO_PAD.encode(json))
+        .map_err(|_| OidcError::MalformedToken)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IdToken {
+    pub iss: String,
+    pub sub: String,
+    /// Client the token was issued to.
+    pub aud: String,
+    pub exp: u64,
+    pub iat: u64,
+    /// Echoed from the authorization request, binding the token to the client's session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+/// Claims about a user, released according to the scopes granted.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+}
+
+impl UserInfo {
+    pub fn new(sub: String, user: &User, scope: &Scope) -> Self {
+        let granted = |name: &str| scope.iter().any(|scope| scope == name);
+        let mut info = Self {
+            sub,
+            ..Default::default()
+        };
+
+        if granted("profile") {
+            info.name = Some(user.name.clone().into_inner());
+        }
+        if granted("email") {
+            info.email = Some(user.email.clone().into_inner());
+            // accounts are created unverified when they must confirm their address
+            info.email_verified = Some(user.status != Status::PendingVerification);
+        }
+        if granted("phone") {
+            info.phone_number = user.phone.as_ref().map(|phone| phone.as_str().to_string());
+        }
+
+        info
+    }
+}
@@ -0,0 +1,53 @@
+use crate::domain::token::Token;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How a one-time code reaches the user.
+#[derive(
+    Debug,
+    Deserialize,
+    Serialize,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    strum::Display,
+    strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Channel {
+    #[default]
+    Email,
+    Sms,
+}
+
+/// Six-digit code for signing in without a password.
+///
+/// Like [`Token`], only the hash is stored; the short code is protected by its expiry and
+/// the attempt limit rather than by its length.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OneTimeCode(String);
+
+impl OneTimeCode {
+    pub fn generate() -> Self {
+        Self(format!("{:06}", rand::thread_rng().gen_range(0..1_000_000)))
+    }
+
+    pub fn hash(&self) -> String {
+        Token::from(self.0.trim()).hash()
+    }
+}
+
+impl From<&str> for OneTimeCode {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl std::fmt::Display for OneTimeCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
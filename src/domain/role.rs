@@ -0,0 +1,148 @@
+use crate::Email;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoleError {
+    #[error("invalid role name: {0}")]
+    InvalidRole(String),
+
+    #[error("invalid permission name: {0}")]
+    InvalidPermission(String),
+}
+
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.:".contains(c))
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct RoleName(String);
+
+impl RoleName {
+    pub fn new(name: &str) -> Result<Self, RoleError> {
+        match is_valid_name(name) {
+            true => Ok(Self(name.to_string())),
+            false => Err(RoleError::InvalidRole(name.to_string())),
+        }
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl std::fmt::Display for RoleName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for RoleName {
+    type Err = RoleError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::new(value)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct PermissionName(String);
+
+impl PermissionName {
+    pub fn new(name: &str) -> Result<Self, RoleError> {
+        match is_valid_name(name) {
+            true => Ok(Self(name.to_string())),
+            false => Err(RoleError::InvalidPermission(name.to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl FromStr for PermissionName {
+    type Err = RoleError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::new(value)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Permission {
+    pub name: PermissionName,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Role {
+    pub name: RoleName,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub permissions: Vec<PermissionName>,
+}
+
+/// Who a request is made on behalf of.
+#[derive(Debug, Clone)]
+pub enum Principal {
+    /// Keys printed to the server log by `/api/user/key`; they are not bound to a user and
+    /// may do anything.
+    Operator,
+    User {
+        email: Email,
+        permissions: Vec<PermissionName>,
+        /// The user acting on `email`'s behalf, if the key was issued for impersonation.
+        impersonator: Option<Email>,
+    },
+    /// A machine client holding an access token from the client credentials grant; the
+    /// scopes granted to it that name permissions are its permissions.
+    Client {
+        id: String,
+        permissions: Vec<PermissionName>,
+    },
+}
+
+impl Principal {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        match self {
+            Principal::Operator => true,
+            Principal::User { permissions, .. } | Principal::Client { permissions, .. } => {
+                permissions
+                    .iter()
+                    .any(|granted| granted.as_str() == permission)
+            }
+        }
+    }
+
+    pub fn email(&self) -> Option<&Email> {
+        match self {
+            Principal::Operator | Principal::Client { .. } => None,
+            Principal::User { email, .. } => Some(email),
+        }
+    }
+
+    /// How the principal is named where a change records who made it.
+    pub fn name(&self) -> String {
+        match self {
+            Principal::Operator => "operator".to_string(),
+            Principal::User { email, .. } => email.clone().into_inner(),
+            Principal::Client { id, .. } => format!("client:{id}"),
+        }
+    }
+
+    pub fn impersonator(&self) -> Option<&Email> {
+        match self {
+            Principal::Operator | Principal::Client { .. } => None,
+            Principal::User { impersonator, .. } => impersonator.as_ref(),
+        }
+    }
+}
@@ -0,0 +1,694 @@
+use super::group::GroupName;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_RESPONSE_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const SERVICE_PROVIDER_CONFIG_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+const RESOURCE_TYPE_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:ResourceType";
+const SCHEMA_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Schema";
+
+/// Most resources returned in one page.
+pub const MAX_RESULTS: u32 = 200;
+
+/// Errors of SCIM requests, reported with a `scimType` (RFC 7644, section 3.12).
+#[derive(Debug, thiserror::Error)]
+pub enum ScimError {
+    #[error("resource not found")]
+    UnknownResource,
+    #[error("unsupported filter: {0}")]
+    InvalidFilter(String),
+    #[error("invalid path: {0}")]
+    InvalidPath(String),
+    #[error("no attribute matches the path: {0}")]
+    NoTarget(String),
+    #[error("invalid value: {0}")]
+    InvalidValue(String),
+    #[error("invalid request: {0}")]
+    InvalidSyntax(String),
+    #[error("{0} cannot be changed")]
+    Mutability(String),
+    #[error("{0} already exists")]
+    Uniqueness(String),
+}
+
+impl ScimError {
+    pub fn scim_type(&self) -> Option<&'static str> {
+        match self {
+            ScimError::UnknownResource => None,
+            ScimError::InvalidFilter(_) => Some("invalidFilter"),
+            ScimError::InvalidPath(_) => Some("invalidPath"),
+            ScimError::NoTarget(_) => Some("noTarget"),
+            ScimError::InvalidValue(_) => Some("invalidValue"),
+            ScimError::InvalidSyntax(_) => Some("invalidSyntax"),
+            ScimError::Mutability(_) => Some("mutability"),
+            ScimError::Uniqueness(_) => Some("uniqueness"),
+        }
+    }
+}
+
+/// A user as provisioning systems see it: `id` and `userName` are the user's email.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub user_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<ScimName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "lenient_bool",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub active: Option<bool>,
+    /// Only ever written.
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emails: Vec<MultiValued>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phone_numbers: Vec<MultiValued>,
+    /// Groups the user belongs to, directly or through nested groups; read-only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<Member>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+impl ScimUser {
+    /// The name the user should have, given the one they have now: the first of
+    /// `displayName`, `name.formatted` and the given and family names that differs from it.
+    pub fn name_given(&self, current: &str) -> Option<String> {
+        let name = self.name.clone().unwrap_or_default();
+        let parts = [name.given_name, name.family_name]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        [self.display_name.clone(), name.formatted, Some(parts)]
+            .into_iter()
+            .flatten()
+            .map(|name| name.trim().to_string())
+            .find(|name| !name.is_empty() && name != current)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimName {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+}
+
+/// One of the values of a multi-valued attribute, such as `emails`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MultiValued {
+    pub value: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "lenient_bool",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub primary: Option<bool>,
+}
+
+/// A member of a group, or a group a user belongs to.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Member {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+    #[serde(rename = "$ref", default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// `User` or `Group`.
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+impl Member {
+    /// Whether the member is a group rather than a user; without a `type`, values that are
+    /// not emails are taken for group names.
+    pub fn is_group(&self) -> bool {
+        match &self.kind {
+            Some(kind) => kind.eq_ignore_ascii_case("group"),
+            None => !self.value.contains('@'),
+        }
+    }
+}
+
+/// A group: `id` is the group's name and `displayName` its description.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<Member>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+impl ScimGroup {
+    /// The name of a group created with this `displayName`: lowercase, with dashes for
+    /// anything not allowed in group names.
+    pub fn name(&self) -> Result<GroupName, ScimError> {
+        let mut name = String::new();
+        for c in self.display_name.trim().to_lowercase().chars() {
+            match c.is_ascii_alphanumeric() || "_.:".contains(c) {
+                true => name.push(c),
+                false if !name.ends_with('-') => name.push('-'),
+                false => (),
+            }
+        }
+        let name: String = name.trim_matches('-').chars().take(64).collect();
+
+        GroupName::new(name.trim_end_matches('-'))
+            .map_err(|_| ScimError::InvalidValue(format!("displayName: {}", self.display_name)))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: String,
+    pub location: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: Vec<String>,
+    pub total_results: u64,
+    pub start_index: u32,
+    pub items_per_page: u32,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    pub fn new(resources: Vec<T>, total_results: u64, start_index: u32) -> Self {
+        Self {
+            schemas: vec![LIST_RESPONSE_SCHEMA.to_string()],
+            total_results,
+            start_index,
+            items_per_page: resources.len() as u32,
+            resources,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PatchRequest {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PatchOperation {
+    /// `add`, `replace` or `remove`, in any case.
+    pub op: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+/// Booleans, which some clients send as `"True"` and `"False"`.
+fn lenient_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(value)) => Ok(Some(value)),
+        Some(Value::String(value)) if value.eq_ignore_ascii_case("true") => Ok(Some(true)),
+        Some(Value::String(value)) if value.eq_ignore_ascii_case("false") => Ok(Some(false)),
+        Some(value) => Err(D::Error::custom(format!("invalid boolean: {value}"))),
+    }
+}
+
+/// A filter of the form `attribute eq value`, the only kind supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub attribute: String,
+    pub value: Value,
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self, ScimError> {
+        let invalid = || ScimError::InvalidFilter(filter.to_string());
+        let (attribute, rest) = filter
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        let (op, value) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        if !op.eq_ignore_ascii_case("eq") {
+            return Err(invalid());
+        }
+        let value: Value = serde_json::from_str(value.trim()).map_err(|_| invalid())?;
+        if !(value.is_string() || value.is_boolean()) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            attribute: strip_schema(attribute).to_string(),
+            value,
+        })
+    }
+
+    /// Whether the filter names `attribute`, which is compared case-insensitively.
+    pub fn is_on(&self, attribute: &str) -> bool {
+        self.attribute.eq_ignore_ascii_case(attribute)
+    }
+
+    /// The string the attribute must equal; strings are compared case-insensitively.
+    pub fn text(&self) -> Result<&str, ScimError> {
+        self.value
+            .as_str()
+            .ok_or_else(|| ScimError::InvalidFilter(format!("{} takes a string", self.attribute)))
+    }
+
+    fn matches(&self, item: &Value) -> bool {
+        let value = item.as_object().and_then(|item| {
+            item.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(&self.attribute))
+                .map(|(_, value)| value)
+        });
+        match (&self.value, value) {
+            (Value::String(expected), Some(Value::String(value))) => {
+                expected.to_lowercase() == value.to_lowercase()
+            }
+            (expected, Some(value)) => expected == value,
+            (_, None) => false,
+        }
+    }
+}
+
+/// Drops the core schema a path or filter attribute may be qualified with.
+fn strip_schema(path: &str) -> &str {
+    [USER_SCHEMA, GROUP_SCHEMA]
+        .iter()
+        .find_map(|schema| path.strip_prefix(schema)?.strip_prefix(':'))
+        .unwrap_or(path)
+}
+
+/// Attribute names in the case they are written in resources; clients may use any case.
+const ATTRIBUTES: [&str; 20] = [
+    "schemas",
+    "id",
+    "externalId",
+    "userName",
+    "name",
+    "formatted",
+    "givenName",
+    "familyName",
+    "displayName",
+    "active",
+    "password",
+    "emails",
+    "phoneNumbers",
+    "groups",
+    "members",
+    "value",
+    "display",
+    "type",
+    "primary",
+    "meta",
+];
+
+fn key(object: &Map<String, Value>, name: &str) -> String {
+    object
+        .keys()
+        .map(String::as_str)
+        .chain(ATTRIBUTES)
+        .find(|key| key.eq_ignore_ascii_case(name))
+        .unwrap_or(name)
+        .to_string()
+}
+
+/// A PATCH path: `attribute`, `attribute.sub`, `attribute[filter]` or
+/// `attribute[filter].sub`.
+struct Path {
+    attribute: String,
+    filter: Option<Filter>,
+    sub: Option<String>,
+}
+
+impl Path {
+    fn parse(path: &str) -> Result<Self, ScimError> {
+        let invalid = || ScimError::InvalidPath(path.to_string());
+        let stripped = strip_schema(path);
+        let (attribute, filter, sub) = match stripped.find('[') {
+            Some(open) => {
+                let close = stripped
+                    .rfind(']')
+                    .filter(|close| *close > open)
+                    .ok_or_else(invalid)?;
+                let filter = Filter::parse(&stripped[open + 1..close]).map_err(|_| invalid())?;
+                let sub = match &stripped[close + 1..] {
+                    "" => None,
+                    rest => Some(rest.strip_prefix('.').ok_or_else(invalid)?.to_string()),
+                };
+                (&stripped[..open], Some(filter), sub)
+            }
+            None => match stripped.split_once('.') {
+                Some((attribute, sub)) => (attribute, None, Some(sub.to_string())),
+                None => (stripped, None, None),
+            },
+        };
+
+        let valid = |name: &str| !name.is_empty() && name.chars().all(char::is_alphanumeric);
+        if !valid(attribute) || sub.as_deref().is_some_and(|sub| !valid(sub)) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            attribute: attribute.to_string(),
+            filter,
+            sub,
+        })
+    }
+}
+
+/// Applies a PATCH operation to the JSON representation of a resource (RFC 7644, section
+/// 3.5.2). Attributes of other schemas, such as the enterprise user extension, are not
+/// supported and left alone.
+pub fn apply_patch(resource: &mut Value, operation: &PatchOperation) -> Result<(), ScimError> {
+    let object = resource
+        .as_object_mut()
+        .ok_or_else(|| ScimError::InvalidValue("resource is not an object".to_string()))?;
+    let path = match operation.path.as_deref() {
+        Some(path) if path.starts_with("urn:") && strip_schema(path) == path => return Ok(()),
+        Some(path) => Some(Path::parse(path)?),
+        None => None,
+    };
+    let value = || {
+        operation
+            .value
+            .as_ref()
+            .ok_or_else(|| ScimError::InvalidValue(format!("{} needs a value", operation.op)))
+    };
+
+    match (operation.op.to_ascii_lowercase().as_str(), path) {
+        (op @ ("add" | "replace"), None) => {
+            let values = value()?.as_object().ok_or_else(|| {
+                ScimError::InvalidValue("value must be an object when there is no path".into())
+            })?;
+            for (name, value) in values {
+                if name.starts_with("urn:") && strip_schema(name) == name {
+                    continue;
+                }
+                set(object, &Path::parse(name)?, value, op == "add")?;
+            }
+            Ok(())
+        }
+        (op @ ("add" | "replace"), Some(path)) => set(object, &path, value()?, op == "add"),
+        ("remove", Some(path)) => {
+            remove(object, &path, operation.value.as_ref());
+            Ok(())
+        }
+        ("remove", None) => Err(ScimError::NoTarget("remove needs a path".to_string())),
+        (op, _) => Err(ScimError::InvalidSyntax(format!(
+            "unsupported operation: {op}"
+        ))),
+    }
+}
+
+fn set(
+    object: &mut Map<String, Value>,
+    path: &Path,
+    value: &Value,
+    add: bool,
+) -> Result<(), ScimError> {
+    let name = key(object, &path.attribute);
+    match (&path.filter, &path.sub) {
+        (None, None) => match (object.get_mut(&name), value) {
+            (Some(Value::Array(items)), Value::Array(values)) if add => {
+                for value in values {
+                    if !items.contains(value) {
+                        items.push(value.clone());
+                    }
+                }
+            }
+            (Some(Value::Array(items)), value @ Value::Object(_)) if add => {
+                if !items.contains(value) {
+                    items.push(value.clone());
+                }
+            }
+            (Some(Value::Object(existing)), Value::Object(values)) => merge(existing, values),
+            _ => {
+                object.insert(name, value.clone());
+            }
+        },
+        (None, Some(sub)) => {
+            let inner = object
+                .entry(name)
+                .or_insert_with(|| json!({}))
+                .as_object_mut()
+                .ok_or_else(|| ScimError::InvalidPath(format!("{}.{sub}", path.attribute)))?;
+            inner.insert(key(inner, sub), value.clone());
+        }
+        (Some(filter), sub) => {
+            let no_target = || ScimError::NoTarget(path.attribute.clone());
+            let items = object
+                .get_mut(&name)
+                .and_then(Value::as_array_mut)
+                .ok_or_else(no_target)?;
+            let mut matched = false;
+            for item in items.iter_mut().filter(|item| filter.matches(item)) {
+                matched = true;
+                match (sub, item.as_object_mut(), value) {
+                    (Some(sub), Some(item), value) => {
+                        item.insert(key(item, sub), value.clone());
+                    }
+                    (None, Some(item), Value::Object(values)) => merge(item, values),
+                    (None, _, value) => *item = value.clone(),
+                    (Some(_), None, _) => return Err(no_target()),
+                }
+            }
+            if !matched {
+                return Err(no_target());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn merge(object: &mut Map<String, Value>, values: &Map<String, Value>) {
+    for (name, value) in values {
+        object.insert(key(object, name), value.clone());
+    }
+}
+
+fn remove(object: &mut Map<String, Value>, path: &Path, value: Option<&Value>) {
+    let name = key(object, &path.attribute);
+    match (&path.filter, &path.sub) {
+        (None, None) => match (object.get_mut(&name), value) {
+            // the values to remove listed in `value`, the way some clients remove members
+            (Some(Value::Array(items)), Some(Value::Array(values))) => items.retain(|item| {
+                !values.iter().any(|value| {
+                    value.get("value").is_some() && value.get("value") == item.get("value")
+                })
+            }),
+            _ => {
+                object.remove(&name);
+            }
+        },
+        (None, Some(sub)) => {
+            if let Some(inner) = object.get_mut(&name).and_then(Value::as_object_mut) {
+                inner.remove(&key(inner, sub));
+            }
+        }
+        (Some(filter), None) => {
+            if let Some(items) = object.get_mut(&name).and_then(Value::as_array_mut) {
+                items.retain(|item| !filter.matches(item));
+            }
+        }
+        (Some(filter), Some(sub)) => {
+            if let Some(items) = object.get_mut(&name).and_then(Value::as_array_mut) {
+                for item in items.iter_mut().filter(|item| filter.matches(item)) {
+                    if let Some(item) = item.as_object_mut() {
+                        item.remove(&key(item, sub));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What this server supports, at `{base}/ServiceProviderConfig`.
+pub fn service_provider_config(base: &str) -> Value {
+    json!({
+        "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+        "patch": { "supported": true },
+        "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+        "filter": { "supported": true, "maxResults": MAX_RESULTS },
+        "changePassword": { "supported": true },
+        "sort": { "supported": false },
+        "etag": { "supported": false },
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "OAuth Bearer Token",
+            "description": "An access token from the client credentials grant with the \
+                users:read, users:write, groups:read and groups:write scopes",
+            "primary": true,
+        }],
+        "meta": {
+            "resourceType": "ServiceProviderConfig",
+            "location": format!("{base}/ServiceProviderConfig"),
+        },
+    })
+}
+
+pub fn resource_types(base: &str) -> Vec<Value> {
+    [
+        ("User", "/Users", USER_SCHEMA),
+        ("Group", "/Groups", GROUP_SCHEMA),
+    ]
+    .into_iter()
+    .map(|(name, endpoint, schema)| {
+        json!({
+            "schemas": [RESOURCE_TYPE_SCHEMA],
+            "id": name,
+            "name": name,
+            "endpoint": endpoint,
+            "schema": schema,
+            "meta": {
+                "resourceType": "ResourceType",
+                "location": format!("{base}/ResourceTypes/{name}"),
+            },
+        })
+    })
+    .collect()
+}
+
+/// Definitions of the attributes of users and groups that are supported.
+pub fn schemas(base: &str) -> Vec<Value> {
+    let value = || attribute("value", "string", false, true, "readWrite", &[]);
+    let display = || attribute("display", "string", false, false, "readOnly", &[]);
+    let kind = || attribute("type", "string", false, false, "readWrite", &[]);
+    let user = [
+        attribute("userName", "string", false, true, "readWrite", &[]),
+        attribute(
+            "name",
+            "complex",
+            false,
+            false,
+            "readWrite",
+            &[
+                attribute("formatted", "string", false, false, "readWrite", &[]),
+                attribute("givenName", "string", false, false, "readWrite", &[]),
+                attribute("familyName", "string", false, false, "readWrite", &[]),
+            ],
+        ),
+        attribute("displayName", "string", false, false, "readWrite", &[]),
+        attribute("active", "boolean", false, false, "readWrite", &[]),
+        attribute("password", "string", false, false, "writeOnly", &[]),
+        attribute(
+            "emails",
+            "complex",
+            true,
+            false,
+            "readOnly",
+            &[
+                value(),
+                kind(),
+                attribute("primary", "boolean", false, false, "readOnly", &[]),
+            ],
+        ),
+        attribute(
+            "phoneNumbers",
+            "complex",
+            true,
+            false,
+            "readWrite",
+            &[value(), kind()],
+        ),
+        attribute(
+            "groups",
+            "complex",
+            true,
+            false,
+            "readOnly",
+            &[value(), display()],
+        ),
+    ];
+    let group = [
+        attribute("displayName", "string", false, true, "readWrite", &[]),
+        attribute(
+            "members",
+            "complex",
+            true,
+            false,
+            "readWrite",
+            &[value(), display(), kind()],
+        ),
+    ];
+
+    [
+        (USER_SCHEMA, "User", &user[..]),
+        (GROUP_SCHEMA, "Group", &group[..]),
+    ]
+    .into_iter()
+    .map(|(id, name, attributes)| {
+        json!({
+            "schemas": [SCHEMA_SCHEMA],
+            "id": id,
+            "name": name,
+            "attributes": attributes,
+            "meta": {
+                "resourceType": "Schema",
+                "location": format!("{base}/Schemas/{id}"),
+            },
+        })
+    })
+    .collect()
+}
+
+fn attribute(
+    name: &str,
+    kind: &str,
+    multi_valued: bool,
+    required: bool,
+    mutability: &str,
+    sub_attributes: &[Value],
+) -> Value {
+    let mut attribute = json!({
+        "name": name,
+        "type": kind,
+        "multiValued": multi_valued,
+        "required": required,
+        "caseExact": false,
+        "mutability": mutability,
+        "returned": if mutability == "writeOnly" { "never" } else { "default" },
+        "uniqueness": if name == "userName" { "server" } else { "none" },
+    });
+    if !sub_attributes.is_empty() {
+        attribute["subAttributes"] = json!(sub_attributes);
+    }
+    attribute
+}
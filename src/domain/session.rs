@@ -0,0 +1,19 @@
+use crate::Email;
+use serde::{Deserialize, Serialize};
+
+/// A signed-in client, backed by a user-bound API key.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Session {
+    pub id: String,
+    pub created_at: String,
+    /// Time of the last request made with the session, to the minute.
+    pub last_seen_at: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Value of the device cookie the session was started with.
+    pub device_id: Option<String>,
+    /// Set for impersonation sessions.
+    pub impersonator: Option<Email>,
+    /// The session the request listing it was made with.
+    pub current: bool,
+}
@@ -0,0 +1,66 @@
+use super::role::is_valid_name;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TenantError {
+    #[error("invalid tenant id: {0}")]
+    InvalidTenant(String),
+
+    #[error("tenant not found: {0}")]
+    UnknownTenant(String),
+}
+
+/// Identifies the tenant (organization) every user, key, role and group belongs to.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct TenantId(String);
+
+impl TenantId {
+    /// Tenant that owns everything created before tenants existed, and every request made
+    /// outside of `/api/t/<tenant>`.
+    pub const DEFAULT: &'static str = "default";
+
+    pub fn new(id: &str) -> Result<Self, TenantError> {
+        match is_valid_name(id) && !id.contains(':') {
+            true => Ok(Self(id.to_string())),
+            false => Err(TenantError::InvalidTenant(id.to_string())),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.0 == Self::DEFAULT
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl Default for TenantId {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_string())
+    }
+}
+
+impl std::fmt::Display for TenantId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for TenantId {
+    type Err = TenantError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::new(value)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Tenant {
+    pub id: TenantId,
+    pub name: String,
+}
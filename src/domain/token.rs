@@ -0,0 +1,37 @@
+use base64::engine::general_purpose;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Random secret handed to a user out of band (e.g. by email).
+///
+/// Only [`Token::hash`] is ever stored, so a leaked database does not leak usable tokens.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Token(String);
+
+impl Token {
+    pub fn generate() -> Self {
+        let bytes: Vec<u8> = (0..32).map(|_| rand::random::<u8>()).collect();
+        Self(general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn hash(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(self.0.as_bytes()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Token {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::UserError;
+
+/// Free-form JSON object attached to a user, e.g. locale or app-specific flags.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Metadata(Map<String, Value>);
+
+impl Metadata {
+    /// Largest serialized size accepted for a single metadata namespace.
+    pub const MAX_BYTES: usize = 16 * 1024;
+
+    pub fn new(raw: &str) -> Result<Self, UserError> {
+        let value =
+            serde_json::from_str(raw).map_err(|e| UserError::InvalidMetadata(e.to_string()))?;
+        Self::from_value(value)
+    }
+
+    pub fn from_value(value: Value) -> Result<Self, UserError> {
+        match value {
+            Value::Object(map) => {
+                let metadata = Self(map);
+                match metadata.to_json().len() > Self::MAX_BYTES {
+                    true => Err(UserError::InvalidMetadata(format!(
+                        "metadata larger than {} bytes",
+                        Self::MAX_BYTES
+                    ))),
+                    false => Ok(metadata),
+                }
+            }
+            _ => Err(UserError::InvalidMetadata(
+                "metadata must be a JSON object".to_string(),
+            )),
+        }
+    }
+
+    /// Applies a JSON merge patch (RFC 7396) and validates the result.
+    pub fn merge_patch(&self, patch: &Value) -> Result<Self, UserError> {
+        let mut target = Value::Object(self.0.clone());
+        merge(&mut target, patch);
+        Self::from_value(target)
+    }
+
+    pub fn to_json(&self) -> String {
+        Value::Object(self.0.clone()).to_string()
+    }
+
+    pub fn into_inner(self) -> Map<String, Value> {
+        self.0
+    }
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+            if let Value::Object(target) = target {
+                for (key, value) in patch {
+                    match value {
+                        Value::Null => {
+                            target.remove(key);
+                        }
+                        value => merge(target.entry(key.as_str()).or_insert(Value::Null), value),
+                    }
+                }
+            }
+        }
+        patch => *target = patch.clone(),
+    }
+}
@@ -4,5 +4,14 @@ pub use email::Email;
 pub mod name;
 pub use name::Name;
 
+pub mod phone;
+pub use phone::Phone;
+
 pub mod password;
 pub use password::Password;
+
+pub mod status;
+pub use status::Status;
+
+pub mod metadata;
+pub use metadata::Metadata;
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::UserError;
+
+/// Phone number in E.164 form, e.g. `+4915112345678`.
+///
+/// Spaces, dashes, dots and parentheses are accepted on input and dropped.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Phone(String);
+
+impl Phone {
+    pub fn new(number: &str) -> Result<Self, UserError> {
+        let number: String = number
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+            .collect();
+        let digits = number.strip_prefix('+').unwrap_or_default();
+
+        let valid = (2..=15).contains(&digits.len())
+            && !digits.starts_with('0')
+            && digits.chars().all(|c| c.is_ascii_digit());
+        match valid {
+            true => Ok(Self(number)),
+            false => Err(UserError::InvalidPhone(format!(
+                "{number} is not an E.164 number"
+            ))),
+        }
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Phone {
+    type Error = UserError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
+}
+
+impl From<Phone> for String {
+    fn from(phone: Phone) -> Self {
+        phone.0
+    }
+}
+
+impl FromStr for Phone {
+    type Err = UserError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::new(value)
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::UserError;
+
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Status {
+    PendingVerification,
+    Active,
+    Disabled,
+    Locked,
+    Deleted,
+}
+
+impl Status {
+    pub fn new(status: &str) -> Result<Self, UserError> {
+        Self::from_str(status).map_err(|_| UserError::InvalidStatus(status.to_string()))
+    }
+
+    /// Only active accounts may sign in or be issued credentials.
+    pub fn can_sign_in(&self) -> bool {
+        *self == Status::Active
+    }
+
+    pub fn can_transition_to(&self, next: Status) -> bool {
+        use Status::*;
+
+        matches!(
+            (self, next),
+            (PendingVerification, Active | Disabled | Deleted)
+                | (Active, Disabled | Locked | Deleted)
+                | (Disabled, Active | Deleted)
+                | (Locked, Active | Disabled | Deleted)
+        )
+    }
+}
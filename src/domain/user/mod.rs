@@ -12,11 +12,32 @@ pub enum UserError {
 
     #[error("invalid password: {0}")]
     InvalidPassword(String),
+
+    #[error("invalid phone number: {0}")]
+    InvalidPhone(String),
+
+    #[error("invalid status: {0}")]
+    InvalidStatus(String),
+
+    #[error("invalid metadata: {0}")]
+    InvalidMetadata(String),
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct User {
     pub name: field::Name,
     pub email: field::Email,
+    #[serde(skip_serializing)]
     pub password: field::Password,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<field::Phone>,
+    pub status: field::Status,
+    /// Data the user may change themselves, e.g. locale or timezone.
+    #[serde(default)]
+    pub user_metadata: field::Metadata,
+    /// Data only administrators may change.
+    #[serde(default)]
+    pub app_metadata: field::Metadata,
+    #[serde(default)]
+    pub roles: Vec<crate::domain::role::RoleName>,
 }
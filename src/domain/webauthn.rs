@@ -0,0 +1,273 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ciborium::value::Value;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebAuthnError {
+    #[error("malformed {0}")]
+    Malformed(&'static str),
+    #[error("client data is for another ceremony or origin")]
+    ClientData,
+    #[error("authenticator data is for another relying party")]
+    RelyingParty,
+    #[error("user presence was not confirmed")]
+    UserPresence,
+    #[error("unsupported public key, only ES256 is accepted")]
+    UnsupportedKey,
+    #[error("challenge is unknown, expired or already used")]
+    Challenge,
+    #[error("unknown credential")]
+    UnknownCredential,
+    #[error("invalid signature")]
+    Signature,
+    #[error("signature counter did not increase, the authenticator may be cloned")]
+    Counter,
+}
+
+/// The site credentials are scoped to, checked in every ceremony.
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    /// Domain credentials are bound to.
+    pub id: String,
+    pub name: String,
+    /// Origin client data must come from, e.g. `https://auth.example.com`.
+    pub origin: String,
+}
+
+impl RelyingParty {
+    /// Derives the relying party from the public URL of the server: its host becomes the
+    /// RP ID and its scheme, host and port the expected origin.
+    pub fn from_url(url: &str, name: &str) -> Result<Self, WebAuthnError> {
+        let url = url::Url::parse(url).map_err(|_| WebAuthnError::Malformed("public URL"))?;
+        let id = url
+            .host_str()
+            .ok_or(WebAuthnError::Malformed("public URL"))?
+            .to_string();
+
+        Ok(Self {
+            id,
+            name: name.to_string(),
+            origin: url.origin().ascii_serialization(),
+        })
+    }
+}
+
+/// Decodes the unpadded base64url encoding WebAuthn uses for binary values.
+pub fn decode(value: &str, what: &'static str) -> Result<Vec<u8>, WebAuthnError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| WebAuthnError::Malformed(what))
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// `clientDataJSON` as collected by the browser.
+#[derive(Debug, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub challenge: String,
+    pub origin: String,
+}
+
+impl ClientData {
+    pub const CREATE: &'static str = "webauthn.create";
+    pub const GET: &'static str = "webauthn.get";
+
+    pub fn parse(raw: &[u8]) -> Result<Self, WebAuthnError> {
+        serde_json::from_slice(raw).map_err(|_| WebAuthnError::Malformed("client data"))
+    }
+
+    /// Checks that the data was collected for a `kind` ceremony on the relying party's
+    /// origin. The challenge is checked by whoever issued it.
+    pub fn verify(&self, kind: &str, rp: &RelyingParty) -> Result<(), WebAuthnError> {
+        match self.kind == kind && self.origin == rp.origin {
+            true => Ok(()),
+            false => Err(WebAuthnError::ClientData),
+        }
+    }
+}
+
+/// Credential created by a registration ceremony.
+#[derive(Debug)]
+pub struct AttestedCredential {
+    pub id: Vec<u8>,
+    pub public_key: PublicKey,
+}
+
+/// Authenticator data as signed by the authenticator.
+#[derive(Debug)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    /// Only present in registration responses.
+    pub credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    const USER_PRESENT: u8 = 0x01;
+    const USER_VERIFIED: u8 = 0x04;
+    const ATTESTED_CREDENTIAL: u8 = 0x40;
+
+    pub fn parse(raw: &[u8]) -> Result<Self, WebAuthnError> {
+        let malformed = WebAuthnError::Malformed("authenticator data");
+        if raw.len() < 37 {
+            return Err(malformed);
+        }
+        let flags = raw[32];
+        let sign_count = u32::from_be_bytes([raw[33], raw[34], raw[35], raw[36]]);
+
+        let credential = match flags & Self::ATTESTED_CREDENTIAL {
+            0 => None,
+            _ => {
+                // AAGUID (16 bytes), then a 2-byte length and the credential id
+                let rest = raw.get(37 + 16..).ok_or(malformed)?;
+                let len = match rest {
+                    [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+                    _ => return Err(WebAuthnError::Malformed("authenticator data")),
+                };
+                let id = rest
+                    .get(2..2 + len)
+                    .ok_or(WebAuthnError::Malformed("authenticator data"))?
+                    .to_vec();
+                let key: Value = ciborium::de::from_reader(&rest[2 + len..])
+                    .map_err(|_| WebAuthnError::Malformed("credential public key"))?;
+
+                Some(AttestedCredential {
+                    id,
+                    public_key: PublicKey::from_cose(&key)?,
+                })
+            }
+        };
+
+        Ok(Self {
+            rp_id_hash: raw[..32].to_vec(),
+            flags,
+            sign_count,
+            credential,
+        })
+    }
+
+    pub fn user_verified(&self) -> bool {
+        self.flags & Self::USER_VERIFIED != 0
+    }
+
+    /// Checks the data is scoped to the relying party and the user was present.
+    pub fn verify(&self, rp: &RelyingParty) -> Result<(), WebAuthnError> {
+        if self.rp_id_hash != Sha256::digest(rp.id.as_bytes()).as_slice() {
+            return Err(WebAuthnError::RelyingParty);
+        }
+        match self.flags & Self::USER_PRESENT {
+            0 => Err(WebAuthnError::UserPresence),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Extracts the authenticator data from an attestation object.
+///
+/// Attestation statements are not verified: registration asks for `none` attestation, so
+/// the authenticator model is not vouched for either way.
+pub fn attested_auth_data(attestation_object: &[u8]) -> Result<Vec<u8>, WebAuthnError> {
+    let malformed = || WebAuthnError::Malformed("attestation object");
+    let object: Value = ciborium::de::from_reader(attestation_object).map_err(|_| malformed())?;
+
+    object
+        .as_map()
+        .and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some("authData"))
+        })
+        .and_then(|(_, value)| value.as_bytes())
+        .cloned()
+        .ok_or_else(malformed)
+}
+
+/// ES256 (ECDSA P-256 with SHA-256) public key, kept as an uncompressed SEC1 point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey(Vec<u8>);
+
+impl PublicKey {
+    /// COSE algorithm identifier for ES256.
+    pub const ES256: i64 = -7;
+
+    pub fn from_cose(key: &Value) -> Result<Self, WebAuthnError> {
+        let entries = key.as_map().ok_or(WebAuthnError::UnsupportedKey)?;
+        let get = |label: i128| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_integer().map(i128::from) == Some(label))
+                .map(|(_, value)| value)
+        };
+        let int = |label| get(label).and_then(Value::as_integer).map(i128::from);
+        let bytes = |label| get(label).and_then(Value::as_bytes);
+
+        // kty EC2, alg ES256, crv P-256
+        if int(1) != Some(2) || int(3) != Some(Self::ES256 as i128) || int(-1) != Some(1) {
+            return Err(WebAuthnError::UnsupportedKey);
+        }
+        match (bytes(-2), bytes(-3)) {
+            (Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => {
+                Ok(Self([&[0x04], x.as_slice(), y.as_slice()].concat()))
+            }
+            _ => Err(WebAuthnError::UnsupportedKey),
+        }
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Checks an assertion signature, made over the authenticator data followed by the
+    /// SHA-256 hash of the client data.
+    pub fn verify(
+        &self,
+        auth_data: &[u8],
+        client_data: &[u8],
+        signature: &[u8],
+    ) -> Result<(), WebAuthnError> {
+        let message = [auth_data, Sha256::digest(client_data).as_slice()].concat();
+        ring::signature::UnparsedPublicKey::new(&ring::signature::ECDSA_P256_SHA256_ASN1, &self.0)
+            .verify(&message, signature)
+            .map_err(|_| WebAuthnError::Signature)
+    }
+}
+
+/// What a challenge was issued for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Ceremony {
+    Register,
+    /// Passkey sign-in, optionally for a given user.
+    Authenticate,
+    /// Passkey assertion completing a password sign-in.
+    SecondFactor,
+    /// Passkey assertion completing a sign-in through an identity provider.
+    FederatedSecondFactor,
+    /// Passkey assertion completing a sign-in with an emailed link.
+    MagicLinkSecondFactor,
+    /// Passkey assertion completing a sign-in with a one-time code.
+    OtpSecondFactor,
+}
+
+/// A passkey registered to a user.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Credential {
+    /// Base64url credential id.
+    pub id: String,
+    pub name: Option<String>,
+    /// Transport hints reported by the browser, e.g. `usb` or `internal`.
+    pub transports: Vec<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
@@ -7,18 +7,88 @@ use data::AppDatabase;
 pub use data::{DataError, DatabasePool};
 pub use domain::user::field::Email;
 pub use domain::user::{User, UserError};
+pub use domain::webauthn::RelyingParty;
+use rocket::fairing::AdHoc;
 use rocket::{Build, Rocket};
+pub use service::ldap::LdapServer;
+pub use service::mailer::{Links, Mailer};
+pub use service::maintenance::Maintenance;
+pub use service::sender::{LocalSender, MessageSender};
 pub use service::ServiceError;
+pub use web::oauth::SessionCookie;
 
 pub fn rocket(config: RocketConfig) -> Rocket<Build> {
     rocket::build()
         .manage::<AppDatabase>(config.database)
-        // .manage::<Maintenance>(config.maintenance)
+        .manage::<Maintenance>(config.maintenance)
+        .manage::<Mailer>(config.mailer)
+        .manage::<Links>(config.links)
+        .manage::<Box<dyn MessageSender>>(config.sender)
+        .manage::<RelyingParty>(config.relying_party)
+        .manage::<SessionCookie>(config.session_cookie)
+        .manage::<Option<LdapServer>>(config.ldap)
+        .attach(AdHoc::on_liftoff("Maintenance", |rocket| {
+            Box::pin(async move {
+                let pool = rocket
+                    .state::<AppDatabase>()
+                    .map(|db| db.get_pool().clone());
+                let maintenance = rocket.state::<Maintenance>().copied();
+                if let (Some(pool), Some(maintenance)) = (pool, maintenance) {
+                    maintenance.spawn(pool);
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("LDAP", |rocket| {
+            Box::pin(async move {
+                let pool = rocket
+                    .state::<AppDatabase>()
+                    .map(|db| db.get_pool().clone());
+                let ldap = rocket.state::<Option<LdapServer>>().cloned().flatten();
+                let mailer = rocket.state::<Mailer>().cloned();
+                let links = rocket.state::<Links>().cloned();
+                if let (Some(pool), Some(ldap), Some(mailer), Some(links)) =
+                    (pool, ldap, mailer, links)
+                {
+                    ldap.spawn(pool, mailer, links);
+                }
+            })
+        }))
+        // `/api/t/<tenant>/...`, `/oauth/t/<tenant>/...`, `/auth/t/<tenant>/...` and
+        // `/scim/t/<tenant>/...` are served by the routes below, scoped to that tenant
+        .attach(AdHoc::on_request("Tenant", |req, _| {
+            Box::pin(async move { web::guard::route_tenant(req) })
+        }))
         .mount("/api/user", web::api::routes())
-        .register("/api/user", web::api::catcher::catchers())
+        .mount("/api/user/webauthn", web::webauthn::routes())
+        .mount("/api/user/identities", web::identity::routes())
+        .mount("/api/users", web::api::list_routes())
+        .mount("/api/roles", web::role::routes())
+        .mount("/api/groups", web::group::routes())
+        .mount("/api/tenants", web::tenant::routes())
+        .mount("/api/invitations", web::invitation::routes())
+        .mount("/api/audit", web::audit::routes())
+        .mount("/api/clients", web::client::routes())
+        .mount("/api/providers", web::provider::routes())
+        .mount("/api/access-rules", web::access::routes())
+        .mount("/oauth", web::oauth::routes())
+        .mount("/", web::oauth::discovery_routes())
+        .mount("/auth", web::forward::routes())
+        .mount("/scim/v2", web::scim::routes())
+        .register("/api", web::api::catcher::catchers())
+        .register("/scim", web::scim::catcher::catchers())
 }
 
 pub struct RocketConfig {
     pub database: AppDatabase,
-    // pub maintenance: Maintenance,
+    pub maintenance: Maintenance,
+    pub mailer: Mailer,
+    pub links: Links,
+    /// Delivers one-time codes by email and SMS.
+    pub sender: Box<dyn MessageSender>,
+    /// Site passkeys are registered for.
+    pub relying_party: RelyingParty,
+    /// Hosts the sign-in session is shared with.
+    pub session_cookie: SessionCookie,
+    /// LDAP listener to start along with the server, if any.
+    pub ldap: Option<LdapServer>,
 }
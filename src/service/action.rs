@@ -1,38 +1,4437 @@
 use super::ask;
-use crate::data::{query, DatabasePool};
+use super::audit;
+use super::mailer::{Links, Mail, Mailer};
+use super::sender::{Message, MessageSender, Recipient};
+use crate::data::{model, query, DatabasePool};
+use crate::domain::access::{self, AccessError, AccessRule};
+use crate::domain::audit::{AuditAction, AuditEvent, AuditOutcome};
+use crate::domain::federation::{
+    self, ExternalIdToken, FederatedLogin, FederationError, IdentityProvider, JsonWebKeySet,
+    ProviderMetadata,
+};
+use crate::domain::group::{Group, GroupError, GroupMember, GroupMembers, GroupName};
+use crate::domain::identity::{Identity, IdentityError};
+use crate::domain::invitation::Invitation;
+use crate::domain::login::Login;
+use crate::domain::oauth::{self, Client, ClientType, OAuthError, Scope, TokenKind};
+use crate::domain::oidc::{self, IdToken, SigningKey, UserInfo};
+use crate::domain::otp::{Channel, OneTimeCode};
+use crate::domain::role::{Permission, PermissionName, Principal, Role, RoleName};
+use crate::domain::scim::{
+    self, Filter, ListResponse, Member, Meta, MultiValued, PatchRequest, ScimError, ScimGroup,
+    ScimName, ScimUser,
+};
+use crate::domain::session::Session;
+use crate::domain::tenant::{Tenant, TenantId};
+use crate::domain::token::Token;
+use crate::domain::user::field::{Metadata, Name, Password, Phone, Status};
+use crate::domain::webauthn::{
+    self, AuthenticatorData, Ceremony, ClientData, Credential, PublicKey, RelyingParty,
+    WebAuthnError,
+};
 use crate::web::api::ApiKey;
 // use crate::domain::user;
-use crate::{ServiceError, User};
+use crate::{Email, ServiceError, User};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::convert::TryInto;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub async fn new_user(req: ask::NewUser, pool: &DatabasePool) -> Result<User, ServiceError> {
-    let user = query::new_user(req, pool).await?;
-    Ok(user.try_into()?)
+pub async fn new_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::NewUser,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let result = async { Ok(query::new_user(tenant, req, pool).await?.try_into()?) }.await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::UserCreate,
+        Some(target),
+        json!({}),
+        result,
+        pool,
+    )
+    .await
 }
 
-pub async fn get_user(req: ask::GetUser, pool: &DatabasePool) -> Result<User, ServiceError> {
+pub async fn get_user(
+    tenant: &TenantId,
+    req: ask::GetUser,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
     let _user_password = req.password.clone();
-    let user: User = query::get_user(req, pool).await?.try_into()?;
+    let user: User = query::get_user(tenant, req, pool).await?.try_into()?;
     Ok(user)
 }
 
-pub async fn update_user(req: ask::UpdateUser, pool: &DatabasePool) -> Result<User, ServiceError> {
-    let user = query::update_user(req, pool).await?;
-    Ok(user.try_into()?)
+/// Checks the credentials in `req` and that the account is allowed to sign in.
+///
+/// Attempts on existing accounts are added to the login history; a successful sign-in
+/// from a device the user has not used before is reported to them by mail.
+pub async fn authenticate(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::GetUser,
+    mailer: &Mailer,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let password = req.password.clone();
+    let result = async {
+        let user = get_user(tenant, req, pool).await?;
+
+        let checked = match check_password(tenant, &user, password, pool).await {
+            Ok(()) if query::has_webauthn_credentials(tenant, &target, pool).await? => Err(
+                ServiceError::Forbidden("passkey required as second factor".to_string()),
+            ),
+            checked => checked,
+        };
+        record_sign_in(tenant, ctx, &user, checked, mailer, links, pool).await?;
+
+        Ok(user)
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::Login,
+        Some(target),
+        json!({ "method": "password", "device_id": ctx.device_id.clone() }),
+        result,
+        pool,
+    )
+    .await
 }
 
-pub async fn generate_api_key(pool: &DatabasePool) -> Result<ApiKey, ServiceError> {
-    let api_key = ApiKey::default();
-    Ok(query::save_api_key(api_key, pool).await?)
+async fn check_password(
+    tenant: &TenantId,
+    user: &User,
+    password: Option<Password>,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    match password {
+        Some(password) if password == user.password => sign_in_allowed(tenant, user, pool).await,
+        _ => Err(ServiceError::InvalidDetail),
+    }
+}
+
+/// Checks that a user whose identity has been proven may actually sign in.
+async fn sign_in_allowed(
+    tenant: &TenantId,
+    user: &User,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let email = user.email.clone().into_inner();
+    if query::password_reset_required(tenant, &email, pool).await? {
+        return Err(ServiceError::Forbidden(
+            "password reset required".to_string(),
+        ));
+    }
+
+    match user.status.can_sign_in() {
+        true => Ok(()),
+        false => Err(ServiceError::AccountStatus(user.status)),
+    }
+}
+
+/// Adds a sign-in attempt to the user's login history and passes on its outcome, warning
+/// the user when a successful one comes from a new device.
+async fn record_sign_in(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    user: &User,
+    checked: Result<(), ServiceError>,
+    mailer: &Mailer,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let login = model::NewLogin::new(ctx, &user.email, checked.is_ok());
+    let recorded = query::record_login(tenant, login, pool).await?;
+    checked?;
+
+    if recorded.new_device && recorded.known_devices > 0 {
+        // the sign-in itself succeeded; a lost notification is only logged
+        if let Err(e) = notify_new_device(tenant, ctx, user, recorded.id, mailer, links, pool).await
+        {
+            eprintln!("new device notification failed: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// How long a "this wasn't me" link stays valid, as an SQLite datetime modifier.
+const LOGIN_ALERT_TTL: &str = "+7 days";
+
+async fn notify_new_device(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    user: &User,
+    login_id: i64,
+    mailer: &Mailer,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let token = Token::generate();
+    let email = user.email.clone().into_inner();
+    query::save_login_alert(
+        tenant,
+        &token.hash(),
+        &email,
+        login_id,
+        LOGIN_ALERT_TTL,
+        pool,
+    )
+    .await?;
+
+    let unknown = "unknown".to_string();
+    mailer.send(&Mail {
+        to: user.email.clone(),
+        subject: "New sign-in to your account".to_string(),
+        body: format!(
+            "Your account was just signed in to from a new device.\n\n\
+            IP address: {}\n\
+            Browser: {}\n\n\
+            If this wasn't you, open the link below. It signs you out everywhere and \
+            requires a new password before the next sign-in:\n\n{}",
+            ctx.ip.as_ref().unwrap_or(&unknown),
+            ctx.user_agent.as_ref().unwrap_or(&unknown),
+            links.api(tenant, &format!("/user/not-me?token={token}"))
+        ),
+    })
+}
+
+/// Handles a "this wasn't me" link from a new-device notification.
+pub async fn report_login(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    token: Token,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let (target, result) = match query::report_login(tenant, &token.hash(), pool).await {
+        Ok(Some(email)) => (Some(email), Ok(())),
+        Ok(None) => (
+            None,
+            Err(ServiceError::InvalidRequest(
+                "link is invalid, expired or already used".to_string(),
+            )),
+        ),
+        Err(e) => (None, Err(e.into())),
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::LoginReported,
+        target,
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+/// The most recent sign-in attempts on `email`'s account, newest first.
+pub async fn login_history(
+    tenant: &TenantId,
+    email: Email,
+    pool: &DatabasePool,
+) -> Result<Vec<Login>, ServiceError> {
+    Ok(query::login_history(tenant, &email.into_inner(), 100, pool)
+        .await?
+        .into_iter()
+        .map(Login::from)
+        .collect())
+}
+
+pub async fn list_users(
+    tenant: &TenantId,
+    req: ask::ListUsers,
+    pool: &DatabasePool,
+) -> Result<ask::UserPage, ServiceError> {
+    let page = query::list_users(tenant, req, pool).await?;
+
+    Ok(ask::UserPage {
+        users: page
+            .users
+            .into_iter()
+            .map(|user| User::try_from(user).map(ask::ListedUser::from))
+            .collect::<Result<_, _>>()?,
+        next_cursor: page.next_cursor,
+    })
+}
+
+pub async fn update_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    mut req: ask::UpdateUser,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let detail = json!({
+        "name": req.name.is_some(),
+        "password": req.password.is_some(),
+        "phone": req.phone.is_some(),
+        "user_metadata": req.user_metadata.is_some(),
+    });
+    let result = async {
+        if let Some(patch) = req.user_metadata.take() {
+            query::merge_metadata(
+                tenant,
+                &req.email.clone().into_inner(),
+                query::MetadataKind::User,
+                |current| {
+                    Ok::<_, ServiceError>(Metadata::new(current)?.merge_patch(&patch)?.to_json())
+                },
+                pool,
+            )
+            .await?;
+        }
+
+        Ok(query::update_user(tenant, req, pool).await?.try_into()?)
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::UserUpdate,
+        Some(target),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn update_app_metadata(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::UpdateAppMetadata,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let detail = json!({ "patch": req.app_metadata.clone() });
+    let result = async {
+        let user = query::merge_metadata(
+            tenant,
+            &req.email.clone().into_inner(),
+            query::MetadataKind::App,
+            |current| {
+                Ok::<_, ServiceError>(
+                    Metadata::new(current)?
+                        .merge_patch(&req.app_metadata)?
+                        .to_json(),
+                )
+            },
+            pool,
+        )
+        .await?;
+        Ok(user.try_into()?)
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::UserAppMetadata,
+        Some(target),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn delete_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::DeleteUser,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let detail = json!({ "permanent": req.permanent });
+    let result = match req.permanent {
+        true => query::delete_user(tenant, req, pool).await,
+        false => query::deactivate_user(tenant, req, pool).await,
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::UserDelete,
+        Some(target),
+        detail,
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+pub async fn set_user_status(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    req: ask::UpdateStatus,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let detail = json!({ "status": req.status, "reason": req.reason.clone() });
+    let result = async {
+        let email = req.email.clone();
+        let user = get_user(
+            tenant,
+            ask::GetUser {
+                email: email.clone(),
+                password: None,
+            },
+            pool,
+        )
+        .await?;
+
+        if !user.status.can_transition_to(req.status) {
+            return Err(ServiceError::InvalidTransition(user.status, req.status));
+        }
+
+        let to = req.status;
+        let change = model::UpdateStatus::new(req, user.status, actor.name());
+        match query::set_user_status(tenant, change, pool).await? {
+            query::StatusChange::Applied => (),
+            query::StatusChange::Conflict => {
+                return Err(ServiceError::InvalidTransition(user.status, to))
+            }
+        }
+
+        match to {
+            Status::Deleted => Ok(User { status: to, ..user }),
+            _ => {
+                get_user(
+                    tenant,
+                    ask::GetUser {
+                        email,
+                        password: None,
+                    },
+                    pool,
+                )
+                .await
+            }
+        }
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::UserStatus,
+        Some(target),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn purge_deleted_users(
+    retention: Duration,
+    pool: &DatabasePool,
+) -> Result<u64, ServiceError> {
+    Ok(query::purge_deleted_users(retention.as_secs() as i64, pool).await?)
+}
+
+pub async fn list_roles(tenant: &TenantId, pool: &DatabasePool) -> Result<Vec<Role>, ServiceError> {
+    query::list_roles(tenant, pool)
+        .await?
+        .into_iter()
+        .map(|role| Ok(role.try_into()?))
+        .collect()
+}
+
+pub async fn save_role(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    role: Role,
+    pool: &DatabasePool,
+) -> Result<Role, ServiceError> {
+    let target = role.name.clone().into_inner();
+    let detail = json!({ "permissions": role.permissions.clone() });
+    let result = async { Ok(query::save_role(tenant, role, pool).await?.try_into()?) }.await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::RoleSave,
+        Some(target),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn delete_role(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    name: RoleName,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let target = name.clone().into_inner();
+    let result = query::delete_role(tenant, &name.into_inner(), pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::RoleDelete,
+        Some(target),
+        json!({}),
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+pub async fn list_permissions(pool: &DatabasePool) -> Result<Vec<Permission>, ServiceError> {
+    query::list_permissions(pool)
+        .await?
+        .into_iter()
+        .map(|permission| Ok(permission.try_into()?))
+        .collect()
+}
+
+/// Permissions are shared by every tenant, so changes are recorded in the default tenant.
+pub async fn save_permission(
+    ctx: &audit::Context,
+    permission: Permission,
+    pool: &DatabasePool,
+) -> Result<Permission, ServiceError> {
+    let target = permission.name.as_str().to_string();
+    let result = async { Ok(query::save_permission(permission, pool).await?.try_into()?) }.await;
+
+    audit::record(
+        &TenantId::default(),
+        ctx,
+        AuditAction::PermissionSave,
+        Some(target),
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn grant_role(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::RoleGrant,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let email = req.email.clone();
+    let detail = json!({ "role": req.role.clone() });
+    let result = async {
+        query::grant_role(tenant, req, pool).await?;
+        get_user(
+            tenant,
+            ask::GetUser {
+                email: email.clone(),
+                password: None,
+            },
+            pool,
+        )
+        .await
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::RoleGrant,
+        Some(email.into_inner()),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn revoke_role(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::RoleGrant,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let detail = json!({ "role": req.role.clone() });
+    let result = query::revoke_role(tenant, req, pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::RoleRevoke,
+        Some(target),
+        detail,
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+pub async fn list_groups(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<Group>, ServiceError> {
+    query::list_groups(tenant, pool)
+        .await?
+        .into_iter()
+        .map(|group| Ok(group.try_into()?))
+        .collect()
+}
+
+pub async fn save_group(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    group: Group,
+    pool: &DatabasePool,
+) -> Result<Group, ServiceError> {
+    let target = group.name.to_string();
+    let result = async { Ok(query::save_group(tenant, group, pool).await?.try_into()?) }.await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::GroupSave,
+        Some(target),
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn delete_group(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    name: GroupName,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let target = name.to_string();
+    let result = query::delete_group(tenant, &name.into_inner(), pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::GroupDelete,
+        Some(target),
+        json!({}),
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+pub async fn group_members(
+    tenant: &TenantId,
+    name: GroupName,
+    pool: &DatabasePool,
+) -> Result<GroupMembers, ServiceError> {
+    let (users, groups) = query::group_members(tenant, &name.into_inner(), pool).await?;
+
+    Ok(GroupMembers {
+        users: users
+            .iter()
+            .map(|email| Email::new(email))
+            .collect::<Result<_, _>>()?,
+        groups: groups
+            .iter()
+            .map(|group| GroupName::new(group))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+pub async fn add_group_member(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::GroupMembership,
+    pool: &DatabasePool,
+) -> Result<GroupMembers, ServiceError> {
+    let group = req.group.clone();
+    let member = req.member.clone();
+    let detail = json!({ "member": member.clone() });
+
+    let result = match (query::add_group_member(tenant, req, pool).await, member) {
+        (Err(e), _) => Err(e.into()),
+        (Ok(query::MembershipChange::WouldCycle), GroupMember::Group(child)) => {
+            Err(GroupError::Cycle(group.clone(), child).into())
+        }
+        _ => group_members(tenant, group.clone(), pool).await,
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::GroupMemberAdd,
+        Some(group.to_string()),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn remove_group_member(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::GroupMembership,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let target = req.group.to_string();
+    let detail = json!({ "member": req.member.clone() });
+    let result = query::remove_group_member(tenant, req, pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::GroupMemberRemove,
+        Some(target),
+        detail,
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+/// Groups a user belongs to, directly or through nested groups.
+pub async fn user_groups(
+    tenant: &TenantId,
+    email: Email,
+    pool: &DatabasePool,
+) -> Result<Vec<GroupName>, ServiceError> {
+    Ok(query::user_groups(tenant, &email.into_inner(), pool)
+        .await?
+        .iter()
+        .map(|group| GroupName::new(group))
+        .collect::<Result<_, _>>()?)
+}
+
+/// Resolves the user (if any) an API key was issued to, along with their permissions.
+pub async fn principal(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<Principal, ServiceError> {
+    let (owner, impersonator) = match query::api_key_owner(tenant, api_key, pool).await? {
+        query::KeyOwner::User {
+            email,
+            impersonator,
+        } => (email, impersonator),
+        query::KeyOwner::Operator => return Ok(Principal::Operator),
+    };
+
+    let user = get_user(
+        tenant,
+        ask::GetUser {
+            email: owner.as_str().into(),
+            password: None,
+        },
+        pool,
+    )
+    .await?;
+    if !user.status.can_sign_in() {
+        return Err(ServiceError::AccountStatus(user.status));
+    }
+
+    let permissions = query::user_permissions(tenant, &owner, pool)
+        .await?
+        .iter()
+        .map(|permission| PermissionName::new(permission))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Principal::User {
+        email: user.email,
+        permissions,
+        impersonator: impersonator.as_deref().map(Email::from),
+    })
+}
+
+/// Resolves an access token, which services and command line tools use instead of API
+/// keys. The scopes of the token that name permissions are what it may do: a token from
+/// the client credentials grant acts for its client, any other for its user, who must
+/// still hold the permissions. Tokens granting no permissions, e.g. for OpenID Connect
+/// alone, are not accepted for users.
+pub async fn token_principal(
+    tenant: &TenantId,
+    access_token: &str,
+    pool: &DatabasePool,
+) -> Result<Principal, ServiceError> {
+    let grant = query::oauth_access_grant(tenant, &Token::from(access_token).hash(), pool)
+        .await?
+        .ok_or(OAuthError::InvalidToken)?;
+
+    let scope = Scope::parse(&grant.scope);
+    let permissions: Vec<PermissionName> = scope
+        .iter()
+        .filter_map(|scope| PermissionName::new(scope).ok())
+        .collect();
+
+    let email = match grant.email {
+        Some(email) => email,
+        None => {
+            return Ok(Principal::Client {
+                id: grant.client_id,
+                permissions,
+            })
+        }
+    };
+    if permissions.is_empty() {
+        return Err(OAuthError::InsufficientScope.into());
+    }
+    let user = active_user(tenant, &email, pool)
+        .await?
+        .ok_or(OAuthError::InvalidToken)?;
+    let held = query::user_permissions(tenant, &email, pool).await?;
+
+    Ok(Principal::User {
+        email: user.email,
+        permissions: permissions
+            .into_iter()
+            .filter(|permission| held.iter().any(|held| held == permission.as_str()))
+            .collect(),
+        impersonator: None,
+    })
+}
+
+/// Signs a user in and issues an API key that acts with their permissions.
+pub async fn issue_api_key(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::GetUser,
+    mailer: &Mailer,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<ask::IssuedKey, ServiceError> {
+    let user = authenticate(tenant, ctx, req, mailer, links, pool).await?;
+
+    start_session(tenant, ctx, user, pool).await
+}
+
+/// Issues an API key to a user who has just signed in.
+async fn start_session(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    user: User,
+    pool: &DatabasePool,
+) -> Result<ask::IssuedKey, ServiceError> {
+    let email = user.email.clone().into_inner();
+    let result = async {
+        let api_key = ApiKey::default();
+        let session = model::NewSession::new(api_key.clone(), ctx, &user.email);
+        let session = query::save_user_api_key(tenant, session, pool).await?;
+        let groups = user_groups(tenant, user.email.clone(), pool).await?;
+
+        Ok(ask::IssuedKey {
+            api_key: api_key.to_base64(),
+            email: user.email,
+            roles: user.roles,
+            groups,
+            session: Some(session),
+            impersonator: None,
+        })
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::ApiKeyIssue,
+        Some(email),
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+/// How long an emailed sign-in link stays valid, as an SQLite datetime modifier.
+const MAGIC_LINK_TTL: &str = "+15 minutes";
+
+/// Emails a sign-in link to `req.email` if it belongs to an account that may sign in.
+///
+/// Returns the nonce that binds the link to the requesting browser. One is returned whether
+/// or not a link was sent, so the response does not reveal which addresses have accounts.
+pub async fn request_magic_link(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::MagicLinkRequest,
+    mailer: &Mailer,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<Token, ServiceError> {
+    let nonce = Token::generate();
+    let target = req.email.clone().into_inner();
+    let result = async {
+        let user = match get_user(
+            tenant,
+            ask::GetUser {
+                email: req.email,
+                password: None,
+            },
+            pool,
+        )
+        .await
+        {
+            Ok(user) => user,
+            Err(ServiceError::InvalidDetail) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if sign_in_allowed(tenant, &user, pool).await.is_err() {
+            return Ok(false);
+        }
+
+        let token = Token::generate();
+        query::save_magic_link(
+            tenant,
+            &token.hash(),
+            &target,
+            &nonce.hash(),
+            MAGIC_LINK_TTL,
+            pool,
+        )
+        .await?;
+
+        mailer.send(&Mail {
+            to: user.email,
+            subject: "Your sign-in link".to_string(),
+            body: format!(
+                "Open the link below to sign in. It expires in 15 minutes, works once and \
+                only in the browser it was requested from:\n\n{}",
+                links.api(tenant, &format!("/user/magic-link?token={token}"))
+            ),
+        })?;
+
+        Ok(true)
+    }
+    .await;
+    let detail = match &result {
+        Ok(sent) => json!({ "sent": sent }),
+        Err(_) => json!({}),
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::MagicLinkRequest,
+        Some(target),
+        detail,
+        result.map(|_| nonce),
+        pool,
+    )
+    .await
+}
+
+/// Signs a user in with an emailed link. `nonce` is the one handed out when the link was
+/// requested; without it the link is refused and stays usable.
+///
+/// Users with passkeys are asked for one instead of getting a session.
+#[allow(clippy::too_many_arguments)]
+pub async fn consume_magic_link(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    token: Token,
+    nonce: Option<Token>,
+    mailer: &Mailer,
+    links: &Links,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::SignIn, ServiceError> {
+    let mut second_factor = None;
+    let result = async {
+        let invalid =
+            || ServiceError::InvalidRequest("link is invalid, expired or already used".to_string());
+        let nonce = nonce.ok_or_else(invalid)?;
+        let email = query::consume_magic_link(tenant, &token.hash(), &nonce.hash(), pool)
+            .await?
+            .ok_or_else(invalid)?;
+
+        let user = get_user(
+            tenant,
+            ask::GetUser {
+                email: email.as_str().into(),
+                password: None,
+            },
+            pool,
+        )
+        .await?;
+        let checked = match sign_in_allowed(tenant, &user, pool).await {
+            Ok(()) if query::has_webauthn_credentials(tenant, &email, pool).await? => {
+                second_factor = Some(email);
+                Err(ServiceError::Forbidden(
+                    "passkey required as second factor".to_string(),
+                ))
+            }
+            checked => checked,
+        };
+        record_sign_in(tenant, ctx, &user, checked, mailer, links, pool).await?;
+
+        Ok(user)
+    }
+    .await;
+    let target = result
+        .as_ref()
+        .ok()
+        .map(|user| user.email.clone().into_inner())
+        .or_else(|| second_factor.clone());
+
+    let user = audit::record(
+        tenant,
+        ctx,
+        AuditAction::Login,
+        target,
+        json!({ "method": "magic_link", "device_id": ctx.device_id.clone() }),
+        result,
+        pool,
+    )
+    .await;
+
+    finish_first_factor(
+        tenant,
+        ctx,
+        user,
+        second_factor,
+        Ceremony::MagicLinkSecondFactor,
+        rp,
+        pool,
+    )
+    .await
+}
+
+/// Starts a session for a user who signed in with a first factor alone or, when they were
+/// refused for having passkeys, asks for one of them.
+async fn finish_first_factor(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    user: Result<User, ServiceError>,
+    second_factor: Option<String>,
+    ceremony: Ceremony,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::SignIn, ServiceError> {
+    let user = match (user, second_factor) {
+        (Err(ServiceError::Forbidden(_)), Some(email)) => {
+            let options = passkey_challenge(tenant, Some(email), ceremony, rp, pool).await?;
+            return Ok(ask::SignIn::PasskeyRequired {
+                passkey_required: options,
+            });
+        }
+        (user, _) => user?,
+    };
+
+    start_session(tenant, ctx, user, pool)
+        .await
+        .map(ask::SignIn::SignedIn)
+}
+
+/// How long a one-time code stays valid, as an SQLite datetime modifier.
+const OTP_TTL: &str = "+10 minutes";
+
+/// How far back failed attempts and requested codes are counted, as an SQLite datetime
+/// modifier.
+const OTP_WINDOW: &str = "-1 hour";
+
+/// Wrong codes a user may enter within [`OTP_WINDOW`], whichever codes they were sent.
+const OTP_MAX_ATTEMPTS: i64 = 5;
+
+/// Codes a user may be sent within [`OTP_WINDOW`].
+const OTP_MAX_REQUESTS: i64 = 5;
+
+/// Sends a one-time sign-in code if `req.email` belongs to an account that may sign in,
+/// has a phone number for SMS, and has not been sent [`OTP_MAX_REQUESTS`] codes within
+/// [`OTP_WINDOW`]. Whether a code was sent is only recorded in the audit log.
+pub async fn request_otp(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::OtpRequest,
+    sender: &dyn MessageSender,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let target = req.email.clone().into_inner();
+    let result = async {
+        let user = match get_user(
+            tenant,
+            ask::GetUser {
+                email: req.email,
+                password: None,
+            },
+            pool,
+        )
+        .await
+        {
+            Ok(user) => user,
+            Err(ServiceError::InvalidDetail) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if sign_in_allowed(tenant, &user, pool).await.is_err() {
+            return Ok(false);
+        }
+
+        let to = match (req.channel, user.phone) {
+            (Channel::Email, _) => Recipient::Email(user.email),
+            (Channel::Sms, Some(phone)) => Recipient::Sms(phone),
+            (Channel::Sms, None) => return Ok(false),
+        };
+
+        let code = OneTimeCode::generate();
+        let saved = query::save_otp_code(
+            tenant,
+            &target,
+            &req.channel.to_string(),
+            &code.hash(),
+            OTP_TTL,
+            OTP_WINDOW,
+            OTP_MAX_REQUESTS,
+            pool,
+        )
+        .await?;
+        if !saved {
+            return Ok(false);
+        }
+
+        sender.send(&Message {
+            to,
+            subject: "Your sign-in code".to_string(),
+            body: format!("Your sign-in code is {code}. It expires in 10 minutes."),
+        })?;
+
+        Ok(true)
+    }
+    .await;
+    let detail = match &result {
+        Ok(sent) => json!({ "channel": req.channel, "sent": sent }),
+        Err(_) => json!({ "channel": req.channel }),
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::OtpRequest,
+        Some(target),
+        detail,
+        result.map(|_| ()),
+        pool,
+    )
+    .await
+}
+
+/// Signs a user in with a one-time code sent by [`request_otp`]. Users with passkeys are
+/// asked for one instead of getting a session.
+pub async fn verify_otp(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::OtpLogin,
+    mailer: &Mailer,
+    links: &Links,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::SignIn, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let mut second_factor = None;
+    let result = async {
+        let user = get_user(
+            tenant,
+            ask::GetUser {
+                email: req.email,
+                password: None,
+            },
+            pool,
+        )
+        .await?;
+
+        let check = query::check_otp_code(
+            tenant,
+            &target,
+            &req.code.hash(),
+            OTP_MAX_ATTEMPTS,
+            OTP_WINDOW,
+            pool,
+        )
+        .await?;
+        let checked = match check {
+            query::OtpCheck::Verified => sign_in_allowed(tenant, &user, pool).await,
+            query::OtpCheck::Rejected => Err(ServiceError::InvalidDetail),
+        };
+        let checked = match checked {
+            Ok(()) if query::has_webauthn_credentials(tenant, &target, pool).await? => {
+                second_factor = Some(target.clone());
+                Err(ServiceError::Forbidden(
+                    "passkey required as second factor".to_string(),
+                ))
+            }
+            checked => checked,
+        };
+        record_sign_in(tenant, ctx, &user, checked, mailer, links, pool).await?;
+
+        Ok(user)
+    }
+    .await;
+
+    let user = audit::record(
+        tenant,
+        ctx,
+        AuditAction::Login,
+        Some(target.clone()),
+        json!({ "method": "otp", "device_id": ctx.device_id.clone() }),
+        result,
+        pool,
+    )
+    .await;
+
+    finish_first_factor(
+        tenant,
+        ctx,
+        user,
+        second_factor,
+        Ceremony::OtpSecondFactor,
+        rp,
+        pool,
+    )
+    .await
+}
+
+/// How long a passkey ceremony may take, as an SQLite datetime modifier.
+const PASSKEY_CHALLENGE_TTL: &str = "+5 minutes";
+
+/// [`PASSKEY_CHALLENGE_TTL`] in milliseconds, as passed to the browser.
+const PASSKEY_TIMEOUT_MS: u32 = 5 * 60 * 1000;
+
+fn credential_descriptors(credentials: Vec<model::Credential>) -> Vec<ask::CredentialDescriptor> {
+    credentials
+        .into_iter()
+        .map(Credential::from)
+        .map(|credential| ask::CredentialDescriptor {
+            kind: "public-key".to_string(),
+            id: credential.id,
+            transports: credential.transports,
+        })
+        .collect()
+}
+
+/// Begins registering a passkey for a signed-in user.
+pub async fn start_passkey_registration(
+    tenant: &TenantId,
+    email: Email,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::PasskeyCreationOptions, ServiceError> {
+    let user = get_user(
+        tenant,
+        ask::GetUser {
+            email,
+            password: None,
+        },
+        pool,
+    )
+    .await?;
+    let email = user.email.clone().into_inner();
+    let existing = query::list_webauthn_credentials(tenant, &email, pool).await?;
+
+    let challenge = Token::generate();
+    query::save_webauthn_challenge(
+        tenant,
+        challenge.as_str(),
+        Some(&email),
+        &Ceremony::Register.to_string(),
+        PASSKEY_CHALLENGE_TTL,
+        pool,
+    )
+    .await?;
+
+    let handle = Sha256::digest(format!("{tenant}/{email}").as_bytes());
+    Ok(ask::PasskeyCreationOptions {
+        challenge: challenge.to_string(),
+        rp: ask::RelyingPartyEntity {
+            id: rp.id.clone(),
+            name: rp.name.clone(),
+        },
+        user: ask::PasskeyUser {
+            id: webauthn::encode(&handle),
+            name: email,
+            display_name: user.name.into_inner(),
+        },
+        pub_key_cred_params: vec![ask::CredentialParameters {
+            kind: "public-key".to_string(),
+            alg: PublicKey::ES256,
+        }],
+        timeout: PASSKEY_TIMEOUT_MS,
+        exclude_credentials: credential_descriptors(existing),
+        authenticator_selection: ask::AuthenticatorSelection {
+            resident_key: "preferred".to_string(),
+            user_verification: "preferred".to_string(),
+        },
+        attestation: "none".to_string(),
+    })
+}
+
+/// Verifies the browser's answer to [`start_passkey_registration`] and stores the passkey.
+pub async fn finish_passkey_registration(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    email: Email,
+    req: ask::RegisterPasskey,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<Credential, ServiceError> {
+    let target = email.clone().into_inner();
+    let result = async {
+        let client_data = webauthn::decode(&req.response.client_data_json, "client data")?;
+        let client_data = ClientData::parse(&client_data)?;
+        client_data.verify(ClientData::CREATE, rp)?;
+
+        match query::consume_webauthn_challenge(tenant, &client_data.challenge, pool).await? {
+            Some(challenge)
+                if challenge.purpose == Ceremony::Register.to_string()
+                    && challenge.email.as_ref() == Some(&target) => {}
+            _ => return Err(WebAuthnError::Challenge.into()),
+        }
+
+        let attestation = webauthn::decode(&req.response.attestation_object, "attestation object")?;
+        let auth_data = AuthenticatorData::parse(&webauthn::attested_auth_data(&attestation)?)?;
+        auth_data.verify(rp)?;
+        let credential = auth_data
+            .credential
+            .as_ref()
+            .ok_or(WebAuthnError::Malformed("attested credential data"))?;
+        if webauthn::encode(&credential.id) != req.id.trim_end_matches('=') {
+            return Err(WebAuthnError::Malformed("credential id").into());
+        }
+
+        let model = model::NewCredential::new(
+            &email,
+            credential,
+            auth_data.sign_count,
+            &req.response.transports,
+            req.name,
+        );
+        query::save_webauthn_credential(tenant, model, pool).await?;
+
+        query::list_webauthn_credentials(tenant, &target, pool)
+            .await?
+            .into_iter()
+            .map(Credential::from)
+            .find(|stored| stored.id == webauthn::encode(&credential.id))
+            .ok_or(ServiceError::NotFound)
+    }
+    .await;
+    let detail = match &result {
+        Ok(credential) => json!({ "credential": credential.id }),
+        Err(_) => json!({}),
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::PasskeyRegister,
+        Some(target),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn list_passkeys(
+    tenant: &TenantId,
+    email: Email,
+    pool: &DatabasePool,
+) -> Result<Vec<Credential>, ServiceError> {
+    Ok(
+        query::list_webauthn_credentials(tenant, &email.into_inner(), pool)
+            .await?
+            .into_iter()
+            .map(Credential::from)
+            .collect(),
+    )
+}
+
+pub async fn delete_passkey(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    email: Email,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let email = email.into_inner();
+    let result = async {
+        let identity = match query::passkey_identity(tenant, &email, id, pool).await? {
+            Some(identity) => identity,
+            None => return Ok(query::DeletionStatus::NotFound),
+        };
+        // deleted through its identity, so that the last way to sign in is kept; there is
+        // no password to replace
+        match query::delete_identity(tenant, &email, &identity, "", pool).await? {
+            query::IdentityDeletion::Deleted(_) => Ok(query::DeletionStatus::Deleted),
+            query::IdentityDeletion::NotFound => Ok(query::DeletionStatus::NotFound),
+            query::IdentityDeletion::LastSignInMethod => {
+                Err(IdentityError::LastSignInMethod.into())
+            }
+        }
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::PasskeyDelete,
+        Some(email),
+        json!({ "credential": id }),
+        result,
+        pool,
+    )
+    .await
 }
 
+/// Begins a passkey sign-in; see [`ask::PasskeyLoginStart`] for the variants.
+///
+/// A wrong password fails here and is recorded like any failed sign-in. Unknown emails get
+/// options like any other, so they cannot be told apart from accounts without passkeys.
+#[allow(clippy::too_many_arguments)]
+pub async fn start_passkey_login(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::PasskeyLoginStart,
+    mailer: &Mailer,
+    links: &Links,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::PasskeyRequestOptions, ServiceError> {
+    let user = match &req.email {
+        Some(email) => match get_user(
+            tenant,
+            ask::GetUser {
+                email: email.clone(),
+                password: None,
+            },
+            pool,
+        )
+        .await
+        {
+            Ok(user) => Some(user),
+            Err(ServiceError::InvalidDetail) => None,
+            Err(e) => return Err(e),
+        },
+        None => None,
+    };
+
+    let ceremony = match req.password {
+        None => Ceremony::Authenticate,
+        Some(password) => {
+            let checked = match &user {
+                Some(user) => match check_password(tenant, user, Some(password), pool).await {
+                    Ok(()) => Ok(()),
+                    Err(e) => record_sign_in(tenant, ctx, user, Err(e), mailer, links, pool).await,
+                },
+                None => Err(ServiceError::InvalidDetail),
+            };
+            if checked.is_err() {
+                audit::record(
+                    tenant,
+                    ctx,
+                    AuditAction::Login,
+                    req.email.map(Email::into_inner),
+                    json!({ "method": "password+passkey", "device_id": ctx.device_id.clone() }),
+                    checked,
+                    pool,
+                )
+                .await?;
+            }
+            Ceremony::SecondFactor
+        }
+    };
+
+    let email = user.map(|user| user.email.into_inner());
+    passkey_challenge(tenant, email, ceremony, rp, pool).await
+}
+
+/// Issues a challenge for a passkey assertion, restricted to the passkeys of `email` if
+/// given.
+async fn passkey_challenge(
+    tenant: &TenantId,
+    email: Option<String>,
+    ceremony: Ceremony,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::PasskeyRequestOptions, ServiceError> {
+    let allowed = match &email {
+        Some(email) => query::list_webauthn_credentials(tenant, email, pool).await?,
+        None => vec![],
+    };
+
+    let challenge = Token::generate();
+    query::save_webauthn_challenge(
+        tenant,
+        challenge.as_str(),
+        email.as_deref(),
+        &ceremony.to_string(),
+        PASSKEY_CHALLENGE_TTL,
+        pool,
+    )
+    .await?;
+
+    Ok(ask::PasskeyRequestOptions {
+        challenge: challenge.to_string(),
+        timeout: PASSKEY_TIMEOUT_MS,
+        rp_id: rp.id.clone(),
+        allow_credentials: credential_descriptors(allowed),
+        user_verification: "preferred".to_string(),
+    })
+}
+
+/// Verifies the browser's answer to [`start_passkey_login`] and starts a session.
+#[allow(clippy::too_many_arguments)]
+pub async fn finish_passkey_login(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::PasskeyAssertion,
+    mailer: &Mailer,
+    links: &Links,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::IssuedKey, ServiceError> {
+    let mut target = None;
+    let mut method = "passkey";
+    let result = async {
+        let raw_client_data = webauthn::decode(&req.response.client_data_json, "client data")?;
+        let client_data = ClientData::parse(&raw_client_data)?;
+        client_data.verify(ClientData::GET, rp)?;
+
+        let challenge = query::consume_webauthn_challenge(tenant, &client_data.challenge, pool)
+            .await?
+            .ok_or(WebAuthnError::Challenge)?;
+        let id = req.id.trim_end_matches('=');
+        let credential = query::webauthn_credential(tenant, id, pool)
+            .await?
+            .ok_or(WebAuthnError::UnknownCredential)?;
+        target = Some(credential.email.clone());
+
+        let ceremony: Ceremony = challenge
+            .purpose
+            .parse()
+            .map_err(|_| WebAuthnError::Challenge)?;
+        method = match ceremony {
+            Ceremony::SecondFactor => "password+passkey",
+            Ceremony::FederatedSecondFactor => "federated+passkey",
+            Ceremony::MagicLinkSecondFactor => "magic_link+passkey",
+            Ceremony::OtpSecondFactor => "otp+passkey",
+            _ => "passkey",
+        };
+        let bound_elsewhere = challenge
+            .email
+            .as_ref()
+            .is_some_and(|email| email != &credential.email);
+        if ceremony == Ceremony::Register || bound_elsewhere {
+            return Err(WebAuthnError::Challenge.into());
+        }
+
+        let raw_auth_data =
+            webauthn::decode(&req.response.authenticator_data, "authenticator data")?;
+        let auth_data = AuthenticatorData::parse(&raw_auth_data)?;
+        auth_data.verify(rp)?;
+        PublicKey::from_bytes(credential.public_key).verify(
+            &raw_auth_data,
+            &raw_client_data,
+            &webauthn::decode(&req.response.signature, "signature")?,
+        )?;
+
+        // authenticators without a counter always report zero
+        let sign_count = i64::from(auth_data.sign_count);
+        if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+            return Err(WebAuthnError::Counter.into());
+        }
+        query::record_webauthn_use(tenant, id, sign_count, pool).await?;
+
+        let user = get_user(
+            tenant,
+            ask::GetUser {
+                email: credential.email.as_str().into(),
+                password: None,
+            },
+            pool,
+        )
+        .await?;
+        let checked = sign_in_allowed(tenant, &user, pool).await;
+        record_sign_in(tenant, ctx, &user, checked, mailer, links, pool).await?;
+
+        Ok(user)
+    }
+    .await;
+
+    let user = audit::record(
+        tenant,
+        ctx,
+        AuditAction::Login,
+        target,
+        json!({ "method": method, "device_id": ctx.device_id.clone() }),
+        result,
+        pool,
+    )
+    .await?;
+
+    start_session(tenant, ctx, user, pool).await
+}
+
+pub async fn generate_api_key(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    pool: &DatabasePool,
+) -> Result<ApiKey, ServiceError> {
+    let result = query::save_api_key(tenant, ApiKey::default(), pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::ApiKeyIssue,
+        None,
+        json!({ "operator": true }),
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+/// Revokes a key; revoking an impersonation key ends the impersonation.
 pub async fn revoke_api_key(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<query::RevocationStatus, ServiceError> {
+    let target = match query::api_key_owner(tenant, api_key.clone(), pool).await? {
+        query::KeyOwner::User {
+            impersonator: Some(_),
+            ..
+        } => return stop_impersonation(tenant, ctx, api_key, pool).await,
+        query::KeyOwner::User { email, .. } => Some(email),
+        query::KeyOwner::Operator => None,
+    };
+    let result = query::revoke_api_key(tenant, api_key, pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::ApiKeyRevoke,
+        target,
+        json!({}),
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+/// How long an impersonation key stays valid, as an SQLite datetime modifier.
+const IMPERSONATION_TTL: &str = "+60 minutes";
+
+/// Issues a key that acts as `req.email` on behalf of `actor`, recording the start in the
+/// audit log.
+pub async fn start_impersonation(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    req: ask::Impersonate,
+    pool: &DatabasePool,
+) -> Result<ask::IssuedKey, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let detail = json!({ "reason": req.reason.clone() });
+
+    let (impersonator, user) = match impersonation_target(tenant, actor, req.email, pool).await {
+        Ok(found) => found,
+        Err(e) => {
+            return audit::record(
+                tenant,
+                ctx,
+                AuditAction::ImpersonationStart,
+                Some(target),
+                detail,
+                Err(e),
+                pool,
+            )
+            .await
+        }
+    };
+    let api_key = ApiKey::default();
+    query::start_impersonation(
+        tenant,
+        model::NewImpersonation::new(
+            api_key.clone(),
+            &user.email,
+            &impersonator,
+            IMPERSONATION_TTL,
+        ),
+        model::NewAuditEvent::new(
+            ctx,
+            AuditAction::ImpersonationStart,
+            Some(target),
+            AuditOutcome::Success,
+            detail,
+        ),
+        pool,
+    )
+    .await?;
+
+    let groups = user_groups(tenant, user.email.clone(), pool).await?;
+
+    Ok(ask::IssuedKey {
+        api_key: api_key.to_base64(),
+        email: user.email,
+        roles: user.roles,
+        groups,
+        session: None,
+        impersonator: Some(impersonator),
+    })
+}
+
+/// Checks that `principal` may impersonate `email`; returns the actor's email and the user to
+/// impersonate.
+async fn impersonation_target(
+    tenant: &TenantId,
+    principal: &Principal,
+    email: Email,
+    pool: &DatabasePool,
+) -> Result<(Email, User), ServiceError> {
+    let actor = match principal {
+        Principal::User {
+            email,
+            impersonator: None,
+            ..
+        } => email,
+        Principal::User { .. } => {
+            return Err(ServiceError::Forbidden(
+                "cannot impersonate while impersonating".to_string(),
+            ))
+        }
+        Principal::Operator | Principal::Client { .. } => {
+            return Err(ServiceError::Forbidden(
+                "impersonation requires a user API key".to_string(),
+            ))
+        }
+    };
+
+    if *actor == email {
+        return Err(ServiceError::InvalidRequest(
+            "cannot impersonate yourself".to_string(),
+        ));
+    }
+
+    let user = get_user(
+        tenant,
+        ask::GetUser {
+            email,
+            password: None,
+        },
+        pool,
+    )
+    .await?;
+    if !user.status.can_sign_in() {
+        return Err(ServiceError::AccountStatus(user.status));
+    }
+    // the key issued acts with the target's permissions
+    let permissions =
+        query::user_permissions(tenant, &user.email.clone().into_inner(), pool).await?;
+    if let Some(permission) = permissions
+        .iter()
+        .find(|permission| !principal.has_permission(permission))
+    {
+        return Err(ServiceError::Forbidden(format!(
+            "cannot impersonate a user with a permission you do not hold: {permission}"
+        )));
+    }
+
+    Ok((actor.clone(), user))
+}
+
+/// Revokes an impersonation key, recording the end in the audit log.
+pub async fn stop_impersonation(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<query::RevocationStatus, ServiceError> {
+    let email = match query::api_key_owner(tenant, api_key.clone(), pool).await? {
+        query::KeyOwner::User {
+            email,
+            impersonator: Some(_),
+        } => email,
+        _ => {
+            return Err(ServiceError::InvalidRequest(
+                "not an impersonation key".to_string(),
+            ))
+        }
+    };
+
+    let event = model::NewAuditEvent::new(
+        ctx,
+        AuditAction::ImpersonationStop,
+        Some(email),
+        AuditOutcome::Success,
+        json!({}),
+    );
+
+    Ok(query::stop_impersonation(tenant, api_key, event, pool).await?)
+}
+
+pub async fn api_key_is_valid(
+    tenant: &TenantId,
     api_key: ApiKey,
     pool: &DatabasePool,
+) -> Result<bool, ServiceError> {
+    let valid = query::api_key_is_valid(tenant, api_key.clone(), pool).await?;
+    if valid {
+        query::touch_api_key(tenant, api_key, pool).await?;
+    }
+
+    Ok(valid)
+}
+
+/// Lists a user's sessions, flagging the one made with `current`.
+pub async fn list_sessions(
+    tenant: &TenantId,
+    email: Email,
+    current: Option<ApiKey>,
+    pool: &DatabasePool,
+) -> Result<Vec<Session>, ServiceError> {
+    Ok(
+        query::list_sessions(tenant, &email.into_inner(), current, pool)
+            .await?
+            .into_iter()
+            .map(Session::from)
+            .collect(),
+    )
+}
+
+pub async fn revoke_session(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    email: Email,
+    id: &str,
+    pool: &DatabasePool,
 ) -> Result<query::RevocationStatus, ServiceError> {
-    Ok(query::revoke_api_key(api_key, pool).await?)
+    let email = email.into_inner();
+    let result = query::revoke_session(tenant, &email, id, pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::SessionRevoke,
+        Some(email),
+        json!({ "session": id }),
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
+}
+
+/// Signs a user out everywhere, returning the number of sessions revoked.
+pub async fn revoke_sessions(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    email: Email,
+    pool: &DatabasePool,
+) -> Result<u64, ServiceError> {
+    let email = email.into_inner();
+    let result = query::revoke_sessions(tenant, &email, pool).await;
+    let detail = match &result {
+        Ok(revoked) => json!({ "revoked": revoked }),
+        Err(_) => json!({}),
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::SessionRevokeAll,
+        Some(email),
+        detail,
+        result.map_err(ServiceError::from),
+        pool,
+    )
+    .await
 }
 
-pub async fn api_key_is_valid(api_key: ApiKey, pool: &DatabasePool) -> Result<bool, ServiceError> {
-    Ok(query::api_key_is_valid(api_key, pool).await?)
+pub async fn list_tenants(pool: &DatabasePool) -> Result<Vec<Tenant>, ServiceError> {
+    query::list_tenants(pool)
+        .await?
+        .into_iter()
+        .map(|tenant| Ok(tenant.try_into()?))
+        .collect()
+}
+
+/// Tenants are managed from the default tenant, so changes are recorded there.
+pub async fn save_tenant(
+    ctx: &audit::Context,
+    tenant: Tenant,
+    pool: &DatabasePool,
+) -> Result<Tenant, ServiceError> {
+    let target = tenant.id.to_string();
+    let detail = json!({ "name": tenant.name.clone() });
+    let result = async { Ok(query::save_tenant(tenant, pool).await?.try_into()?) }.await;
+
+    audit::record(
+        &TenantId::default(),
+        ctx,
+        AuditAction::TenantSave,
+        Some(target),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn tenant_exists(tenant: &TenantId, pool: &DatabasePool) -> Result<bool, ServiceError> {
+    Ok(query::tenant_exists(tenant, pool).await?)
+}
+
+/// Records an invitation and mails its single-use token to the invitee.
+pub async fn invite_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    req: ask::NewInvitation,
+    mailer: &Mailer,
+    pool: &DatabasePool,
+) -> Result<Invitation, ServiceError> {
+    let target = req.email.clone().into_inner();
+    let detail = json!({ "role": req.role.clone(), "expires_in_days": req.expires_in_days });
+    let result = async {
+        if !(1..=ask::NewInvitation::MAX_EXPIRY_DAYS).contains(&req.expires_in_days) {
+            return Err(ServiceError::InvalidRequest(format!(
+                "expires_in_days must be between 1 and {}",
+                ask::NewInvitation::MAX_EXPIRY_DAYS
+            )));
+        }
+        // accepting grants the role, so inviting must not hand out more than the inviter has
+        let role: Role = query::get_role(tenant, &req.role.to_string(), pool)
+            .await?
+            .try_into()?;
+        if let Some(permission) = role
+            .permissions
+            .iter()
+            .find(|permission| !actor.has_permission(permission.as_str()))
+        {
+            return Err(ServiceError::Forbidden(format!(
+                "cannot grant a permission you do not hold: {}",
+                permission.as_str()
+            )));
+        }
+
+        let token = Token::generate();
+        let invitation: Invitation = query::new_invitation(
+            tenant,
+            model::NewInvitation::new(req, &token, ctx.actor.clone()),
+            pool,
+        )
+        .await?
+        .try_into()?;
+
+        mailer.send(&Mail {
+            to: invitation.email.clone(),
+            subject: format!("You have been invited to join {tenant}"),
+            body: format!(
+                "You have been invited to join {tenant} as {}.\n\n\
+                Invitation token: {token}\n\n\
+                The invitation can be used once and expires at {} UTC.",
+                invitation.role, invitation.expires_at
+            ),
+        })?;
+
+        Ok(invitation)
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::InvitationCreate,
+        Some(target),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn list_invitations(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<Invitation>, ServiceError> {
+    query::list_invitations(tenant, pool)
+        .await?
+        .into_iter()
+        .map(|invitation| Ok(invitation.try_into()?))
+        .collect()
+}
+
+/// Accepts an invitation, creating the account if the invitee does not have one yet.
+pub async fn accept_invitation(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::AcceptInvitation,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let (target, result) = match query::accept_invitation(tenant, req.into(), pool).await {
+        Ok(query::InvitationAcceptance::Accepted(email)) => {
+            let user = get_user(
+                tenant,
+                ask::GetUser {
+                    email: email.as_str().into(),
+                    password: None,
+                },
+                pool,
+            )
+            .await;
+            (Some(email), user)
+        }
+        Ok(query::InvitationAcceptance::Invalid) => (
+            None,
+            Err(ServiceError::InvalidRequest(
+                "invitation is invalid, expired or already used".to_string(),
+            )),
+        ),
+        Ok(query::InvitationAcceptance::AccountRequired) => (
+            None,
+            Err(ServiceError::InvalidRequest(
+                "name and password are required to create the account".to_string(),
+            )),
+        ),
+        Err(e) => (None, Err(e.into())),
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::InvitationAccept,
+        target,
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn list_audit_events(
+    tenant: &TenantId,
+    req: ask::ListAuditEvents,
+    pool: &DatabasePool,
+) -> Result<ask::AuditPage, ServiceError> {
+    let (events, next_cursor) =
+        query::list_audit_events(tenant, model::ListAuditEvents::page(req), pool).await?;
+
+    Ok(ask::AuditPage {
+        events: events
+            .into_iter()
+            .map(AuditEvent::try_from)
+            .collect::<Result<_, _>>()?,
+        next_cursor,
+    })
+}
+
+/// Every event matching `req`, oldest first.
+pub async fn export_audit_events(
+    tenant: &TenantId,
+    req: ask::ListAuditEvents,
+    pool: &DatabasePool,
+) -> Result<Vec<AuditEvent>, ServiceError> {
+    query::export_audit_events(tenant, model::ListAuditEvents::export(req), pool)
+        .await?
+        .into_iter()
+        .map(|event| Ok(event.try_into()?))
+        .collect()
+}
+
+pub async fn verify_audit_log(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<ask::AuditVerification, ServiceError> {
+    Ok(match query::verify_audit_chain(tenant, pool).await? {
+        query::AuditChain::Intact(verified) => ask::AuditVerification {
+            verified,
+            broken_at: None,
+        },
+        query::AuditChain::Broken { verified, at } => ask::AuditVerification {
+            verified,
+            broken_at: Some(at),
+        },
+    })
+}
+
+/// Registers a client. Scopes naming permissions let the client act with them through the
+/// client credentials grant, so `actor` may only hand out permissions it holds itself.
+pub async fn register_client(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    req: ask::NewClient,
+    pool: &DatabasePool,
+) -> Result<ask::RegisteredClient, ServiceError> {
+    let detail = json!({ "name": req.name.clone(), "client_type": req.client_type });
+    let result = async {
+        if req.name.trim().is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                "client name must not be empty".to_string(),
+            ));
+        }
+        if req.redirect_uris.is_empty() {
+            return Err(
+                OAuthError::InvalidRedirectUri("at least one is required".to_string()).into(),
+            );
+        }
+        for uri in req
+            .redirect_uris
+            .iter()
+            .chain(&req.post_logout_redirect_uris)
+        {
+            oauth::validate_redirect_uri(uri)?;
+        }
+        if let Some(scope) = req
+            .scopes
+            .iter()
+            .find(|scope| !Scope::is_valid_token(scope))
+        {
+            return Err(ServiceError::InvalidRequest(format!(
+                "invalid scope: {scope}"
+            )));
+        }
+        let permissions = list_permissions(pool).await?;
+        if let Some(scope) = req.scopes.iter().find(|scope| {
+            permissions
+                .iter()
+                .any(|permission| permission.name.as_str() == scope.as_str())
+                && !actor.has_permission(scope)
+        }) {
+            return Err(ServiceError::Forbidden(format!(
+                "cannot grant a permission you do not hold: {scope}"
+            )));
+        }
+
+        let id = format!("{:032x}", rand::random::<u128>());
+        let secret = match req.client_type {
+            ClientType::Confidential => Some(Token::generate()),
+            ClientType::Public => None,
+        };
+        let model = model::NewClient::new(&id, &req, secret.as_ref());
+        let client = query::save_oauth_client(tenant, model, pool)
+            .await?
+            .try_into()?;
+
+        Ok(ask::RegisteredClient {
+            client,
+            client_secret: secret.map(|secret| secret.to_string()),
+        })
+    }
+    .await;
+    let target = result
+        .as_ref()
+        .ok()
+        .map(|registered| registered.client.id.clone());
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::ClientRegister,
+        target,
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn list_clients(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<Client>, ServiceError> {
+    Ok(query::list_oauth_clients(tenant, pool)
+        .await?
+        .into_iter()
+        .map(Client::try_from)
+        .collect::<Result<_, _>>()?)
+}
+
+pub async fn get_client(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<Client, ServiceError> {
+    Ok(query::oauth_client(tenant, id, pool)
+        .await?
+        .ok_or(ServiceError::NotFound)?
+        .try_into()?)
+}
+
+/// Removes a client along with its grants, codes and tokens.
+pub async fn delete_client(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let result = query::delete_oauth_client(tenant, id, pool)
+        .await
+        .map_err(ServiceError::from);
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::ClientDelete,
+        Some(id.to_string()),
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+/// How long an authorization code stays valid, as an SQLite datetime modifier.
+const OAUTH_CODE_TTL: &str = "+5 minutes";
+
+/// Lifetime of access tokens issued at the token endpoint.
+const ACCESS_TOKEN_TTL_SECS: u64 = 60 * 60;
+
+/// How long a refresh token stays valid, as an SQLite datetime modifier.
+const REFRESH_TOKEN_TTL: &str = "+30 days";
+
+/// Looks up the client of an authorization request and checks its redirect URI. Until this
+/// passes, errors must be shown to the user instead of being sent to the redirect URI.
+pub async fn authorization_client(
+    tenant: &TenantId,
+    req: &ask::AuthorizeRequest,
+    pool: &DatabasePool,
+) -> Result<Client, ServiceError> {
+    let client: Client = query::oauth_client(tenant, &req.client_id, pool)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidRequest("unknown client".to_string()))?
+        .try_into()?;
+
+    match client.allows_redirect(&req.redirect_uri) {
+        true => Ok(client),
+        false => Err(OAuthError::InvalidRedirectUri(format!(
+            "{}: not registered for this client",
+            req.redirect_uri
+        ))
+        .into()),
+    }
+}
+
+/// Checks the rest of an authorization request and returns the scope it asks for.
+pub fn authorization_scope(
+    client: &Client,
+    req: &ask::AuthorizeRequest,
+) -> Result<Scope, OAuthError> {
+    if req.response_type != "code" {
+        return Err(OAuthError::UnsupportedResponseType);
+    }
+    match (
+        req.code_challenge.as_deref(),
+        req.code_challenge_method.as_deref(),
+    ) {
+        (Some(challenge), Some("S256")) if !challenge.is_empty() => {}
+        (Some(_), _) => {
+            return Err(OAuthError::InvalidRequest(
+                "code_challenge_method must be S256".to_string(),
+            ))
+        }
+        (None, _) => {
+            return Err(OAuthError::InvalidRequest(
+                "code_challenge is required".to_string(),
+            ))
+        }
+    }
+
+    requested_scope(client, req.scope.as_deref())
+}
+
+/// The scope a user is asked to grant `client`; without a `scope`, everything the client
+/// was registered with is requested. The OpenID Connect scopes are open to every client.
+fn requested_scope(client: &Client, scope: Option<&str>) -> Result<Scope, OAuthError> {
+    let registered = Scope::from(client.scopes.as_slice());
+    let allowed = registered.union(&Scope::parse(&oidc::STANDARD_SCOPES.join(" ")));
+    let requested = match scope.map(Scope::parse) {
+        Some(scope) if !scope.is_empty() => scope,
+        _ => registered,
+    };
+    match allowed.contains(&requested) {
+        true => Ok(requested),
+        false => Err(OAuthError::InvalidScope),
+    }
+}
+
+/// The user signed in to the authorization page with `api_key`, if the key is still valid
+/// and the user may still sign in.
+pub async fn session_user(
+    tenant: &TenantId,
+    api_key: ApiKey,
+    pool: &DatabasePool,
+) -> Result<Option<Email>, ServiceError> {
+    if !api_key_is_valid(tenant, api_key.clone(), pool).await? {
+        return Ok(None);
+    }
+
+    match principal(tenant, api_key, pool).await {
+        Ok(Principal::User {
+            email,
+            impersonator: None,
+            ..
+        }) => Ok(Some(email)),
+        _ => Ok(None),
+    }
+}
+
+/// Whether `email` has already consented to give `client` everything in `scope`.
+pub async fn has_consent(
+    tenant: &TenantId,
+    client: &Client,
+    email: &Email,
+    scope: &Scope,
+    pool: &DatabasePool,
+) -> Result<bool, ServiceError> {
+    let email = email.clone().into_inner();
+    let granted = query::oauth_grant_scope(tenant, &client.id, &email, pool).await?;
+
+    Ok(granted.is_some_and(|granted| Scope::parse(&granted).contains(scope)))
+}
+
+/// Records a user's consent to `scope` and issues an authorization code for the request.
+pub async fn authorize(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    client: &Client,
+    email: Email,
+    scope: Scope,
+    req: &ask::AuthorizeRequest,
+    pool: &DatabasePool,
+) -> Result<Token, ServiceError> {
+    let target = email.clone().into_inner();
+    let result = async {
+        let granted = query::oauth_grant_scope(tenant, &client.id, &target, pool)
+            .await?
+            .map(|granted| Scope::parse(&granted))
+            .unwrap_or_default();
+        let granted = granted.union(&scope).to_string();
+        query::save_oauth_grant(tenant, &client.id, &target, &granted, pool).await?;
+
+        let code = Token::generate();
+        let model = model::NewAuthorizationCode::new(&code, client, &email, &scope, req);
+        query::save_oauth_code(tenant, model, OAUTH_CODE_TTL, pool).await?;
+
+        Ok(code)
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::OAuthAuthorize,
+        Some(target),
+        json!({ "client": client.id.clone(), "scope": scope.to_string() }),
+        result,
+        pool,
+    )
+    .await
+}
+
+/// How long a device code stays valid.
+const DEVICE_CODE_TTL_SECS: u64 = 10 * 60;
+
+/// Seconds a device must wait between polls, and the amount added each time it polls too
+/// fast.
+const DEVICE_POLL_INTERVAL_SECS: u64 = 5;
+
+/// Starts the device authorization grant (RFC 8628) for a device that cannot show a
+/// browser: it gets a code to poll the token endpoint with, and a short code for its user
+/// to approve on another device.
+pub async fn authorize_device(
+    tenant: &TenantId,
+    req: ask::DeviceAuthorizationRequest,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<ask::DeviceAuthorization, ServiceError> {
+    let client_id = req.client_id.as_deref().unwrap_or_default();
+    let client = authenticate_client(tenant, client_id, req.client_secret.as_deref(), pool).await?;
+    let scope = requested_scope(&client, req.scope.as_deref())?;
+
+    let device_code = Token::generate();
+    let user_code = oauth::UserCode::generate();
+    let model = model::NewDeviceCode::new(
+        &device_code,
+        &user_code,
+        &client,
+        &scope,
+        DEVICE_POLL_INTERVAL_SECS,
+    );
+    let expires_in = format!("+{DEVICE_CODE_TTL_SECS} seconds");
+    query::save_oauth_device_code(tenant, model, &expires_in, pool).await?;
+
+    let verification_uri = links.oauth(tenant, "/device");
+    let mut complete = url::Url::parse(&verification_uri)
+        .map_err(|e| ServiceError::InvalidRequest(format!("public URL: {e}")))?;
+    complete
+        .query_pairs_mut()
+        .append_pair("user_code", &user_code.to_string());
+
+    Ok(ask::DeviceAuthorization {
+        device_code: device_code.to_string(),
+        user_code: user_code.to_string(),
+        verification_uri,
+        verification_uri_complete: complete.to_string(),
+        expires_in: DEVICE_CODE_TTL_SECS,
+        interval: DEVICE_POLL_INTERVAL_SECS,
+    })
+}
+
+/// The device request a user code, as typed by the user, stands for.
+pub async fn device_request(
+    tenant: &TenantId,
+    user_code: &str,
+    pool: &DatabasePool,
+) -> Result<ask::DeviceRequest, ServiceError> {
+    let unknown = || OAuthError::InvalidGrant("the code is invalid or has expired".to_string());
+    let user_code = oauth::UserCode::parse(user_code).ok_or_else(unknown)?;
+    let request = query::oauth_device_request(tenant, user_code.as_str(), pool)
+        .await?
+        .ok_or_else(unknown)?;
+    let client = query::oauth_client(tenant, &request.client_id, pool)
+        .await?
+        .ok_or_else(unknown)?
+        .try_into()?;
+
+    Ok(ask::DeviceRequest {
+        client,
+        scope: Scope::parse(&request.scope),
+        user_code,
+    })
+}
+
+/// Approves a device request for `email`, recording the user's consent as for the
+/// authorization code grant. The device picks up its tokens with its next poll.
+pub async fn approve_device_request(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    request: &ask::DeviceRequest,
+    email: Email,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let target = email.into_inner();
+    let client = &request.client;
+    let result = async {
+        decide_device_request(tenant, request, Some(&target), true, pool).await?;
+
+        let granted = query::oauth_grant_scope(tenant, &client.id, &target, pool)
+            .await?
+            .map(|granted| Scope::parse(&granted))
+            .unwrap_or_default();
+        let granted = granted.union(&request.scope).to_string();
+        query::save_oauth_grant(tenant, &client.id, &target, &granted, pool).await?;
+
+        Ok(())
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::OAuthAuthorize,
+        Some(target),
+        json!({
+            "client": client.id.clone(),
+            "scope": request.scope.to_string(),
+            "device": true,
+        }),
+        result,
+        pool,
+    )
+    .await
+}
+
+/// Denies a device request; the device is told so with its next poll. Users need not sign
+/// in to deny a request.
+pub async fn deny_device_request(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    request: &ask::DeviceRequest,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let result = decide_device_request(tenant, request, None, false, pool).await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::OAuthDeny,
+        None,
+        json!({ "client": request.client.id.clone(), "device": true }),
+        result,
+        pool,
+    )
+    .await
+}
+
+async fn decide_device_request(
+    tenant: &TenantId,
+    request: &ask::DeviceRequest,
+    email: Option<&str>,
+    approved: bool,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let user_code = request.user_code.as_str();
+    match query::decide_oauth_device_request(tenant, user_code, email, approved, pool).await? {
+        true => Ok(()),
+        false => {
+            Err(OAuthError::InvalidGrant("the code is invalid or has expired".to_string()).into())
+        }
+    }
+}
+
+/// Handles a token endpoint request: authenticates the client and exchanges the grant it
+/// presents for a new access and refresh token, plus an ID token for OpenID Connect
+/// requests. With client credentials, the client only gets an access token acting for
+/// itself.
+pub async fn exchange_token(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::TokenRequest,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<ask::TokenResponse, ServiceError> {
+    let detail = json!({ "client": req.client_id.clone(), "grant_type": req.grant_type.clone() });
+    let result = async {
+        let client_id = req.client_id.as_deref().unwrap_or_default();
+        let client =
+            authenticate_client(tenant, client_id, req.client_secret.as_deref(), pool).await?;
+
+        let grant = match req.grant_type.as_str() {
+            "authorization_code" => redeem_code(tenant, &client, &req, pool).await?,
+            "refresh_token" => redeem_refresh_token(tenant, &client, &req, pool).await?,
+            oauth::DEVICE_CODE_GRANT_TYPE => {
+                redeem_device_code(tenant, &client, &req, pool).await?
+            }
+            "client_credentials" => {
+                let scope = client_credentials_scope(&client, &req)?;
+                let tokens = issue_access_token(tenant, &client, None, &scope, pool).await?;
+                return Ok((None, tokens));
+            }
+            _ => return Err(OAuthError::UnsupportedGrantType.into()),
+        };
+
+        // the user may have been disabled since the grant was made
+        if active_user(tenant, &grant.email, pool).await?.is_none() {
+            return Err(
+                OAuthError::InvalidGrant("the user may no longer sign in".to_string()).into(),
+            );
+        }
+
+        let mut tokens = issue_tokens(tenant, &client, &grant.email, &grant.scope, pool).await?;
+        if grant.scope.iter().any(|scope| scope == oidc::OPENID) {
+            let now = unix_time();
+            let claims = IdToken {
+                iss: links.issuer(tenant),
+                sub: oidc::subject(tenant, &grant.email),
+                aud: client.id.clone(),
+                exp: now + ID_TOKEN_TTL_SECS,
+                iat: now,
+                nonce: grant.nonce,
+            };
+            tokens.id_token = Some(signing_key(pool).await?.sign(&claims)?);
+        }
+        Ok((Some(grant.email), tokens))
+    }
+    .await;
+    // a device waiting for its user polls every few seconds; only the outcome is recorded
+    if let Err(ServiceError::OAuth(OAuthError::AuthorizationPending | OAuthError::SlowDown)) =
+        result
+    {
+        return result.map(|(_, tokens)| tokens);
+    }
+    let target = result.as_ref().ok().and_then(|(email, _)| email.clone());
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::OAuthToken,
+        target,
+        detail,
+        result.map(|(_, tokens)| tokens),
+        pool,
+    )
+    .await
+}
+
+/// Tells a resource server whether `req.token` is active and what it grants. Access and
+/// refresh tokens are looked up first, then sessions (API keys); tokens of users who may no
+/// longer sign in are inactive. Only confidential clients may ask.
+pub async fn introspect_token(
+    tenant: &TenantId,
+    req: ask::TokenLookup,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<ask::TokenIntrospection, ServiceError> {
+    let client_id = req.client_id.as_deref().unwrap_or_default();
+    let client = authenticate_client(tenant, client_id, req.client_secret.as_deref(), pool).await?;
+    if client.client_type != ClientType::Confidential {
+        return Err(OAuthError::UnauthorizedClient.into());
+    }
+
+    let inactive = ask::TokenIntrospection::default();
+    let token = query::oauth_token(tenant, &Token::from(req.token.as_str()).hash(), pool).await?;
+    if let Some(token) = token {
+        if let Some(email) = &token.email {
+            if active_user(tenant, email, pool).await?.is_none() {
+                return Ok(inactive);
+            }
+        }
+        let token_type = match token.kind.parse() {
+            Ok(TokenKind::Refresh) => "refresh_token",
+            _ => "access_token",
+        };
+
+        return Ok(ask::TokenIntrospection {
+            active: true,
+            scope: Some(token.scope),
+            client_id: Some(token.client_id),
+            sub: token
+                .email
+                .as_ref()
+                .map(|email| oidc::subject(tenant, email)),
+            username: token.email,
+            token_type: Some(token_type.to_string()),
+            exp: Some(token.expires_at),
+            iat: Some(token.issued_at),
+            iss: Some(links.issuer(tenant)),
+        });
+    }
+
+    let session = match req.token.parse::<ApiKey>() {
+        Ok(api_key) => query::session_token(tenant, api_key, pool).await?,
+        Err(_) => None,
+    };
+    let session = match session {
+        Some(session) => session,
+        None => return Ok(inactive),
+    };
+    if let Some(email) = &session.owner {
+        if active_user(tenant, email, pool).await?.is_none() {
+            return Ok(inactive);
+        }
+    }
+
+    Ok(ask::TokenIntrospection {
+        active: true,
+        sub: session
+            .owner
+            .as_ref()
+            .map(|email| oidc::subject(tenant, email)),
+        username: session.owner,
+        token_type: Some("session".to_string()),
+        exp: session.expires_at,
+        iat: session.issued_at,
+        iss: Some(links.issuer(tenant)),
+        ..inactive
+    })
+}
+
+/// Revokes an access or refresh token issued to the requesting client, or a session (API
+/// key). Revoking a refresh token also revokes the access tokens issued alongside it. As
+/// required by RFC 7009, unknown tokens are not an error.
+pub async fn revoke_token(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::TokenLookup,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let detail = json!({
+        "client": req.client_id.clone(),
+        "token_type_hint": req.token_type_hint.clone(),
+    });
+    let result = async {
+        let client_id = req.client_id.as_deref().unwrap_or_default();
+        let client =
+            authenticate_client(tenant, client_id, req.client_secret.as_deref(), pool).await?;
+
+        let token_hash = Token::from(req.token.as_str()).hash();
+        if let Some(revoked) =
+            query::revoke_oauth_token(tenant, &token_hash, &client.id, pool).await?
+        {
+            return Ok(revoked.email);
+        }
+
+        let api_key = match req.token.parse::<ApiKey>() {
+            Ok(api_key) => api_key,
+            Err(_) => return Ok(None),
+        };
+        match query::session_token(tenant, api_key.clone(), pool).await? {
+            Some(session) => {
+                query::revoke_api_key(tenant, api_key, pool).await?;
+                Ok(session.owner)
+            }
+            None => Ok(None),
+        }
+    }
+    .await;
+    let target = result.as_ref().ok().cloned().flatten();
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::OAuthRevoke,
+        target,
+        detail,
+        result.map(|_| ()),
+        pool,
+    )
+    .await
+}
+
+/// The user `email` if they still exist and may sign in.
+async fn active_user(
+    tenant: &TenantId,
+    email: &str,
+    pool: &DatabasePool,
+) -> Result<Option<User>, ServiceError> {
+    let user = get_user(
+        tenant,
+        ask::GetUser {
+            email: email.into(),
+            password: None,
+        },
+        pool,
+    )
+    .await;
+
+    match user {
+        Ok(user) if sign_in_allowed(tenant, &user, pool).await.is_ok() => Ok(Some(user)),
+        Ok(_) | Err(ServiceError::NotFound | ServiceError::InvalidDetail) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Confidential clients must present their secret; public clients have none to present.
+async fn authenticate_client(
+    tenant: &TenantId,
+    id: &str,
+    secret: Option<&str>,
+    pool: &DatabasePool,
+) -> Result<Client, ServiceError> {
+    let client: Client = query::oauth_client(tenant, id, pool)
+        .await?
+        .ok_or(OAuthError::InvalidClient)?
+        .try_into()?;
+    let secret_hash = query::oauth_client_secret_hash(tenant, id, pool).await?;
+
+    match (client.client_type, secret_hash, secret) {
+        (ClientType::Public, _, None) => Ok(client),
+        (ClientType::Confidential, Some(hash), Some(secret))
+            if ring::constant_time::verify_slices_are_equal(
+                Token::from(secret).hash().as_bytes(),
+                hash.as_bytes(),
+            )
+            .is_ok() =>
+        {
+            Ok(client)
+        }
+        _ => Err(OAuthError::InvalidClient.into()),
+    }
+}
+
+fn missing_parameter(name: &str) -> OAuthError {
+    OAuthError::InvalidRequest(format!("{name} is required"))
+}
+
+/// What a code or refresh token presented at the token endpoint grants.
+struct Grant {
+    email: String,
+    scope: Scope,
+    nonce: Option<String>,
+}
+
+/// Redeems an authorization code, returning the user and scope it was issued for. The code
+/// is used up even if the rest of the request turns out to be invalid.
+async fn redeem_code(
+    tenant: &TenantId,
+    client: &Client,
+    req: &ask::TokenRequest,
+    pool: &DatabasePool,
+) -> Result<Grant, ServiceError> {
+    let code = req
+        .code
+        .as_deref()
+        .ok_or_else(|| missing_parameter("code"))?;
+    let granted = query::consume_oauth_code(tenant, &Token::from(code).hash(), pool)
+        .await?
+        .ok_or_else(|| {
+            OAuthError::InvalidGrant("authorization code is invalid, expired or used".to_string())
+        })?;
+
+    if granted.client_id != client.id || req.redirect_uri.as_ref() != Some(&granted.redirect_uri) {
+        return Err(OAuthError::InvalidGrant(
+            "authorization code was issued to another client or redirect URI".to_string(),
+        )
+        .into());
+    }
+    let verifier = req
+        .code_verifier
+        .as_deref()
+        .ok_or_else(|| missing_parameter("code_verifier"))?;
+    oauth::verify_code_challenge(&granted.code_challenge, verifier)?;
+
+    Ok(Grant {
+        email: granted.email,
+        scope: Scope::parse(&granted.scope),
+        nonce: granted.nonce,
+    })
+}
+
+/// Redeems (and thereby rotates) a refresh token, optionally narrowing its scope.
+async fn redeem_refresh_token(
+    tenant: &TenantId,
+    client: &Client,
+    req: &ask::TokenRequest,
+    pool: &DatabasePool,
+) -> Result<Grant, ServiceError> {
+    let token = req
+        .refresh_token
+        .as_deref()
+        .ok_or_else(|| missing_parameter("refresh_token"))?;
+    let grant =
+        query::consume_oauth_refresh_token(tenant, &Token::from(token).hash(), &client.id, pool)
+            .await?
+            .ok_or_else(|| {
+                OAuthError::InvalidGrant("refresh token is invalid or expired".to_string())
+            })?;
+
+    let granted = Scope::parse(&grant.scope);
+    let scope = match req.scope.as_deref().map(Scope::parse) {
+        Some(scope) if !scope.is_empty() => scope,
+        _ => granted.clone(),
+    };
+    match granted.contains(&scope) {
+        true => Ok(Grant {
+            email: grant.email,
+            scope,
+            nonce: None,
+        }),
+        false => Err(OAuthError::InvalidScope.into()),
+    }
+}
+
+/// Redeems a device code once the user has approved it; until then, tells the device to
+/// keep polling, or to slow down if it polls too fast.
+async fn redeem_device_code(
+    tenant: &TenantId,
+    client: &Client,
+    req: &ask::TokenRequest,
+    pool: &DatabasePool,
+) -> Result<Grant, ServiceError> {
+    let device_code = req
+        .device_code
+        .as_deref()
+        .ok_or_else(|| missing_parameter("device_code"))?;
+    let poll = query::poll_oauth_device_code(
+        tenant,
+        &Token::from(device_code).hash(),
+        &client.id,
+        DEVICE_POLL_INTERVAL_SECS as i64,
+        pool,
+    )
+    .await?
+    .ok_or_else(|| OAuthError::InvalidGrant("device code is invalid".to_string()))?;
+
+    match poll {
+        query::DevicePoll { expired: true, .. } => Err(OAuthError::ExpiredToken.into()),
+        query::DevicePoll {
+            approved: Some(true),
+            email: Some(email),
+            scope,
+            ..
+        } => Ok(Grant {
+            email,
+            scope: Scope::parse(&scope),
+            nonce: None,
+        }),
+        query::DevicePoll {
+            approved: Some(_), ..
+        } => Err(OAuthError::AccessDenied.into()),
+        query::DevicePoll { too_fast: true, .. } => Err(OAuthError::SlowDown.into()),
+        query::DevicePoll { .. } => Err(OAuthError::AuthorizationPending.into()),
+    }
+}
+
+/// Only confidential clients can use client credentials, and only for the scopes they were
+/// registered with; without a `scope`, all of them are granted.
+fn client_credentials_scope(client: &Client, req: &ask::TokenRequest) -> Result<Scope, OAuthError> {
+    if client.client_type != ClientType::Confidential {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+
+    let registered = Scope::from(client.scopes.as_slice());
+    let scope = match req.scope.as_deref().map(Scope::parse) {
+        Some(scope) if !scope.is_empty() => scope,
+        _ => registered.clone(),
+    };
+    match registered.contains(&scope) {
+        true => Ok(scope),
+        false => Err(OAuthError::InvalidScope),
+    }
+}
+
+/// Issues an access token for `email`, or for the client itself without one.
+async fn issue_access_token(
+    tenant: &TenantId,
+    client: &Client,
+    email: Option<&str>,
+    scope: &Scope,
+    pool: &DatabasePool,
+) -> Result<ask::TokenResponse, ServiceError> {
+    let access_token = Token::generate();
+    let model =
+        model::NewOAuthToken::new(&access_token, TokenKind::Access, &client.id, email, scope);
+    let expires_in = format!("+{ACCESS_TOKEN_TTL_SECS} seconds");
+    query::save_oauth_token(tenant, model, &expires_in, pool).await?;
+
+    Ok(ask::TokenResponse {
+        access_token: access_token.to_string(),
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_TTL_SECS,
+        refresh_token: None,
+        scope: scope.to_string(),
+        id_token: None,
+    })
+}
+
+async fn issue_tokens(
+    tenant: &TenantId,
+    client: &Client,
+    email: &str,
+    scope: &Scope,
+    pool: &DatabasePool,
+) -> Result<ask::TokenResponse, ServiceError> {
+    let mut tokens = issue_access_token(tenant, client, Some(email), scope, pool).await?;
+
+    let refresh_token = Token::generate();
+    let model = model::NewOAuthToken::new(
+        &refresh_token,
+        TokenKind::Refresh,
+        &client.id,
+        Some(email),
+        scope,
+    );
+    query::save_oauth_token(tenant, model, REFRESH_TOKEN_TTL, pool).await?;
+    tokens.refresh_token = Some(refresh_token.to_string());
+
+    Ok(tokens)
+}
+
+/// Lifetime of ID tokens.
+const ID_TOKEN_TTL_SECS: u64 = 60 * 60;
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// The key new ID tokens are signed with; one is generated the first time it is needed.
+async fn signing_key(pool: &DatabasePool) -> Result<SigningKey, ServiceError> {
+    if let Some(key) = query::signing_keys(pool).await?.into_iter().next() {
+        return Ok(key.try_into()?);
+    }
+
+    let key = SigningKey::generate()?;
+    query::save_signing_key(&key.id, SigningKey::ALGORITHM, key.pkcs8(), pool).await?;
+    Ok(key)
+}
+
+/// Public keys ID tokens can be checked with, as a JSON Web Key Set.
+pub async fn jwks(pool: &DatabasePool) -> Result<serde_json::Value, ServiceError> {
+    signing_key(pool).await?;
+    let keys = query::signing_keys(pool)
+        .await?
+        .into_iter()
+        .map(|key| Ok(SigningKey::try_from(key)?.jwk()))
+        .collect::<Result<Vec<_>, ServiceError>>()?;
+
+    Ok(json!({ "keys": keys }))
+}
+
+/// OpenID Provider metadata of `tenant`, served for discovery.
+pub fn openid_configuration(tenant: &TenantId, links: &Links) -> serde_json::Value {
+    json!({
+        "issuer": links.issuer(tenant),
+        "authorization_endpoint": links.oauth(tenant, "/authorize"),
+        "token_endpoint": links.oauth(tenant, "/token"),
+        "userinfo_endpoint": links.oauth(tenant, "/userinfo"),
+        "jwks_uri": links.oauth(tenant, "/jwks"),
+        "end_session_endpoint": links.oauth(tenant, "/logout"),
+        "introspection_endpoint": links.oauth(tenant, "/introspect"),
+        "revocation_endpoint": links.oauth(tenant, "/revoke"),
+        "device_authorization_endpoint": links.oauth(tenant, "/device_authorization"),
+        "response_types_supported": ["code"],
+        "grant_types_supported": [
+            "authorization_code",
+            "refresh_token",
+            "client_credentials",
+            oauth::DEVICE_CODE_GRANT_TYPE,
+        ],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": [SigningKey::ALGORITHM],
+        "scopes_supported": oidc::STANDARD_SCOPES,
+        "claims_supported": oidc::CLAIMS,
+        "token_endpoint_auth_methods_supported":
+            ["client_secret_basic", "client_secret_post", "none"],
+        "introspection_endpoint_auth_methods_supported":
+            ["client_secret_basic", "client_secret_post"],
+        "revocation_endpoint_auth_methods_supported":
+            ["client_secret_basic", "client_secret_post", "none"],
+        "code_challenge_methods_supported": ["S256"],
+    })
+}
+
+/// Claims about the user an access token with the `openid` scope was issued for.
+pub async fn user_info(
+    tenant: &TenantId,
+    access_token: &str,
+    pool: &DatabasePool,
+) -> Result<UserInfo, ServiceError> {
+    let grant = query::oauth_access_grant(tenant, &Token::from(access_token).hash(), pool)
+        .await?
+        .ok_or(OAuthError::InvalidToken)?;
+    // client credentials carry no user to describe
+    let email = grant.email.ok_or(OAuthError::InvalidToken)?;
+    let scope = Scope::parse(&grant.scope);
+    if !scope.iter().any(|scope| scope == oidc::OPENID) {
+        return Err(OAuthError::InsufficientScope.into());
+    }
+
+    match active_user(tenant, &email, pool).await? {
+        Some(user) => Ok(UserInfo::new(oidc::subject(tenant, &email), &user, &scope)),
+        None => Err(OAuthError::InvalidToken.into()),
+    }
+}
+
+/// RP-initiated logout: ends the authorization page session, if any, and returns where to
+/// send the user afterwards. A redirect is only allowed to a URI registered by the client,
+/// which is identified by `client_id` or by the ID token it passes as a hint.
+pub async fn end_session(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    session: Option<ApiKey>,
+    req: &ask::LogoutRequest,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<Option<String>, ServiceError> {
+    let detail = json!({ "client": req.client_id.clone() });
+    let result = async {
+        let hinted = match &req.id_token_hint {
+            Some(hint) => Some(id_token_hint(tenant, hint, links, pool).await?.aud),
+            None => None,
+        };
+        let client_id = match (hinted, req.client_id.clone()) {
+            (Some(hinted), Some(client_id)) if hinted != client_id => {
+                return Err(OAuthError::InvalidRequest(
+                    "client_id does not match id_token_hint".to_string(),
+                )
+                .into())
+            }
+            (Some(client_id), _) | (None, Some(client_id)) => Some(client_id),
+            (None, None) => None,
+        };
+
+        let redirect = match (&req.post_logout_redirect_uri, client_id) {
+            (None, _) => None,
+            (Some(_), None) => {
+                return Err(OAuthError::InvalidRequest(
+                    "post_logout_redirect_uri requires client_id or id_token_hint".to_string(),
+                )
+                .into())
+            }
+            (Some(uri), Some(client_id)) => {
+                let client = get_client(tenant, &client_id, pool)
+                    .await
+                    .map_err(|_| OAuthError::InvalidRequest("unknown client".to_string()))?;
+                match client.allows_post_logout_redirect(uri) {
+                    true => Some(uri.clone()),
+                    false => {
+                        return Err(OAuthError::InvalidRedirectUri(format!(
+                            "{uri}: not registered for this client"
+                        ))
+                        .into())
+                    }
+                }
+            }
+        };
+
+        let email = match session {
+            Some(api_key) => match session_user(tenant, api_key.clone(), pool).await? {
+                Some(email) => {
+                    query::revoke_api_key(tenant, api_key, pool).await?;
+                    Some(email.into_inner())
+                }
+                None => None,
+            },
+            None => None,
+        };
+
+        Ok((email, redirect))
+    }
+    .await;
+    let target = result.as_ref().ok().and_then(|(email, _)| email.clone());
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::Logout,
+        target,
+        detail,
+        result.map(|(_, redirect)| redirect),
+        pool,
+    )
+    .await
+}
+
+/// Whether a logout must be confirmed by the signed-in user before their session ends, so
+/// that other sites cannot sign them out with a link. A request carrying an ID token
+/// issued to that user is taken to come from a client they signed in to.
+pub async fn logout_needs_confirmation(
+    tenant: &TenantId,
+    session: Option<ApiKey>,
+    req: &ask::LogoutRequest,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<bool, ServiceError> {
+    let email = match session {
+        Some(api_key) => session_user(tenant, api_key, pool).await?,
+        None => None,
+    };
+    let email = match email {
+        Some(email) => email.into_inner(),
+        None => return Ok(false),
+    };
+
+    match &req.id_token_hint {
+        Some(hint) => match id_token_hint(tenant, hint, links, pool).await {
+            Ok(claims) => Ok(claims.sub != oidc::subject(tenant, &email)),
+            // refused by end_session before the session is touched
+            Err(_) => Ok(false),
+        },
+        None => Ok(true),
+    }
+}
+
+/// Claims of an ID token passed as `id_token_hint`. Expired tokens are accepted, as
+/// logging out often happens after they expire.
+async fn id_token_hint(
+    tenant: &TenantId,
+    hint: &str,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<IdToken, ServiceError> {
+    let invalid = || OAuthError::InvalidRequest("id_token_hint is invalid".to_string());
+    let kid = oidc::key_id(hint).map_err(|_| invalid())?;
+    let key: SigningKey = query::signing_keys(pool)
+        .await?
+        .into_iter()
+        .find(|key| key.id() == kid)
+        .ok_or_else(invalid)?
+        .try_into()?;
+    let claims: IdToken = key.verify(hint).map_err(|_| invalid())?;
+
+    match claims.iss == links.issuer(tenant) {
+        true => Ok(claims),
+        false => Err(invalid().into()),
+    }
+}
+
+/// Scopes asked of an identity provider unless others are configured.
+const DEFAULT_PROVIDER_SCOPES: [&str; 3] = ["openid", "email", "profile"];
+
+/// How long to wait for an identity provider to answer.
+const PROVIDER_TIMEOUT_SECS: u64 = 10;
+
+/// Adds an upstream OpenID Connect provider users may sign in with.
+pub async fn register_identity_provider(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    req: ask::NewIdentityProvider,
+    pool: &DatabasePool,
+) -> Result<IdentityProvider, ServiceError> {
+    let detail = json!({ "name": req.name.clone(), "issuer": req.issuer.clone() });
+    let result = async {
+        federation::validate_provider_id(&req.id)?;
+        federation::validate_issuer(&req.issuer)?;
+        if req.name.trim().is_empty() || req.client_id.is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                "name and client_id must not be empty".to_string(),
+            ));
+        }
+        let scopes = match &req.scopes {
+            Some(scopes) => scopes.clone(),
+            None => DEFAULT_PROVIDER_SCOPES.map(str::to_string).to_vec(),
+        };
+        if !scopes.iter().any(|scope| scope == oidc::OPENID) {
+            return Err(
+                FederationError::InvalidProvider("scopes must include openid".to_string()).into(),
+            );
+        }
+        if query::identity_provider(tenant, &req.id, pool)
+            .await?
+            .is_some()
+        {
+            return Err(FederationError::DuplicateProvider(req.id.clone()).into());
+        }
+
+        let model = model::NewIdentityProvider::new(&req, &scopes);
+        Ok(query::save_identity_provider(tenant, model, pool)
+            .await?
+            .try_into()?)
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::ProviderRegister,
+        Some(req.id),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+pub async fn list_identity_providers(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<IdentityProvider>, ServiceError> {
+    Ok(query::list_identity_providers(tenant, pool)
+        .await?
+        .into_iter()
+        .map(IdentityProvider::try_from)
+        .collect::<Result<_, _>>()?)
+}
+
+pub async fn get_identity_provider(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<IdentityProvider, ServiceError> {
+    Ok(query::identity_provider(tenant, id, pool)
+        .await?
+        .ok_or(FederationError::UnknownProvider)?
+        .try_into()?)
+}
+
+/// Removes a provider; identities linked through it can no longer sign in.
+pub async fn delete_identity_provider(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let result = query::delete_identity_provider(tenant, id, pool)
+        .await
+        .map_err(ServiceError::from);
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::ProviderDelete,
+        Some(id.to_string()),
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+/// The user signed in with `session`, who alone may link identities to their account.
+async fn link_session_user(
+    tenant: &TenantId,
+    session: Option<ApiKey>,
+    pool: &DatabasePool,
+) -> Result<String, ServiceError> {
+    let email = match session {
+        Some(session) => session_user(tenant, session, pool).await?,
+        None => None,
+    };
+
+    email
+        .map(Email::into_inner)
+        .ok_or_else(|| FederationError::LinkSession.into())
+}
+
+/// Where a provider sends users back to.
+fn federated_redirect_uri(tenant: &TenantId, provider_id: &str, links: &Links) -> String {
+    links.oauth(tenant, &format!("/login/{provider_id}/callback"))
+}
+
+/// Starts a sign-in through `provider_id`, returning the pending sign-in the browser must
+/// keep and the provider URL to send the user to. With `link_request`, a token from
+/// [`start_identity_link`], the identity is linked to the user who requested it instead;
+/// `session` must then be a session of that user, so the link only works in their browser.
+pub async fn start_federated_login(
+    tenant: &TenantId,
+    provider_id: &str,
+    return_to: Option<String>,
+    link_request: Option<Token>,
+    session: Option<ApiKey>,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<(FederatedLogin, String), ServiceError> {
+    let provider = get_identity_provider(tenant, provider_id, pool).await?;
+    let metadata = provider_metadata(&provider).await?;
+
+    let link_to = match link_request {
+        Some(token) => {
+            let email = link_session_user(tenant, session, pool).await?;
+            if !query::consume_identity_link_request(
+                tenant,
+                &token.hash(),
+                &provider.id,
+                &email,
+                pool,
+            )
+            .await?
+            {
+                return Err(FederationError::InvalidLinkRequest.into());
+            }
+            Some(email)
+        }
+        None => None,
+    };
+    let login = FederatedLogin::new(&provider.id, return_to, link_to);
+    let redirect_uri = federated_redirect_uri(tenant, &provider.id, links);
+    let url = login.authorization_url(&metadata, &provider, &redirect_uri)?;
+
+    Ok((login, url))
+}
+
+/// Finishes a sign-in through a provider once it sends the user back. The identity signs
+/// in as the user it has been linked to; on first sign-in, it is linked to the account
+/// with the same address if the provider has verified it and is trusted to do so.
+///
+/// Users with passkeys must then complete the sign-in with one, as after a password: no
+/// session is started, and the options returned start the ceremony that
+/// [`finish_passkey_login`] finishes.
+///
+/// A link is only made while `session` is still a session of the user who asked for it.
+#[allow(clippy::too_many_arguments)]
+pub async fn finish_federated_login(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    login: FederatedLogin,
+    callback: ask::FederatedCallback,
+    session: Option<ApiKey>,
+    mailer: &Mailer,
+    links: &Links,
+    rp: &RelyingParty,
+    pool: &DatabasePool,
+) -> Result<ask::FederatedSignIn, ServiceError> {
+    if let Some(link_to) = login.link_to.clone() {
+        let result = async {
+            if link_session_user(tenant, session, pool).await? != link_to {
+                return Err(FederationError::LinkSession.into());
+            }
+            let (provider, claims) =
+                verified_identity(tenant, &login, callback, links, pool).await?;
+            match query::federated_identity_email(tenant, &provider.id, &claims.sub, pool).await? {
+                Some(linked) if linked == link_to => Ok(()),
+                Some(_) => Err(FederationError::AlreadyLinked.into()),
+                None => {
+                    query::save_federated_identity(
+                        tenant,
+                        &provider.id,
+                        &claims.sub,
+                        &link_to,
+                        pool,
+                    )
+                    .await?;
+                    Ok(())
+                }
+            }
+        }
+        .await;
+
+        audit::record(
+            tenant,
+            ctx,
+            AuditAction::IdentityLink,
+            Some(link_to),
+            json!({ "provider": login.provider.clone(), "method": "link_request" }),
+            result,
+            pool,
+        )
+        .await?;
+        return Ok(ask::FederatedSignIn::Linked);
+    }
+
+    let mut second_factor = None;
+    let result = async {
+        let (provider, claims) = verified_identity(tenant, &login, callback, links, pool).await?;
+        let email =
+            match query::federated_identity_email(tenant, &provider.id, &claims.sub, pool).await? {
+                Some(email) => email,
+                None => link_by_email(tenant, ctx, &provider, &claims, pool).await?,
+            };
+
+        let user = get_user(
+            tenant,
+            ask::GetUser {
+                email: email.as_str().into(),
+                password: None,
+            },
+            pool,
+        )
+        .await?;
+        let checked = match sign_in_allowed(tenant, &user, pool).await {
+            Ok(()) if query::has_webauthn_credentials(tenant, &email, pool).await? => {
+                second_factor = Some(email);
+                Err(ServiceError::Forbidden(
+                    "passkey required as second factor".to_string(),
+                ))
+            }
+            checked => checked,
+        };
+        record_sign_in(tenant, ctx, &user, checked, mailer, links, pool).await?;
+
+        Ok(user)
+    }
+    .await;
+    let target = result
+        .as_ref()
+        .ok()
+        .map(|user| user.email.clone().into_inner())
+        .or_else(|| second_factor.clone());
+
+    let user = audit::record(
+        tenant,
+        ctx,
+        AuditAction::Login,
+        target,
+        json!({
+            "method": "federated",
+            "provider": login.provider.clone(),
+            "device_id": ctx.device_id.clone(),
+        }),
+        result,
+        pool,
+    )
+    .await;
+    let user = match (user, second_factor) {
+        (Err(ServiceError::Forbidden(_)), Some(email)) => {
+            let ceremony = Ceremony::FederatedSecondFactor;
+            let options = passkey_challenge(tenant, Some(email), ceremony, rp, pool).await?;
+            return Ok(ask::FederatedSignIn::PasskeyRequired(options));
+        }
+        (user, _) => user?,
+    };
+
+    start_session(tenant, ctx, user, pool)
+        .await
+        .map(ask::FederatedSignIn::SignedIn)
+}
+
+/// Links a new identity to the account with the address the provider has verified, if the
+/// provider may be trusted with that.
+async fn link_by_email(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    provider: &IdentityProvider,
+    claims: &ExternalIdToken,
+    pool: &DatabasePool,
+) -> Result<String, ServiceError> {
+    let email = match claims.verified_email() {
+        Some(email) if provider.link_by_email => email,
+        _ => return Err(FederationError::NotLinked.into()),
+    };
+    let user = get_user(
+        tenant,
+        ask::GetUser {
+            email: email.into(),
+            password: None,
+        },
+        pool,
+    )
+    .await;
+    let email = match user {
+        Ok(user) => user.email.into_inner(),
+        Err(ServiceError::NotFound | ServiceError::InvalidDetail) => {
+            return Err(FederationError::NotLinked.into())
+        }
+        Err(e) => return Err(e),
+    };
+
+    let result = query::save_federated_identity(tenant, &provider.id, &claims.sub, &email, pool)
+        .await
+        .map_err(ServiceError::from);
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::IdentityLink,
+        Some(email.clone()),
+        json!({ "provider": provider.id.clone(), "method": "verified_email" }),
+        result,
+        pool,
+    )
+    .await?;
+
+    Ok(email)
+}
+
+/// Redeems the code the provider sent the user back with and returns the validated claims
+/// of the ID token it is exchanged for.
+async fn verified_identity(
+    tenant: &TenantId,
+    login: &FederatedLogin,
+    callback: ask::FederatedCallback,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<(IdentityProvider, ExternalIdToken), ServiceError> {
+    if callback.state.as_deref() != Some(login.state.as_str()) {
+        return Err(FederationError::InvalidState.into());
+    }
+    if let Some(error) = callback.error {
+        return Err(FederationError::Refused(callback.error_description.unwrap_or(error)).into());
+    }
+    let code = callback
+        .code
+        .ok_or_else(|| FederationError::Refused("no authorization code".to_string()))?;
+
+    let provider = get_identity_provider(tenant, &login.provider, pool).await?;
+    let secret = query::identity_provider_secret(tenant, &provider.id, pool)
+        .await?
+        .ok_or(FederationError::UnknownProvider)?;
+    let metadata = provider_metadata(&provider).await?;
+
+    let redirect_uri = federated_redirect_uri(tenant, &provider.id, links);
+    let client = provider_client()?;
+    // client_secret_basic form-encodes both parts first (RFC 6749, section 2.3.1)
+    let encode =
+        |part: &str| -> String { url::form_urlencoded::byte_serialize(part.as_bytes()).collect() };
+    let response = client
+        .post(&metadata.token_endpoint)
+        .basic_auth(encode(&provider.client_id), Some(encode(&secret)))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", &redirect_uri),
+            ("code_verifier", &login.code_verifier),
+        ])
+        .send()
+        .await
+        .map_err(unavailable)?;
+
+    #[derive(serde::Deserialize)]
+    struct TokenResponse {
+        id_token: Option<String>,
+        error: Option<String>,
+        error_description: Option<String>,
+    }
+    let tokens: TokenResponse = response.json().await.map_err(unavailable)?;
+    let id_token = match tokens {
+        TokenResponse {
+            id_token: Some(id_token),
+            ..
+        } => id_token,
+        TokenResponse {
+            error_description: Some(error),
+            ..
+        }
+        | TokenResponse {
+            error: Some(error), ..
+        } => return Err(FederationError::Refused(error).into()),
+        _ => return Err(FederationError::Refused("no ID token".to_string()).into()),
+    };
+
+    let keys: JsonWebKeySet = client
+        .get(&metadata.jwks_uri)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(unavailable)?
+        .json()
+        .await
+        .map_err(unavailable)?;
+    let claims: ExternalIdToken = keys.verify(&id_token)?;
+    claims.validate(
+        &metadata.issuer,
+        &provider.client_id,
+        &login.nonce,
+        unix_time(),
+    )?;
+
+    Ok((provider, claims))
+}
+
+fn provider_client() -> Result<reqwest::Client, ServiceError> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(PROVIDER_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| unavailable(e).into())
+}
+
+fn unavailable(error: reqwest::Error) -> FederationError {
+    FederationError::Unavailable(error.to_string())
+}
+
+/// Fetches a provider's discovery document, which must be for the configured issuer.
+async fn provider_metadata(provider: &IdentityProvider) -> Result<ProviderMetadata, ServiceError> {
+    let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+    let metadata: ProviderMetadata = provider_client()?
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(unavailable)?
+        .json()
+        .await
+        .map_err(unavailable)?;
+
+    match metadata.issuer.trim_end_matches('/') == provider.issuer {
+        true => Ok(metadata),
+        false => Err(FederationError::Unavailable(format!(
+            "discovery document is for another issuer: {}",
+            metadata.issuer
+        ))
+        .into()),
+    }
+}
+
+/// How recently a session must have signed in to link an identity without confirming the
+/// password.
+const REAUTHENTICATION_WINDOW_SECS: i64 = 5 * 60;
+
+const IDENTITY_LINK_TTL: &str = "+10 minutes";
+const IDENTITY_LINK_TTL_SECS: u64 = 10 * 60;
+
+/// The user's ways to sign in.
+pub async fn list_identities(
+    tenant: &TenantId,
+    email: Email,
+    pool: &DatabasePool,
+) -> Result<Vec<Identity>, ServiceError> {
+    Ok(query::list_identities(tenant, &email.into_inner(), pool)
+        .await?
+        .into_iter()
+        .map(Identity::try_from)
+        .collect::<Result<_, _>>()?)
+}
+
+/// Starts linking an identity at a provider to the user, who must have re-authenticated:
+/// either by confirming their password, or by having started `session` within the last
+/// few minutes. Returns where to send the user's browser to sign in at the provider.
+pub async fn start_identity_link(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    email: Email,
+    req: ask::LinkIdentity,
+    session: Option<ApiKey>,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<ask::IdentityLinkStarted, ServiceError> {
+    let target = email.clone().into_inner();
+    let detail = json!({
+        "provider": req.provider.clone(),
+        "password": req.password.is_some(),
+    });
+    let result = async {
+        let provider = get_identity_provider(tenant, &req.provider, pool).await?;
+
+        let reauthenticated = match req.password {
+            Some(password) => {
+                let user = get_user(
+                    tenant,
+                    ask::GetUser {
+                        email: email.clone(),
+                        password: None,
+                    },
+                    pool,
+                )
+                .await?;
+                password == user.password
+            }
+            None => match session {
+                Some(api_key) => query::session_age(tenant, api_key, pool)
+                    .await?
+                    .is_some_and(|age| age <= REAUTHENTICATION_WINDOW_SECS),
+                None => false,
+            },
+        };
+        if !reauthenticated {
+            return Err(IdentityError::ReauthenticationRequired.into());
+        }
+
+        let token = Token::generate();
+        query::save_identity_link_request(
+            tenant,
+            &token.hash(),
+            &target,
+            &provider.id,
+            IDENTITY_LINK_TTL,
+            pool,
+        )
+        .await?;
+
+        Ok(ask::IdentityLinkStarted {
+            url: links.oauth(tenant, &format!("/login/{}?link={token}", provider.id)),
+            expires_in: IDENTITY_LINK_TTL_SECS,
+        })
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::IdentityLinkStart,
+        Some(target.clone()),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+/// Removes one of the user's ways to sign in, unless it is the last. Removing the password
+/// replaces it with a random one nobody knows, until the user sets a new one.
+pub async fn unlink_identity(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    email: Email,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let email = email.into_inner();
+    let unusable_password = Token::generate();
+    let result =
+        match query::delete_identity(tenant, &email, id, unusable_password.as_str(), pool).await {
+            Ok(query::IdentityDeletion::Deleted(kind)) => Ok(kind),
+            Ok(query::IdentityDeletion::NotFound) => Err(IdentityError::UnknownIdentity.into()),
+            Ok(query::IdentityDeletion::LastSignInMethod) => {
+                Err(IdentityError::LastSignInMethod.into())
+            }
+            Err(e) => Err(e.into()),
+        };
+    let detail = match &result {
+        Ok(kind) => json!({ "identity": id, "kind": kind }),
+        Err(_) => json!({ "identity": id }),
+    };
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::IdentityUnlink,
+        Some(email),
+        detail,
+        result.map(|_| ()),
+        pool,
+    )
+    .await
+}
+
+pub async fn list_access_rules(
+    tenant: &TenantId,
+    pool: &DatabasePool,
+) -> Result<Vec<AccessRule>, ServiceError> {
+    Ok(query::list_access_rules(tenant, pool)
+        .await?
+        .into_iter()
+        .map(AccessRule::try_from)
+        .collect::<Result<_, _>>()?)
+}
+
+/// Sets who may reach `host` through the proxy, replacing its rule if it has one.
+pub async fn set_access_rule(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    host: &str,
+    req: ask::SetAccessRule,
+    pool: &DatabasePool,
+) -> Result<AccessRule, ServiceError> {
+    let detail = json!({ "roles": req.roles.clone(), "groups": req.groups.clone() });
+    let result = async {
+        access::validate_host(host)?;
+        let roles = list_roles(tenant, pool).await?;
+        if let Some(role) = req
+            .roles
+            .iter()
+            .find(|name| !roles.iter().any(|role| &role.name == *name))
+        {
+            return Err(ServiceError::InvalidRequest(format!(
+                "unknown role: {role}"
+            )));
+        }
+        let groups = list_groups(tenant, pool).await?;
+        if let Some(group) = req
+            .groups
+            .iter()
+            .find(|name| !groups.iter().any(|group| &group.name == *name))
+        {
+            return Err(ServiceError::InvalidRequest(format!(
+                "unknown group: {group}"
+            )));
+        }
+
+        let model = model::NewAccessRule::new(host, &req);
+        Ok(query::save_access_rule(tenant, model, pool)
+            .await?
+            .try_into()?)
+    }
+    .await;
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::AccessRuleSet,
+        Some(host.to_string()),
+        detail,
+        result,
+        pool,
+    )
+    .await
+}
+
+/// Removes the rule for `host`, closing it to everyone unless a wildcard covers it.
+pub async fn delete_access_rule(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    host: &str,
+    pool: &DatabasePool,
+) -> Result<query::DeletionStatus, ServiceError> {
+    let result = query::delete_access_rule(tenant, host, pool)
+        .await
+        .map_err(ServiceError::from);
+
+    audit::record(
+        tenant,
+        ctx,
+        AuditAction::AccessRuleDelete,
+        Some(host.to_string()),
+        json!({}),
+        result,
+        pool,
+    )
+    .await
+}
+
+/// Decides whether a request a reverse proxy received for `host` may pass, given the
+/// session key it came with. Hosts without a rule are closed to everyone.
+///
+/// Not audited: the proxy asks about every request.
+pub async fn verify_access(
+    tenant: &TenantId,
+    host: &str,
+    session: Option<ApiKey>,
+    pool: &DatabasePool,
+) -> Result<ask::VerifiedAccess, ServiceError> {
+    let host = access::normalize_host(host);
+    let rules = list_access_rules(tenant, pool).await?;
+    let rule = access::find_rule(&rules, &host).ok_or(AccessError::UnknownHost(host.clone()))?;
+
+    let email = match session {
+        Some(api_key) => session_user(tenant, api_key, pool)
+            .await?
+            .map(Email::into_inner),
+        None => None,
+    };
+    let user = match email {
+        Some(email) => active_user(tenant, &email, pool).await?,
+        None => None,
+    };
+    let user = user.ok_or(AccessError::Unauthenticated)?;
+
+    let email = user.email.clone().into_inner();
+    let groups = query::user_groups(tenant, &email, pool).await?;
+    if !rule.allows(&user.roles, &groups) {
+        return Err(AccessError::Denied(host).into());
+    }
+
+    Ok(ask::VerifiedAccess {
+        subject: oidc::subject(tenant, &email),
+        email: user.email,
+        roles: user.roles,
+    })
+}
+
+/// Checks where to send a user after signing in for a host behind the proxy: an http(s)
+/// URL of a host with an access rule, so the sign-in page cannot be used to redirect
+/// anywhere else.
+pub async fn forward_destination(
+    tenant: &TenantId,
+    url: &str,
+    pool: &DatabasePool,
+) -> Result<String, ServiceError> {
+    let invalid = || ServiceError::InvalidRequest("invalid destination".to_string());
+    let url = url::Url::parse(url).map_err(|_| invalid())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid());
+    }
+    let host = url
+        .host_str()
+        .map(access::normalize_host)
+        .ok_or_else(invalid)?;
+
+    let rules = list_access_rules(tenant, pool).await?;
+    match access::find_rule(&rules, &host) {
+        Some(_) => Ok(url.to_string()),
+        None => Err(AccessError::UnknownHost(host).into()),
+    }
+}
+
+/// A page of users in their SCIM representation, optionally filtered with
+/// `attribute eq value` on `userName`, `emails.value`, `id`, `displayName` or `active`.
+/// `start_index` counts from 1.
+pub async fn scim_users(
+    tenant: &TenantId,
+    links: &Links,
+    filter: Option<&str>,
+    start_index: u32,
+    count: u32,
+    pool: &DatabasePool,
+) -> Result<ListResponse<ScimUser>, ServiceError> {
+    let start_index = start_index.max(1);
+    let mut req = ask::FindUsers {
+        offset: start_index - 1,
+        limit: count.min(scim::MAX_RESULTS),
+        ..Default::default()
+    };
+    if let Some(filter) = filter.map(Filter::parse).transpose()? {
+        if ["userName", "id", "emails", "emails.value"]
+            .iter()
+            .any(|attribute| filter.is_on(attribute))
+        {
+            req.email = Some(Email::new(filter.text()?)?);
+        } else if filter.is_on("displayName") || filter.is_on("name.formatted") {
+            req.name = Some(filter.text()?.to_string());
+        } else if filter.is_on("active") {
+            req.active = Some(filter.value.as_bool().ok_or_else(|| {
+                ScimError::InvalidFilter("active takes true or false".to_string())
+            })?);
+        } else {
+            return Err(ScimError::InvalidFilter(filter.attribute).into());
+        }
+    }
+
+    let (users, total) = query::find_users(tenant, req, pool).await?;
+    let mut resources = Vec::with_capacity(users.len());
+    for user in users {
+        resources.push(scim_user_of(tenant, links, user.try_into()?, pool).await?);
+    }
+
+    Ok(ListResponse::new(resources, total as u64, start_index))
+}
+
+pub async fn scim_user(
+    tenant: &TenantId,
+    links: &Links,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<ScimUser, ServiceError> {
+    let user = scim_target(tenant, id, pool).await?;
+
+    scim_user_of(tenant, links, user, pool).await
+}
+
+/// Provisions a user, with a random password unless one is given so they sign in some
+/// other way, such as through their identity provider.
+pub async fn create_scim_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    links: &Links,
+    user: ScimUser,
+    pool: &DatabasePool,
+) -> Result<ScimUser, ServiceError> {
+    let email = Email::new(user.user_name.trim())
+        .map_err(|_| ScimError::InvalidValue("userName is required".to_string()))?;
+    match scim_target(tenant, user.user_name.trim(), pool).await {
+        Ok(_) => return Err(ScimError::Uniqueness(format!("user {}", user.user_name)).into()),
+        Err(ServiceError::Scim(ScimError::UnknownResource)) => (),
+        Err(e) => return Err(e),
+    }
+
+    let name = user
+        .name_given("")
+        .unwrap_or_else(|| user.user_name.trim().to_string());
+    let password = match &user.password {
+        Some(password) => Password::new(password)?,
+        None => Password::new(Token::generate().as_str())?,
+    };
+    let phone = scim_phone(&user, None)?;
+    let created = new_user(
+        tenant,
+        ctx,
+        ask::NewUser {
+            email: email.clone(),
+            name: Name::new(&name)?,
+            password,
+            phone,
+        },
+        pool,
+    )
+    .await?;
+
+    let created = match user.active {
+        Some(false) => set_scim_status(tenant, ctx, actor, email, Status::Disabled, pool).await?,
+        _ => created,
+    };
+
+    scim_user_of(tenant, links, created, pool).await
+}
+
+/// Replaces the attributes of a user that Authy keeps: their name, phone number, password
+/// and whether they are active. `userName` cannot change.
+pub async fn replace_scim_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    links: &Links,
+    id: &str,
+    user: ScimUser,
+    pool: &DatabasePool,
+) -> Result<ScimUser, ServiceError> {
+    let current = scim_target(tenant, id, pool).await?;
+    let email = current.email.clone();
+    if !user.user_name.is_empty() && !user.user_name.eq_ignore_ascii_case(id) {
+        return Err(ScimError::Mutability("userName".to_string()).into());
+    }
+
+    let update = ask::UpdateUser {
+        email: email.clone(),
+        name: user
+            .name_given(&current.name.clone().into_inner())
+            .map(|name| Name::new(&name))
+            .transpose()?,
+        password: user.password.as_deref().map(Password::new).transpose()?,
+        phone: scim_phone(&user, current.phone.as_ref())?,
+        user_metadata: None,
+    };
+    if update.name.is_some() || update.password.is_some() || update.phone.is_some() {
+        update_user(tenant, ctx, update, pool).await?;
+    }
+
+    let status = match (current.status, user.active) {
+        (Status::Active, Some(false)) => Some(Status::Disabled),
+        (Status::Disabled | Status::Locked | Status::PendingVerification, Some(true)) => {
+            Some(Status::Active)
+        }
+        _ => None,
+    };
+    if let Some(status) = status {
+        set_scim_status(tenant, ctx, actor, email.clone(), status, pool).await?;
+    }
+
+    let user = scim_target(tenant, &email.into_inner(), pool).await?;
+    scim_user_of(tenant, links, user, pool).await
+}
+
+/// Applies PATCH operations to the user's SCIM representation and replaces the user with
+/// the result.
+pub async fn patch_scim_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    links: &Links,
+    id: &str,
+    req: PatchRequest,
+    pool: &DatabasePool,
+) -> Result<ScimUser, ServiceError> {
+    let mut resource = json!(scim_user(tenant, links, id, pool).await?);
+    for operation in &req.operations {
+        scim::apply_patch(&mut resource, operation)?;
+    }
+    let user: ScimUser =
+        serde_json::from_value(resource).map_err(|e| ScimError::InvalidValue(e.to_string()))?;
+
+    replace_scim_user(tenant, ctx, actor, links, id, user, pool).await
+}
+
+/// Deprovisions a user, who is soft-deleted like through `DELETE /api/user/`.
+pub async fn delete_scim_user(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let req = ask::DeleteUser {
+        email: Email::from(id),
+        permanent: false,
+    };
+    match delete_user(tenant, ctx, req, pool).await? {
+        query::DeletionStatus::Deleted => Ok(()),
+        query::DeletionStatus::NotFound => Err(ScimError::UnknownResource.into()),
+    }
+}
+
+/// A page of groups, optionally filtered with `displayName eq value` or `id eq value`.
+pub async fn scim_groups(
+    tenant: &TenantId,
+    links: &Links,
+    filter: Option<&str>,
+    start_index: u32,
+    count: u32,
+    pool: &DatabasePool,
+) -> Result<ListResponse<ScimGroup>, ServiceError> {
+    let start_index = start_index.max(1);
+    let filter = filter.map(Filter::parse).transpose()?;
+    let mut groups = list_groups(tenant, pool).await?;
+    if let Some(filter) = filter {
+        let value = filter.text()?.to_lowercase();
+        if filter.is_on("displayName") {
+            groups.retain(|group| scim_display_name(group).to_lowercase() == value);
+        } else if filter.is_on("id") {
+            groups.retain(|group| group.name.to_string() == value);
+        } else {
+            return Err(ScimError::InvalidFilter(filter.attribute).into());
+        }
+    }
+
+    let total = groups.len() as u64;
+    let mut resources = vec![];
+    for group in groups
+        .into_iter()
+        .skip(start_index as usize - 1)
+        .take(count.min(scim::MAX_RESULTS) as usize)
+    {
+        resources.push(scim_group_of(tenant, links, group, pool).await?);
+    }
+
+    Ok(ListResponse::new(resources, total, start_index))
+}
+
+pub async fn scim_group(
+    tenant: &TenantId,
+    links: &Links,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<ScimGroup, ServiceError> {
+    let group = scim_group_target(tenant, id, pool).await?;
+
+    scim_group_of(tenant, links, group, pool).await
+}
+
+/// Creates a group named after its `displayName`, which becomes its description.
+pub async fn create_scim_group(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    links: &Links,
+    group: ScimGroup,
+    pool: &DatabasePool,
+) -> Result<ScimGroup, ServiceError> {
+    let name = group.name()?;
+    let groups = list_groups(tenant, pool).await?;
+    if groups.iter().any(|existing| existing.name == name) {
+        return Err(ScimError::Uniqueness(format!("group {name}")).into());
+    }
+
+    let saved = save_group(
+        tenant,
+        ctx,
+        Group {
+            name,
+            description: group.display_name.trim().to_string(),
+        },
+        pool,
+    )
+    .await?;
+    set_scim_members(tenant, ctx, &saved.name, &group.members, pool).await?;
+
+    scim_group_of(tenant, links, saved, pool).await
+}
+
+/// Replaces the description and direct members of a group.
+pub async fn replace_scim_group(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    links: &Links,
+    id: &str,
+    group: ScimGroup,
+    pool: &DatabasePool,
+) -> Result<ScimGroup, ServiceError> {
+    let mut current = scim_group_target(tenant, id, pool).await?;
+    let display_name = group.display_name.trim();
+    if !display_name.is_empty() && display_name != scim_display_name(&current) {
+        current.description = display_name.to_string();
+        current = save_group(tenant, ctx, current, pool).await?;
+    }
+    set_scim_members(tenant, ctx, &current.name, &group.members, pool).await?;
+
+    scim_group_of(tenant, links, current, pool).await
+}
+
+/// Applies PATCH operations, typically adding or removing members, to the group's SCIM
+/// representation and replaces the group with the result.
+pub async fn patch_scim_group(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    links: &Links,
+    id: &str,
+    req: PatchRequest,
+    pool: &DatabasePool,
+) -> Result<ScimGroup, ServiceError> {
+    let mut resource = json!(scim_group(tenant, links, id, pool).await?);
+    for operation in &req.operations {
+        scim::apply_patch(&mut resource, operation)?;
+    }
+    let group: ScimGroup =
+        serde_json::from_value(resource).map_err(|e| ScimError::InvalidValue(e.to_string()))?;
+
+    replace_scim_group(tenant, ctx, links, id, group, pool).await
+}
+
+pub async fn delete_scim_group(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let name = GroupName::new(id).map_err(|_| ScimError::UnknownResource)?;
+    match delete_group(tenant, ctx, name, pool).await? {
+        query::DeletionStatus::Deleted => Ok(()),
+        query::DeletionStatus::NotFound => Err(ScimError::UnknownResource.into()),
+    }
+}
+
+/// The user a SCIM `id` names.
+async fn scim_target(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let req = ask::GetUser {
+        email: Email::from(id),
+        password: None,
+    };
+    match get_user(tenant, req, pool).await {
+        Err(ServiceError::InvalidDetail) => Err(ScimError::UnknownResource.into()),
+        result => result,
+    }
+}
+
+async fn scim_user_of(
+    tenant: &TenantId,
+    links: &Links,
+    user: User,
+    pool: &DatabasePool,
+) -> Result<ScimUser, ServiceError> {
+    let email = user.email.into_inner();
+    let name = user.name.into_inner();
+    let groups = query::user_groups(tenant, &email, pool).await?;
+
+    Ok(ScimUser {
+        schemas: vec![scim::USER_SCHEMA.to_string()],
+        id: Some(email.clone()),
+        user_name: email.clone(),
+        name: Some(ScimName {
+            formatted: Some(name.clone()),
+            ..Default::default()
+        }),
+        display_name: Some(name),
+        active: Some(user.status == Status::Active),
+        password: None,
+        emails: vec![MultiValued {
+            value: email.clone(),
+            kind: Some("work".to_string()),
+            primary: Some(true),
+        }],
+        phone_numbers: user
+            .phone
+            .map(|phone| MultiValued {
+                value: phone.into_inner(),
+                kind: Some("mobile".to_string()),
+                primary: Some(true),
+            })
+            .into_iter()
+            .collect(),
+        groups: groups
+            .into_iter()
+            .map(|group| Member {
+                reference: Some(links.scim(tenant, &format!("/Groups/{group}"))),
+                value: group,
+                display: None,
+                kind: None,
+            })
+            .collect(),
+        meta: Some(Meta {
+            resource_type: "User".to_string(),
+            location: links.scim(tenant, &format!("/Users/{email}")),
+        }),
+    })
+}
+
+/// The user's phone number to set: the primary one of `phoneNumbers`, or else the first,
+/// unless it is the one they already have.
+fn scim_phone(user: &ScimUser, current: Option<&Phone>) -> Result<Option<Phone>, ServiceError> {
+    let number = user
+        .phone_numbers
+        .iter()
+        .find(|phone| phone.primary == Some(true))
+        .or_else(|| user.phone_numbers.first());
+    match number {
+        Some(number) if current.map(Phone::as_str) != Some(number.value.as_str()) => {
+            Ok(Some(Phone::new(&number.value)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+async fn set_scim_status(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    actor: &Principal,
+    email: Email,
+    status: Status,
+    pool: &DatabasePool,
+) -> Result<User, ServiceError> {
+    let req = ask::UpdateStatus {
+        email,
+        status,
+        reason: Some("changed through SCIM".to_string()),
+    };
+
+    set_user_status(tenant, ctx, actor, req, pool).await
+}
+
+async fn scim_group_target(
+    tenant: &TenantId,
+    id: &str,
+    pool: &DatabasePool,
+) -> Result<Group, ServiceError> {
+    list_groups(tenant, pool)
+        .await?
+        .into_iter()
+        .find(|group| group.name.to_string() == id)
+        .ok_or_else(|| ScimError::UnknownResource.into())
+}
+
+/// A group's `displayName`: its description, or its name if it has none.
+fn scim_display_name(group: &Group) -> String {
+    match group.description.is_empty() {
+        true => group.name.to_string(),
+        false => group.description.clone(),
+    }
+}
+
+async fn scim_group_of(
+    tenant: &TenantId,
+    links: &Links,
+    group: Group,
+    pool: &DatabasePool,
+) -> Result<ScimGroup, ServiceError> {
+    let display_name = scim_display_name(&group);
+    let name = group.name.to_string();
+    let members = group_members(tenant, group.name, pool).await?;
+    let users = members.users.into_iter().map(|email| {
+        let email = email.into_inner();
+        Member {
+            reference: Some(links.scim(tenant, &format!("/Users/{email}"))),
+            value: email,
+            display: None,
+            kind: Some("User".to_string()),
+        }
+    });
+    let groups = members.groups.into_iter().map(|group| Member {
+        reference: Some(links.scim(tenant, &format!("/Groups/{group}"))),
+        value: group.into_inner(),
+        display: None,
+        kind: Some("Group".to_string()),
+    });
+
+    Ok(ScimGroup {
+        schemas: vec![scim::GROUP_SCHEMA.to_string()],
+        id: Some(name.clone()),
+        display_name,
+        members: users.chain(groups).collect(),
+        meta: Some(Meta {
+            resource_type: "Group".to_string(),
+            location: links.scim(tenant, &format!("/Groups/{name}")),
+        }),
+    })
+}
+
+/// Makes `members` the direct members of a group, adding and removing the difference.
+async fn set_scim_members(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    group: &GroupName,
+    members: &[Member],
+    pool: &DatabasePool,
+) -> Result<(), ServiceError> {
+    let mut wanted = vec![];
+    for member in members {
+        let unknown = || ScimError::InvalidValue(format!("unknown member: {}", member.value));
+        let member = match member.is_group() {
+            true => {
+                let name = GroupName::new(&member.value).map_err(|_| unknown())?;
+                scim_group_target(tenant, &member.value, pool)
+                    .await
+                    .map_err(|_| unknown())?;
+                GroupMember::Group(name)
+            }
+            false => GroupMember::User(
+                scim_target(tenant, &member.value, pool)
+                    .await
+                    .map_err(|_| unknown())?
+                    .email,
+            ),
+        };
+        wanted.push(member);
+    }
+
+    let current = group_members(tenant, group.clone(), pool).await?;
+    let current: Vec<GroupMember> = current
+        .users
+        .into_iter()
+        .map(GroupMember::User)
+        .chain(current.groups.into_iter().map(GroupMember::Group))
+        .collect();
+
+    for member in current.iter().filter(|member| !wanted.contains(member)) {
+        let req = ask::GroupMembership {
+            group: group.clone(),
+            member: member.clone(),
+        };
+        remove_group_member(tenant, ctx, req, pool).await?;
+    }
+    for member in wanted.iter().filter(|member| !current.contains(member)) {
+        let req = ask::GroupMembership {
+            group: group.clone(),
+            member: member.clone(),
+        };
+        add_group_member(tenant, ctx, req, pool).await?;
+    }
+
+    Ok(())
+}
+
+/// A page of the users the LDAP listener serves, or only the one with `email`, each with
+/// the groups they belong to.
+pub async fn directory_users(
+    tenant: &TenantId,
+    email: Option<Email>,
+    offset: u32,
+    limit: u32,
+    pool: &DatabasePool,
+) -> Result<Vec<(User, Vec<GroupName>)>, ServiceError> {
+    let req = ask::FindUsers {
+        email,
+        offset,
+        limit,
+        ..Default::default()
+    };
+    let (users, _) = query::find_users(tenant, req, pool).await?;
+
+    let mut entries = Vec::with_capacity(users.len());
+    for user in users {
+        let user: User = user.try_into()?;
+        let groups = user_groups(tenant, user.email.clone(), pool).await?;
+        entries.push((user, groups));
+    }
+    Ok(entries)
 }
@@ -1,3 +1,9 @@
+use crate::domain::audit::{AuditEvent, AuditOutcome};
+use crate::domain::group::{GroupMember, GroupName};
+use crate::domain::oauth::{Client, ClientType, Scope, UserCode};
+use crate::domain::otp::{Channel, OneTimeCode};
+use crate::domain::role::RoleName;
+use crate::domain::token::Token;
 use crate::domain::user::field;
 use crate::Email;
 
@@ -24,6 +30,8 @@ pub struct NewUser {
     pub email: Email,
     pub name: field::Name,
     pub password: field::Password,
+    #[serde(default)]
+    pub phone: Option<field::Phone>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -31,4 +39,544 @@ pub struct UpdateUser {
     pub email: Email,
     pub name: Option<field::Name>,
     pub password: Option<field::Password>,
+    #[serde(default)]
+    pub phone: Option<field::Phone>,
+    /// JSON merge patch applied to the user's own metadata.
+    #[serde(default)]
+    pub user_metadata: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeleteUser {
+    pub email: Email,
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UpdateStatus {
+    pub email: Email,
+    pub status: field::Status,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, strum::EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SortBy {
+    #[default]
+    CreatedAt,
+    Name,
+    Email,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, strum::EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ListUsers {
+    pub status: Option<field::Status>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    pub email_domain: Option<String>,
+    /// Matches the start of the user's name or email.
+    pub search: Option<String>,
+    #[serde(default)]
+    pub sort: SortBy,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UserPage {
+    pub users: Vec<ListedUser>,
+    pub next_cursor: Option<String>,
+}
+
+/// A user as listed to administrators, without their password.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ListedUser {
+    pub name: field::Name,
+    pub email: Email,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<field::Phone>,
+    pub status: field::Status,
+    pub user_metadata: field::Metadata,
+    pub app_metadata: field::Metadata,
+    pub roles: Vec<RoleName>,
+}
+
+impl From<crate::User> for ListedUser {
+    fn from(user: crate::User) -> Self {
+        Self {
+            name: user.name,
+            email: user.email,
+            phone: user.phone,
+            status: user.status,
+            user_metadata: user.user_metadata,
+            app_metadata: user.app_metadata,
+            roles: user.roles,
+        }
+    }
+}
+
+/// Users matching a SCIM filter or LDAP search, found a page at a time.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct FindUsers {
+    pub email: Option<Email>,
+    pub name: Option<String>,
+    /// Whether the users are active, or in any other status.
+    pub active: Option<bool>,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UpdateAppMetadata {
+    pub email: Email,
+    /// JSON merge patch applied to the admin-only metadata.
+    pub app_metadata: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RoleGrant {
+    pub email: Email,
+    pub role: RoleName,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IssuedKey {
+    pub api_key: String,
+    pub email: Email,
+    pub roles: Vec<RoleName>,
+    /// Effective group memberships, including those inherited through nested groups.
+    pub groups: Vec<GroupName>,
+    /// Id of the session the key starts, for listing and revoking it later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Set when the key was issued for impersonation; such keys expire on their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator: Option<Email>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GroupMembership {
+    pub group: GroupName,
+    pub member: GroupMember,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NewInvitation {
+    pub email: Email,
+    pub role: RoleName,
+    #[serde(default = "NewInvitation::default_expiry")]
+    pub expires_in_days: u32,
+}
+
+impl NewInvitation {
+    pub const MAX_EXPIRY_DAYS: u32 = 30;
+
+    fn default_expiry() -> u32 {
+        7
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AcceptInvitation {
+    pub token: Token,
+    /// Only needed when the invitee does not have an account yet.
+    pub name: Option<field::Name>,
+    pub password: Option<field::Password>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MagicLinkRequest {
+    pub email: Email,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OtpRequest {
+    pub email: Email,
+    /// SMS codes go to the phone number on the account.
+    #[serde(default)]
+    pub channel: Channel,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OtpLogin {
+    pub email: Email,
+    pub code: OneTimeCode,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Impersonate {
+    pub email: Email,
+    /// Recorded in the audit log, e.g. a support ticket reference.
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ListAuditEvents {
+    pub actor: Option<Email>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Id of the last event of the previous page; events are listed newest first.
+    pub cursor: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuditVerification {
+    /// Number of hash-chained events checked.
+    pub verified: u64,
+    /// First event whose hash does not match, if the chain was tampered with.
+    pub broken_at: Option<i64>,
+}
+
+/// Identifies a credential to the browser, in the WebAuthn JSON encoding.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transports: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyUser {
+    /// Opaque user handle; does not reveal the email.
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// Options for `navigator.credentials.create()`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyCreationOptions {
+    pub challenge: String,
+    pub rp: RelyingPartyEntity,
+    pub user: PasskeyUser,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    /// Milliseconds.
+    pub timeout: u32,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: String,
+}
+
+/// How a sign-in with an emailed link or a one-time code ended.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum SignIn {
+    SignedIn(IssuedKey),
+    /// The user has passkeys, one of which must complete the sign-in through
+    /// `/api/user/webauthn/login/finish`.
+    PasskeyRequired {
+        passkey_required: PasskeyRequestOptions,
+    },
+}
+
+/// Options for `navigator.credentials.get()`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyRequestOptions {
+    pub challenge: String,
+    /// Milliseconds.
+    pub timeout: u32,
+    pub rp_id: String,
+    /// Empty for passkey-first sign-in, letting the authenticator offer its passkeys.
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// Result of `navigator.credentials.create()`, binary fields base64url encoded.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RegisterPasskey {
+    pub id: String,
+    pub response: AttestationResponse,
+    /// Label for telling passkeys apart, e.g. "work laptop".
+    pub name: Option<String>,
+}
+
+/// Starts a passkey sign-in. Without an email any passkey for this site is accepted; with a
+/// password, the passkey completes a password sign-in as a second factor.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PasskeyLoginStart {
+    pub email: Option<Email>,
+    pub password: Option<field::Password>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+/// Result of `navigator.credentials.get()`, binary fields base64url encoded.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PasskeyAssertion {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NewClient {
+    pub name: String,
+    pub client_type: ClientType,
+    pub redirect_uris: Vec<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub post_logout_redirect_uris: Vec<String>,
+}
+
+/// A newly registered client. The secret of a confidential client is only ever shown here.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RegisteredClient {
+    #[serde(flatten)]
+    pub client: Client,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+/// Query of an OAuth authorization request.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// OpenID Connect: echoed in the ID token.
+    pub nonce: Option<String>,
+    /// OpenID Connect: `none` fails instead of showing the sign-in page.
+    pub prompt: Option<String>,
+}
+
+/// Form posted to the token endpoint; which fields are needed depends on `grant_type`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub device_code: Option<String>,
+    pub scope: Option<String>,
+    /// Client credentials sent in the body instead of an `Authorization` header.
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    /// Issued when the `openid` scope was granted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// Form posted to the device authorization endpoint (RFC 8628, section 3.1).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeviceAuthorizationRequest {
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Codes for a device to show its user and to poll the token endpoint with.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    /// `verification_uri` with the user code filled in, e.g. for a QR code.
+    pub verification_uri_complete: String,
+    pub expires_in: u64,
+    /// Seconds to wait between polls.
+    pub interval: u64,
+}
+
+/// A device request waiting for the user to approve it.
+#[derive(Debug, Clone)]
+pub struct DeviceRequest {
+    pub client: Client,
+    pub scope: Scope,
+    pub user_code: UserCode,
+}
+
+/// Form posted to the introspection and revocation endpoints.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TokenLookup {
+    pub token: String,
+    /// `access_token` or `refresh_token`; every kind of token is looked up regardless.
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Introspection response (RFC 7662, section 2.2); only `active` is set for tokens that
+/// are unknown, expired or revoked.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TokenIntrospection {
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Email of the user the token acts for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// `access_token`, `refresh_token` or `session` for API keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+}
+
+/// RP-initiated logout request.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LogoutRequest {
+    /// An ID token issued to the client, identifying it.
+    pub id_token_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub post_logout_redirect_uri: Option<String>,
+    pub state: Option<String>,
+}
+
+/// An upstream OpenID Connect provider to add. Authy must be registered with it as a
+/// confidential client, with `/oauth/login/<id>/callback` as the redirect URI.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NewIdentityProvider {
+    pub id: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Defaults to `openid email profile`.
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    #[serde(default)]
+    pub link_by_email: bool,
+}
+
+/// Query the provider sends the user back with.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FederatedCallback {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// How a sign-in through an identity provider ended.
+#[derive(Debug, Clone)]
+pub enum FederatedSignIn {
+    /// The identity was linked to the user who asked for it; no session is started.
+    Linked,
+    SignedIn(IssuedKey),
+    /// The user has passkeys, one of which must complete the sign-in.
+    PasskeyRequired(PasskeyRequestOptions),
+}
+
+/// Request to link an identity at a provider to the calling user. Without `password`, the
+/// session must have signed in within the last few minutes.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LinkIdentity {
+    pub provider: String,
+    #[serde(default)]
+    pub password: Option<field::Password>,
+}
+
+/// Where to send the user's browser to sign in at the provider, which links the identity.
+/// The browser must be signed in to Authy as the same user.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IdentityLinkStarted {
+    pub url: String,
+    pub expires_in: u64,
+}
+
+/// Who may reach a host behind the proxy. With neither roles nor groups, every signed-in
+/// user may.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SetAccessRule {
+    #[serde(default)]
+    pub roles: Vec<RoleName>,
+    #[serde(default)]
+    pub groups: Vec<GroupName>,
+}
+
+/// The user a request through the proxy is made by, passed on to the host in headers.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VerifiedAccess {
+    pub subject: String,
+    pub email: Email,
+    pub roles: Vec<RoleName>,
 }
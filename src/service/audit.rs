@@ -0,0 +1,49 @@
+use super::ServiceError;
+use crate::data::{model, query, DatabasePool};
+use crate::domain::audit::{AuditAction, AuditOutcome};
+use crate::domain::tenant::TenantId;
+use crate::Email;
+
+/// Who made a request and from where; attached to every audit event.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub actor: Option<Email>,
+    pub impersonator: Option<Email>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    /// Value of the device cookie, if the client sent one.
+    pub device_id: Option<String>,
+}
+
+/// Records the outcome of an action and passes its result through.
+///
+/// If the event cannot be written, a successful result is turned into an error so nothing
+/// happens unrecorded without the caller noticing; a failed result keeps its own error.
+pub(super) async fn record<T>(
+    tenant: &TenantId,
+    ctx: &Context,
+    action: AuditAction,
+    target: Option<String>,
+    detail: serde_json::Value,
+    result: Result<T, ServiceError>,
+    pool: &DatabasePool,
+) -> Result<T, ServiceError> {
+    let (outcome, detail) = match &result {
+        Ok(_) => (AuditOutcome::Success, detail),
+        Err(e) => (
+            AuditOutcome::Failure,
+            serde_json::json!({ "error": e.to_string(), "request": detail }),
+        ),
+    };
+
+    let event = model::NewAuditEvent::new(ctx, action, target, outcome, detail);
+    match (query::record_audit_event(tenant, event, pool).await, result) {
+        (Ok(()), result) => result,
+        (Err(e), Ok(_)) => Err(e.into()),
+        (Err(e), Err(original)) => {
+            eprintln!("failed to record audit event: {e}");
+            Err(original)
+        }
+    }
+}
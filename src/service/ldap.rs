@@ -0,0 +1,386 @@
+use super::action;
+use super::ask;
+use super::audit;
+use super::mailer::{Links, Mailer};
+use crate::data::DatabasePool;
+use crate::domain::group::GroupName;
+use crate::domain::ldap::{self, Entry, Message, Request, Response, ResultCode, Scope, Search};
+use crate::domain::tenant::TenantId;
+use crate::domain::user::field::Password;
+use crate::web::api::ApiKey;
+use crate::web::guard::{Permission, UsersRead};
+use crate::{Email, ServiceError, User};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Users fetched at a time while searching.
+const PAGE_SIZE: u32 = 200;
+
+/// Most entries a search returns, whatever its size limit.
+const MAX_ENTRIES: usize = 1000;
+
+/// Optional LDAP listener for applications that only speak LDAP. They authenticate users
+/// with a simple bind as `uid=<email>,<base_dn>` and look them up with searches under
+/// `base_dn`. Searching needs a bind first: as a user, who only finds their own entry, or
+/// as `cn=<any name>,<base_dn>` with an API key holding `users:read` as the password.
+///
+/// There is no StartTLS or LDAPS, so binds are only accepted from loopback peers, where
+/// passwords do not cross the network; other hosts need a TLS tunnel, e.g. stunnel.
+#[derive(Debug, Clone)]
+pub struct LdapServer {
+    pub address: SocketAddr,
+    pub base_dn: String,
+    /// Tenant whose users are served.
+    pub tenant: TenantId,
+}
+
+impl LdapServer {
+    pub fn spawn(
+        self,
+        pool: DatabasePool,
+        mailer: Mailer,
+        links: Links,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let listener = match TcpListener::bind(self.address).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("LDAP listener failed to bind {}: {e}", self.address);
+                    return;
+                }
+            };
+            println!("LDAP listening on {} for {}", self.address, self.base_dn);
+            if !is_loopback(&self.address) {
+                println!("LDAP binds are only accepted from loopback peers, as there is no TLS");
+            }
+
+            let directory = Arc::new(Directory {
+                server: self,
+                pool,
+                mailer,
+                links,
+            });
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let directory = directory.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = directory.serve(stream, peer).await {
+                                eprintln!("LDAP connection from {peer} failed: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("LDAP accept failed: {e}"),
+                }
+            }
+        })
+    }
+}
+
+/// Whether `address` is on this host; IPv4 peers of an IPv6 listener count too.
+fn is_loopback(address: &SocketAddr) -> bool {
+    match address.ip() {
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or(ip.is_loopback(), |ip| ip.is_loopback()),
+        ip => ip.is_loopback(),
+    }
+}
+
+/// Who a connection is bound as.
+enum Bound {
+    Anonymous,
+    User(Email),
+    /// An API key allowed to read users.
+    Service,
+}
+
+struct Directory {
+    server: LdapServer,
+    pool: DatabasePool,
+    mailer: Mailer,
+    links: Links,
+}
+
+impl Directory {
+    async fn serve(&self, mut stream: TcpStream, peer: SocketAddr) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let mut bound = Bound::Anonymous;
+
+        loop {
+            loop {
+                let (message, length) = match Message::parse(&buf) {
+                    Ok(Some(parsed)) => parsed,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let notice =
+                            Response::Disconnection(ResultCode::ProtocolError, e.to_string());
+                        stream.write_all(&notice.encode(0)).await?;
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+                    }
+                };
+                buf.drain(..length);
+                let responses = match message.request {
+                    Request::Unbind => return Ok(()),
+                    Request::Abandon => vec![],
+                    Request::Bind {
+                        version,
+                        name,
+                        password,
+                    } => {
+                        let (now, code, text) = self.bind(version, &name, password, peer).await;
+                        bound = now;
+                        vec![Response::Bind(code, text)]
+                    }
+                    Request::Search(search) => self.search(search, &bound).await,
+                    Request::Unsupported(tag) => vec![Response::Unsupported(tag)],
+                };
+                for response in responses {
+                    stream.write_all(&response.encode(message.id)).await?;
+                }
+            }
+
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// Checks a simple bind; a failed one leaves the connection anonymous.
+    async fn bind(
+        &self,
+        version: i64,
+        name: &str,
+        password: Option<String>,
+        peer: SocketAddr,
+    ) -> (Bound, ResultCode, String) {
+        let refused = |code, text: &str| (Bound::Anonymous, code, text.to_string());
+        let base = &self.server.base_dn;
+        let password = match password {
+            _ if version != 3 => return refused(ResultCode::ProtocolError, "only LDAPv3"),
+            Some(password) => password,
+            None => {
+                return refused(
+                    ResultCode::AuthMethodNotSupported,
+                    "only simple bind is supported",
+                )
+            }
+        };
+        match (name.is_empty(), password.is_empty()) {
+            (true, true) => return refused(ResultCode::Success, ""),
+            (false, true) => {
+                return refused(
+                    ResultCode::UnwillingToPerform,
+                    "unauthenticated bind is not allowed",
+                )
+            }
+            _ => (),
+        }
+        if !is_loopback(&peer) {
+            return refused(
+                ResultCode::ConfidentialityRequired,
+                "binds are only accepted from this host, as LDAP is served without TLS",
+            );
+        }
+
+        let email = ldap::rdn_value(name, "uid", base)
+            .or_else(|| ldap::rdn_value(name, "mail", base))
+            .or_else(|| (name.contains('@') && !name.contains('=')).then(|| name.to_string()));
+        let result = match (email, ldap::rdn_value(name, "cn", base)) {
+            (Some(email), _) => self.bind_user(&email, &password, peer).await,
+            (None, Some(_)) => self.bind_service(&password).await,
+            (None, None) => Err(ServiceError::InvalidDetail),
+        };
+
+        match result {
+            Ok(bound) => (bound, ResultCode::Success, String::new()),
+            Err(
+                ServiceError::InvalidDetail
+                | ServiceError::NotFound
+                | ServiceError::AccountStatus(_)
+                | ServiceError::Forbidden(_),
+            ) => refused(ResultCode::InvalidCredentials, "invalid credentials"),
+            Err(e) => {
+                eprintln!("LDAP bind error: {e}");
+                refused(ResultCode::OperationsError, "bind failed")
+            }
+        }
+    }
+
+    /// Signs a user in like the API does, recording the attempt in their login history.
+    async fn bind_user(
+        &self,
+        email: &str,
+        password: &str,
+        peer: SocketAddr,
+    ) -> Result<Bound, ServiceError> {
+        let ctx = audit::Context {
+            ip: Some(peer.ip().to_string()),
+            user_agent: Some("LDAP".to_string()),
+            ..Default::default()
+        };
+        let req = ask::GetUser {
+            email: Email::from(email),
+            password: Some(Password::from(password)),
+        };
+        let user = action::authenticate(
+            &self.server.tenant,
+            &ctx,
+            req,
+            &self.mailer,
+            &self.links,
+            &self.pool,
+        )
+        .await?;
+
+        Ok(Bound::User(user.email))
+    }
+
+    async fn bind_service(&self, api_key: &str) -> Result<Bound, ServiceError> {
+        let api_key = ApiKey::from_str(api_key).map_err(|_| ServiceError::InvalidDetail)?;
+        let principal = action::principal(&self.server.tenant, api_key, &self.pool).await?;
+
+        match principal.has_permission(UsersRead::NAME) {
+            true => Ok(Bound::Service),
+            false => Err(ServiceError::InvalidDetail),
+        }
+    }
+
+    async fn search(&self, search: Search, bound: &Bound) -> Vec<Response> {
+        let base = &self.server.base_dn;
+        let done = |code, text: &str| Response::SearchDone(code, text.to_string());
+
+        // the root DSE, which clients read to find the naming context
+        if search.base.is_empty() && search.scope == Scope::Base {
+            let entry = Entry::new(String::new())
+                .with("objectClass", vec!["top".to_string()])
+                .with("namingContexts", vec![base.clone()])
+                .with("supportedLDAPVersion", vec!["3".to_string()]);
+            let mut responses = vec![];
+            if search.filter.matches(&entry) {
+                let entry = entry.select(&search.attributes);
+                responses.push(Response::SearchEntry(entry, search.types_only));
+            }
+            responses.push(done(ResultCode::Success, ""));
+            return responses;
+        }
+
+        let own = match bound {
+            Bound::Anonymous => {
+                return vec![done(
+                    ResultCode::InsufficientAccessRights,
+                    "bind before searching",
+                )]
+            }
+            Bound::User(email) => Some(email),
+            Bound::Service => None,
+        };
+
+        // a single user's entry, or the users under the base DN
+        let email = match ldap::rdn_value(&search.base, "uid", base) {
+            Some(_) if search.scope == Scope::OneLevel => {
+                return vec![done(ResultCode::Success, "")]
+            }
+            Some(email) => Some(email),
+            None if search.scope == Scope::Base => return vec![done(ResultCode::Success, "")],
+            None if ldap::is_within(base, &search.base) => {
+                search.filter.email().map(str::to_string)
+            }
+            None => return vec![done(ResultCode::NoSuchObject, "")],
+        };
+        // users only ever find their own entry, so no one else's is looked up
+        let email = match (own, email) {
+            (Some(own), Some(email)) if !own.clone().into_inner().eq_ignore_ascii_case(&email) => {
+                return vec![done(ResultCode::Success, "")]
+            }
+            (Some(own), _) => Some(own.clone().into_inner()),
+            (None, email) => email,
+        };
+        let limit = match usize::try_from(search.size_limit) {
+            Ok(limit) if limit > 0 => limit.min(MAX_ENTRIES),
+            _ => MAX_ENTRIES,
+        };
+
+        let mut responses = vec![];
+        let mut offset = 0;
+        loop {
+            let users = action::directory_users(
+                &self.server.tenant,
+                email.as_deref().map(Email::from),
+                offset,
+                PAGE_SIZE,
+                &self.pool,
+            )
+            .await;
+            let users = match users {
+                Ok(users) => users,
+                Err(e) => {
+                    eprintln!("LDAP search error: {e}");
+                    return vec![done(ResultCode::Other, "search failed")];
+                }
+            };
+
+            for (user, groups) in &users {
+                let entry = self.entry(user, groups);
+                if !search.filter.matches(&entry) {
+                    continue;
+                }
+                if responses.len() == limit {
+                    responses.push(done(ResultCode::SizeLimitExceeded, ""));
+                    return responses;
+                }
+                let entry = entry.select(&search.attributes);
+                responses.push(Response::SearchEntry(entry, search.types_only));
+            }
+
+            if users.len() < PAGE_SIZE as usize {
+                break;
+            }
+            offset += PAGE_SIZE;
+        }
+
+        responses.push(done(ResultCode::Success, ""));
+        responses
+    }
+
+    /// A user's entry, an `inetOrgPerson` with their groups in `memberOf` as
+    /// `cn=<group>,ou=groups,<base_dn>`.
+    fn entry(&self, user: &User, groups: &[GroupName]) -> Entry {
+        let base = &self.server.base_dn;
+        let email = user.email.clone().into_inner();
+        let name = user.name.clone().into_inner();
+        let surname = name.split_whitespace().last().unwrap_or(&name).to_string();
+        let object_classes = ["top", "person", "organizationalPerson", "inetOrgPerson"];
+
+        Entry::new(ldap::user_dn(&email, base))
+            .with(
+                "objectClass",
+                object_classes.iter().map(ToString::to_string).collect(),
+            )
+            .with("uid", vec![email.clone()])
+            .with("mail", vec![email])
+            .with("cn", vec![name.clone()])
+            .with("displayName", vec![name])
+            .with("sn", vec![surname])
+            .with(
+                "telephoneNumber",
+                user.phone
+                    .iter()
+                    .map(|phone| phone.as_str().to_string())
+                    .collect(),
+            )
+            .with(
+                "memberOf",
+                groups
+                    .iter()
+                    .map(|group| format!("cn={group},ou=groups,{base}"))
+                    .collect(),
+            )
+    }
+}
@@ -0,0 +1,102 @@
+use super::ServiceError;
+use crate::domain::tenant::TenantId;
+use crate::Email;
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mail {
+    pub to: Email,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers outgoing mail.
+///
+/// Authy does not speak SMTP itself: mail is either printed to stdout (like generated API
+/// keys) or appended to an outbox file, one JSON object per line, for a relay to pick up.
+#[derive(Debug, Clone, Default)]
+pub enum Mailer {
+    #[default]
+    Stdout,
+    Outbox(PathBuf),
+}
+
+impl Mailer {
+    pub fn send(&self, mail: &Mail) -> Result<(), ServiceError> {
+        match self {
+            Self::Stdout => {
+                println!(
+                    "Mail to {}: {}\n{}",
+                    mail.to.clone().into_inner(),
+                    mail.subject,
+                    mail.body
+                );
+                Ok(())
+            }
+            Self::Outbox(path) => {
+                let line =
+                    serde_json::to_string(mail).map_err(|e| ServiceError::Mail(e.to_string()))?;
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| writeln!(file, "{line}"))
+                    .map_err(|e| ServiceError::Mail(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Builds absolute links to this server for use in mail.
+#[derive(Debug, Clone)]
+pub struct Links {
+    base: String,
+}
+
+impl Links {
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: base.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Link to an API `path` (relative to `/api`) within `tenant`.
+    pub fn api(&self, tenant: &TenantId, path: &str) -> String {
+        match tenant.is_default() {
+            true => format!("{}/api{path}", self.base),
+            false => format!("{}/api/t/{tenant}{path}", self.base),
+        }
+    }
+
+    /// Link to an OAuth endpoint `path` (relative to `/oauth`) within `tenant`.
+    pub fn oauth(&self, tenant: &TenantId, path: &str) -> String {
+        match tenant.is_default() {
+            true => format!("{}/oauth{path}", self.base),
+            false => format!("{}/oauth/t/{tenant}{path}", self.base),
+        }
+    }
+
+    /// Link to a SCIM endpoint `path` (relative to `/scim/v2`) within `tenant`.
+    pub fn scim(&self, tenant: &TenantId, path: &str) -> String {
+        match tenant.is_default() {
+            true => format!("{}/scim/v2{path}", self.base),
+            false => format!("{}/scim/t/{tenant}/v2{path}", self.base),
+        }
+    }
+
+    /// OpenID Connect issuer identifier of `tenant`.
+    pub fn issuer(&self, tenant: &TenantId) -> String {
+        match tenant.is_default() {
+            true => self.base.clone(),
+            false => format!("{}/oauth/t/{tenant}", self.base),
+        }
+    }
+}
+
+impl Default for Links {
+    fn default() -> Self {
+        Self::new("http://localhost:8000")
+    }
+}
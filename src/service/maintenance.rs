@@ -0,0 +1,36 @@
+use super::action;
+use crate::data::DatabasePool;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Maintenance {
+    /// How long a soft-deleted user is kept before being erased for good.
+    pub retention: Duration,
+    /// How often the purge job runs.
+    pub interval: Duration,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            retention: Duration::from_secs(30 * 24 * 60 * 60),
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl Maintenance {
+    pub fn spawn(self, pool: DatabasePool) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                match action::purge_deleted_users(self.retention, &pool).await {
+                    Ok(0) => (),
+                    Ok(count) => println!("purged {count} deleted user(s)"),
+                    Err(e) => eprintln!("user purge failed: {e}"),
+                }
+            }
+        })
+    }
+}
@@ -1,20 +1,68 @@
 pub mod action;
 pub mod ask;
+pub mod audit;
+pub mod ldap;
+pub mod mailer;
+pub mod maintenance;
+pub mod sender;
 
+use crate::domain::access::AccessError;
+use crate::domain::federation::FederationError;
+use crate::domain::group::GroupError;
+use crate::domain::identity::IdentityError;
+use crate::domain::oauth::OAuthError;
+use crate::domain::oidc::OidcError;
+use crate::domain::role::RoleError;
+use crate::domain::scim::ScimError;
+use crate::domain::tenant::TenantError;
+use crate::domain::user::field::Status;
+use crate::domain::webauthn::WebAuthnError;
 pub use crate::{DataError, UserError};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ServiceError {
     #[error("user error: {0}")]
     User(#[from] UserError),
+    #[error("role error: {0}")]
+    Role(#[from] RoleError),
+    #[error("group error: {0}")]
+    Group(#[from] GroupError),
+    #[error("tenant error: {0}")]
+    Tenant(#[from] TenantError),
+    #[error("webauthn error: {0}")]
+    WebAuthn(#[from] WebAuthnError),
+    #[error("oauth error: {0}")]
+    OAuth(#[from] OAuthError),
+    #[error("openid connect error: {0}")]
+    Oidc(#[from] OidcError),
+    #[error("federation error: {0}")]
+    Federation(#[from] FederationError),
+    #[error("identity error: {0}")]
+    Identity(#[from] IdentityError),
+    #[error("access error: {0}")]
+    Access(#[from] AccessError),
+    #[error("scim error: {0}")]
+    Scim(#[from] ScimError),
     #[error("database error: {0}")]
     Data(DataError),
     #[error("not found")]
     NotFound,
     #[error("permissions not met: {0}")]
     PermissionError(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
     #[error("invalid user detail")]
     InvalidDetail,
+    #[error("account is {0}")]
+    AccountStatus(Status),
+    #[error("cannot change status from {0} to {1}")]
+    InvalidTransition(Status, Status),
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("failed to send mail: {0}")]
+    Mail(String),
+    #[error("failed to send message: {0}")]
+    Message(String),
 }
 
 impl From<DataError> for ServiceError {
@@ -24,6 +72,8 @@ impl From<DataError> for ServiceError {
                 sqlx::Error::RowNotFound => Self::InvalidDetail,
                 other => Self::Data(DataError::Database(other)),
             },
+            e @ DataError::InvalidCursor => Self::InvalidRequest(e.to_string()),
+            e @ DataError::InvalidRecord(_) => Self::Data(e),
         }
     }
 }
@@ -0,0 +1,79 @@
+use super::ServiceError;
+use crate::domain::otp::Channel;
+use crate::domain::user::field::Phone;
+use crate::Email;
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Where a [`Message`] goes; the variant picks the channel.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recipient {
+    Email(Email),
+    Sms(Phone),
+}
+
+impl Recipient {
+    pub fn channel(&self) -> Channel {
+        match self {
+            Self::Email(_) => Channel::Email,
+            Self::Sms(_) => Channel::Sms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub to: Recipient,
+    /// Subject line for email; SMS senders leave it out.
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers short messages, such as one-time codes, by email or SMS.
+///
+/// Implement this to plug in a mail or SMS provider. [`LocalSender`] is used when none is
+/// configured.
+pub trait MessageSender: Send + Sync {
+    fn send(&self, message: &Message) -> Result<(), ServiceError>;
+}
+
+/// Prints messages to stdout or appends them to a file, one JSON object per line, in the
+/// same way as [`Mailer`](super::mailer::Mailer). Meant for development and testing.
+#[derive(Debug, Clone, Default)]
+pub enum LocalSender {
+    #[default]
+    Stdout,
+    File(PathBuf),
+}
+
+impl MessageSender for LocalSender {
+    fn send(&self, message: &Message) -> Result<(), ServiceError> {
+        match self {
+            Self::Stdout => {
+                let to = match &message.to {
+                    Recipient::Email(email) => email.clone().into_inner(),
+                    Recipient::Sms(phone) => phone.as_str().to_string(),
+                };
+                println!(
+                    "{} to {to}: {}\n{}",
+                    message.to.channel(),
+                    message.subject,
+                    message.body
+                );
+                Ok(())
+            }
+            Self::File(path) => {
+                let line = serde_json::to_string(message)
+                    .map_err(|e| ServiceError::Message(e.to_string()))?;
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| writeln!(file, "{line}"))
+                    .map_err(|e| ServiceError::Message(e.to_string()))
+            }
+        }
+    }
+}
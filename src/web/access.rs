@@ -0,0 +1,57 @@
+use super::api::ApiError;
+use super::guard::{AccessRead, AccessWrite, RequirePermission};
+use crate::data::query::DeletionStatus;
+use crate::data::AppDatabase;
+use crate::domain::access::AccessRule;
+use crate::domain::tenant::TenantId;
+use crate::service::{action, ask, audit};
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[rocket::get("/")]
+pub async fn list_rules(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<AccessRead>,
+) -> Result<Json<Vec<AccessRule>>, ApiError> {
+    let rules = action::list_access_rules(&tenant, database.get_pool()).await?;
+
+    Ok(Json(rules))
+}
+
+/// Sets who may reach `host` through `/auth/verify`; `host` is a host name, or a wildcard
+/// such as `*.example.com`.
+#[rocket::put("/<host>", data = "<req>")]
+pub async fn set_rule(
+    host: &str,
+    req: Json<ask::SetAccessRule>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<AccessWrite>,
+) -> Result<Json<AccessRule>, ApiError> {
+    let rule =
+        action::set_access_rule(&tenant, &ctx, host, req.into_inner(), database.get_pool()).await?;
+
+    Ok(Json(rule))
+}
+
+#[rocket::delete("/<host>")]
+pub async fn delete_rule(
+    host: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<AccessWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    match action::delete_access_rule(&tenant, &ctx, host, database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("access rule deleted")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json(
+            "access rule not found".to_string(),
+        ))),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list_rules, set_rule, delete_rule]
+}
@@ -1,12 +1,28 @@
-use crate::data::query::RevocationStatus;
+use crate::data::query::{DeletionStatus, RevocationStatus};
 use crate::data::AppDatabase;
+use crate::domain::access::AccessError;
+use crate::domain::federation::FederationError;
+use crate::domain::group::GroupError;
+use crate::domain::identity::IdentityError;
+use crate::domain::login::Login;
+use crate::domain::role::Principal;
+use crate::domain::scim::ScimError;
+use crate::domain::session::Session;
+use crate::domain::tenant::{TenantError, TenantId};
 use crate::service;
 use crate::service::action;
 use crate::service::ask::{GetUser, UpdateUser};
-use crate::ServiceError;
-// use base64::engine::{general_purpose, GeneralPurpose};
-// use base64::Engine;
-use rocket::http::{CookieJar, Status};
+use crate::service::audit;
+use crate::service::mailer::{Links, Mailer};
+use crate::service::sender::MessageSender;
+use crate::web::guard::{
+    forbid_impersonation, require_operator, require_self_or, user_email, Device, RequirePermission,
+    UsersImpersonate, UsersRead, UsersWrite,
+};
+use crate::{RelyingParty, ServiceError};
+use base64::engine::general_purpose;
+use base64::Engine;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::serde::json::Json;
 use rocket::Responder;
@@ -31,8 +47,7 @@ pub struct ApiKey(Vec<u8>);
 
 impl ApiKey {
     pub fn to_base64(&self) -> String {
-        base64::encode(self.0.as_slice())
-        // Engine::encode(general_purpose::GeneralPurpose, self.0.as_slice())
+        general_purpose::STANDARD.encode(self.0.as_slice())
     }
 
     pub fn into_inner(self) -> Vec<u8> {
@@ -51,7 +66,8 @@ impl FromStr for ApiKey {
     type Err = ApiKeyError;
 
     fn from_str(key: &str) -> Result<Self, Self::Err> {
-        base64::decode(key)
+        general_purpose::STANDARD
+            .decode(key)
             .map(ApiKey)
             .map_err(|e| Self::Err::DecodeError(e.to_string()))
     }
@@ -74,25 +90,89 @@ pub enum ApiError {
     #[error("key error")]
     #[response(status = 400, content_type = "json")]
     KeyError(Json<ApiKeyError>),
+    #[error("forbidden")]
+    #[response(status = 403, content_type = "json")]
+    Forbidden(Json<String>),
+    #[error("conflict")]
+    #[response(status = 409, content_type = "json")]
+    Conflict(Json<String>),
+    #[error("bad request")]
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<String>),
 }
 
 impl From<ServiceError> for ApiError {
     fn from(err: ServiceError) -> Self {
         match err {
             ServiceError::User(e) => Self::User(Json(format!("usr parsing error: {}", e))),
+            e @ ServiceError::Role(_) => Self::BadRequest(Json(e.to_string())),
+            e @ ServiceError::Group(GroupError::Cycle(..)) => Self::Conflict(Json(e.to_string())),
+            e @ ServiceError::Group(_) => Self::BadRequest(Json(e.to_string())),
+            e @ ServiceError::Tenant(TenantError::UnknownTenant(_)) => {
+                Self::NotFound(Json(e.to_string()))
+            }
+            e @ ServiceError::Tenant(_) => Self::BadRequest(Json(e.to_string())),
+            e @ ServiceError::WebAuthn(_) => Self::BadRequest(Json(e.to_string())),
+            e @ ServiceError::OAuth(_) => Self::BadRequest(Json(e.to_string())),
+            ServiceError::Oidc(e) => {
+                eprintln!("{e}");
+                Self::Server(Json("a server error occured".to_owned()))
+            }
+            e @ ServiceError::Federation(FederationError::UnknownProvider) => {
+                Self::NotFound(Json(e.to_string()))
+            }
+            e @ ServiceError::Federation(FederationError::DuplicateProvider(_)) => {
+                Self::Conflict(Json(e.to_string()))
+            }
+            e @ ServiceError::Federation(_) => Self::BadRequest(Json(e.to_string())),
+            e @ ServiceError::Identity(IdentityError::UnknownIdentity) => {
+                Self::NotFound(Json(e.to_string()))
+            }
+            e @ ServiceError::Identity(IdentityError::LastSignInMethod) => {
+                Self::Conflict(Json(e.to_string()))
+            }
+            e @ ServiceError::Identity(IdentityError::ReauthenticationRequired) => {
+                Self::Forbidden(Json(e.to_string()))
+            }
+            e @ ServiceError::Access(AccessError::InvalidHost(_)) => {
+                Self::BadRequest(Json(e.to_string()))
+            }
+            e @ ServiceError::Access(AccessError::Unauthenticated) => {
+                Self::User(Json(e.to_string()))
+            }
+            e @ ServiceError::Access(_) => Self::Forbidden(Json(e.to_string())),
+            e @ ServiceError::Scim(ScimError::UnknownResource) => {
+                Self::NotFound(Json(e.to_string()))
+            }
+            e @ ServiceError::Scim(ScimError::Uniqueness(_)) => Self::Conflict(Json(e.to_string())),
+            e @ ServiceError::Scim(_) => Self::BadRequest(Json(e.to_string())),
             ServiceError::NotFound => Self::NotFound(Json("invalid user detail".to_owned())),
             ServiceError::Data(e) => {
                 println!("{}", e);
                 if e.to_string().contains("UNIQUE constraint failed") {
                     Self::DuplicateUser(Json("User already registered".to_owned()))
+                } else if e.to_string().contains("FOREIGN KEY constraint failed") {
+                    Self::BadRequest(Json("unknown user, role or permission".to_owned()))
                 } else {
                     Self::Server(Json("a server error occured".to_owned()))
                 }
             }
             ServiceError::PermissionError(msg) => Self::User(Json(msg)),
+            ServiceError::Forbidden(msg) => Self::Forbidden(Json(msg)),
             ServiceError::InvalidDetail => {
                 Self::NotFound(Json(String::from("invalid user detail")))
             }
+            e @ ServiceError::AccountStatus(_) => Self::Forbidden(Json(e.to_string())),
+            e @ ServiceError::InvalidTransition(..) => Self::Conflict(Json(e.to_string())),
+            e @ ServiceError::InvalidRequest(_) => Self::BadRequest(Json(e.to_string())),
+            ServiceError::Mail(e) => {
+                eprintln!("{}", e);
+                Self::Server(Json("failed to send mail".to_owned()))
+            }
+            ServiceError::Message(e) => {
+                eprintln!("{}", e);
+                Self::Server(Json("failed to send message".to_owned()))
+            }
         }
     }
 }
@@ -121,12 +201,19 @@ impl<'r> FromRequest<'r> for ApiKey {
                     _ => return server_error(),
                 };
 
+                let tenant = match req.guard::<TenantId>().await {
+                    Outcome::Success(tenant) => tenant,
+                    Outcome::Failure(e) => return Outcome::Failure(e),
+                    Outcome::Forward(f) => return Outcome::Forward(f),
+                };
+
                 let api_key = match ApiKey::from_str(key) {
                     Ok(key) => key,
                     Err(e) => return key_error(e),
                 };
 
-                match action::api_key_is_valid(api_key.clone(), db.get_pool()).await {
+                // keys only work within the tenant they were issued for
+                match action::api_key_is_valid(&tenant, api_key.clone(), db.get_pool()).await {
                     Ok(valid) if valid => Outcome::Success(api_key),
                     Ok(valid) if !valid => {
                         key_error(ApiKeyError::NotFound("API key not found".to_string()))
@@ -139,18 +226,50 @@ impl<'r> FromRequest<'r> for ApiKey {
 }
 
 #[rocket::get("/key")]
-pub async fn new_api_key(database: &State<AppDatabase>) -> Result<Json<&str>, ApiError> {
-    let api_key = action::generate_api_key(database.get_pool()).await?;
-    println!("API Key: {}", api_key.to_base64());
+pub async fn new_api_key(
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+) -> Result<Json<&str>, ApiError> {
+    let api_key = action::generate_api_key(&tenant, &ctx, database.get_pool()).await?;
+    println!("API Key ({}): {}", tenant, api_key.to_base64());
     Ok(Json("Api key generated. See logs for details."))
 }
 
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/key", data = "<req>")]
+pub async fn issue_api_key(
+    req: Json<service::ask::GetUser>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    _api_key: ApiKey,
+) -> Result<Json<service::ask::IssuedKey>, ApiError> {
+    ctx.device_id = Some(device.0);
+    let issued = action::issue_api_key(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        mailer,
+        links,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(issued))
+}
+
 #[rocket::get("/logout")]
 pub async fn revoke_api_key(
+    tenant: TenantId,
+    ctx: audit::Context,
     database: &State<AppDatabase>,
     api_key: ApiKey,
 ) -> Result<Json<&str>, ApiError> {
-    let res = action::revoke_api_key(api_key, database.get_pool()).await?;
+    let res = action::revoke_api_key(&tenant, &ctx, api_key, database.get_pool()).await?;
 
     match res {
         RevocationStatus::Revoked => Ok(Json("logout successful")),
@@ -158,37 +277,245 @@ pub async fn revoke_api_key(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 #[rocket::post("/login", data = "<req>")]
 pub async fn get_user(
     req: Json<service::ask::GetUser>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
     database: &State<AppDatabase>,
-    _api_key: ApiKey,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    principal: Principal,
 ) -> Result<Json<crate::User>, ApiError> {
-    // use crate::domain::user::field::password;
+    require_operator(&principal)?;
+    ctx.device_id = Some(device.0);
+    let user = action::authenticate(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        mailer,
+        links,
+        database.get_pool(),
+    )
+    .await?;
 
-    let req_body = service::ask::GetUser {
-        email: req.email.clone().into(),
-        password: req.password.clone().into(),
-    };
+    Ok(Json(user))
+}
+
+/// Private cookie holding the nonce that binds a sign-in link to the browser requesting it.
+pub const MAGIC_LINK_COOKIE: &str = "authy_magic_link";
 
-    let user = action::get_user(req.into_inner(), database.get_pool()).await?;
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/magic-link", data = "<req>")]
+pub async fn request_magic_link(
+    req: Json<service::ask::MagicLinkRequest>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    cookies: &CookieJar<'_>,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    _api_key: ApiKey,
+) -> Result<Json<&'static str>, ApiError> {
+    let nonce = action::request_magic_link(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        mailer,
+        links,
+        database.get_pool(),
+    )
+    .await?;
+
+    cookies.add_private(
+        Cookie::build(MAGIC_LINK_COOKIE, nonce.to_string())
+            .http_only(true)
+            // sent along when the link is followed from a mail client
+            .same_site(SameSite::Lax)
+            .max_age(rocket::time::Duration::minutes(15))
+            .finish(),
+    );
+
+    Ok(Json(
+        "If the address belongs to an account, a sign-in link has been sent to it.",
+    ))
+}
+
+/// Target of emailed sign-in links; opened from mail, so it takes no API key.
+#[allow(clippy::too_many_arguments)]
+#[rocket::get("/magic-link?<token>")]
+pub async fn consume_magic_link(
+    token: &str,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    cookies: &CookieJar<'_>,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    rp: &State<RelyingParty>,
+) -> Result<Json<service::ask::SignIn>, ApiError> {
+    ctx.device_id = Some(device.0);
+    let nonce = cookies
+        .get_private(MAGIC_LINK_COOKIE)
+        .map(|cookie| cookie.value().into());
+    let issued = action::consume_magic_link(
+        &tenant,
+        &ctx,
+        token.into(),
+        nonce,
+        mailer,
+        links,
+        rp,
+        database.get_pool(),
+    )
+    .await?;
+    cookies.remove_private(Cookie::named(MAGIC_LINK_COOKIE));
+
+    Ok(Json(issued))
+}
+
+#[rocket::post("/otp", data = "<req>")]
+pub async fn request_otp(
+    req: Json<service::ask::OtpRequest>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    sender: &State<Box<dyn MessageSender>>,
+    _api_key: ApiKey,
+) -> Result<Json<&'static str>, ApiError> {
+    action::request_otp(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        sender.as_ref(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(
+        "If the account exists and can receive it, a sign-in code has been sent.",
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/otp/verify", data = "<req>")]
+pub async fn verify_otp(
+    req: Json<service::ask::OtpLogin>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    rp: &State<RelyingParty>,
+    _api_key: ApiKey,
+) -> Result<Json<service::ask::SignIn>, ApiError> {
+    ctx.device_id = Some(device.0);
+    let issued = action::verify_otp(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        mailer,
+        links,
+        rp,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(issued))
+}
+
+/// Sign-in attempts on the calling user's own account.
+#[rocket::get("/logins")]
+pub async fn login_history(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    principal: Principal,
+) -> Result<Json<Vec<Login>>, ApiError> {
+    let logins =
+        action::login_history(&tenant, user_email(&principal)?, database.get_pool()).await?;
+
+    Ok(Json(logins))
+}
+
+/// The calling user's own sessions.
+#[rocket::get("/sessions")]
+pub async fn list_sessions(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    principal: Principal,
+    api_key: ApiKey,
+) -> Result<Json<Vec<Session>>, ApiError> {
+    let sessions = action::list_sessions(
+        &tenant,
+        user_email(&principal)?,
+        Some(api_key),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(sessions))
+}
 
-    if req_body.password.is_some()
-        && req_body.password.unwrap().into_inner() == user.clone().password.into_inner()
-    {
-        Ok(Json(user))
-    } else {
-        Err(ApiError::NotFound(Json("invalid user detail".to_string())))
+#[rocket::delete("/sessions/<id>")]
+pub async fn revoke_session(
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    principal: Principal,
+) -> Result<Json<&'static str>, ApiError> {
+    let email = user_email(&principal)?;
+    match action::revoke_session(&tenant, &ctx, email, id, database.get_pool()).await? {
+        RevocationStatus::Revoked => Ok(Json("session revoked")),
+        RevocationStatus::NotFound => {
+            Err(ApiError::NotFound(Json("session not found".to_string())))
+        }
     }
 }
 
+/// Signs the calling user out everywhere, including the session making the request.
+#[rocket::delete("/sessions")]
+pub async fn revoke_sessions(
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    principal: Principal,
+) -> Result<Json<u64>, ApiError> {
+    let email = user_email(&principal)?;
+    let revoked = action::revoke_sessions(&tenant, &ctx, email, database.get_pool()).await?;
+
+    Ok(Json(revoked))
+}
+
+/// Target of the "this wasn't me" link in new-device notifications; opened from mail, so
+/// it takes no API key.
+#[rocket::get("/not-me?<token>")]
+pub async fn report_login(
+    token: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+) -> Result<Json<&'static str>, ApiError> {
+    action::report_login(&tenant, &ctx, token.into(), database.get_pool()).await?;
+
+    Ok(Json(
+        "All sessions have been signed out. Set a new password before signing in again.",
+    ))
+}
+
 #[rocket::post("/", data = "<req>")]
 pub async fn new_user(
     req: Json<service::ask::NewUser>,
+    tenant: TenantId,
+    ctx: audit::Context,
     database: &State<AppDatabase>,
-    _api_key: ApiKey,
+    _auth: RequirePermission<UsersWrite>,
 ) -> Result<Json<crate::User>, ApiError> {
-    let user = action::new_user(req.into_inner(), database.get_pool()).await?;
+    let user = action::new_user(&tenant, &ctx, req.into_inner(), database.get_pool()).await?;
 
     Ok(Json(user))
 }
@@ -196,10 +523,19 @@ pub async fn new_user(
 #[rocket::patch("/", data = "<req>")]
 pub async fn update_user(
     req: Json<service::ask::UpdateUser>,
+    tenant: TenantId,
+    ctx: audit::Context,
     database: &State<AppDatabase>,
-    _api_key: ApiKey,
+    principal: Principal,
 ) -> Result<Json<crate::User>, ApiError> {
+    require_self_or::<UsersWrite>(&principal, &req.email)?;
+    // both can be used to sign in
+    if req.password.is_some() || req.phone.is_some() {
+        forbid_impersonation(&principal)?;
+    }
+
     let user = action::get_user(
+        &tenant,
         GetUser {
             email: req.clone().email.clone(),
             password: None,
@@ -218,15 +554,219 @@ pub async fn update_user(
             true => req.password.clone(),
             false => Some(user.password),
         },
+        phone: req.phone.clone(),
+        user_metadata: req.user_metadata.clone(),
+    };
+
+    let user = action::update_user(&tenant, &ctx, update_req, database.get_pool()).await?;
+
+    Ok(Json(user))
+}
+
+#[rocket::delete("/", data = "<req>")]
+pub async fn delete_user(
+    req: Json<service::ask::DeleteUser>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersWrite>,
+) -> Result<Json<&str>, ApiError> {
+    let res = action::delete_user(&tenant, &ctx, req.into_inner(), database.get_pool()).await?;
+
+    match res {
+        DeletionStatus::Deleted => Ok(Json("user deleted")),
+        DeletionStatus::NotFound => {
+            Err(ApiError::NotFound(Json("invalid user detail".to_string())))
+        }
+    }
+}
+
+#[rocket::put("/status", data = "<req>")]
+pub async fn set_user_status(
+    req: Json<service::ask::UpdateStatus>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    auth: RequirePermission<UsersWrite>,
+) -> Result<Json<crate::User>, ApiError> {
+    let user = action::set_user_status(
+        &tenant,
+        &ctx,
+        &auth.principal,
+        req.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(user))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::get(
+    "/?<status>&<created_after>&<created_before>&<domain>&<q>&<sort>&<order>&<cursor>&<limit>"
+)]
+pub async fn list_users(
+    status: Option<&str>,
+    created_after: Option<String>,
+    created_before: Option<String>,
+    domain: Option<String>,
+    q: Option<String>,
+    sort: Option<&str>,
+    order: Option<&str>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersRead>,
+) -> Result<Json<service::ask::UserPage>, ApiError> {
+    fn parse<T: FromStr>(value: Option<&str>, name: &str) -> Result<Option<T>, ApiError> {
+        value
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| ApiError::BadRequest(Json(format!("invalid {name}: {value}"))))
+            })
+            .transpose()
+    }
+
+    let req = service::ask::ListUsers {
+        status: parse(status, "status")?,
+        created_after,
+        created_before,
+        email_domain: domain,
+        search: q,
+        sort: parse(sort, "sort")?.unwrap_or_default(),
+        order: parse(order, "order")?.unwrap_or_default(),
+        cursor,
+        limit,
     };
 
-    let user = action::update_user(update_req, database.get_pool()).await?;
+    let page = action::list_users(&tenant, req, database.get_pool()).await?;
+
+    Ok(Json(page))
+}
+
+#[rocket::patch("/app_metadata", data = "<req>")]
+pub async fn update_app_metadata(
+    req: Json<service::ask::UpdateAppMetadata>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersWrite>,
+) -> Result<Json<crate::User>, ApiError> {
+    let user =
+        action::update_app_metadata(&tenant, &ctx, req.into_inner(), database.get_pool()).await?;
 
     Ok(Json(user))
 }
 
+#[rocket::post("/impersonate", data = "<req>")]
+pub async fn start_impersonation(
+    req: Json<service::ask::Impersonate>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    auth: RequirePermission<UsersImpersonate>,
+) -> Result<Json<service::ask::IssuedKey>, ApiError> {
+    let issued = action::start_impersonation(
+        &tenant,
+        &ctx,
+        &auth.principal,
+        req.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(issued))
+}
+
+#[rocket::delete("/impersonate")]
+pub async fn stop_impersonation(
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    api_key: ApiKey,
+) -> Result<Json<&str>, ApiError> {
+    match action::stop_impersonation(&tenant, &ctx, api_key, database.get_pool()).await? {
+        RevocationStatus::Revoked => Ok(Json("impersonation stopped")),
+        RevocationStatus::NotFound => Err(ApiError::NotFound(Json("invalid request".to_string()))),
+    }
+}
+
+#[rocket::get("/<email>/sessions")]
+pub async fn user_sessions(
+    email: &str,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersRead>,
+) -> Result<Json<Vec<Session>>, ApiError> {
+    let sessions = action::list_sessions(&tenant, email.into(), None, database.get_pool()).await?;
+
+    Ok(Json(sessions))
+}
+
+#[rocket::delete("/<email>/sessions/<id>")]
+pub async fn revoke_user_session(
+    email: &str,
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    match action::revoke_session(&tenant, &ctx, email.into(), id, database.get_pool()).await? {
+        RevocationStatus::Revoked => Ok(Json("session revoked")),
+        RevocationStatus::NotFound => {
+            Err(ApiError::NotFound(Json("session not found".to_string())))
+        }
+    }
+}
+
+#[rocket::delete("/<email>/sessions")]
+pub async fn revoke_user_sessions(
+    email: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersWrite>,
+) -> Result<Json<u64>, ApiError> {
+    let revoked = action::revoke_sessions(&tenant, &ctx, email.into(), database.get_pool()).await?;
+
+    Ok(Json(revoked))
+}
+
+pub fn list_routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        list_users,
+        user_sessions,
+        revoke_user_session,
+        revoke_user_sessions
+    ]
+}
+
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes![get_user, new_user, update_user, new_api_key, revoke_api_key]
+    rocket::routes![
+        get_user,
+        request_magic_link,
+        consume_magic_link,
+        request_otp,
+        verify_otp,
+        login_history,
+        report_login,
+        list_sessions,
+        revoke_session,
+        revoke_sessions,
+        new_user,
+        update_user,
+        delete_user,
+        set_user_status,
+        update_app_metadata,
+        new_api_key,
+        issue_api_key,
+        revoke_api_key,
+        start_impersonation,
+        stop_impersonation
+    ]
 }
 
 pub mod catcher {
@@ -261,13 +801,19 @@ pub mod catcher {
         Json("API key missing/invalid")
     }
 
+    #[catch(403)]
+    fn forbidden() -> Json<&'static str> {
+        Json("permission denied")
+    }
+
     pub fn catchers() -> Vec<Catcher> {
         catchers![
             default,
             internal_error,
             not_found,
             request_error,
-            missing_api_key
+            missing_api_key,
+            forbidden
         ]
     }
 }
@@ -0,0 +1,101 @@
+use super::api::ApiError;
+use super::guard::{AuditRead, RequirePermission};
+use crate::data::AppDatabase;
+use crate::domain::tenant::TenantId;
+use crate::service::{action, ask};
+use rocket::http::ContentType;
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[allow(clippy::too_many_arguments)]
+fn filters(
+    actor: Option<&str>,
+    action: Option<String>,
+    target: Option<String>,
+    outcome: Option<&str>,
+    since: Option<String>,
+    until: Option<String>,
+    cursor: Option<i64>,
+    limit: Option<u32>,
+) -> Result<ask::ListAuditEvents, ApiError> {
+    Ok(ask::ListAuditEvents {
+        actor: actor.map(Into::into),
+        action,
+        target,
+        outcome: outcome
+            .map(|outcome| {
+                outcome
+                    .parse()
+                    .map_err(|_| ApiError::BadRequest(Json(format!("invalid outcome: {outcome}"))))
+            })
+            .transpose()?,
+        since,
+        until,
+        cursor,
+        limit,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::get("/?<actor>&<action>&<target>&<outcome>&<since>&<until>&<cursor>&<limit>")]
+pub async fn list_audit_events(
+    actor: Option<&str>,
+    action: Option<String>,
+    target: Option<String>,
+    outcome: Option<&str>,
+    since: Option<String>,
+    until: Option<String>,
+    cursor: Option<i64>,
+    limit: Option<u32>,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<AuditRead>,
+) -> Result<Json<ask::AuditPage>, ApiError> {
+    let req = filters(actor, action, target, outcome, since, until, cursor, limit)?;
+    let page = action::list_audit_events(&tenant, req, database.get_pool()).await?;
+
+    Ok(Json(page))
+}
+
+/// Matching events as JSON lines, oldest first.
+#[allow(clippy::too_many_arguments)]
+#[rocket::get("/export?<actor>&<action>&<target>&<outcome>&<since>&<until>")]
+pub async fn export_audit_events(
+    actor: Option<&str>,
+    action: Option<String>,
+    target: Option<String>,
+    outcome: Option<&str>,
+    since: Option<String>,
+    until: Option<String>,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<AuditRead>,
+) -> Result<(ContentType, String), ApiError> {
+    let req = filters(actor, action, target, outcome, since, until, None, None)?;
+    let events = action::export_audit_events(&tenant, req, database.get_pool()).await?;
+
+    let mut lines = String::new();
+    for event in events {
+        let line = serde_json::to_string(&event)
+            .map_err(|_| ApiError::Server(Json("a server error occured".to_owned())))?;
+        lines.push_str(&line);
+        lines.push('\n');
+    }
+
+    Ok((ContentType::new("application", "x-ndjson"), lines))
+}
+
+#[rocket::get("/verify")]
+pub async fn verify_audit_log(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<AuditRead>,
+) -> Result<Json<ask::AuditVerification>, ApiError> {
+    let verification = action::verify_audit_log(&tenant, database.get_pool()).await?;
+
+    Ok(Json(verification))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list_audit_events, export_audit_events, verify_audit_log]
+}
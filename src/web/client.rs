@@ -0,0 +1,76 @@
+use super::api::ApiError;
+use super::guard::{ClientsRead, ClientsWrite, RequirePermission};
+use crate::data::query::DeletionStatus;
+use crate::data::AppDatabase;
+use crate::domain::oauth::Client;
+use crate::domain::tenant::TenantId;
+use crate::service::{action, ask, audit, ServiceError};
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[rocket::get("/")]
+pub async fn list_clients(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<ClientsRead>,
+) -> Result<Json<Vec<Client>>, ApiError> {
+    let clients = action::list_clients(&tenant, database.get_pool()).await?;
+
+    Ok(Json(clients))
+}
+
+#[rocket::get("/<id>")]
+pub async fn get_client(
+    id: &str,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<ClientsRead>,
+) -> Result<Json<Client>, ApiError> {
+    match action::get_client(&tenant, id, database.get_pool()).await {
+        Ok(client) => Ok(Json(client)),
+        Err(ServiceError::NotFound) => {
+            Err(ApiError::NotFound(Json("client not found".to_string())))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Registers a client; the response carries the secret of a confidential client, which
+/// cannot be retrieved again.
+#[rocket::post("/", data = "<req>")]
+pub async fn register_client(
+    req: Json<ask::NewClient>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    auth: RequirePermission<ClientsWrite>,
+) -> Result<Json<ask::RegisteredClient>, ApiError> {
+    let client = action::register_client(
+        &tenant,
+        &ctx,
+        &auth.principal,
+        req.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(client))
+}
+
+#[rocket::delete("/<id>")]
+pub async fn delete_client(
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<ClientsWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    match action::delete_client(&tenant, &ctx, id, database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("client deleted")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json("client not found".to_string()))),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list_clients, get_client, register_client, delete_client]
+}
@@ -0,0 +1,132 @@
+use super::api::ApiKey;
+use super::oauth::{BearerToken, SESSION_COOKIE};
+use crate::data::AppDatabase;
+use crate::domain::access::AccessError;
+use crate::domain::tenant::TenantId;
+use crate::service::mailer::Links;
+use crate::service::{action, ServiceError};
+use rocket::http::{CookieJar, Header};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::Redirect;
+use rocket::State;
+use std::str::FromStr;
+
+/// The request a reverse proxy asks about, described by the headers it adds:
+/// `X-Original-URL` (set for nginx `auth_request`), or `X-Forwarded-Proto`,
+/// `X-Forwarded-Host` and `X-Forwarded-Uri` (Traefik ForwardAuth).
+pub struct ForwardedRequest {
+    host: Option<String>,
+    url: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ForwardedRequest {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        if let Some(url) = headers
+            .get_one("x-original-url")
+            .and_then(|url| url::Url::parse(url).ok())
+        {
+            return Outcome::Success(Self {
+                host: url.host_str().map(str::to_string),
+                url: Some(url.to_string()),
+            });
+        }
+
+        let host = headers.get_one("x-forwarded-host").map(str::to_string);
+        let url = host.as_ref().map(|host| {
+            let proto = headers.get_one("x-forwarded-proto").unwrap_or("https");
+            let uri = headers.get_one("x-forwarded-uri").unwrap_or("/");
+            format!("{proto}://{host}{uri}")
+        });
+
+        Outcome::Success(Self { host, url })
+    }
+}
+
+/// Answer to a reverse proxy.
+#[derive(rocket::Responder)]
+pub enum Verification {
+    /// The request may pass, with `X-Auth-User`, `X-Auth-Email` and `X-Auth-Roles`.
+    Allowed(
+        &'static str,
+        Header<'static>,
+        Header<'static>,
+        Header<'static>,
+    ),
+    #[response(status = 401)]
+    Unauthenticated(String),
+    /// To the sign-in page, which sends the user back to the request's URL.
+    Login(Redirect),
+    #[response(status = 403)]
+    Denied(String),
+    #[response(status = 400)]
+    Invalid(&'static str),
+    #[response(status = 500)]
+    Error(&'static str),
+}
+
+/// Forward-auth endpoint for reverse proxies, such as nginx `auth_request` and Traefik
+/// ForwardAuth. Answers 200 if the request the proxy received may pass, naming the user in
+/// `X-Auth-User` (their OpenID Connect subject), `X-Auth-Email` and `X-Auth-Roles`; 401 if
+/// it carries neither a session cookie nor a session key as its bearer token; and 403 if
+/// the user may not reach its host. With `redirect=true`, requests without a session are sent to the
+/// sign-in page instead, for proxies that pass redirects on to the browser.
+#[rocket::get("/verify?<redirect>")]
+pub async fn verify(
+    redirect: Option<bool>,
+    forwarded: ForwardedRequest,
+    token: BearerToken,
+    tenant: TenantId,
+    cookies: &CookieJar<'_>,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+) -> Verification {
+    let host = match &forwarded.host {
+        Some(host) => host,
+        None => return Verification::Invalid("missing X-Original-URL or X-Forwarded-Host"),
+    };
+    // only session keys: access tokens issued to OAuth clients are meant for those clients
+    let bearer = token.0.is_some();
+    let session = match token.0 {
+        Some(token) => ApiKey::from_str(&token).ok(),
+        None => cookies
+            .get_private(SESSION_COOKIE)
+            .and_then(|cookie| ApiKey::from_str(cookie.value()).ok()),
+    };
+
+    let verified = action::verify_access(&tenant, host, session, database.get_pool()).await;
+    match verified {
+        Ok(access) => {
+            let roles: Vec<String> = access.roles.iter().map(ToString::to_string).collect();
+            Verification::Allowed(
+                "OK",
+                Header::new("X-Auth-User", access.subject),
+                Header::new("X-Auth-Email", access.email.into_inner()),
+                Header::new("X-Auth-Roles", roles.join(",")),
+            )
+        }
+        Err(ServiceError::Access(AccessError::Unauthenticated)) => match forwarded.url {
+            Some(url) if redirect == Some(true) && !bearer => {
+                let query = url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair("rd", &url)
+                    .finish();
+                Verification::Login(Redirect::found(
+                    links.oauth(&tenant, &format!("/login?{query}")),
+                ))
+            }
+            _ => Verification::Unauthenticated(AccessError::Unauthenticated.to_string()),
+        },
+        Err(ServiceError::Access(e)) => Verification::Denied(e.to_string()),
+        Err(e) => {
+            eprintln!("forward auth error: {e}");
+            Verification::Error("a server error occured")
+        }
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![verify]
+}
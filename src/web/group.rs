@@ -0,0 +1,129 @@
+use super::api::ApiError;
+use super::guard::{GroupsRead, GroupsWrite, RequirePermission};
+use crate::data::query::DeletionStatus;
+use crate::data::AppDatabase;
+use crate::domain::group::{Group, GroupMember, GroupMembers, GroupName};
+use crate::domain::tenant::TenantId;
+use crate::service::{action, ask, audit, ServiceError};
+use crate::Email;
+use rocket::serde::json::Json;
+use rocket::State;
+
+fn group_name(name: &str) -> Result<GroupName, ApiError> {
+    Ok(GroupName::new(name).map_err(ServiceError::from)?)
+}
+
+#[rocket::get("/")]
+pub async fn list_groups(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsRead>,
+) -> Result<Json<Vec<Group>>, ApiError> {
+    let groups = action::list_groups(&tenant, database.get_pool()).await?;
+
+    Ok(Json(groups))
+}
+
+#[rocket::put("/", data = "<req>")]
+pub async fn save_group(
+    req: Json<Group>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Json<Group>, ApiError> {
+    let group = action::save_group(&tenant, &ctx, req.into_inner(), database.get_pool()).await?;
+
+    Ok(Json(group))
+}
+
+#[rocket::delete("/<name>")]
+pub async fn delete_group(
+    name: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    match action::delete_group(&tenant, &ctx, group_name(name)?, database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("group deleted")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json("group not found".to_string()))),
+    }
+}
+
+#[rocket::get("/<name>/members")]
+pub async fn group_members(
+    name: &str,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsRead>,
+) -> Result<Json<GroupMembers>, ApiError> {
+    let members = action::group_members(&tenant, group_name(name)?, database.get_pool()).await?;
+
+    Ok(Json(members))
+}
+
+#[rocket::post("/<name>/members", data = "<req>")]
+pub async fn add_group_member(
+    name: &str,
+    req: Json<GroupMember>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Json<GroupMembers>, ApiError> {
+    let req = ask::GroupMembership {
+        group: group_name(name)?,
+        member: req.into_inner(),
+    };
+
+    let members = action::add_group_member(&tenant, &ctx, req, database.get_pool()).await?;
+
+    Ok(Json(members))
+}
+
+#[rocket::delete("/<name>/members", data = "<req>")]
+pub async fn remove_group_member(
+    name: &str,
+    req: Json<GroupMember>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    let req = ask::GroupMembership {
+        group: group_name(name)?,
+        member: req.into_inner(),
+    };
+
+    match action::remove_group_member(&tenant, &ctx, req, database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("member removed")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json("not a member".to_string()))),
+    }
+}
+
+/// Groups a user is in, including those inherited through nested groups.
+#[rocket::get("/user?<email>")]
+pub async fn user_groups(
+    email: &str,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsRead>,
+) -> Result<Json<Vec<GroupName>>, ApiError> {
+    let email = Email::new(email).map_err(ServiceError::from)?;
+    let groups = action::user_groups(&tenant, email, database.get_pool()).await?;
+
+    Ok(Json(groups))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        list_groups,
+        save_group,
+        delete_group,
+        group_members,
+        add_group_member,
+        remove_group_member,
+        user_groups
+    ]
+}
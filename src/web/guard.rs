@@ -0,0 +1,333 @@
+use super::api::{ApiError, ApiKey, API_KEY_HEADER};
+use crate::data::AppDatabase;
+use crate::domain::role::Principal;
+use crate::domain::tenant::{TenantError, TenantId};
+use crate::service::{action, audit, ServiceError};
+use crate::Email;
+use rocket::http::uri::Origin;
+use rocket::http::{Cookie, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::marker::PhantomData;
+
+/// A permission name checked by [`RequirePermission`].
+pub trait Permission: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+macro_rules! permission {
+    ($ty:ident, $name:literal) => {
+        pub struct $ty;
+
+        impl Permission for $ty {
+            const NAME: &'static str = $name;
+        }
+    };
+}
+
+permission!(UsersRead, "users:read");
+permission!(UsersWrite, "users:write");
+permission!(UsersImpersonate, "users:impersonate");
+permission!(RolesRead, "roles:read");
+permission!(RolesWrite, "roles:write");
+permission!(GroupsRead, "groups:read");
+permission!(GroupsWrite, "groups:write");
+permission!(TenantsRead, "tenants:read");
+permission!(TenantsWrite, "tenants:write");
+permission!(AuditRead, "audit:read");
+permission!(ClientsRead, "clients:read");
+permission!(ClientsWrite, "clients:write");
+permission!(ProvidersRead, "providers:read");
+permission!(ProvidersWrite, "providers:write");
+permission!(AccessRead, "access:read");
+permission!(AccessWrite, "access:write");
+
+/// Tenant named in the original request path, recorded by [`route_tenant`].
+struct TenantPath(Option<String>);
+
+/// Path prefixes that can be scoped to a tenant with `/t/<tenant>`.
+const TENANT_SCOPED: [&str; 4] = ["/api", "/oauth", "/auth", "/scim"];
+
+/// Rewrites `/api/t/<tenant>/...` to `/api/...` (and likewise for `/oauth`, `/auth` and
+/// `/scim`) so tenant-scoped requests reach the same routes as the default tenant,
+/// remembering the tenant for the [`TenantId`] guard.
+pub fn route_tenant(req: &mut Request<'_>) {
+    let path = req.uri().path().as_str().to_owned();
+    let (prefix, rest) = match TENANT_SCOPED.iter().find_map(|prefix| {
+        let rest = path.strip_prefix(prefix)?.strip_prefix("/t/")?;
+        Some((prefix, rest))
+    }) {
+        Some(scoped) => scoped,
+        None => return,
+    };
+
+    let (tenant, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let uri = match req.uri().query() {
+        Some(query) => format!("{prefix}/{rest}?{query}"),
+        None => format!("{prefix}/{rest}"),
+    };
+
+    if let Ok(uri) = Origin::parse_owned(uri) {
+        req.local_cache(|| TenantPath(Some(tenant.to_owned())));
+        req.set_uri(uri);
+    }
+}
+
+/// Resolves the tenant a request was made for; requests outside of `/api/t/<tenant>`
+/// belong to the default tenant.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TenantId {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        async fn resolve(req: &Request<'_>) -> Result<TenantId, (Status, String)> {
+            let id = match &req.local_cache(|| TenantPath(None)).0 {
+                Some(id) => id.as_str(),
+                None => TenantId::DEFAULT,
+            };
+
+            let tenant = TenantId::new(id).map_err(|e| (Status::BadRequest, e.to_string()))?;
+
+            let db = match req.guard::<&State<AppDatabase>>().await {
+                Outcome::Success(db) => db,
+                _ => return Err((Status::InternalServerError, "server error".to_string())),
+            };
+
+            match action::tenant_exists(&tenant, db.get_pool()).await {
+                Ok(true) => Ok(tenant),
+                Ok(false) => Err((
+                    Status::NotFound,
+                    TenantError::UnknownTenant(id.to_string()).to_string(),
+                )),
+                Err(_) => Err((Status::InternalServerError, "server error".to_string())),
+            }
+        }
+
+        match req.local_cache_async(resolve(req)).await {
+            Ok(tenant) => Outcome::Success(tenant.clone()),
+            Err((status, msg)) => Outcome::Failure((*status, ApiError::User(Json(msg.clone())))),
+        }
+    }
+}
+
+/// Resolves who the caller's API key acts for. An OAuth access token granting permissions
+/// may be sent as a `Bearer` token instead, by services (client credentials) and by
+/// `authyclient login` (device grant).
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Principal {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let bearer = match req.headers().get_one(API_KEY_HEADER) {
+            Some(_) => None,
+            None => bearer_token(req),
+        };
+        if let Some(token) = bearer {
+            return token_principal(req, token).await;
+        }
+
+        let api_key = match req.guard::<ApiKey>().await {
+            Outcome::Success(api_key) => api_key,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            Outcome::Forward(f) => return Outcome::Forward(f),
+        };
+
+        let tenant = match req.guard::<TenantId>().await {
+            Outcome::Success(tenant) => tenant,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            Outcome::Forward(f) => return Outcome::Forward(f),
+        };
+
+        let db = match req.guard::<&State<AppDatabase>>().await {
+            Outcome::Success(db) => db,
+            _ => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    ApiError::Server(Json("server error".to_string())),
+                ))
+            }
+        };
+
+        match action::principal(&tenant, api_key, db.get_pool()).await {
+            Ok(principal) => Outcome::Success(principal),
+            Err(e) => Outcome::Failure((Status::Forbidden, e.into())),
+        }
+    }
+}
+
+fn bearer_token<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    req.headers()
+        .get_one("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+async fn token_principal(req: &Request<'_>, token: &str) -> Outcome<Principal, ApiError> {
+    let tenant = match req.guard::<TenantId>().await {
+        Outcome::Success(tenant) => tenant,
+        Outcome::Failure(e) => return Outcome::Failure(e),
+        Outcome::Forward(f) => return Outcome::Forward(f),
+    };
+
+    let db = match req.guard::<&State<AppDatabase>>().await {
+        Outcome::Success(db) => db,
+        _ => {
+            return Outcome::Failure((
+                Status::InternalServerError,
+                ApiError::Server(Json("server error".to_string())),
+            ))
+        }
+    };
+
+    match action::token_principal(&tenant, token, db.get_pool()).await {
+        Ok(principal) => Outcome::Success(principal),
+        Err(ServiceError::OAuth(e)) => {
+            Outcome::Failure((Status::Unauthorized, ApiError::User(Json(e.to_string()))))
+        }
+        Err(e) => Outcome::Failure((Status::InternalServerError, e.into())),
+    }
+}
+
+/// Request guard that only succeeds if the caller's API key carries permission `P`.
+pub struct RequirePermission<P: Permission> {
+    pub principal: Principal,
+    _permission: PhantomData<P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: Permission> FromRequest<'r> for RequirePermission<P> {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.guard::<Principal>().await {
+            Outcome::Success(principal) if principal.has_permission(P::NAME) => {
+                Outcome::Success(Self {
+                    principal,
+                    _permission: PhantomData,
+                })
+            }
+            Outcome::Success(_) => Outcome::Failure((
+                Status::Forbidden,
+                ApiError::Forbidden(Json(format!("missing permission: {}", P::NAME))),
+            )),
+            Outcome::Failure(e) => Outcome::Failure(e),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+/// Rejects sensitive actions, such as password changes, made with an impersonation key.
+pub fn forbid_impersonation(principal: &Principal) -> Result<(), ApiError> {
+    match principal.impersonator() {
+        Some(impersonator) => Err(ApiError::Forbidden(Json(format!(
+            "not allowed while impersonating (impersonator: {})",
+            impersonator.clone().into_inner()
+        )))),
+        None => Ok(()),
+    }
+}
+
+/// Restricts endpoints that check credentials on a user's behalf to operator keys.
+pub fn require_operator(principal: &Principal) -> Result<(), ApiError> {
+    match principal {
+        Principal::Operator => Ok(()),
+        _ => Err(ApiError::Forbidden(Json(
+            "requires an operator API key".to_string(),
+        ))),
+    }
+}
+
+/// Lets users act on their own account, and anyone else only with permission `P`.
+pub fn require_self_or<P: Permission>(
+    principal: &Principal,
+    email: &Email,
+) -> Result<(), ApiError> {
+    match principal.email() == Some(email) || principal.has_permission(P::NAME) {
+        true => Ok(()),
+        false => Err(ApiError::Forbidden(Json(format!(
+            "missing permission: {}",
+            P::NAME
+        )))),
+    }
+}
+
+/// Email of the user a principal acts as; operator keys belong to no user.
+pub fn user_email(principal: &Principal) -> Result<Email, ApiError> {
+    principal
+        .email()
+        .cloned()
+        .ok_or_else(|| ApiError::BadRequest(Json("requires a user API key".to_string())))
+}
+
+/// Header clients can set to correlate their requests with audit events.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Collects the caller and request details recorded with audit events. Never fails: a
+/// missing or invalid API key just leaves the actor unset.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for audit::Context {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let authenticated =
+            req.headers().get_one(API_KEY_HEADER).is_some() || bearer_token(req).is_some();
+        let (actor, impersonator) = match authenticated {
+            true => match req.guard::<Principal>().await {
+                Outcome::Success(principal) => (
+                    principal.email().cloned(),
+                    principal.impersonator().cloned(),
+                ),
+                _ => (None, None),
+            },
+            false => (None, None),
+        };
+
+        let request_id = match req.headers().get_one(REQUEST_ID_HEADER) {
+            Some(id) => id.chars().take(128).collect(),
+            None => format!("{:032x}", rand::random::<u128>()),
+        };
+
+        Outcome::Success(audit::Context {
+            actor,
+            impersonator,
+            ip: req.client_ip().map(|ip| ip.to_string()),
+            user_agent: req.headers().get_one("user-agent").map(str::to_owned),
+            request_id: Some(request_id),
+            device_id: req
+                .cookies()
+                .get_private(DEVICE_COOKIE)
+                .map(|cookie| cookie.value().to_string()),
+        })
+    }
+}
+
+/// Private cookie identifying the browser or client a user signs in from.
+pub const DEVICE_COOKIE: &str = "authy_device";
+
+/// The caller's device id, taken from the device cookie; a new id (and cookie) is issued
+/// to clients that do not have one yet.
+pub struct Device(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Device {
+    type Error = ApiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let cookies = req.cookies();
+        if let Some(cookie) = cookies.get_private(DEVICE_COOKIE) {
+            return Outcome::Success(Device(cookie.value().to_string()));
+        }
+
+        let id = format!("{:032x}", rand::random::<u128>());
+        cookies.add_private(
+            Cookie::build(DEVICE_COOKIE, id.clone())
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .permanent()
+                .finish(),
+        );
+
+        Outcome::Success(Device(id))
+    }
+}
@@ -0,0 +1,70 @@
+use super::api::{ApiError, ApiKey};
+use super::guard::{forbid_impersonation, user_email};
+use crate::data::AppDatabase;
+use crate::domain::identity::Identity;
+use crate::domain::role::Principal;
+use crate::domain::tenant::TenantId;
+use crate::service::mailer::Links;
+use crate::service::{action, ask, audit};
+use rocket::serde::json::Json;
+use rocket::State;
+
+/// The calling user's ways to sign in.
+#[rocket::get("/")]
+pub async fn list_identities(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    principal: Principal,
+) -> Result<Json<Vec<Identity>>, ApiError> {
+    let identities =
+        action::list_identities(&tenant, user_email(&principal)?, database.get_pool()).await?;
+
+    Ok(Json(identities))
+}
+
+/// Starts linking an identity at a provider; the response says where to send the user's
+/// browser. Passkeys are added through `/api/user/webauthn`, and setting a password adds
+/// the password back.
+#[rocket::post("/", data = "<req>")]
+pub async fn link_identity(
+    req: Json<ask::LinkIdentity>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    principal: Principal,
+    session: Option<ApiKey>,
+) -> Result<Json<ask::IdentityLinkStarted>, ApiError> {
+    forbid_impersonation(&principal)?;
+    let started = action::start_identity_link(
+        &tenant,
+        &ctx,
+        user_email(&principal)?,
+        req.into_inner(),
+        session,
+        links,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(started))
+}
+
+#[rocket::delete("/<id>")]
+pub async fn unlink_identity(
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    principal: Principal,
+) -> Result<Json<&'static str>, ApiError> {
+    forbid_impersonation(&principal)?;
+    let email = user_email(&principal)?;
+    action::unlink_identity(&tenant, &ctx, email, id, database.get_pool()).await?;
+
+    Ok(Json("identity removed"))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list_identities, link_identity, unlink_identity]
+}
@@ -0,0 +1,60 @@
+use super::api::{ApiError, ApiKey};
+use super::guard::{RequirePermission, UsersRead, UsersWrite};
+use crate::data::AppDatabase;
+use crate::domain::invitation::Invitation;
+use crate::domain::tenant::TenantId;
+use crate::service::mailer::Mailer;
+use crate::service::{action, ask, audit};
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[rocket::get("/")]
+pub async fn list_invitations(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersRead>,
+) -> Result<Json<Vec<Invitation>>, ApiError> {
+    let invitations = action::list_invitations(&tenant, database.get_pool()).await?;
+
+    Ok(Json(invitations))
+}
+
+#[rocket::post("/", data = "<req>")]
+pub async fn invite_user(
+    req: Json<ask::NewInvitation>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    auth: RequirePermission<UsersWrite>,
+) -> Result<Json<Invitation>, ApiError> {
+    let invitation = action::invite_user(
+        &tenant,
+        &ctx,
+        &auth.principal,
+        req.into_inner(),
+        mailer,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(invitation))
+}
+
+#[rocket::post("/accept", data = "<req>")]
+pub async fn accept_invitation(
+    req: Json<ask::AcceptInvitation>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _api_key: ApiKey,
+) -> Result<Json<crate::User>, ApiError> {
+    let user =
+        action::accept_invitation(&tenant, &ctx, req.into_inner(), database.get_pool()).await?;
+
+    Ok(Json(user))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list_invitations, invite_user, accept_invitation]
+}
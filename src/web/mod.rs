@@ -1,3 +1,17 @@
+pub mod access;
 pub mod api;
+pub mod audit;
+pub mod client;
+pub mod forward;
+pub mod group;
+pub mod guard;
+pub mod identity;
+pub mod invitation;
+pub mod oauth;
+pub mod provider;
+pub mod role;
+pub mod scim;
+pub mod tenant;
+pub mod webauthn;
 
 // pub const PASSWORD_COOKIE: &str = "password";
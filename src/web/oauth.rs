@@ -0,0 +1,1393 @@
+use super::api::{ApiError, ApiKey};
+use super::guard::Device;
+use crate::data::{AppDatabase, DatabasePool};
+use crate::domain::federation::{FederatedLogin, FederationError, IdentityProvider};
+use crate::domain::oauth::{Client, OAuthError, Scope};
+use crate::domain::oidc::UserInfo;
+use crate::domain::tenant::TenantId;
+use crate::domain::token::Token;
+use crate::service::ask::{self, AuthorizeRequest};
+use crate::service::mailer::{Links, Mailer};
+use crate::service::{action, audit, ServiceError};
+use crate::{Email, RelyingParty};
+use base64::engine::general_purpose;
+use base64::Engine;
+use rocket::form::Form;
+use rocket::http::{Cookie, CookieJar, Header, RawStr, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::RawHtml;
+use rocket::response::{self, Redirect, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Private cookie holding the API key of the user signed in to the authorization page.
+pub const SESSION_COOKIE: &str = "authy_session";
+
+/// Where browsers send the session cookie: only to this server, or with `domain` (such as
+/// `example.com`) to every host under it too, so that requests to hosts behind a reverse
+/// proxy carry the session to `/auth/verify`.
+#[derive(Debug, Clone, Default)]
+pub struct SessionCookie {
+    pub domain: Option<String>,
+}
+
+impl SessionCookie {
+    fn build(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build(SESSION_COOKIE, value)
+            .http_only(true)
+            // not sent with cross-site posts, which could otherwise approve requests on the
+            // user's behalf
+            .same_site(SameSite::Lax)
+            .finish();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+}
+
+/// Private cookie holding a sign-in through an identity provider until it sends the user
+/// back.
+const FEDERATED_COOKIE: &str = "authy_federated";
+
+/// Parameters of an OAuth request, from its query or form body.
+type Params = HashMap<String, String>;
+
+/// Reads OAuth parameters into `T` the way JSON bodies are read everywhere else.
+fn parse<T: DeserializeOwned>(params: Params) -> Result<T, OAuthError> {
+    serde_json::to_value(params)
+        .and_then(serde_json::from_value)
+        .map_err(|e| OAuthError::InvalidRequest(e.to_string()))
+}
+
+/// Outcome of an authorization request.
+#[derive(rocket::Responder)]
+pub enum Authorization {
+    /// Sign-in and consent page; it must not be framed by other sites.
+    Page(RawHtml<String>, Header<'static>),
+    /// Back to the client with a code or an error.
+    Redirect(Redirect),
+    /// The client or redirect URI is invalid, so there is nowhere safe to redirect to.
+    #[response(status = 400)]
+    Invalid(RawHtml<String>),
+}
+
+impl Authorization {
+    fn page(body: String) -> Self {
+        Self::Page(RawHtml(body), Header::new("X-Frame-Options", "DENY"))
+    }
+
+    fn invalid(error: ServiceError) -> Self {
+        Self::Invalid(RawHtml(page(
+            "Invalid request",
+            &format!("<p>{}</p>", escape(&error_message(error))),
+        )))
+    }
+
+    /// Sends the user back to the client with `params` and the request's `state`.
+    fn redirect(req: &AuthorizeRequest, params: &[(&str, &str)]) -> Self {
+        match url::Url::parse(&req.redirect_uri) {
+            Ok(mut url) => {
+                url.query_pairs_mut().extend_pairs(params);
+                if let Some(state) = &req.state {
+                    url.query_pairs_mut().append_pair("state", state);
+                }
+                Self::Redirect(Redirect::to(url.to_string()))
+            }
+            Err(_) => {
+                Self::invalid(OAuthError::InvalidRedirectUri(req.redirect_uri.clone()).into())
+            }
+        }
+    }
+
+    /// Reports an error to the client; only valid once the redirect URI has been checked.
+    fn error(req: &AuthorizeRequest, error: ServiceError) -> Self {
+        match error {
+            ServiceError::OAuth(e) => Self::redirect(
+                req,
+                &[("error", e.code()), ("error_description", &e.to_string())],
+            ),
+            e => {
+                eprintln!("authorization error: {e}");
+                Self::redirect(
+                    req,
+                    &[
+                        ("error", "server_error"),
+                        ("error_description", "a server error occured"),
+                    ],
+                )
+            }
+        }
+    }
+}
+
+/// What to tell the user about an error on one of the authorization pages.
+fn error_message(error: ServiceError) -> String {
+    match error {
+        ServiceError::OAuth(e) => e.to_string(),
+        ServiceError::Federation(e) => e.to_string(),
+        ServiceError::Access(e) => e.to_string(),
+        ServiceError::Forbidden(message) => message,
+        e @ ServiceError::InvalidRequest(_) => e.to_string(),
+        e @ ServiceError::AccountStatus(_) => e.to_string(),
+        e => {
+            eprintln!("authorization error: {e}");
+            "a server error occured".to_string()
+        }
+    }
+}
+
+/// Checks an authorization request, returning the client and the scope it asks for.
+async fn validate(
+    tenant: &TenantId,
+    req: &AuthorizeRequest,
+    pool: &DatabasePool,
+) -> Result<(Client, Scope), Authorization> {
+    let client = action::authorization_client(tenant, req, pool)
+        .await
+        .map_err(Authorization::invalid)?;
+    let scope = action::authorization_scope(&client, req)
+        .map_err(|e| Authorization::error(req, e.into()))?;
+
+    Ok((client, scope))
+}
+
+/// The key in the browser's session cookie, if it has one.
+fn session_key(cookies: &CookieJar<'_>) -> Option<ApiKey> {
+    ApiKey::from_str(cookies.get_private(SESSION_COOKIE)?.value()).ok()
+}
+
+async fn signed_in_user(
+    tenant: &TenantId,
+    cookies: &CookieJar<'_>,
+    pool: &DatabasePool,
+) -> Option<Email> {
+    let api_key = session_key(cookies)?;
+
+    action::session_user(tenant, api_key, pool)
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn issue_code(
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    client: &Client,
+    email: Email,
+    scope: Scope,
+    req: &AuthorizeRequest,
+    pool: &DatabasePool,
+) -> Authorization {
+    match action::authorize(tenant, ctx, client, email, scope, req, pool).await {
+        Ok(code) => Authorization::redirect(req, &[("code", code.as_str())]),
+        Err(e) => Authorization::error(req, e),
+    }
+}
+
+/// Authorization endpoint. Users who are signed in and have already consented to the
+/// requested scope go straight back to the client; everyone else gets the consent page,
+/// unless the client asked for `prompt=none`.
+#[rocket::get("/authorize?<params..>")]
+pub async fn authorize(
+    params: Params,
+    tenant: TenantId,
+    ctx: audit::Context,
+    cookies: &CookieJar<'_>,
+    database: &State<AppDatabase>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let req: AuthorizeRequest = match parse(params) {
+        Ok(req) => req,
+        Err(e) => return Authorization::invalid(e.into()),
+    };
+    let (client, scope) = match validate(&tenant, &req, pool).await {
+        Ok(validated) => validated,
+        Err(response) => return response,
+    };
+
+    let user = signed_in_user(&tenant, cookies, pool).await;
+    if let Some(email) = &user {
+        match action::has_consent(&tenant, &client, email, &scope, pool).await {
+            Ok(true) => {
+                return issue_code(&tenant, &ctx, &client, email.clone(), scope, &req, pool).await
+            }
+            Ok(false) => {}
+            Err(e) => return Authorization::error(&req, e),
+        }
+    }
+    if req.prompt.as_deref() == Some("none") {
+        let error = match user {
+            Some(_) => OAuthError::ConsentRequired,
+            None => OAuthError::LoginRequired,
+        };
+        return Authorization::error(&req, error.into());
+    }
+
+    let providers = sign_in_providers(&tenant, pool).await;
+    Authorization::page(consent_page(
+        &client,
+        &req,
+        &scope,
+        user.as_ref(),
+        &providers,
+        None,
+    ))
+}
+
+/// Credentials posted with a page, unless the user is already signed in.
+#[derive(Debug, Deserialize)]
+pub struct SignIn {
+    email: Option<String>,
+    password: Option<String>,
+}
+
+/// The user signed in to the authorization pages, or else the one signing in with the
+/// credentials posted with the page, who then stays signed in. Errors are shown on the page.
+#[allow(clippy::too_many_arguments)]
+async fn sign_in(
+    tenant: &TenantId,
+    ctx: &mut audit::Context,
+    device: Device,
+    form: &SignIn,
+    cookies: &CookieJar<'_>,
+    session_cookie: &SessionCookie,
+    mailer: &Mailer,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Result<Email, String> {
+    if let Some(email) = signed_in_user(tenant, cookies, pool).await {
+        return Ok(email);
+    }
+
+    ctx.device_id = Some(device.0);
+    let credentials = ask::GetUser::from_raw(
+        form.email.as_deref().unwrap_or_default(),
+        form.password.as_deref().unwrap_or_default(),
+    );
+    let issued = action::issue_api_key(tenant, ctx, credentials, mailer, links, pool)
+        .await
+        .map_err(|e| match e {
+            ServiceError::Forbidden(message) => message,
+            e @ ServiceError::AccountStatus(_) => e.to_string(),
+            _ => "wrong email or password".to_string(),
+        })?;
+
+    cookies.add_private(session_cookie.build(issued.api_key));
+    Ok(issued.email)
+}
+
+/// Providers offered on the sign-in pages.
+async fn sign_in_providers(tenant: &TenantId, pool: &DatabasePool) -> Vec<IdentityProvider> {
+    action::list_identity_providers(tenant, pool)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("could not list identity providers: {e}");
+            Vec::new()
+        })
+}
+
+/// Form posted by the consent page.
+#[derive(Debug, Deserialize)]
+pub struct Consent {
+    #[serde(flatten)]
+    request: AuthorizeRequest,
+    #[serde(flatten)]
+    sign_in: SignIn,
+    /// `allow` or `deny`.
+    decision: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/authorize", data = "<form>")]
+pub async fn consent(
+    form: Form<Params>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    cookies: &CookieJar<'_>,
+    session_cookie: &State<SessionCookie>,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let form: Consent = match parse(form.into_inner()) {
+        Ok(form) => form,
+        Err(e) => return Authorization::invalid(e.into()),
+    };
+    let req = &form.request;
+    let (client, scope) = match validate(&tenant, req, pool).await {
+        Ok(validated) => validated,
+        Err(response) => return response,
+    };
+
+    if form.decision != "allow" {
+        return Authorization::error(req, OAuthError::AccessDenied.into());
+    }
+
+    let signed_in = sign_in(
+        &tenant,
+        &mut ctx,
+        device,
+        &form.sign_in,
+        cookies,
+        session_cookie,
+        mailer,
+        links,
+        pool,
+    )
+    .await;
+    let email = match signed_in {
+        Ok(email) => email,
+        Err(message) => {
+            let providers = sign_in_providers(&tenant, pool).await;
+            let page = consent_page(&client, req, &scope, None, &providers, Some(&message));
+            return Authorization::page(page);
+        }
+    };
+
+    issue_code(&tenant, &ctx, &client, email, scope, req, pool).await
+}
+
+/// Verification page of the device grant, where users enter the code a device shows them
+/// and approve its request; `verification_uri_complete` links here with the code filled in.
+#[rocket::get("/device?<params..>")]
+pub async fn device(
+    params: Params,
+    tenant: TenantId,
+    cookies: &CookieJar<'_>,
+    database: &State<AppDatabase>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let user_code = match params.get("user_code") {
+        Some(user_code) => user_code,
+        None => return Authorization::page(device_code_page(None)),
+    };
+
+    match action::device_request(&tenant, user_code, pool).await {
+        Ok(request) => {
+            let user = signed_in_user(&tenant, cookies, pool).await;
+            let providers = sign_in_providers(&tenant, pool).await;
+            Authorization::page(device_consent_page(
+                &request,
+                user.as_ref(),
+                &providers,
+                None,
+            ))
+        }
+        Err(e) => Authorization::page(device_code_page(Some(&error_message(e)))),
+    }
+}
+
+/// Form posted by the device consent page.
+#[derive(Debug, Deserialize)]
+pub struct DeviceConsent {
+    user_code: String,
+    #[serde(flatten)]
+    sign_in: SignIn,
+    /// `allow` or `deny`.
+    decision: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/device", data = "<form>")]
+pub async fn device_consent(
+    form: Form<Params>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    cookies: &CookieJar<'_>,
+    session_cookie: &State<SessionCookie>,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let form: DeviceConsent = match parse(form.into_inner()) {
+        Ok(form) => form,
+        Err(e) => return Authorization::invalid(e.into()),
+    };
+    let request = match action::device_request(&tenant, &form.user_code, pool).await {
+        Ok(request) => request,
+        Err(e) => return Authorization::page(device_code_page(Some(&error_message(e)))),
+    };
+
+    if form.decision != "allow" {
+        return match action::deny_device_request(&tenant, &ctx, &request, pool).await {
+            Ok(()) => Authorization::page(page(
+                "Request denied",
+                "<p>The device was not given access. You can close this window.</p>",
+            )),
+            Err(e) => Authorization::page(device_code_page(Some(&error_message(e)))),
+        };
+    }
+
+    let signed_in = sign_in(
+        &tenant,
+        &mut ctx,
+        device,
+        &form.sign_in,
+        cookies,
+        session_cookie,
+        mailer,
+        links,
+        pool,
+    )
+    .await;
+    let email = match signed_in {
+        Ok(email) => email,
+        Err(message) => {
+            let providers = sign_in_providers(&tenant, pool).await;
+            let page = device_consent_page(&request, None, &providers, Some(&message));
+            return Authorization::page(page);
+        }
+    };
+
+    match action::approve_device_request(&tenant, &ctx, &request, email, pool).await {
+        Ok(()) => Authorization::page(page(
+            "Device connected",
+            "<p>You can return to your device now.</p>",
+        )),
+        Err(e) => Authorization::page(device_code_page(Some(&error_message(e)))),
+    }
+}
+
+/// Sign-in page for hosts behind a reverse proxy, which `/auth/verify` sends users without
+/// a session to; `rd` is the URL to go back to once signed in.
+#[rocket::get("/login?<params..>")]
+pub async fn login(
+    params: Params,
+    tenant: TenantId,
+    cookies: &CookieJar<'_>,
+    database: &State<AppDatabase>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let rd = params.get("rd").map(String::as_str).unwrap_or_default();
+    let destination = match action::forward_destination(&tenant, rd, pool).await {
+        Ok(destination) => destination,
+        Err(e) => return Authorization::invalid(e),
+    };
+
+    if signed_in_user(&tenant, cookies, pool).await.is_some() {
+        return Authorization::Redirect(Redirect::to(destination));
+    }
+    let providers = sign_in_providers(&tenant, pool).await;
+    Authorization::page(login_page(&destination, &providers, None))
+}
+
+/// Form posted by the sign-in page.
+#[derive(Debug, Deserialize)]
+pub struct Login {
+    rd: String,
+    #[serde(flatten)]
+    sign_in: SignIn,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/login", data = "<form>")]
+pub async fn login_post(
+    form: Form<Params>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    cookies: &CookieJar<'_>,
+    session_cookie: &State<SessionCookie>,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let form: Login = match parse(form.into_inner()) {
+        Ok(form) => form,
+        Err(e) => return Authorization::invalid(e.into()),
+    };
+    let destination = match action::forward_destination(&tenant, &form.rd, pool).await {
+        Ok(destination) => destination,
+        Err(e) => return Authorization::invalid(e),
+    };
+
+    let signed_in = sign_in(
+        &tenant,
+        &mut ctx,
+        device,
+        &form.sign_in,
+        cookies,
+        session_cookie,
+        mailer,
+        links,
+        pool,
+    )
+    .await;
+    match signed_in {
+        Ok(_) => Authorization::Redirect(Redirect::to(destination)),
+        Err(message) => {
+            let providers = sign_in_providers(&tenant, pool).await;
+            Authorization::page(login_page(&destination, &providers, Some(&message)))
+        }
+    }
+}
+
+/// Where users may be sent back to after signing in through a provider: the authorization,
+/// device and sign-in pages, relative to `/oauth`.
+fn valid_return_to(return_to: &str) -> bool {
+    return_to.starts_with("authorize?")
+        || return_to.starts_with("device?")
+        || return_to.starts_with("login?")
+}
+
+/// Starts a sign-in through an identity provider. With `link`, a token handed out by
+/// `POST /api/user/identities`, the provider's identity is linked to the user who asked
+/// for it instead, in a browser signed in as them.
+#[rocket::get("/login/<provider>?<params..>")]
+pub async fn federated_login(
+    provider: &str,
+    params: Params,
+    tenant: TenantId,
+    cookies: &CookieJar<'_>,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let return_to = params.get("return_to").cloned();
+    if return_to
+        .as_deref()
+        .is_some_and(|path| !valid_return_to(path))
+    {
+        return Authorization::invalid(ServiceError::InvalidRequest(
+            "invalid return_to".to_string(),
+        ));
+    }
+    let link_request = params.get("link").map(|token| Token::from(token.as_str()));
+    let session = session_key(cookies);
+
+    let started = action::start_federated_login(
+        &tenant,
+        provider,
+        return_to,
+        link_request,
+        session,
+        links,
+        pool,
+    )
+    .await;
+    let (login, url) = match started {
+        Ok(started) => started,
+        Err(e) => return Authorization::invalid(e),
+    };
+    let login = match serde_json::to_string(&login) {
+        Ok(login) => login,
+        Err(e) => return Authorization::invalid(ServiceError::InvalidRequest(e.to_string())),
+    };
+    cookies.add_private(
+        Cookie::build(FEDERATED_COOKIE, login)
+            .http_only(true)
+            // sent when the provider redirects back, but not with cross-site posts
+            .same_site(SameSite::Lax)
+            .max_age(rocket::time::Duration::minutes(10))
+            .finish(),
+    );
+
+    Authorization::Redirect(Redirect::to(url))
+}
+
+/// Redirection endpoint the identity provider sends users back to.
+#[allow(clippy::too_many_arguments)]
+#[rocket::get("/login/<provider>/callback?<params..>")]
+pub async fn federated_callback(
+    provider: &str,
+    params: Params,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    cookies: &CookieJar<'_>,
+    session_cookie: &State<SessionCookie>,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    rp: &State<RelyingParty>,
+) -> Authorization {
+    let pool = database.get_pool();
+    let login = cookies
+        .get_private(FEDERATED_COOKIE)
+        .and_then(|cookie| serde_json::from_str::<FederatedLogin>(cookie.value()).ok())
+        .filter(|login| login.provider == provider);
+    cookies.remove_private(Cookie::named(FEDERATED_COOKIE));
+    let login = match login {
+        Some(login) => login,
+        None => return Authorization::invalid(FederationError::InvalidState.into()),
+    };
+    let callback: ask::FederatedCallback = match parse(params) {
+        Ok(callback) => callback,
+        Err(e) => return Authorization::invalid(e.into()),
+    };
+
+    ctx.device_id = Some(device.0);
+    let return_to = login
+        .return_to
+        .as_ref()
+        .map(|path| links.oauth(&tenant, &format!("/{path}")));
+    let session = session_key(cookies);
+    let finished = action::finish_federated_login(
+        &tenant, &ctx, login, callback, session, mailer, links, rp, pool,
+    )
+    .await;
+    match finished {
+        Ok(ask::FederatedSignIn::Linked) => Authorization::page(page(
+            "Account linked",
+            "<p>You can now sign in with this provider. You can close this window.</p>",
+        )),
+        Ok(ask::FederatedSignIn::SignedIn(issued)) => {
+            cookies.add_private(session_cookie.build(issued.api_key));
+            match return_to {
+                Some(url) => Authorization::Redirect(Redirect::to(url)),
+                None => Authorization::page(page(
+                    "Signed in",
+                    "<p>You are signed in. You can close this window.</p>",
+                )),
+            }
+        }
+        Ok(ask::FederatedSignIn::PasskeyRequired(options)) => {
+            let finish = links.oauth(&tenant, "/login/passkey");
+            Authorization::page(passkey_page(&options, &finish, return_to.as_deref()))
+        }
+        Err(e) => Authorization::invalid(e),
+    }
+}
+
+/// Finishes a sign-in with a passkey in the browser, such as the one asked for after a
+/// provider on [`passkey_page`]; answers 200 once the session cookie is set.
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/login/passkey", data = "<req>")]
+pub async fn passkey_login(
+    req: Json<ask::PasskeyAssertion>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    cookies: &CookieJar<'_>,
+    session_cookie: &State<SessionCookie>,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    rp: &State<RelyingParty>,
+) -> Result<Json<&'static str>, ApiError> {
+    ctx.device_id = Some(device.0);
+    let issued = action::finish_passkey_login(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        mailer,
+        links,
+        rp,
+        database.get_pool(),
+    )
+    .await?;
+    cookies.add_private(session_cookie.build(issued.api_key));
+
+    Ok(Json("signed in"))
+}
+
+/// Client credentials sent with HTTP Basic authentication, if any.
+pub struct BasicCredentials(Option<(String, String)>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BasicCredentials {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // both parts are form-urlencoded before being joined (RFC 6749, section 2.3.1)
+        let decode = |part: &str| RawStr::new(part).url_decode_lossy().into_owned();
+        let credentials = req
+            .headers()
+            .get_one("authorization")
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| general_purpose::STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                let (id, secret) = decoded.split_once(':')?;
+                Some((decode(id), decode(secret)))
+            });
+
+        Outcome::Success(Self(credentials))
+    }
+}
+
+impl BasicCredentials {
+    /// Puts HTTP Basic credentials in place of those from the body; a client must not use
+    /// both.
+    fn apply(
+        self,
+        client_id: &mut Option<String>,
+        client_secret: &mut Option<String>,
+    ) -> Result<(), OAuthError> {
+        if let Some((id, secret)) = self.0 {
+            let conflicting =
+                client_secret.is_some() || client_id.as_ref().is_some_and(|body_id| *body_id != id);
+            if conflicting {
+                return Err(OAuthError::InvalidRequest(
+                    "use only one way of authenticating the client".to_string(),
+                ));
+            }
+            *client_id = Some(id);
+            *client_secret = Some(secret);
+        }
+
+        Ok(())
+    }
+}
+
+/// Error response of the token endpoint (RFC 6749, section 5.2).
+pub struct TokenError(ServiceError);
+
+impl<'r> Responder<'r, 'static> for TokenError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (status, code, description) = match self.0 {
+            ServiceError::OAuth(e @ OAuthError::InvalidClient) => {
+                (Status::Unauthorized, e.code(), e.to_string())
+            }
+            ServiceError::OAuth(e) => (Status::BadRequest, e.code(), e.to_string()),
+            e => {
+                eprintln!("token endpoint error: {e}");
+                (
+                    Status::InternalServerError,
+                    "server_error",
+                    "a server error occured".to_string(),
+                )
+            }
+        };
+
+        let body = Json(json!({ "error": code, "error_description": description }));
+        let mut response = Response::build_from(body.respond_to(req)?);
+        response.status(status);
+        if status == Status::Unauthorized {
+            response.raw_header("WWW-Authenticate", r#"Basic realm="authy""#);
+        }
+
+        response.ok()
+    }
+}
+
+/// Keeps token endpoint responses out of caches.
+pub struct NoStore<R>(R);
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for NoStore<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.0.respond_to(req)?)
+            .raw_header("Cache-Control", "no-store")
+            .raw_header("Pragma", "no-cache")
+            .ok()
+    }
+}
+
+/// Token endpoint. Clients authenticate with HTTP Basic or with `client_id` and
+/// `client_secret` in the form; public clients only send their `client_id`.
+#[rocket::post("/token", data = "<req>")]
+pub async fn token(
+    req: Form<Params>,
+    credentials: BasicCredentials,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+) -> NoStore<Result<Json<ask::TokenResponse>, TokenError>> {
+    let result = async {
+        let mut req: ask::TokenRequest = parse(req.into_inner())?;
+        credentials.apply(&mut req.client_id, &mut req.client_secret)?;
+
+        action::exchange_token(&tenant, &ctx, req, links, database.get_pool()).await
+    }
+    .await;
+
+    NoStore(result.map(Json).map_err(TokenError))
+}
+
+/// Device authorization endpoint (RFC 8628, section 3.1). Clients authenticate as they do
+/// at the token endpoint.
+#[rocket::post("/device_authorization", data = "<req>")]
+pub async fn device_authorization(
+    req: Form<Params>,
+    credentials: BasicCredentials,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+) -> NoStore<Result<Json<ask::DeviceAuthorization>, TokenError>> {
+    let result = async {
+        let mut req: ask::DeviceAuthorizationRequest = parse(req.into_inner())?;
+        credentials.apply(&mut req.client_id, &mut req.client_secret)?;
+
+        action::authorize_device(&tenant, req, links, database.get_pool()).await
+    }
+    .await;
+
+    NoStore(result.map(Json).map_err(TokenError))
+}
+
+fn token_lookup(
+    params: Params,
+    credentials: BasicCredentials,
+) -> Result<ask::TokenLookup, ServiceError> {
+    let mut req: ask::TokenLookup = parse(params)?;
+    credentials.apply(&mut req.client_id, &mut req.client_secret)?;
+
+    Ok(req)
+}
+
+/// Token introspection endpoint (RFC 7662), for resource servers to check the tokens and
+/// sessions they are sent.
+#[rocket::post("/introspect", data = "<req>")]
+pub async fn introspect(
+    req: Form<Params>,
+    credentials: BasicCredentials,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+) -> NoStore<Result<Json<ask::TokenIntrospection>, TokenError>> {
+    let result = async {
+        let req = token_lookup(req.into_inner(), credentials)?;
+        action::introspect_token(&tenant, req, links, database.get_pool()).await
+    }
+    .await;
+
+    NoStore(result.map(Json).map_err(TokenError))
+}
+
+/// Token revocation endpoint (RFC 7009). Succeeds for unknown tokens too, so clients can
+/// simply retry until they get a response.
+#[rocket::post("/revoke", data = "<req>")]
+pub async fn revoke(
+    req: Form<Params>,
+    credentials: BasicCredentials,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+) -> Result<(), TokenError> {
+    let req = token_lookup(req.into_inner(), credentials).map_err(TokenError)?;
+
+    action::revoke_token(&tenant, &ctx, req, database.get_pool())
+        .await
+        .map_err(TokenError)
+}
+
+/// Access token sent with the `Bearer` scheme, if any.
+pub struct BearerToken(pub(super) Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = req
+            .headers()
+            .get_one("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+
+        Outcome::Success(Self(token))
+    }
+}
+
+/// Error response of a resource protected by an access token (RFC 6750, section 3).
+pub struct BearerError(ServiceError);
+
+impl<'r> Responder<'r, 'static> for BearerError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (status, error) = match self.0 {
+            ServiceError::OAuth(e @ OAuthError::InsufficientScope) => (Status::Forbidden, e),
+            ServiceError::OAuth(e) => (Status::Unauthorized, e),
+            e => {
+                eprintln!("userinfo error: {e}");
+                return Err(Status::InternalServerError);
+            }
+        };
+        let challenge = match error {
+            OAuthError::InsufficientScope => {
+                format!(r#"Bearer error="{}", scope="openid""#, error.code())
+            }
+            _ => format!(r#"Bearer error="{}""#, error.code()),
+        };
+
+        let body = Json(json!({ "error": error.code(), "error_description": error.to_string() }));
+        Response::build_from(body.respond_to(req)?)
+            .status(status)
+            .raw_header("WWW-Authenticate", challenge)
+            .ok()
+    }
+}
+
+async fn user_info(
+    tenant: &TenantId,
+    token: BearerToken,
+    pool: &DatabasePool,
+) -> NoStore<Result<Json<UserInfo>, BearerError>> {
+    let result = match token.0 {
+        Some(token) => action::user_info(tenant, &token, pool).await,
+        None => Err(OAuthError::InvalidToken.into()),
+    };
+
+    NoStore(result.map(Json).map_err(BearerError))
+}
+
+/// OpenID Connect UserInfo endpoint.
+#[rocket::get("/userinfo")]
+pub async fn userinfo(
+    token: BearerToken,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+) -> NoStore<Result<Json<UserInfo>, BearerError>> {
+    user_info(&tenant, token, database.get_pool()).await
+}
+
+#[rocket::post("/userinfo")]
+pub async fn userinfo_post(
+    token: BearerToken,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+) -> NoStore<Result<Json<UserInfo>, BearerError>> {
+    user_info(&tenant, token, database.get_pool()).await
+}
+
+/// Keys ID tokens are signed with.
+#[rocket::get("/jwks")]
+pub async fn jwks(database: &State<AppDatabase>) -> Result<Json<serde_json::Value>, ApiError> {
+    Ok(Json(action::jwks(database.get_pool()).await?))
+}
+
+/// OpenID Provider metadata. The default tenant's is served at the root, as its issuer is
+/// the site itself; other tenants' under `/oauth/t/<tenant>`.
+#[rocket::get("/.well-known/openid-configuration")]
+pub fn discovery(tenant: TenantId, links: &State<Links>) -> Json<serde_json::Value> {
+    Json(action::openid_configuration(&tenant, links))
+}
+
+/// Outcome of a logout request.
+#[derive(rocket::Responder)]
+pub enum Logout {
+    Page(RawHtml<String>, Header<'static>),
+    /// Back to the client's post-logout redirect URI.
+    Redirect(Redirect),
+    #[response(status = 400)]
+    Invalid(RawHtml<String>),
+}
+
+async fn end_session(
+    params: Params,
+    tenant: &TenantId,
+    ctx: &audit::Context,
+    cookies: &CookieJar<'_>,
+    session_cookie: &SessionCookie,
+    links: &Links,
+    pool: &DatabasePool,
+) -> Logout {
+    let result = async {
+        let req: ask::LogoutRequest = parse(params)?;
+        let session = session_key(cookies);
+        let redirect = action::end_session(tenant, ctx, session, &req, links, pool).await?;
+        cookies.remove_private(session_cookie.build(String::new()));
+
+        Ok::<_, ServiceError>((req, redirect))
+    }
+    .await;
+
+    match result {
+        Ok((req, Some(redirect))) => match url::Url::parse(&redirect) {
+            Ok(mut url) => {
+                if let Some(state) = &req.state {
+                    url.query_pairs_mut().append_pair("state", state);
+                }
+                Logout::Redirect(Redirect::to(url.to_string()))
+            }
+            Err(_) => Logout::Invalid(RawHtml(page(
+                "Invalid request",
+                "<p>invalid post_logout_redirect_uri</p>",
+            ))),
+        },
+        Ok((_, None)) => Logout::Page(
+            RawHtml(page("Signed out", "<p>You are signed out.</p>")),
+            Header::new("X-Frame-Options", "DENY"),
+        ),
+        Err(e) => {
+            let message = match e {
+                ServiceError::OAuth(e) => e.to_string(),
+                e => {
+                    eprintln!("logout error: {e}");
+                    "a server error occured".to_string()
+                }
+            };
+            Logout::Invalid(RawHtml(page(
+                "Invalid request",
+                &format!("<p>{}</p>", escape(&message)),
+            )))
+        }
+    }
+}
+
+/// RP-initiated logout endpoint. Unless the client names the signed-in user with
+/// `id_token_hint`, the user is asked to confirm, so that a link on another site cannot
+/// sign them out.
+#[rocket::get("/logout?<params..>")]
+pub async fn logout(
+    params: Params,
+    tenant: TenantId,
+    ctx: audit::Context,
+    cookies: &CookieJar<'_>,
+    session_cookie: &State<SessionCookie>,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+) -> Logout {
+    if let Ok(req) = parse::<ask::LogoutRequest>(params.clone()) {
+        let session = session_key(cookies);
+        match action::logout_needs_confirmation(&tenant, session, &req, links, database.get_pool())
+            .await
+        {
+            Ok(false) => {}
+            Ok(true) | Err(_) => {
+                return Logout::Page(
+                    RawHtml(logout_page(&req)),
+                    Header::new("X-Frame-Options", "DENY"),
+                )
+            }
+        }
+    }
+
+    end_session(
+        params,
+        &tenant,
+        &ctx,
+        cookies,
+        session_cookie,
+        links,
+        database.get_pool(),
+    )
+    .await
+}
+
+#[rocket::post("/logout", data = "<form>")]
+pub async fn logout_post(
+    form: Form<Params>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    cookies: &CookieJar<'_>,
+    session_cookie: &State<SessionCookie>,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+) -> Logout {
+    let params = form.into_inner();
+    end_session(
+        params,
+        &tenant,
+        &ctx,
+        cookies,
+        session_cookie,
+        links,
+        database.get_pool(),
+    )
+    .await
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        authorize,
+        consent,
+        device,
+        device_consent,
+        login,
+        login_post,
+        federated_login,
+        federated_callback,
+        passkey_login,
+        token,
+        device_authorization,
+        introspect,
+        revoke,
+        userinfo,
+        userinfo_post,
+        jwks,
+        discovery,
+        logout,
+        logout_post
+    ]
+}
+
+/// Routes served outside of `/oauth`.
+pub fn discovery_routes() -> Vec<rocket::Route> {
+    rocket::routes![discovery]
+}
+
+fn consent_page(
+    client: &Client,
+    req: &AuthorizeRequest,
+    scope: &Scope,
+    user: Option<&Email>,
+    providers: &[IdentityProvider],
+    error: Option<&str>,
+) -> String {
+    let fields = [
+        ("response_type", Some(&req.response_type)),
+        ("client_id", Some(&req.client_id)),
+        ("redirect_uri", Some(&req.redirect_uri)),
+        ("scope", req.scope.as_ref()),
+        ("state", req.state.as_ref()),
+        ("code_challenge", req.code_challenge.as_ref()),
+        ("code_challenge_method", req.code_challenge_method.as_ref()),
+        ("nonce", req.nonce.as_ref()),
+    ];
+    let fields = fields
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| (*name, value.as_str())));
+    let hidden: String = fields
+        .clone()
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{name}" value="{}">"#,
+                escape(value)
+            )
+        })
+        .collect();
+    let return_to = format!(
+        "authorize?{}",
+        url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(fields)
+            .finish()
+    );
+
+    let sign_in = sign_in_fields(user, providers, &return_to);
+    let scopes = scope_list(scope);
+    let error = alert(error);
+    let name = escape(&client.name);
+
+    page(
+        &format!("Sign in to {name}"),
+        &format!(
+            r#"{error}<form method="post" action="authorize">{hidden}{sign_in}
+<p>{name} is asking for access to:</p><ul>{scopes}</ul>
+<button name="decision" value="allow">Allow</button>
+<button name="decision" value="deny">Deny</button></form>"#
+        ),
+    )
+}
+
+/// Asks a device's user for the code it shows.
+fn device_code_page(error: Option<&str>) -> String {
+    let error = alert(error);
+
+    page(
+        "Connect a device",
+        &format!(
+            r#"{error}<form method="get" action="device">
+<p><label>Code shown on your device <input name="user_code" autocomplete="off" autocapitalize="characters" required></label></p>
+<button>Continue</button></form>"#
+        ),
+    )
+}
+
+fn device_consent_page(
+    request: &ask::DeviceRequest,
+    user: Option<&Email>,
+    providers: &[IdentityProvider],
+    error: Option<&str>,
+) -> String {
+    let user_code = request.user_code.to_string();
+    let return_to = format!("device?user_code={}", request.user_code.as_str());
+    let sign_in = sign_in_fields(user, providers, &return_to);
+    let scopes = scope_list(&request.scope);
+    let error = alert(error);
+    let name = escape(&request.client.name);
+
+    page(
+        &format!("Connect {name}"),
+        &format!(
+            r#"{error}<form method="post" action="device">
+<input type="hidden" name="user_code" value="{user_code}">
+<p>Only continue if your device shows the code <strong>{user_code}</strong>.</p>{sign_in}
+<p>{name} is asking for access to:</p><ul>{scopes}</ul>
+<button name="decision" value="allow">Allow</button>
+<button name="decision" value="deny">Deny</button></form>"#
+        ),
+    )
+}
+
+/// Asks the user to confirm a logout they did not clearly ask for, posting `req` back.
+fn logout_page(req: &ask::LogoutRequest) -> String {
+    let fields = [
+        ("id_token_hint", req.id_token_hint.as_ref()),
+        ("client_id", req.client_id.as_ref()),
+        (
+            "post_logout_redirect_uri",
+            req.post_logout_redirect_uri.as_ref(),
+        ),
+        ("state", req.state.as_ref()),
+    ];
+    let hidden: String = fields
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| (*name, value)))
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{name}" value="{}">"#,
+                escape(value)
+            )
+        })
+        .collect();
+
+    page(
+        "Sign out",
+        &format!(
+            r#"<form method="post" action="logout">{hidden}
+<p>Do you want to sign out?</p>
+<button>Sign out</button></form>"#
+        ),
+    )
+}
+
+fn login_page(destination: &str, providers: &[IdentityProvider], error: Option<&str>) -> String {
+    let return_to = format!(
+        "login?{}",
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("rd", destination)
+            .finish()
+    );
+    let sign_in = sign_in_fields(None, providers, &return_to);
+    let error = alert(error);
+
+    page(
+        "Sign in",
+        &format!(
+            r#"{error}<form method="post" action="login">
+<input type="hidden" name="rd" value="{}">{sign_in}
+<button>Sign in</button></form>"#,
+            escape(destination)
+        ),
+    )
+}
+
+/// Asks the browser for a passkey assertion with `options`, posts it to `finish` and then
+/// goes on to `next`, if given.
+fn passkey_page(options: &ask::PasskeyRequestOptions, finish: &str, next: Option<&str>) -> String {
+    // kept from ending the script element early
+    let json = |value: serde_json::Value| value.to_string().replace('<', "\\u003c");
+    let options = json(json!(options));
+    let finish = json(json!(finish));
+    let next = json(json!(next));
+
+    page(
+        "Confirm it is you",
+        &format!(
+            r#"<p>Your account is protected by a passkey. Use it to finish signing in.</p>
+<p role="alert" id="error" hidden></p>
+<button id="passkey">Use passkey</button>
+<script>
+const options = {options};
+const bytes = text => Uint8Array.from(atob(text.replace(/-/g, "+").replace(/_/g, "/")), c => c.charCodeAt(0));
+const text = buffer => btoa(String.fromCharCode(...new Uint8Array(buffer))).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+document.getElementById("passkey").addEventListener("click", async () => {{
+  const error = document.getElementById("error");
+  try {{
+    const credential = await navigator.credentials.get({{ publicKey: {{
+      ...options,
+      challenge: bytes(options.challenge),
+      allowCredentials: options.allowCredentials.map(allowed => ({{ ...allowed, id: bytes(allowed.id) }})),
+    }} }});
+    const response = await fetch({finish}, {{
+      method: "POST",
+      headers: {{ "Content-Type": "application/json" }},
+      body: JSON.stringify({{
+        id: credential.id,
+        response: {{
+          clientDataJSON: text(credential.response.clientDataJSON),
+          authenticatorData: text(credential.response.authenticatorData),
+          signature: text(credential.response.signature),
+          userHandle: credential.response.userHandle && text(credential.response.userHandle),
+        }},
+      }}),
+    }});
+    if (!response.ok) throw new Error(await response.json());
+    const next = {next};
+    if (next) location.assign(next);
+    else document.body.innerHTML = "<h1>Signed in</h1><p>You are signed in. You can close this window.</p>";
+  }} catch (e) {{
+    error.textContent = e.message;
+    error.hidden = false;
+  }}
+}});
+</script>"#
+        ),
+    )
+}
+
+/// Credentials fields, and links to sign in through `providers` that come back to
+/// `return_to`, unless the user is already signed in.
+fn sign_in_fields(user: Option<&Email>, providers: &[IdentityProvider], return_to: &str) -> String {
+    if let Some(email) = user {
+        return format!(
+            "<p>Signed in as {}.</p>",
+            escape(&email.clone().into_inner())
+        );
+    }
+
+    let return_to: String = url::form_urlencoded::byte_serialize(return_to.as_bytes()).collect();
+    let providers: String = providers
+        .iter()
+        .map(|provider| {
+            format!(
+                r#"<p><a href="login/{}?return_to={}">Sign in with {}</a></p>"#,
+                provider.id,
+                escape(&return_to),
+                escape(&provider.name)
+            )
+        })
+        .collect();
+    format!(
+        "{}{providers}",
+        concat!(
+            r#"<p><label>Email <input type="email" name="email" autocomplete="username"></label></p>"#,
+            r#"<p><label>Password <input type="password" name="password" autocomplete="current-password"></label></p>"#
+        )
+    )
+}
+
+fn scope_list(scope: &Scope) -> String {
+    match scope.is_empty() {
+        true => "<li>your account</li>".to_string(),
+        false => scope
+            .iter()
+            .map(|scope| format!("<li>{}</li>", escape(scope)))
+            .collect(),
+    }
+}
+
+fn alert(error: Option<&str>) -> String {
+    error
+        .map(|error| format!(r#"<p role="alert">{}</p>"#, escape(error)))
+        .unwrap_or_default()
+}
+
+/// Wraps `body` in an HTML document; `title` must already be escaped.
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width">
+<title>{title}</title></head>
+<body><h1>{title}</h1>
+{body}
+</body></html>"#
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut out, c| {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&#39;"),
+                c => out.push(c),
+            }
+            out
+        })
+}
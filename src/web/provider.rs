@@ -0,0 +1,73 @@
+use super::api::ApiError;
+use super::guard::{ProvidersRead, ProvidersWrite, RequirePermission};
+use crate::data::query::DeletionStatus;
+use crate::data::AppDatabase;
+use crate::domain::federation::IdentityProvider;
+use crate::domain::tenant::TenantId;
+use crate::service::{action, ask, audit};
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[rocket::get("/")]
+pub async fn list_providers(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<ProvidersRead>,
+) -> Result<Json<Vec<IdentityProvider>>, ApiError> {
+    let providers = action::list_identity_providers(&tenant, database.get_pool()).await?;
+
+    Ok(Json(providers))
+}
+
+#[rocket::get("/<id>")]
+pub async fn get_provider(
+    id: &str,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<ProvidersRead>,
+) -> Result<Json<IdentityProvider>, ApiError> {
+    let provider = action::get_identity_provider(&tenant, id, database.get_pool()).await?;
+
+    Ok(Json(provider))
+}
+
+/// Registers an upstream OpenID Connect provider; its client secret is never returned.
+#[rocket::post("/", data = "<req>")]
+pub async fn register_provider(
+    req: Json<ask::NewIdentityProvider>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<ProvidersWrite>,
+) -> Result<Json<IdentityProvider>, ApiError> {
+    let provider =
+        action::register_identity_provider(&tenant, &ctx, req.into_inner(), database.get_pool())
+            .await?;
+
+    Ok(Json(provider))
+}
+
+#[rocket::delete("/<id>")]
+pub async fn delete_provider(
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<ProvidersWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    match action::delete_identity_provider(&tenant, &ctx, id, database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("identity provider deleted")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json(
+            "identity provider not found".to_string(),
+        ))),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        list_providers,
+        get_provider,
+        register_provider,
+        delete_provider
+    ]
+}
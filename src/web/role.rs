@@ -0,0 +1,118 @@
+use super::api::ApiError;
+use super::guard::{RequirePermission, RolesRead, RolesWrite};
+use crate::data::query::DeletionStatus;
+use crate::data::AppDatabase;
+use crate::domain::role::{Permission, Role, RoleName};
+use crate::domain::tenant::TenantId;
+use crate::service::{action, ask, audit, ServiceError};
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[rocket::get("/")]
+pub async fn list_roles(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<RolesRead>,
+) -> Result<Json<Vec<Role>>, ApiError> {
+    let roles = action::list_roles(&tenant, database.get_pool()).await?;
+
+    Ok(Json(roles))
+}
+
+#[rocket::put("/", data = "<req>")]
+pub async fn save_role(
+    req: Json<Role>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<RolesWrite>,
+) -> Result<Json<Role>, ApiError> {
+    let role = action::save_role(&tenant, &ctx, req.into_inner(), database.get_pool()).await?;
+
+    Ok(Json(role))
+}
+
+#[rocket::delete("/<name>")]
+pub async fn delete_role(
+    name: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<RolesWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    let name = RoleName::new(name).map_err(ServiceError::from)?;
+
+    match action::delete_role(&tenant, &ctx, name, database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("role deleted")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json("role not found".to_string()))),
+    }
+}
+
+#[rocket::get("/permissions")]
+pub async fn list_permissions(
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<RolesRead>,
+) -> Result<Json<Vec<Permission>>, ApiError> {
+    let permissions = action::list_permissions(database.get_pool()).await?;
+
+    Ok(Json(permissions))
+}
+
+#[rocket::put("/permissions", data = "<req>")]
+pub async fn save_permission(
+    req: Json<Permission>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<RolesWrite>,
+) -> Result<Json<Permission>, ApiError> {
+    // permissions are shared by every tenant
+    if !tenant.is_default() {
+        return Err(ApiError::Forbidden(Json(
+            "permissions are managed from the default tenant".to_string(),
+        )));
+    }
+
+    let permission = action::save_permission(&ctx, req.into_inner(), database.get_pool()).await?;
+
+    Ok(Json(permission))
+}
+
+#[rocket::post("/grant", data = "<req>")]
+pub async fn grant_role(
+    req: Json<ask::RoleGrant>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<RolesWrite>,
+) -> Result<Json<crate::User>, ApiError> {
+    let user = action::grant_role(&tenant, &ctx, req.into_inner(), database.get_pool()).await?;
+
+    Ok(Json(user))
+}
+
+#[rocket::delete("/grant", data = "<req>")]
+pub async fn revoke_role(
+    req: Json<ask::RoleGrant>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<RolesWrite>,
+) -> Result<Json<&'static str>, ApiError> {
+    match action::revoke_role(&tenant, &ctx, req.into_inner(), database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("role revoked")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json("role not granted".to_string()))),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        list_roles,
+        save_role,
+        delete_role,
+        list_permissions,
+        save_permission,
+        grant_role,
+        revoke_role
+    ]
+}
@@ -0,0 +1,496 @@
+use super::guard::{GroupsRead, GroupsWrite, RequirePermission, UsersRead, UsersWrite};
+use crate::data::AppDatabase;
+use crate::domain::scim::{self, ListResponse, PatchRequest, ScimError, ScimGroup, ScimUser};
+use crate::domain::tenant::TenantId;
+use crate::service::mailer::Links;
+use crate::service::{action, audit, ServiceError};
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::{Request, State};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+type Params = HashMap<String, String>;
+
+/// Page size when a request does not give `count`.
+const DEFAULT_COUNT: u32 = 100;
+
+/// A SCIM response, sent as `application/scim+json`.
+pub struct Scim {
+    status: Status,
+    body: Option<Value>,
+    location: Option<String>,
+}
+
+impl Scim {
+    fn ok<T: Serialize>(body: T) -> Self {
+        Self {
+            status: Status::Ok,
+            body: Some(json!(body)),
+            location: None,
+        }
+    }
+
+    /// `201 Created`, with the new resource's location.
+    fn created<T: Serialize>(body: T, location: Option<String>) -> Self {
+        Self {
+            status: Status::Created,
+            body: Some(json!(body)),
+            location,
+        }
+    }
+
+    fn no_content() -> Self {
+        Self {
+            status: Status::NoContent,
+            body: None,
+            location: None,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Scim {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = match self.body {
+            Some(body) => Response::build_from(Json(body).respond_to(req)?),
+            None => Response::build(),
+        };
+        response
+            .status(self.status)
+            .header(ContentType::new("application", "scim+json"));
+        if let Some(location) = self.location {
+            response.raw_header("Location", location);
+        }
+
+        response.ok()
+    }
+}
+
+/// Error response of the SCIM endpoints (RFC 7644, section 3.12).
+pub struct ScimFailure(ServiceError);
+
+impl From<ServiceError> for ScimFailure {
+    fn from(err: ServiceError) -> Self {
+        Self(err)
+    }
+}
+
+impl From<ScimError> for ScimFailure {
+    fn from(err: ScimError) -> Self {
+        Self(err.into())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ScimFailure {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (status, scim_type, detail) = match self.0 {
+            ServiceError::Scim(e @ ScimError::UnknownResource) => {
+                (Status::NotFound, None, e.to_string())
+            }
+            ServiceError::Scim(e @ ScimError::Uniqueness(_)) => {
+                (Status::Conflict, e.scim_type(), e.to_string())
+            }
+            ServiceError::Scim(e) => (Status::BadRequest, e.scim_type(), e.to_string()),
+            e @ (ServiceError::NotFound | ServiceError::InvalidDetail) => {
+                (Status::NotFound, None, e.to_string())
+            }
+            e @ (ServiceError::User(_)
+            | ServiceError::Group(_)
+            | ServiceError::Role(_)
+            | ServiceError::InvalidRequest(_)) => {
+                (Status::BadRequest, Some("invalidValue"), e.to_string())
+            }
+            e @ ServiceError::InvalidTransition(..) => (Status::Conflict, None, e.to_string()),
+            ServiceError::Forbidden(msg) | ServiceError::PermissionError(msg) => {
+                (Status::Forbidden, None, msg)
+            }
+            ServiceError::Data(e) if e.to_string().contains("UNIQUE constraint failed") => (
+                Status::Conflict,
+                Some("uniqueness"),
+                "resource already exists".to_string(),
+            ),
+            e => {
+                eprintln!("scim error: {e}");
+                (
+                    Status::InternalServerError,
+                    None,
+                    "a server error occured".to_string(),
+                )
+            }
+        };
+
+        Scim {
+            status,
+            body: Some(error_body(status, scim_type, &detail)),
+            location: None,
+        }
+        .respond_to(req)
+    }
+}
+
+fn error_body(status: Status, scim_type: Option<&str>, detail: &str) -> Value {
+    let mut body = json!({
+        "schemas": [scim::ERROR_SCHEMA],
+        "status": status.code.to_string(),
+        "detail": detail,
+    });
+    if let Some(scim_type) = scim_type {
+        body["scimType"] = json!(scim_type);
+    }
+    body
+}
+
+/// `filter`, `startIndex` and `count` of a list request.
+struct ListQuery {
+    filter: Option<String>,
+    start_index: u32,
+    count: u32,
+}
+
+impl ListQuery {
+    fn parse(mut params: Params) -> Result<Self, ScimError> {
+        let mut number = |name: &str, default: u32| match params.remove(name) {
+            Some(value) => value
+                .parse::<u32>()
+                .map_err(|_| ScimError::InvalidValue(format!("{name}: {value}"))),
+            None => Ok(default),
+        };
+        let start_index = number("startIndex", 1)?;
+        let count = number("count", DEFAULT_COUNT)?;
+
+        Ok(Self {
+            filter: params.remove("filter"),
+            start_index,
+            count,
+        })
+    }
+}
+
+/// Users, filtered with `userName eq "..."` and the like, a page at a time.
+#[rocket::get("/Users?<params..>")]
+pub async fn list_users(
+    params: Params,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    _auth: RequirePermission<UsersRead>,
+) -> Result<Scim, ScimFailure> {
+    let query = ListQuery::parse(params)?;
+    let users = action::scim_users(
+        &tenant,
+        links,
+        query.filter.as_deref(),
+        query.start_index,
+        query.count,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Scim::ok(users))
+}
+
+#[rocket::post("/Users", data = "<user>")]
+pub async fn create_user(
+    user: Json<ScimUser>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    auth: RequirePermission<UsersWrite>,
+) -> Result<Scim, ScimFailure> {
+    let user = action::create_scim_user(
+        &tenant,
+        &ctx,
+        &auth.principal,
+        links,
+        user.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+    let location = user.meta.as_ref().map(|meta| meta.location.clone());
+
+    Ok(Scim::created(user, location))
+}
+
+#[rocket::get("/Users/<id>")]
+pub async fn get_user(
+    id: &str,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    _auth: RequirePermission<UsersRead>,
+) -> Result<Scim, ScimFailure> {
+    let user = action::scim_user(&tenant, links, id, database.get_pool()).await?;
+
+    Ok(Scim::ok(user))
+}
+
+#[rocket::put("/Users/<id>", data = "<user>")]
+pub async fn replace_user(
+    id: &str,
+    user: Json<ScimUser>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    auth: RequirePermission<UsersWrite>,
+) -> Result<Scim, ScimFailure> {
+    let user = action::replace_scim_user(
+        &tenant,
+        &ctx,
+        &auth.principal,
+        links,
+        id,
+        user.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Scim::ok(user))
+}
+
+#[rocket::patch("/Users/<id>", data = "<req>")]
+pub async fn patch_user(
+    id: &str,
+    req: Json<PatchRequest>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    auth: RequirePermission<UsersWrite>,
+) -> Result<Scim, ScimFailure> {
+    let user = action::patch_scim_user(
+        &tenant,
+        &ctx,
+        &auth.principal,
+        links,
+        id,
+        req.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Scim::ok(user))
+}
+
+#[rocket::delete("/Users/<id>")]
+pub async fn delete_user(
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<UsersWrite>,
+) -> Result<Scim, ScimFailure> {
+    action::delete_scim_user(&tenant, &ctx, id, database.get_pool()).await?;
+
+    Ok(Scim::no_content())
+}
+
+/// Groups, filtered with `displayName eq "..."`, a page at a time.
+#[rocket::get("/Groups?<params..>")]
+pub async fn list_groups(
+    params: Params,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    _auth: RequirePermission<GroupsRead>,
+) -> Result<Scim, ScimFailure> {
+    let query = ListQuery::parse(params)?;
+    let groups = action::scim_groups(
+        &tenant,
+        links,
+        query.filter.as_deref(),
+        query.start_index,
+        query.count,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Scim::ok(groups))
+}
+
+#[rocket::post("/Groups", data = "<group>")]
+pub async fn create_group(
+    group: Json<ScimGroup>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Scim, ScimFailure> {
+    let group = action::create_scim_group(
+        &tenant,
+        &ctx,
+        links,
+        group.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+    let location = group.meta.as_ref().map(|meta| meta.location.clone());
+
+    Ok(Scim::created(group, location))
+}
+
+#[rocket::get("/Groups/<id>")]
+pub async fn get_group(
+    id: &str,
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    _auth: RequirePermission<GroupsRead>,
+) -> Result<Scim, ScimFailure> {
+    let group = action::scim_group(&tenant, links, id, database.get_pool()).await?;
+
+    Ok(Scim::ok(group))
+}
+
+#[rocket::put("/Groups/<id>", data = "<group>")]
+pub async fn replace_group(
+    id: &str,
+    group: Json<ScimGroup>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Scim, ScimFailure> {
+    let group = action::replace_scim_group(
+        &tenant,
+        &ctx,
+        links,
+        id,
+        group.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Scim::ok(group))
+}
+
+#[rocket::patch("/Groups/<id>", data = "<req>")]
+pub async fn patch_group(
+    id: &str,
+    req: Json<PatchRequest>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    links: &State<Links>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Scim, ScimFailure> {
+    let group = action::patch_scim_group(
+        &tenant,
+        &ctx,
+        links,
+        id,
+        req.into_inner(),
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Scim::ok(group))
+}
+
+#[rocket::delete("/Groups/<id>")]
+pub async fn delete_group(
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<GroupsWrite>,
+) -> Result<Scim, ScimFailure> {
+    action::delete_scim_group(&tenant, &ctx, id, database.get_pool()).await?;
+
+    Ok(Scim::no_content())
+}
+
+/// What this server supports; like the other discovery endpoints, it needs no credentials.
+#[rocket::get("/ServiceProviderConfig")]
+pub fn service_provider_config(tenant: TenantId, links: &State<Links>) -> Scim {
+    Scim::ok(scim::service_provider_config(&links.scim(&tenant, "")))
+}
+
+#[rocket::get("/ResourceTypes")]
+pub fn resource_types(tenant: TenantId, links: &State<Links>) -> Scim {
+    let types = scim::resource_types(&links.scim(&tenant, ""));
+    let total = types.len() as u64;
+
+    Scim::ok(ListResponse::new(types, total, 1))
+}
+
+#[rocket::get("/ResourceTypes/<id>")]
+pub fn resource_type(
+    id: &str,
+    tenant: TenantId,
+    links: &State<Links>,
+) -> Result<Scim, ScimFailure> {
+    scim::resource_types(&links.scim(&tenant, ""))
+        .into_iter()
+        .find(|resource_type| resource_type["id"] == id)
+        .map(Scim::ok)
+        .ok_or_else(|| ScimError::UnknownResource.into())
+}
+
+#[rocket::get("/Schemas")]
+pub fn schemas(tenant: TenantId, links: &State<Links>) -> Scim {
+    let schemas = scim::schemas(&links.scim(&tenant, ""));
+    let total = schemas.len() as u64;
+
+    Scim::ok(ListResponse::new(schemas, total, 1))
+}
+
+#[rocket::get("/Schemas/<id>")]
+pub fn schema(id: &str, tenant: TenantId, links: &State<Links>) -> Result<Scim, ScimFailure> {
+    scim::schemas(&links.scim(&tenant, ""))
+        .into_iter()
+        .find(|schema| schema["id"] == id)
+        .map(Scim::ok)
+        .ok_or_else(|| ScimError::UnknownResource.into())
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        list_users,
+        create_user,
+        get_user,
+        replace_user,
+        patch_user,
+        delete_user,
+        list_groups,
+        create_group,
+        get_group,
+        replace_group,
+        patch_group,
+        delete_group,
+        service_provider_config,
+        resource_types,
+        resource_type,
+        schemas,
+        schema
+    ]
+}
+
+/// SCIM error bodies for requests that fail before reaching a route, such as those
+/// without valid credentials or with malformed JSON.
+pub mod catcher {
+    use super::{error_body, Scim};
+    use rocket::http::Status;
+    use rocket::{catch, catchers, Catcher, Request};
+
+    #[catch(default)]
+    fn default(status: Status, _req: &Request) -> Scim {
+        // bodies that are not JSON or not resources never reach the routes
+        let scim_type = (status.code == 422).then_some("invalidSyntax");
+        let detail = status.reason().unwrap_or("request failed");
+        Scim {
+            status,
+            body: Some(error_body(status, scim_type, detail)),
+            location: None,
+        }
+    }
+
+    pub fn catchers() -> Vec<Catcher> {
+        catchers![default]
+    }
+}
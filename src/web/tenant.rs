@@ -0,0 +1,46 @@
+use super::api::ApiError;
+use super::guard::{RequirePermission, TenantsRead, TenantsWrite};
+use crate::data::AppDatabase;
+use crate::domain::tenant::{Tenant, TenantId};
+use crate::service::{action, audit};
+use rocket::serde::json::Json;
+use rocket::State;
+
+fn require_default(tenant: &TenantId) -> Result<(), ApiError> {
+    match tenant.is_default() {
+        true => Ok(()),
+        false => Err(ApiError::Forbidden(Json(
+            "tenants are managed from the default tenant".to_string(),
+        ))),
+    }
+}
+
+#[rocket::get("/")]
+pub async fn list_tenants(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<TenantsRead>,
+) -> Result<Json<Vec<Tenant>>, ApiError> {
+    require_default(&tenant)?;
+    let tenants = action::list_tenants(database.get_pool()).await?;
+
+    Ok(Json(tenants))
+}
+
+#[rocket::put("/", data = "<req>")]
+pub async fn save_tenant(
+    req: Json<Tenant>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    _auth: RequirePermission<TenantsWrite>,
+) -> Result<Json<Tenant>, ApiError> {
+    require_default(&tenant)?;
+    let tenant = action::save_tenant(&ctx, req.into_inner(), database.get_pool()).await?;
+
+    Ok(Json(tenant))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![list_tenants, save_tenant]
+}
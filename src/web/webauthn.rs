@@ -0,0 +1,149 @@
+use super::api::{ApiError, ApiKey};
+use super::guard::{forbid_impersonation, user_email, Device};
+use crate::data::query::DeletionStatus;
+use crate::data::AppDatabase;
+use crate::domain::role::Principal;
+use crate::domain::tenant::TenantId;
+use crate::domain::webauthn::{Credential, RelyingParty};
+use crate::service::mailer::{Links, Mailer};
+use crate::service::{action, ask, audit};
+use rocket::serde::json::Json;
+use rocket::State;
+
+#[rocket::post("/register/start")]
+pub async fn start_registration(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    rp: &State<RelyingParty>,
+    principal: Principal,
+) -> Result<Json<ask::PasskeyCreationOptions>, ApiError> {
+    forbid_impersonation(&principal)?;
+    let options = action::start_passkey_registration(
+        &tenant,
+        user_email(&principal)?,
+        rp,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(options))
+}
+
+#[rocket::post("/register/finish", data = "<req>")]
+pub async fn finish_registration(
+    req: Json<ask::RegisterPasskey>,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    rp: &State<RelyingParty>,
+    principal: Principal,
+) -> Result<Json<Credential>, ApiError> {
+    forbid_impersonation(&principal)?;
+    let credential = action::finish_passkey_registration(
+        &tenant,
+        &ctx,
+        user_email(&principal)?,
+        req.into_inner(),
+        rp,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(credential))
+}
+
+/// The calling user's passkeys.
+#[rocket::get("/credentials")]
+pub async fn list_credentials(
+    tenant: TenantId,
+    database: &State<AppDatabase>,
+    principal: Principal,
+) -> Result<Json<Vec<Credential>>, ApiError> {
+    let credentials =
+        action::list_passkeys(&tenant, user_email(&principal)?, database.get_pool()).await?;
+
+    Ok(Json(credentials))
+}
+
+#[rocket::delete("/credentials/<id>")]
+pub async fn delete_credential(
+    id: &str,
+    tenant: TenantId,
+    ctx: audit::Context,
+    database: &State<AppDatabase>,
+    principal: Principal,
+) -> Result<Json<&'static str>, ApiError> {
+    forbid_impersonation(&principal)?;
+    let email = user_email(&principal)?;
+    match action::delete_passkey(&tenant, &ctx, email, id, database.get_pool()).await? {
+        DeletionStatus::Deleted => Ok(Json("passkey deleted")),
+        DeletionStatus::NotFound => Err(ApiError::NotFound(Json("passkey not found".to_string()))),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/login/start", data = "<req>")]
+pub async fn start_login(
+    req: Json<ask::PasskeyLoginStart>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    rp: &State<RelyingParty>,
+    _api_key: ApiKey,
+) -> Result<Json<ask::PasskeyRequestOptions>, ApiError> {
+    ctx.device_id = Some(device.0);
+    let options = action::start_passkey_login(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        mailer,
+        links,
+        rp,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(options))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::post("/login/finish", data = "<req>")]
+pub async fn finish_login(
+    req: Json<ask::PasskeyAssertion>,
+    tenant: TenantId,
+    mut ctx: audit::Context,
+    device: Device,
+    database: &State<AppDatabase>,
+    mailer: &State<Mailer>,
+    links: &State<Links>,
+    rp: &State<RelyingParty>,
+    _api_key: ApiKey,
+) -> Result<Json<ask::IssuedKey>, ApiError> {
+    ctx.device_id = Some(device.0);
+    let issued = action::finish_passkey_login(
+        &tenant,
+        &ctx,
+        req.into_inner(),
+        mailer,
+        links,
+        rp,
+        database.get_pool(),
+    )
+    .await?;
+
+    Ok(Json(issued))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        start_registration,
+        finish_registration,
+        list_credentials,
+        delete_credential,
+        start_login,
+        finish_login
+    ]
+}
@@ -0,0 +1,265 @@
+//! Helpers shared by the integration tests.
+
+// each test crate uses only some of them
+#![allow(dead_code)]
+
+use authy::data::{query, AppDatabase};
+use authy::domain::tenant::TenantId;
+use authy::web::api::{ApiKey, API_KEY_HEADER};
+use authy::{
+    DatabasePool, Links, LocalSender, Mailer, Maintenance, RelyingParty, RocketConfig,
+    SessionCookie,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ciborium::value::Value as Cbor;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Where the test servers are served from, and the origin passkey assertions are made for.
+pub const PUBLIC_URL: &str = "http://localhost:8000";
+pub const PASSWORD: &str = "Passw0rd!23";
+
+/// Authy on a fresh database, with an operator key and mail kept in files.
+pub struct TestServer {
+    /// Holds the database and mail, removed along with the server.
+    dir: TempDir,
+    pub client: Client,
+    pub operator_key: String,
+    pub pool: DatabasePool,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(SessionCookie::default()).await
+    }
+
+    pub async fn start_with(session_cookie: SessionCookie) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("authy.db");
+        let database = AppDatabase::new(&format!("sqlite:{}?mode=rwc", path.display())).await;
+        let operator_key =
+            query::save_api_key(&TenantId::default(), ApiKey::default(), database.get_pool())
+                .await
+                .unwrap()
+                .to_base64();
+        let pool = database.get_pool().clone();
+
+        let config = RocketConfig {
+            database,
+            maintenance: Maintenance::default(),
+            mailer: Mailer::Outbox(dir.path().join("mail.jsonl")),
+            links: Links::new(PUBLIC_URL),
+            sender: Box::new(LocalSender::File(dir.path().join("messages.jsonl"))),
+            relying_party: RelyingParty::from_url(PUBLIC_URL, "Authy").unwrap(),
+            session_cookie,
+            ldap: None,
+        };
+        let client = Client::tracked(authy::rocket(config)).await.unwrap();
+
+        Self {
+            dir,
+            client,
+            operator_key,
+            pool,
+        }
+    }
+
+    pub async fn call(&self, method: &str, path: &str, key: &str, body: Value) -> (Status, Value) {
+        let request = match method {
+            "GET" => self.client.get(path),
+            "PUT" => self.client.put(path),
+            "PATCH" => self.client.patch(path),
+            "DELETE" => self.client.delete(path),
+            _ => self.client.post(path),
+        };
+        let response = request
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, key.to_string()))
+            .body(body.to_string())
+            .dispatch()
+            .await;
+        let status = response.status();
+        let body = response.into_string().await.unwrap_or_default();
+
+        (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+    }
+
+    pub async fn operator(&self, method: &str, path: &str, body: Value) -> (Status, Value) {
+        self.call(method, path, &self.operator_key, body).await
+    }
+
+    pub async fn create_user(&self, email: &str) {
+        let user = json!({ "email": email, "password": PASSWORD, "name": "Test" });
+        let (status, _) = self.operator("POST", "/api/user/", user).await;
+        assert_eq!(status, Status::Ok);
+    }
+
+    /// Creates a user and signs them in with their password.
+    pub async fn user_key(&self, email: &str) -> String {
+        self.create_user(email).await;
+        let credentials = json!({ "email": email, "password": PASSWORD });
+        let (status, issued) = self.operator("POST", "/api/user/key", credentials).await;
+        assert_eq!(status, Status::Ok, "{issued}");
+        issued["api_key"].as_str().unwrap().to_string()
+    }
+
+    /// Mail sent so far, oldest first.
+    pub fn mail(&self) -> Vec<Value> {
+        read_lines(self.dir.path().join("mail.jsonl"))
+    }
+
+    /// One-time codes and other messages sent so far, oldest first.
+    pub fn messages(&self) -> Vec<Value> {
+        read_lines(self.dir.path().join("messages.jsonl"))
+    }
+
+    /// The link in the last mail sent, as a path on this server.
+    pub fn last_link(&self) -> String {
+        let mail = self.mail().pop().expect("no mail was sent");
+        let body = mail["body"].as_str().unwrap();
+        let start = body.find(PUBLIC_URL).expect("the mail has no link") + PUBLIC_URL.len();
+        body[start..].split_whitespace().next().unwrap().to_string()
+    }
+
+    /// The code in the last one-time code sent.
+    pub fn last_code(&self) -> String {
+        let message = self.messages().pop().expect("no code was sent");
+        let body = message["body"].as_str().unwrap();
+        let start = body.find("code is ").expect("the message has no code") + "code is ".len();
+        body[start..start + 6].to_string()
+    }
+}
+
+fn read_lines(path: PathBuf) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// A platform authenticator holding a single ES256 passkey.
+pub struct SoftwareAuthenticator {
+    key_pair: EcdsaKeyPair,
+    pub credential_id: Vec<u8>,
+    pub sign_count: u32,
+    rng: SystemRandom,
+}
+
+impl SoftwareAuthenticator {
+    pub fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+
+        Self {
+            key_pair,
+            credential_id: (0..16).map(|_| rand::random::<u8>()).collect(),
+            sign_count: 0,
+            rng,
+        }
+    }
+
+    pub fn credential_id(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.credential_id)
+    }
+
+    fn cose_key(&self) -> Vec<u8> {
+        let point = self.key_pair.public_key().as_ref();
+        let key = Cbor::Map(vec![
+            (Cbor::from(1), Cbor::from(2)),
+            (Cbor::from(3), Cbor::from(-7)),
+            (Cbor::from(-1), Cbor::from(1)),
+            (Cbor::from(-2), Cbor::Bytes(point[1..33].to_vec())),
+            (Cbor::from(-3), Cbor::Bytes(point[33..].to_vec())),
+        ]);
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&key, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn auth_data(&mut self, rp_id: &str, attested: bool) -> Vec<u8> {
+        self.sign_count += 1;
+        // user present and verified, plus attested credential data on registration
+        let flags = 0x01 | 0x04 | if attested { 0x40 } else { 0 };
+
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend(self.sign_count.to_be_bytes());
+        if attested {
+            data.extend([0; 16]);
+            data.extend((self.credential_id.len() as u16).to_be_bytes());
+            data.extend(&self.credential_id);
+            data.extend(self.cose_key());
+        }
+        data
+    }
+
+    fn client_data(kind: &str, options: &Value, origin: &str) -> Vec<u8> {
+        json!({
+            "type": kind,
+            "challenge": options["challenge"],
+            "origin": origin,
+            "crossOrigin": false,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    /// Answers `navigator.credentials.create()`.
+    pub fn create(&mut self, options: &Value) -> Value {
+        let rp_id = options["rp"]["id"].as_str().unwrap().to_string();
+        let client_data = Self::client_data("webauthn.create", options, PUBLIC_URL);
+        let attestation = Cbor::Map(vec![
+            (Cbor::from("fmt"), Cbor::from("none")),
+            (Cbor::from("attStmt"), Cbor::Map(vec![])),
+            (
+                Cbor::from("authData"),
+                Cbor::Bytes(self.auth_data(&rp_id, true)),
+            ),
+        ]);
+        let mut attestation_object = vec![];
+        ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        json!({
+            "id": self.credential_id(),
+            "name": "software key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+                "transports": ["internal"],
+            },
+        })
+    }
+
+    /// Answers `navigator.credentials.get()` as if running on `origin`.
+    pub fn get_from(&mut self, options: &Value, origin: &str) -> Value {
+        let rp_id = options["rpId"].as_str().unwrap().to_string();
+        let client_data = Self::client_data("webauthn.get", options, origin);
+        let auth_data = self.auth_data(&rp_id, false);
+        let message = [auth_data.as_slice(), &Sha256::digest(&client_data)].concat();
+        let signature = self.key_pair.sign(&self.rng, &message).unwrap();
+
+        json!({
+            "id": self.credential_id(),
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                "userHandle": null,
+            },
+        })
+    }
+
+    pub fn get(&mut self, options: &Value) -> Value {
+        self.get_from(options, PUBLIC_URL)
+    }
+}
@@ -0,0 +1,706 @@
+//! Sign-in through an upstream OpenID Connect provider, played by a mock served on the
+//! loopback interface.
+
+mod common;
+
+use authy::domain::oauth::verify_code_challenge;
+use authy::domain::oidc::SigningKey;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::{SoftwareAuthenticator, TestServer, PASSWORD, PUBLIC_URL};
+use rocket::form::Form;
+use rocket::http::{ContentType, Cookie, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CLIENT_ID: &str = "authy";
+const CLIENT_SECRET: &str = "s3cret/+";
+
+/// An authorization the mock provider has granted, redeemable once.
+struct Grant {
+    claims: Value,
+    code_challenge: String,
+    redirect_uri: String,
+}
+
+struct MockState {
+    issuer: String,
+    key: SigningKey,
+    grants: Mutex<HashMap<String, Grant>>,
+}
+
+#[rocket::get("/.well-known/openid-configuration")]
+fn mock_discovery(state: &State<Arc<MockState>>) -> Json<Value> {
+    let issuer = &state.issuer;
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+    }))
+}
+
+#[rocket::get("/jwks")]
+fn mock_jwks(state: &State<Arc<MockState>>) -> Json<Value> {
+    Json(json!({ "keys": [state.key.jwk()] }))
+}
+
+/// The `Authorization` header, if any.
+struct AuthorizationHeader(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthorizationHeader {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let value = req.headers().get_one("authorization").map(str::to_string);
+        Outcome::Success(Self(value))
+    }
+}
+
+#[rocket::post("/token", data = "<form>")]
+fn mock_token(
+    form: Form<HashMap<String, String>>,
+    authorization: AuthorizationHeader,
+    state: &State<Arc<MockState>>,
+) -> Result<Json<Value>, status::BadRequest<Json<Value>>> {
+    let error = |error: &str| status::BadRequest(Some(Json(json!({ "error": error }))));
+
+    // both parts are form-urlencoded first
+    let expected = format!(
+        "Basic {}",
+        STANDARD.encode(format!("{CLIENT_ID}:s3cret%2F%2B"))
+    );
+    if authorization.0.as_deref() != Some(expected.as_str()) {
+        return Err(error("invalid_client"));
+    }
+
+    let code = form.get("code").cloned().unwrap_or_default();
+    let grant = state
+        .grants
+        .lock()
+        .unwrap()
+        .remove(&code)
+        .ok_or_else(|| error("invalid_grant"))?;
+    let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+    if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+        || form.get("redirect_uri") != Some(&grant.redirect_uri)
+        || verify_code_challenge(&grant.code_challenge, &verifier).is_err()
+    {
+        return Err(error("invalid_grant"));
+    }
+
+    Ok(Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": state.key.sign(&grant.claims).unwrap(),
+    })))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Authy, with the mock provider registered as `mock`.
+struct Federation {
+    server: TestServer,
+    issuer: String,
+    mock: Arc<MockState>,
+}
+
+impl Deref for Federation {
+    type Target = TestServer;
+
+    fn deref(&self) -> &TestServer {
+        &self.server
+    }
+}
+
+impl Federation {
+    async fn start() -> Self {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let issuer = format!("http://127.0.0.1:{port}");
+        let mock_config = rocket::Config {
+            port,
+            address: [127, 0, 0, 1].into(),
+            log_level: rocket::config::LogLevel::Off,
+            ..rocket::Config::debug_default()
+        };
+        let mock = Arc::new(MockState {
+            issuer: issuer.clone(),
+            key: SigningKey::generate().unwrap(),
+            grants: Mutex::new(HashMap::new()),
+        });
+        let provider = rocket::custom(mock_config)
+            .manage(mock.clone())
+            .mount("/", rocket::routes![mock_discovery, mock_jwks, mock_token]);
+        rocket::tokio::spawn(provider.launch());
+        while rocket::tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_err()
+        {
+            rocket::tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let server = Self {
+            server: TestServer::start().await,
+            issuer,
+            mock,
+        };
+        let (status, provider) = server
+            .operator(
+                "POST",
+                "/api/providers/",
+                json!({
+                    "id": "mock",
+                    "name": "Mock IdP",
+                    "issuer": server.issuer,
+                    "client_id": CLIENT_ID,
+                    "client_secret": CLIENT_SECRET,
+                    "link_by_email": true,
+                }),
+            )
+            .await;
+        assert_eq!(status, Status::Ok, "{provider}");
+        assert_eq!(provider["scopes"], json!(["openid", "email", "profile"]));
+        assert!(provider.get("client_secret").is_none());
+
+        server
+    }
+
+    /// Starts a sign-in and returns the parameters of the request sent to the provider.
+    async fn start_login(&self, query: &str) -> HashMap<String, String> {
+        self.start_login_with(query, None).await
+    }
+
+    /// Starts a sign-in in a browser signed in to Authy with `session`, if given.
+    async fn start_login_with(
+        &self,
+        query: &str,
+        session: Option<&str>,
+    ) -> HashMap<String, String> {
+        let mut request = self.client.get(format!("/oauth/login/mock{query}"));
+        if let Some(session) = session {
+            request = request.private_cookie(Cookie::new("authy_session", session.to_string()));
+        }
+        let response = request.dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let location = url::Url::parse(response.headers().get_one("location").unwrap()).unwrap();
+        assert_eq!(location.path(), "/authorize");
+        location.query_pairs().into_owned().collect()
+    }
+
+    /// Has the mock provider authenticate a user with `claims`, on top of those required,
+    /// and returns the code it sends back.
+    fn grant(&self, request: &HashMap<String, String>, claims: Value) -> String {
+        let now = unix_time();
+        let mut id_token = json!({
+            "iss": self.issuer,
+            "aud": CLIENT_ID,
+            "iat": now,
+            "exp": now + 300,
+            "nonce": request["nonce"],
+        });
+        id_token
+            .as_object_mut()
+            .unwrap()
+            .extend(claims.as_object().unwrap().clone());
+
+        let code = format!("{:032x}", rand::random::<u128>());
+        self.mock.grants.lock().unwrap().insert(
+            code.clone(),
+            Grant {
+                claims: id_token,
+                code_challenge: request["code_challenge"].clone(),
+                redirect_uri: request["redirect_uri"].clone(),
+            },
+        );
+        code
+    }
+
+    async fn callback(&self, query: &str) -> (Status, String, Option<String>) {
+        self.callback_with(query, None).await
+    }
+
+    async fn callback_with(
+        &self,
+        query: &str,
+        session: Option<&str>,
+    ) -> (Status, String, Option<String>) {
+        let mut request = self
+            .client
+            .get(format!("/oauth/login/mock/callback?{query}"));
+        if let Some(session) = session {
+            request = request.private_cookie(Cookie::new("authy_session", session.to_string()));
+        }
+        let response = request.dispatch().await;
+        let status = response.status();
+        let location = response.headers().get_one("location").map(str::to_string);
+        let body = response.into_string().await.unwrap_or_default();
+
+        (status, body, location)
+    }
+
+    /// Signs in through the mock provider as the user it describes with `claims`.
+    async fn sign_in(&self, claims: Value) -> (Status, String) {
+        let request = self.start_login("").await;
+        let code = self.grant(&request, claims);
+        let (status, body, _) = self
+            .callback(&format!("code={code}&state={}", request["state"]))
+            .await;
+        (status, body)
+    }
+
+    async fn login_events(&self) -> Vec<Value> {
+        let (status, events) = self
+            .operator("GET", "/api/audit/?action=login", json!({}))
+            .await;
+        assert_eq!(status, Status::Ok);
+        events["events"].as_array().unwrap().clone()
+    }
+}
+
+#[rocket::async_test]
+async fn links_a_verified_email_and_signs_in_again_by_subject() {
+    let server = Federation::start().await;
+    server.create_user("alice@example.com").await;
+
+    let request = server.start_login("").await;
+    assert_eq!(request["client_id"], CLIENT_ID);
+    assert_eq!(request["scope"], "openid email profile");
+    assert_eq!(request["code_challenge_method"], "S256");
+    assert_eq!(
+        request["redirect_uri"],
+        format!("{PUBLIC_URL}/oauth/login/mock/callback")
+    );
+
+    let (status, body) = server
+        .sign_in(json!({
+            "sub": "alice-at-idp",
+            "email": "alice@example.com",
+            "email_verified": true,
+        }))
+        .await;
+    assert_eq!(status, Status::Ok, "{body}");
+    assert!(body.contains("Signed in"));
+
+    let (_, links) = server
+        .operator("GET", "/api/audit/?action=identity.link", json!({}))
+        .await;
+    assert_eq!(links["events"][0]["target"], "alice@example.com");
+    assert_eq!(links["events"][0]["detail"]["method"], "verified_email");
+
+    // the address has since changed at the provider, but the subject is linked
+    let (status, body) = server
+        .sign_in(json!({ "sub": "alice-at-idp", "email": "alice@corp.example" }))
+        .await;
+    assert_eq!(status, Status::Ok, "{body}");
+
+    let events = server.login_events().await;
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|event| {
+        event["target"] == "alice@example.com"
+            && event["outcome"] == "success"
+            && event["detail"]["method"] == "federated"
+            && event["detail"]["provider"] == "mock"
+    }));
+}
+
+#[rocket::async_test]
+async fn refuses_identities_it_cannot_link() {
+    let server = Federation::start().await;
+    server.create_user("bob@example.com").await;
+
+    let unverified = json!({ "sub": "bob-at-idp", "email": "bob@example.com" });
+    let (status, body) = server.sign_in(unverified).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(body.contains("no account is linked"), "{body}");
+
+    let unknown = json!({
+        "sub": "mallory-at-idp",
+        "email": "mallory@example.com",
+        "email_verified": true,
+    });
+    let (status, body) = server.sign_in(unknown).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(body.contains("no account is linked"), "{body}");
+}
+
+#[rocket::async_test]
+async fn rejects_forged_callbacks_and_tokens() {
+    let server = Federation::start().await;
+    server.create_user("carol@example.com").await;
+    let claims = json!({
+        "sub": "carol-at-idp",
+        "email": "carol@example.com",
+        "email_verified": true,
+    });
+
+    // not started from this browser
+    let request = server.start_login("").await;
+    let code = server.grant(&request, claims.clone());
+    let (status, body, _) = server.callback(&format!("code={code}&state=forged")).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(body.contains("not started from this browser"), "{body}");
+
+    // the pending sign-in was used up by the failed attempt
+    let (status, _, _) = server
+        .callback(&format!("code={code}&state={}", request["state"]))
+        .await;
+    assert_eq!(status, Status::BadRequest);
+
+    // replayed from another sign-in
+    let request = server.start_login("").await;
+    let mut replayed = claims.clone();
+    replayed["nonce"] = json!("another nonce");
+    let code = server.grant(&request, replayed);
+    let (status, body, _) = server
+        .callback(&format!("code={code}&state={}", request["state"]))
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(body.contains("nonce"), "{body}");
+
+    let request = server.start_login("").await;
+    let mut expired = claims.clone();
+    expired["exp"] = json!(unix_time() - 3600);
+    let code = server.grant(&request, expired);
+    let (status, body, _) = server
+        .callback(&format!("code={code}&state={}", request["state"]))
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(body.contains("expired"), "{body}");
+
+    let request = server.start_login("").await;
+    let (status, body, _) = server
+        .callback(&format!(
+            "error=access_denied&error_description=user+cancelled&state={}",
+            request["state"]
+        ))
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(body.contains("user cancelled"), "{body}");
+
+    let events = server.login_events().await;
+    assert!(events.iter().all(|event| event["outcome"] != "success"));
+}
+
+#[rocket::async_test]
+async fn returns_to_the_authorization_pages() {
+    let server = Federation::start().await;
+    server.create_user("dave@example.com").await;
+
+    let response = server
+        .client
+        .get("/oauth/login/mock?return_to=https%3A%2F%2Fevil.example")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let client = json!({ "name": "CLI", "client_type": "public", "redirect_uris": ["http://127.0.0.1/callback"] });
+    let (status, client) = server.operator("POST", "/api/clients/", client).await;
+    assert_eq!(status, Status::Ok, "{client}");
+    let response = server
+        .client
+        .post("/oauth/device_authorization")
+        .header(ContentType::Form)
+        .body(format!("client_id={}", client["id"].as_str().unwrap()))
+        .dispatch()
+        .await;
+    let authorization: Value = response.into_json().await.unwrap();
+    let device_page = format!(
+        "/oauth/device?user_code={}",
+        authorization["user_code"].as_str().unwrap()
+    );
+
+    // offered on the device page, coming back to it
+    let page = server
+        .client
+        .get(&device_page)
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    let link = page
+        .split(r#"<a href="login/mock"#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap_or_else(|| panic!("{page}"));
+    assert!(page.contains("Sign in with Mock IdP"));
+
+    let request = server.start_login(&link.replace("&amp;", "&")).await;
+    let code = server.grant(
+        &request,
+        json!({
+            "sub": "dave-at-idp",
+            "email": "dave@example.com",
+            "email_verified": true,
+        }),
+    );
+    let (status, _, location) = server
+        .callback(&format!("code={code}&state={}", request["state"]))
+        .await;
+    assert_eq!(status, Status::SeeOther);
+    let user_code = authorization["user_code"]
+        .as_str()
+        .unwrap()
+        .replace('-', "");
+    assert_eq!(
+        location,
+        Some(format!("{PUBLIC_URL}/oauth/device?user_code={user_code}"))
+    );
+
+    // signed in to the authorization pages now
+    let page = server
+        .client
+        .get(&device_page)
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(page.contains("Signed in as dave@example.com"), "{page}");
+}
+
+#[rocket::async_test]
+async fn links_identities_after_reauthentication_and_keeps_the_last() {
+    let server = Federation::start().await;
+    server.create_user("erin@example.com").await;
+    let password = json!({ "email": "erin@example.com", "password": "Passw0rd!23" });
+    let (status, issued) = server
+        .operator("POST", "/api/user/key", password.clone())
+        .await;
+    assert_eq!(status, Status::Ok);
+    let key = issued["api_key"].as_str().unwrap();
+
+    let (status, identities) = server
+        .call("GET", "/api/user/identities/", key, json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(identities.as_array().unwrap().len(), 1);
+    assert_eq!(identities[0]["kind"], "password");
+    let password_identity = identities[0]["id"].as_str().unwrap().to_string();
+
+    // signed in too long ago to link without the password
+    sqlx::query("UPDATE api_keys SET created_at = datetime('now', '-1 hour')")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    let link = json!({ "provider": "mock" });
+    let (status, _) = server
+        .call("POST", "/api/user/identities/", key, link)
+        .await;
+    assert_eq!(status, Status::Forbidden);
+
+    let wrong = json!({ "provider": "mock", "password": "wrong password" });
+    let (status, _) = server
+        .call("POST", "/api/user/identities/", key, wrong)
+        .await;
+    assert_eq!(status, Status::Forbidden);
+
+    let confirmed = json!({ "provider": "mock", "password": "Passw0rd!23" });
+    let (status, started) = server
+        .call("POST", "/api/user/identities/", key, confirmed)
+        .await;
+    assert_eq!(status, Status::Ok, "{started}");
+    let url = url::Url::parse(started["url"].as_str().unwrap()).unwrap();
+    assert_eq!(url.path(), "/oauth/login/mock");
+
+    // the provider's address does not matter when linking
+    let query = format!("?{}", url.query().unwrap());
+    let request = server.start_login_with(&query, Some(key)).await;
+    let claims = json!({ "sub": "erin-at-idp", "email": "erin@corp.example" });
+    let code = server.grant(&request, claims.clone());
+    let (status, body, _) = server
+        .callback_with(
+            &format!("code={code}&state={}", request["state"]),
+            Some(key),
+        )
+        .await;
+    assert_eq!(status, Status::Ok, "{body}");
+    assert!(body.contains("Account linked"));
+
+    // link requests work once
+    let response = server
+        .client
+        .get(format!("/oauth/login/mock{query}"))
+        .private_cookie(Cookie::new("authy_session", key.to_string()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let (_, identities) = server
+        .call("GET", "/api/user/identities/", key, json!({}))
+        .await;
+    let federated = &identities[1];
+    assert_eq!(federated["kind"], "federated");
+    assert_eq!(federated["provider"], "mock");
+    assert_eq!(federated["subject"], "erin-at-idp");
+    assert_eq!(federated["name"], "Mock IdP");
+    let federated_identity = federated["id"].as_str().unwrap().to_string();
+
+    let (status, body) = server.sign_in(claims).await;
+    assert_eq!(status, Status::Ok, "{body}");
+
+    let (status, _) = server
+        .call(
+            "DELETE",
+            &format!("/api/user/identities/{password_identity}"),
+            key,
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = server.operator("POST", "/api/user/key", password).await;
+    assert_ne!(status, Status::Ok);
+
+    let (status, error) = server
+        .call(
+            "DELETE",
+            &format!("/api/user/identities/{federated_identity}"),
+            key,
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(
+        error,
+        "identity error: the last way to sign in cannot be removed"
+    );
+
+    let (_, events) = server
+        .operator("GET", "/api/audit/?action=identity.unlink", json!({}))
+        .await;
+    assert_eq!(events["events"][0]["outcome"], "failure");
+    assert_eq!(events["events"][1]["detail"]["kind"], "password");
+}
+
+#[rocket::async_test]
+async fn links_identities_only_in_the_requesting_users_browser() {
+    let server = Federation::start().await;
+    let grace = server.user_key("grace@example.com").await;
+    let mallory = server.user_key("mallory@example.com").await;
+    let link = json!({ "provider": "mock", "password": PASSWORD });
+    let (status, started) = server
+        .call("POST", "/api/user/identities/", &grace, link)
+        .await;
+    assert_eq!(status, Status::Ok, "{started}");
+    let url = url::Url::parse(started["url"].as_str().unwrap()).unwrap();
+    let query = format!("?{}", url.query().unwrap());
+
+    // whoever else gets hold of the link cannot use it, signed in or not
+    for session in [None, Some(mallory.as_str())] {
+        let mut request = server.client.get(format!("/oauth/login/mock{query}"));
+        if let Some(session) = session {
+            request = request.private_cookie(Cookie::new("authy_session", session.to_string()));
+        }
+        assert_eq!(request.dispatch().await.status(), Status::BadRequest);
+    }
+
+    // which leaves it to grace, who cannot be made to finish it as someone else
+    let request = server.start_login_with(&query, Some(&grace)).await;
+    let claims = json!({ "sub": "mallory-at-idp", "email": "mallory@example.com" });
+    let code = server.grant(&request, claims.clone());
+    let (status, body, _) = server
+        .callback_with(
+            &format!("code={code}&state={}", request["state"]),
+            Some(&mallory),
+        )
+        .await;
+    assert_eq!(status, Status::BadRequest, "{body}");
+
+    let (_, identities) = server
+        .call("GET", "/api/user/identities/", &grace, json!({}))
+        .await;
+    assert_eq!(identities.as_array().unwrap().len(), 1, "{identities}");
+    let (_, events) = server
+        .operator("GET", "/api/audit/?action=identity.link", json!({}))
+        .await;
+    assert_eq!(events["events"][0]["outcome"], "failure");
+}
+
+#[rocket::async_test]
+async fn asks_users_with_passkeys_for_one_after_the_provider() {
+    let server = Federation::start().await;
+    server.create_user("frank@example.com").await;
+    let password = json!({ "email": "frank@example.com", "password": "Passw0rd!23" });
+    let (_, issued) = server.operator("POST", "/api/user/key", password).await;
+    let key = issued["api_key"].as_str().unwrap();
+    let mut authenticator = SoftwareAuthenticator::new();
+    let (status, options) = server
+        .call("POST", "/api/user/webauthn/register/start", key, json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    let credential = authenticator.create(&options);
+    let (status, _) = server
+        .call(
+            "POST",
+            "/api/user/webauthn/register/finish",
+            key,
+            credential,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, page) = server
+        .sign_in(json!({
+            "sub": "frank-at-idp",
+            "email": "frank@example.com",
+            "email_verified": true,
+        }))
+        .await;
+    assert_eq!(status, Status::Ok, "{page}");
+    assert!(page.contains("Use passkey"), "{page}");
+    assert!(server
+        .client
+        .cookies()
+        .get_private("authy_session")
+        .is_none());
+
+    let options: Value = page
+        .split("const options = ")
+        .nth(1)
+        .and_then(|rest| rest.split(";\n").next())
+        .and_then(|options| serde_json::from_str(options).ok())
+        .unwrap_or_else(|| panic!("{page}"));
+    assert_eq!(
+        options["allowCredentials"][0]["id"],
+        authenticator.credential_id()
+    );
+    let assertion = authenticator.get(&options);
+    let response = server
+        .client
+        .post("/oauth/login/passkey")
+        .header(ContentType::JSON)
+        .body(assertion.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(server
+        .client
+        .cookies()
+        .get_private("authy_session")
+        .is_some());
+
+    let events = server.login_events().await;
+    assert_eq!(events[0]["outcome"], "success");
+    assert_eq!(events[0]["detail"]["method"], "federated+passkey");
+    assert_eq!(events[1]["outcome"], "failure");
+    assert_eq!(events[1]["detail"]["request"]["method"], "federated");
+}
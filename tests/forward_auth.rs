@@ -0,0 +1,239 @@
+//! `/auth/verify`, asked by a reverse proxy whether to let requests through.
+
+mod common;
+
+use authy::data::{model, query};
+use authy::domain::oauth::{Scope, TokenKind};
+use authy::domain::tenant::TenantId;
+use authy::domain::token::Token;
+use authy::SessionCookie;
+use common::{TestServer, PASSWORD, PUBLIC_URL};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::LocalResponse;
+use serde_json::json;
+
+/// Authy, sharing its session cookie with the hosts under example.com.
+async fn start() -> TestServer {
+    TestServer::start_with(SessionCookie {
+        domain: Some("example.com".to_string()),
+    })
+    .await
+}
+
+/// Asks about a request for `url`, the way nginx does.
+async fn verify<'a>(server: &'a TestServer, url: &str) -> LocalResponse<'a> {
+    server
+        .client
+        .get("/auth/verify")
+        .header(Header::new("X-Original-URL", url.to_string()))
+        .dispatch()
+        .await
+}
+
+/// Signs in on the page `/auth/verify` sends browsers to, returning where it sends
+/// them back to.
+async fn sign_in(server: &TestServer, email: &str, rd: &str) -> String {
+    let response = server
+        .client
+        .post("/oauth/login")
+        .header(ContentType::Form)
+        .body(
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("rd", rd)
+                .append_pair("email", email)
+                .append_pair("password", PASSWORD)
+                .finish(),
+        )
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let cookie = response.cookies().get("authy_session").unwrap();
+    assert_eq!(cookie.domain(), Some("example.com"));
+
+    response.headers().get_one("location").unwrap().to_string()
+}
+
+/// Asks about a request for `url` carrying `token` as its bearer token.
+async fn verify_bearer(server: &TestServer, url: &str, token: &str) -> Status {
+    server
+        .client
+        .get("/auth/verify")
+        .header(Header::new("X-Original-URL", url.to_string()))
+        .header(Header::new("Authorization", format!("Bearer {token}")))
+        .dispatch()
+        .await
+        .status()
+}
+
+async fn sign_out(server: &TestServer) {
+    let response = server
+        .client
+        .post("/oauth/logout")
+        .header(ContentType::Form)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn lets_users_through_to_the_hosts_their_rules_allow() {
+    let server = start().await;
+    server.create_user("alice@example.com").await;
+    server.create_user("bob@example.com").await;
+
+    let (status, _) = server
+        .operator("PUT", "/api/roles/", json!({ "name": "staff" }))
+        .await;
+    assert_eq!(status, Status::Ok);
+    let grant = json!({ "email": "alice@example.com", "role": "staff" });
+    let (status, _) = server.operator("POST", "/api/roles/grant", grant).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = server
+        .operator("PUT", "/api/groups/", json!({ "name": "ops" }))
+        .await;
+    assert_eq!(status, Status::Ok);
+    let member = json!({ "user": "bob@example.com" });
+    let (status, _) = server
+        .operator("POST", "/api/groups/ops/members", member)
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, rule) = server
+        .operator(
+            "PUT",
+            "/api/access-rules/wiki.example.com",
+            json!({ "roles": ["staff"] }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok, "{rule}");
+    assert_eq!(rule["roles"], json!(["staff"]));
+    let (status, _) = server
+        .operator(
+            "PUT",
+            "/api/access-rules/*.ops.example.com",
+            json!({ "groups": ["ops"] }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = server
+        .operator(
+            "PUT",
+            "/api/access-rules/docs.example.com",
+            json!({ "roles": ["nobody"] }),
+        )
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    let (_, rules) = server
+        .operator("GET", "/api/access-rules/", json!({}))
+        .await;
+    assert_eq!(rules.as_array().unwrap().len(), 2);
+
+    // no session: refused, or sent to sign in when the proxy passes redirects on
+    let response = verify(&server, "https://wiki.example.com/page?a=1").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = server
+        .client
+        .get("/auth/verify?redirect=true")
+        .header(Header::new("X-Forwarded-Proto", "https"))
+        .header(Header::new("X-Forwarded-Host", "wiki.example.com"))
+        .header(Header::new("X-Forwarded-Uri", "/page?a=1"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Found);
+    let login = response.headers().get_one("location").unwrap();
+    assert_eq!(
+        login,
+        format!("{PUBLIC_URL}/oauth/login?rd=https%3A%2F%2Fwiki.example.com%2Fpage%3Fa%3D1")
+    );
+    let response = verify(&server, "https://unknown.example.com/").await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // the sign-in page only sends users back to hosts with a rule
+    let page = server
+        .client
+        .get(login.trim_start_matches(PUBLIC_URL))
+        .dispatch()
+        .await;
+    assert_eq!(page.status(), Status::Ok);
+    let page = server
+        .client
+        .get("/oauth/login?rd=https%3A%2F%2Fevil.example.org%2F")
+        .dispatch()
+        .await;
+    assert_eq!(page.status(), Status::BadRequest);
+
+    let back = sign_in(
+        &server,
+        "alice@example.com",
+        "https://wiki.example.com/page?a=1",
+    )
+    .await;
+    assert_eq!(back, "https://wiki.example.com/page?a=1");
+    let response = verify(&server, "https://wiki.example.com/page?a=1").await;
+    assert_eq!(response.status(), Status::Ok);
+    let headers = response.headers();
+    assert_eq!(headers.get_one("X-Auth-Email"), Some("alice@example.com"));
+    assert_eq!(headers.get_one("X-Auth-Roles"), Some("staff"));
+    assert!(!headers.get_one("X-Auth-User").unwrap().is_empty());
+    let response = verify(&server, "https://grafana.ops.example.com/").await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    sign_out(&server).await;
+    let response = verify(&server, "https://wiki.example.com/").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    sign_in(
+        &server,
+        "bob@example.com",
+        "https://grafana.ops.example.com/",
+    )
+    .await;
+    let response = verify(&server, "https://grafana.ops.example.com/").await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("X-Auth-Roles"), Some(""));
+    let response = verify(&server, "https://ops.example.com/").await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = verify(&server, "https://wiki.example.com/").await;
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn only_session_keys_are_accepted_as_bearer_tokens() {
+    let server = start().await;
+    server.create_user("alice@example.com").await;
+    let (status, _) = server
+        .operator("PUT", "/api/access-rules/wiki.example.com", json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    let credentials = json!({ "email": "alice@example.com", "password": PASSWORD });
+    let (status, issued) = server.operator("POST", "/api/user/key", credentials).await;
+    assert_eq!(status, Status::Ok);
+    let session = issued["api_key"].as_str().unwrap();
+    let url = "https://wiki.example.com/";
+    assert_eq!(verify_bearer(&server, url, session).await, Status::Ok);
+
+    // an access token alice gave some other client does not let its holder through
+    let client = json!({
+        "name": "Notes",
+        "client_type": "public",
+        "redirect_uris": ["https://notes.example.org/callback"],
+    });
+    let (status, client) = server.operator("POST", "/api/clients/", client).await;
+    assert_eq!(status, Status::Ok, "{client}");
+    let token = Token::generate();
+    let access = model::NewOAuthToken::new(
+        &token,
+        TokenKind::Access,
+        client["id"].as_str().unwrap(),
+        Some("alice@example.com"),
+        &Scope::parse("openid email"),
+    );
+    query::save_oauth_token(&TenantId::default(), access, "+1 hour", &server.pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        verify_bearer(&server, url, token.as_str()).await,
+        Status::Unauthorized
+    );
+}
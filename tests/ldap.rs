@@ -0,0 +1,369 @@
+//! The LDAP listener: simple bind against Authy's users and search under the base DN.
+
+use authy::data::{query, AppDatabase};
+use authy::domain::tenant::TenantId;
+use authy::domain::user::field::{Name, Password};
+use authy::service::{action, ask, audit};
+use authy::web::api::ApiKey;
+use authy::{Email, LdapServer, Links, Mailer};
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::TcpStream;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+const BASE_DN: &str = "ou=people,dc=example,dc=com";
+const PASSWORD: &str = "Passw0rd!23";
+
+const BIND_RESPONSE: u8 = 0x61;
+const SEARCH_ENTRY: u8 = 0x64;
+const SEARCH_DONE: u8 = 0x65;
+const EXTENDED_RESPONSE: u8 = 0x78;
+
+fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match contents.len() {
+        length @ 0..=0x7f => out.push(length as u8),
+        length => out.extend([0x82, (length >> 8) as u8, length as u8]),
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+fn message(id: u8, op: Vec<u8>) -> Vec<u8> {
+    tlv(0x30, &[tlv(0x02, &[id]), op].concat())
+}
+
+fn bind(id: u8, dn: &str, password: &str) -> Vec<u8> {
+    let op = [
+        tlv(0x02, &[3]),
+        tlv(0x04, dn.as_bytes()),
+        tlv(0x80, password.as_bytes()),
+    ]
+    .concat();
+    message(id, tlv(0x60, &op))
+}
+
+fn equal(attribute: &str, value: &str) -> Vec<u8> {
+    let assertion = [tlv(0x04, attribute.as_bytes()), tlv(0x04, value.as_bytes())].concat();
+    tlv(0xa3, &assertion)
+}
+
+fn search(id: u8, base: &str, filter: Vec<u8>, attributes: &[&str]) -> Vec<u8> {
+    let attributes: Vec<u8> = attributes
+        .iter()
+        .flat_map(|attribute| tlv(0x04, attribute.as_bytes()))
+        .collect();
+    let op = [
+        tlv(0x04, base.as_bytes()),
+        tlv(0x0a, &[2]),
+        tlv(0x0a, &[0]),
+        tlv(0x02, &[0]),
+        tlv(0x02, &[0]),
+        tlv(0x01, &[0]),
+        filter,
+        tlv(0x30, &attributes),
+    ]
+    .concat();
+    message(id, tlv(0x63, &op))
+}
+
+/// Splits the first element off `buf`: its tag, contents and the rest of `buf`.
+fn split(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (length, header) = match *buf.get(1)? {
+        0x81 => (*buf.get(2)? as usize, 3),
+        0x82 => (((*buf.get(2)? as usize) << 8) | *buf.get(3)? as usize, 4),
+        length => (length as usize, 2),
+    };
+    let contents = buf.get(header..header + length)?;
+    Some((buf[0], contents, &buf[header + length..]))
+}
+
+/// A response: the tag of its operation, and its result code or, for entries, its DN
+/// and attributes as text.
+#[derive(Debug)]
+struct Reply {
+    tag: u8,
+    code: u8,
+    text: String,
+}
+
+struct LdapClient {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl LdapClient {
+    async fn connect(port: u16) -> Self {
+        Self::connect_to(([127, 0, 0, 1], port).into()).await
+    }
+
+    async fn connect_to(address: SocketAddr) -> Self {
+        for _ in 0..50 {
+            if let Ok(stream) = TcpStream::connect(address).await {
+                return Self {
+                    stream,
+                    buf: vec![],
+                };
+            }
+            rocket::tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("LDAP listener did not start");
+    }
+
+    /// Sends a request and reads responses up to the one with tag `last`.
+    async fn send(&mut self, request: Vec<u8>, last: u8) -> Vec<Reply> {
+        self.stream.write_all(&request).await.unwrap();
+        let mut replies = vec![];
+        loop {
+            while let Some((_, contents, rest)) = split(&self.buf) {
+                let (_, _, op) = split(contents).unwrap();
+                let (tag, op, _) = split(op).unwrap();
+                let (_, first, rest_of_op) = split(op).unwrap();
+                let reply = match tag {
+                    SEARCH_ENTRY => Reply {
+                        tag,
+                        code: 0,
+                        text: format!(
+                            "{} {}",
+                            String::from_utf8_lossy(first),
+                            String::from_utf8_lossy(rest_of_op)
+                        ),
+                    },
+                    _ => Reply {
+                        tag,
+                        code: first[0],
+                        text: String::new(),
+                    },
+                };
+                self.buf = rest.to_vec();
+                replies.push(reply);
+                if tag == last {
+                    return replies;
+                }
+            }
+
+            let mut chunk = [0u8; 4096];
+            let read = self.stream.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "connection closed");
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn binds_and_searches_users() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("authy.db");
+    let database = AppDatabase::new(&format!("sqlite:{}?mode=rwc", path.display())).await;
+    let pool = database.get_pool().clone();
+    let tenant = TenantId::default();
+    let ctx = audit::Context::default();
+    for (email, name) in [
+        ("alice@example.com", "Alice Smith"),
+        ("bob@example.com", "Bob Jones"),
+    ] {
+        let user = ask::NewUser {
+            email: Email::new(email).unwrap(),
+            name: Name::new(name).unwrap(),
+            password: Password::new(PASSWORD).unwrap(),
+            phone: None,
+        };
+        action::new_user(&tenant, &ctx, user, &pool).await.unwrap();
+    }
+    let operator_key = query::save_api_key(&tenant, ApiKey::default(), &pool)
+        .await
+        .unwrap()
+        .to_base64();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    LdapServer {
+        address: ([127, 0, 0, 1], port).into(),
+        base_dn: BASE_DN.to_string(),
+        tenant,
+    }
+    .spawn(pool, Mailer::default(), Links::default());
+
+    // users bind with their DN and password, and find only themselves
+    let mut client = LdapClient::connect(port).await;
+    let alice = format!("uid=alice@example.com,{BASE_DN}");
+    let replies = client.send(bind(1, &alice, "wrong"), BIND_RESPONSE).await;
+    assert_eq!(replies[0].code, 49);
+    let replies = client
+        .send(
+            search(2, BASE_DN, equal("objectClass", "person"), &[]),
+            SEARCH_DONE,
+        )
+        .await;
+    assert_eq!(replies[0].code, 50);
+    let replies = client
+        .send(
+            bind(
+                3,
+                &format!("UID=alice@example.com,{}", BASE_DN.to_uppercase()),
+                PASSWORD,
+            ),
+            BIND_RESPONSE,
+        )
+        .await;
+    assert_eq!(replies[0].code, 0, "{replies:?}");
+    let replies = client
+        .send(
+            search(4, BASE_DN, equal("objectClass", "inetOrgPerson"), &["cn"]),
+            SEARCH_DONE,
+        )
+        .await;
+    assert_eq!(replies.len(), 2, "{replies:?}");
+    assert!(replies[0].text.starts_with(&alice));
+    assert!(replies[0].text.contains("Alice Smith"));
+    assert!(!replies[0].text.contains("alice@example.com Alice"));
+    assert_eq!(replies[1].code, 0);
+    let replies = client
+        .send(
+            search(5, BASE_DN, equal("uid", "bob@example.com"), &[]),
+            SEARCH_DONE,
+        )
+        .await;
+    assert_eq!(replies.len(), 1, "{replies:?}");
+    assert_eq!(replies[0].code, 0);
+
+    // services bind with an API key and search everyone
+    let mut service = LdapClient::connect(port).await;
+    let replies = service
+        .send(
+            bind(1, &format!("cn=wiki,{BASE_DN}"), &operator_key),
+            BIND_RESPONSE,
+        )
+        .await;
+    assert_eq!(replies[0].code, 0);
+    let replies = service
+        .send(
+            search(2, "dc=example,dc=com", equal("objectClass", "person"), &[]),
+            SEARCH_DONE,
+        )
+        .await;
+    assert_eq!(
+        replies
+            .iter()
+            .filter(|reply| reply.tag == SEARCH_ENTRY)
+            .count(),
+        2
+    );
+    let replies = service
+        .send(
+            search(3, BASE_DN, equal("uid", "BOB@example.com"), &["mail"]),
+            SEARCH_DONE,
+        )
+        .await;
+    assert_eq!(replies.len(), 2);
+    assert!(replies[0].text.contains("bob@example.com"));
+    let replies = service
+        .send(
+            search(4, "dc=other,dc=com", equal("uid", "bob@example.com"), &[]),
+            SEARCH_DONE,
+        )
+        .await;
+    assert_eq!(replies[0].code, 32);
+}
+
+#[rocket::async_test]
+async fn refuses_deeply_nested_filters() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("authy.db");
+    let database = AppDatabase::new(&format!("sqlite:{}?mode=rwc", path.display())).await;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    LdapServer {
+        address: ([127, 0, 0, 1], port).into(),
+        base_dn: BASE_DN.to_string(),
+        tenant: TenantId::default(),
+    }
+    .spawn(
+        database.get_pool().clone(),
+        Mailer::default(),
+        Links::default(),
+    );
+
+    // read before the bind is checked, so anyone could send it
+    let nested = |depth| {
+        (0..depth).fold(equal("uid", "alice@example.com"), |filter, _| {
+            tlv(0xa2, &filter)
+        })
+    };
+    let mut client = LdapClient::connect(port).await;
+    let replies = client
+        .send(search(1, BASE_DN, nested(10), &[]), SEARCH_DONE)
+        .await;
+    assert_eq!(replies[0].code, 50);
+    let deep = client.send(search(2, BASE_DN, nested(5000), &[]), EXTENDED_RESPONSE);
+    let replies = rocket::tokio::time::timeout(std::time::Duration::from_secs(5), deep)
+        .await
+        .expect("no notice of disconnection");
+    assert_eq!(replies[0].code, 2, "{replies:?}");
+    let mut rest = [0u8; 1];
+    assert_eq!(client.stream.read(&mut rest).await.unwrap(), 0);
+}
+
+/// An address of this host other than loopback, if it has a route to anywhere. Connecting
+/// a UDP socket sends nothing; it only picks the interface.
+fn external_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+#[rocket::async_test]
+async fn refuses_binds_from_other_hosts() {
+    let ip = match external_ip() {
+        Some(ip) => ip,
+        None => return eprintln!("skipped: no network interface besides loopback"),
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("authy.db");
+    let database = AppDatabase::new(&format!("sqlite:{}?mode=rwc", path.display())).await;
+    let pool = database.get_pool().clone();
+    let tenant = TenantId::default();
+    let user = ask::NewUser {
+        email: Email::new("alice@example.com").unwrap(),
+        name: Name::new("Alice Smith").unwrap(),
+        password: Password::new(PASSWORD).unwrap(),
+        phone: None,
+    };
+    action::new_user(&tenant, &audit::Context::default(), user, &pool)
+        .await
+        .unwrap();
+    let port = std::net::TcpListener::bind("0.0.0.0:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    LdapServer {
+        address: ([0, 0, 0, 0], port).into(),
+        base_dn: BASE_DN.to_string(),
+        tenant,
+    }
+    .spawn(pool, Mailer::default(), Links::default());
+
+    // the password would have crossed the network in the clear
+    let alice = format!("uid=alice@example.com,{BASE_DN}");
+    let mut remote = LdapClient::connect_to((ip, port).into()).await;
+    let replies = remote.send(bind(1, &alice, PASSWORD), BIND_RESPONSE).await;
+    assert_eq!(replies[0].code, 13, "{replies:?}");
+    let replies = remote
+        .send(
+            search(2, BASE_DN, equal("uid", "alice@example.com"), &[]),
+            SEARCH_DONE,
+        )
+        .await;
+    assert_eq!(replies[0].code, 50);
+
+    let mut local = LdapClient::connect(port).await;
+    let replies = local.send(bind(1, &alice, PASSWORD), BIND_RESPONSE).await;
+    assert_eq!(replies[0].code, 0, "{replies:?}");
+}
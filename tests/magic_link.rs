@@ -0,0 +1,76 @@
+//! Passwordless sign-in with links sent by mail.
+
+mod common;
+
+use common::TestServer;
+use rocket::http::{Cookie, Status};
+use serde_json::{json, Value};
+
+async fn request_link(server: &TestServer, email: &str) -> Status {
+    let request = json!({ "email": email });
+    let (status, _) = server
+        .operator("POST", "/api/user/magic-link", request)
+        .await;
+    status
+}
+
+#[rocket::async_test]
+async fn links_only_work_in_the_browser_that_asked_for_them() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+    assert_eq!(request_link(&server, "alice@example.com").await, Status::Ok);
+    let link = server.last_link();
+
+    // another browser has no readable nonce cookie
+    let response = server
+        .client
+        .get(link.clone())
+        .cookie(Cookie::new("authy_magic_link", "from another browser"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // which leaves the link usable where it was requested
+    let response = server.client.get(link.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let issued: Value = response.into_json().await.unwrap();
+    assert_eq!(issued["email"], "alice@example.com");
+    let (status, _) = server
+        .call(
+            "GET",
+            "/api/user/sessions",
+            issued["api_key"].as_str().unwrap(),
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    // once
+    let response = server.client.get(link).dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn links_respect_the_account_status() {
+    let server = TestServer::start().await;
+    server.create_user("bob@example.com").await;
+    assert_eq!(request_link(&server, "bob@example.com").await, Status::Ok);
+    let link = server.last_link();
+
+    let disable = json!({ "email": "bob@example.com", "status": "disabled" });
+    let (status, _) = server.operator("PUT", "/api/user/status", disable).await;
+    assert_eq!(status, Status::Ok);
+
+    // a link sent before the account was disabled no longer signs in
+    let response = server.client.get(link).dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // and no new one is sent, though the response does not tell
+    let sent = server.mail().len();
+    assert_eq!(request_link(&server, "bob@example.com").await, Status::Ok);
+    assert_eq!(
+        request_link(&server, "nobody@example.com").await,
+        Status::Ok
+    );
+    assert_eq!(server.mail().len(), sent);
+}
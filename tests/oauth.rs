@@ -0,0 +1,666 @@
+//! The OAuth 2.0 and OpenID Connect endpoints under `/oauth`.
+
+mod common;
+
+use authy::domain::oauth::DEVICE_CODE_GRANT_TYPE;
+use authy::domain::oidc;
+use authy::domain::tenant::TenantId;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::{TestServer, PASSWORD, PUBLIC_URL};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::LocalResponse;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+const REDIRECT_URI: &str = "https://app.example.com/callback";
+const SIGNED_OUT_URI: &str = "https://app.example.com/signed-out";
+const VERIFIER: &str = "dBjftJeZ4CVP-mJ92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+/// A client registered for the tests.
+struct App {
+    id: String,
+    secret: Option<String>,
+}
+
+async fn register(server: &TestServer, client_type: &str, scopes: &[&str]) -> App {
+    let client = json!({
+        "name": "App",
+        "client_type": client_type,
+        "redirect_uris": [REDIRECT_URI],
+        "scopes": scopes,
+        "post_logout_redirect_uris": [SIGNED_OUT_URI],
+    });
+    let (status, registered) = server.operator("POST", "/api/clients/", client).await;
+    assert_eq!(status, Status::Ok, "{registered}");
+
+    App {
+        id: registered["id"].as_str().unwrap().to_string(),
+        secret: registered["client_secret"].as_str().map(str::to_string),
+    }
+}
+
+fn form(pairs: &[(&str, &str)]) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+/// Approves an authorization request on the consent page, signing `email` in unless the
+/// browser already is, and returns where the user is sent back to. `params` add to or
+/// replace those of a plain OpenID Connect request with PKCE.
+async fn authorize_with(
+    server: &TestServer,
+    app: &App,
+    email: &str,
+    params: &[(&str, &str)],
+) -> url::Url {
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(VERIFIER.as_bytes()));
+    let mut pairs = vec![
+        ("response_type", "code"),
+        ("client_id", app.id.as_str()),
+        ("redirect_uri", REDIRECT_URI),
+        ("scope", "openid"),
+        ("state", "af0ifjsldkj"),
+        ("code_challenge", challenge.as_str()),
+        ("code_challenge_method", "S256"),
+        ("email", email),
+        ("password", PASSWORD),
+        ("decision", "allow"),
+    ];
+    for (name, value) in params {
+        pairs.retain(|(other, _)| other != name);
+        pairs.push((name, value));
+    }
+
+    let response = server
+        .client
+        .post("/oauth/authorize")
+        .header(ContentType::Form)
+        .body(form(&pairs))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    url::Url::parse(response.headers().get_one("location").unwrap()).unwrap()
+}
+
+/// The code `email` is sent back with after approving a plain request.
+async fn authorize(server: &TestServer, app: &App, email: &str) -> String {
+    let location = authorize_with(server, app, email, &[]).await;
+    query(&location, "code").expect("no code was issued")
+}
+
+fn query(url: &url::Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Posts `params` to the token endpoint, authenticating as `app`.
+async fn token(server: &TestServer, app: &App, params: &[(&str, &str)]) -> (Status, Value) {
+    let mut pairs = vec![("client_id", app.id.as_str())];
+    if let Some(secret) = &app.secret {
+        pairs.push(("client_secret", secret));
+    }
+    pairs.extend_from_slice(params);
+
+    let response = server
+        .client
+        .post("/oauth/token")
+        .header(ContentType::Form)
+        .body(form(&pairs))
+        .dispatch()
+        .await;
+    let status = response.status();
+    (status, response.into_json().await.unwrap())
+}
+
+/// Exchanges a code issued for a plain request for tokens.
+async fn redeem(server: &TestServer, app: &App, code: &str) -> Value {
+    let (status, tokens) = token(
+        server,
+        app,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", VERIFIER),
+        ],
+    )
+    .await;
+    assert_eq!(status, Status::Ok, "{tokens}");
+    tokens
+}
+
+/// Trades the refresh token in `tokens` for new tokens.
+async fn refresh(server: &TestServer, app: &App, tokens: &Value) -> (Status, Value) {
+    let refresh_token = tokens["refresh_token"].as_str().unwrap();
+    token(
+        server,
+        app,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ],
+    )
+    .await
+}
+
+async fn userinfo(server: &TestServer, access_token: &str) -> (Status, Value) {
+    let response = server
+        .client
+        .get("/oauth/userinfo")
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {access_token}"),
+        ))
+        .dispatch()
+        .await;
+    let status = response.status();
+    (status, response.into_json().await.unwrap_or_default())
+}
+
+/// How many sessions `email` holds.
+async fn sessions_of(server: &TestServer, email: &str) -> i64 {
+    sqlx::query_scalar("SELECT count(*) FROM api_keys WHERE owner = ?")
+        .bind(email)
+        .fetch_one(&server.pool)
+        .await
+        .unwrap()
+}
+
+/// Follows a logout link with `params`.
+async fn logout<'a>(server: &'a TestServer, params: &[(&str, &str)]) -> LocalResponse<'a> {
+    server
+        .client
+        .get(format!("/oauth/logout?{}", form(params)))
+        .dispatch()
+        .await
+}
+
+#[rocket::async_test]
+async fn logout_links_ask_first_unless_they_name_the_user() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+    server.create_user("bob@example.com").await;
+    let app = register(&server, "public", &[]).await;
+
+    // an ID token of bob's, from an earlier session in this browser
+    let code = authorize(&server, &app, "bob@example.com").await;
+    let bob = redeem(&server, &app, &code).await["id_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = server
+        .client
+        .post("/oauth/logout")
+        .header(ContentType::Form)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(sessions_of(&server, "bob@example.com").await, 0);
+
+    let code = authorize(&server, &app, "alice@example.com").await;
+    let alice = redeem(&server, &app, &code).await["id_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(sessions_of(&server, "alice@example.com").await, 1);
+
+    // a plain link, as another site could embed
+    let response = logout(&server, &[]).await;
+    assert_eq!(response.status(), Status::Ok);
+    let page = response.into_string().await.unwrap();
+    assert!(
+        page.contains(r#"<form method="post" action="logout">"#),
+        "{page}"
+    );
+    assert_eq!(sessions_of(&server, "alice@example.com").await, 1);
+
+    // or one naming another user
+    let request = [
+        ("id_token_hint", bob.as_str()),
+        ("post_logout_redirect_uri", SIGNED_OUT_URI),
+        ("state", "xyz"),
+    ];
+    let response = logout(&server, &request).await;
+    assert_eq!(response.status(), Status::Ok);
+    let page = response.into_string().await.unwrap();
+    assert!(page.contains(r#"name="state" value="xyz""#), "{page}");
+    assert_eq!(sessions_of(&server, "alice@example.com").await, 1);
+
+    // confirming posts the request back
+    let response = server
+        .client
+        .post("/oauth/logout")
+        .header(ContentType::Form)
+        .body(form(&request))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = response.headers().get_one("location").unwrap();
+    assert!(location.starts_with(SIGNED_OUT_URI), "{location}");
+    assert!(location.contains("state=xyz"), "{location}");
+    assert_eq!(sessions_of(&server, "alice@example.com").await, 0);
+
+    // a client passing the signed-in user's ID token signs them out right away
+    authorize(&server, &app, "alice@example.com").await;
+    let request = [
+        ("id_token_hint", alice.as_str()),
+        ("post_logout_redirect_uri", SIGNED_OUT_URI),
+    ];
+    let response = logout(&server, &request).await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(sessions_of(&server, "alice@example.com").await, 0);
+}
+
+#[rocket::async_test]
+async fn codes_are_only_issued_for_s256_challenges_and_redeemed_with_the_verifier() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+    let app = register(&server, "public", &[]).await;
+
+    let plain = [
+        ("code_challenge", VERIFIER),
+        ("code_challenge_method", "plain"),
+    ];
+    let location = authorize_with(&server, &app, "alice@example.com", &plain).await;
+    assert!(location.as_str().starts_with(REDIRECT_URI), "{location}");
+    assert_eq!(
+        query(&location, "error").as_deref(),
+        Some("invalid_request")
+    );
+    assert_eq!(query(&location, "state").as_deref(), Some("af0ifjsldkj"));
+    assert_eq!(query(&location, "code"), None);
+
+    // a wrong verifier uses the code up
+    let code = authorize(&server, &app, "alice@example.com").await;
+    let wrong = "a-verifier-that-was-never-hashed-into-the-challenge-1234";
+    let (status, error) = token(
+        &server,
+        &app,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", wrong),
+        ],
+    )
+    .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("invalid_grant"));
+    let (status, error) = token(
+        &server,
+        &app,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", VERIFIER),
+        ],
+    )
+    .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("invalid_grant"));
+
+    // the right one works, once
+    let code = authorize(&server, &app, "alice@example.com").await;
+    let tokens = redeem(&server, &app, &code).await;
+    assert_eq!(tokens["token_type"], json!("Bearer"));
+    assert!(tokens["refresh_token"].is_string(), "{tokens}");
+    let (status, error) = token(
+        &server,
+        &app,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", VERIFIER),
+        ],
+    )
+    .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("invalid_grant"));
+}
+
+#[rocket::async_test]
+async fn refresh_tokens_rotate_and_end_with_the_users_sessions() {
+    let server = TestServer::start().await;
+    let key = server.user_key("alice@example.com").await;
+    let app = register(&server, "public", &[]).await;
+    let code = authorize(&server, &app, "alice@example.com").await;
+    let first = redeem(&server, &app, &code).await;
+
+    let (status, second) = refresh(&server, &app, &first).await;
+    assert_eq!(status, Status::Ok, "{second}");
+    assert_ne!(second["refresh_token"], first["refresh_token"]);
+    assert_ne!(second["access_token"], first["access_token"]);
+
+    // the old refresh token was replaced
+    let (status, error) = refresh(&server, &app, &first).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("invalid_grant"));
+
+    let access_token = second["access_token"].as_str().unwrap();
+    assert_eq!(userinfo(&server, access_token).await.0, Status::Ok);
+
+    // signing out everywhere ends what was handed to clients as well
+    let (status, _) = server
+        .call("DELETE", "/api/user/sessions", &key, json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    let (status, error) = refresh(&server, &app, &second).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("invalid_grant"));
+    assert_eq!(
+        userinfo(&server, access_token).await.0,
+        Status::Unauthorized
+    );
+}
+
+/// The claims of a JWT, without checking its signature.
+fn claims(jwt: &str) -> Value {
+    let payload = jwt.split('.').nth(1).expect("not a JWT");
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
+}
+
+#[rocket::async_test]
+async fn id_tokens_identify_the_user_to_the_client_and_echo_the_nonce() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+    let app = register(&server, "public", &[]).await;
+
+    let nonce = [("nonce", "n-0S6_WzA2Mj")];
+    let location = authorize_with(&server, &app, "alice@example.com", &nonce).await;
+    let code = query(&location, "code").unwrap();
+    let tokens = redeem(&server, &app, &code).await;
+    let id_token = claims(tokens["id_token"].as_str().unwrap());
+
+    let (_, discovery) = server
+        .operator("GET", "/.well-known/openid-configuration", json!({}))
+        .await;
+    assert_eq!(id_token["iss"], discovery["issuer"]);
+    assert_eq!(id_token["iss"], json!(PUBLIC_URL));
+    assert_eq!(id_token["aud"], json!(app.id));
+    assert_eq!(id_token["nonce"], json!("n-0S6_WzA2Mj"));
+    let subject = oidc::subject(&TenantId::default(), "alice@example.com");
+    assert_eq!(id_token["sub"], json!(subject));
+    let (iat, exp) = (id_token["iat"].as_u64(), id_token["exp"].as_u64());
+    assert!(iat.unwrap() < exp.unwrap(), "{id_token}");
+
+    let access_token = tokens["access_token"].as_str().unwrap();
+    let (status, user) = userinfo(&server, access_token).await;
+    assert_eq!(status, Status::Ok, "{user}");
+    assert_eq!(user["sub"], json!(subject));
+
+    // refreshed ID tokens are not bound to the original request
+    let (status, refreshed) = refresh(&server, &app, &tokens).await;
+    assert_eq!(status, Status::Ok, "{refreshed}");
+    let refreshed = claims(refreshed["id_token"].as_str().unwrap());
+    assert_eq!(refreshed["sub"], json!(subject));
+    assert!(refreshed.get("nonce").is_none(), "{refreshed}");
+
+    // and without the openid scope there is none
+    let scope = [("scope", "email")];
+    let location = authorize_with(&server, &app, "alice@example.com", &scope).await;
+    let tokens = redeem(&server, &app, &query(&location, "code").unwrap()).await;
+    assert!(tokens.get("id_token").is_none(), "{tokens}");
+}
+
+/// Calls the API with an access token instead of an API key.
+async fn api(server: &TestServer, path: &str, access_token: &str) -> Status {
+    server
+        .client
+        .get(path)
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {access_token}"),
+        ))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+async fn client_credentials_are_limited_to_the_scopes_asked_for() {
+    let server = TestServer::start().await;
+    let service = register(&server, "confidential", &["users:read", "roles:read"]).await;
+
+    let (status, tokens) = token(
+        &server,
+        &service,
+        &[
+            ("grant_type", "client_credentials"),
+            ("scope", "users:read"),
+        ],
+    )
+    .await;
+    assert_eq!(status, Status::Ok, "{tokens}");
+    assert_eq!(tokens["scope"], json!("users:read"));
+    assert!(tokens.get("refresh_token").is_none(), "{tokens}");
+    let access_token = tokens["access_token"].as_str().unwrap();
+    assert_eq!(api(&server, "/api/users/", access_token).await, Status::Ok);
+    assert_eq!(
+        api(&server, "/api/roles/", access_token).await,
+        Status::Forbidden
+    );
+
+    // without a scope, everything the client was registered with
+    let (status, tokens) = token(&server, &service, &[("grant_type", "client_credentials")]).await;
+    assert_eq!(status, Status::Ok, "{tokens}");
+    let access_token = tokens["access_token"].as_str().unwrap();
+    assert_eq!(api(&server, "/api/roles/", access_token).await, Status::Ok);
+
+    // and never more
+    let (status, error) = token(
+        &server,
+        &service,
+        &[
+            ("grant_type", "client_credentials"),
+            ("scope", "users:write"),
+        ],
+    )
+    .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("invalid_scope"));
+
+    let forged = App {
+        id: service.id.clone(),
+        secret: Some("not the secret".to_string()),
+    };
+    let (status, error) = token(&server, &forged, &[("grant_type", "client_credentials")]).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(error["error"], json!("invalid_client"));
+
+    // public clients have no credentials of their own
+    let app = register(&server, "public", &["users:read"]).await;
+    let (status, error) = token(&server, &app, &[("grant_type", "client_credentials")]).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("unauthorized_client"));
+}
+
+/// Posts `token` to the introspection or revocation `endpoint`, authenticating as `app`.
+async fn lookup(server: &TestServer, app: &App, endpoint: &str, token: &str) -> (Status, Value) {
+    let mut pairs = vec![("token", token), ("client_id", app.id.as_str())];
+    if let Some(secret) = &app.secret {
+        pairs.push(("client_secret", secret));
+    }
+
+    let response = server
+        .client
+        .post(format!("/oauth/{endpoint}"))
+        .header(ContentType::Form)
+        .body(form(&pairs))
+        .dispatch()
+        .await;
+    let status = response.status();
+    (status, response.into_json().await.unwrap_or_default())
+}
+
+#[rocket::async_test]
+async fn introspection_reports_revoked_and_expired_tokens_as_inactive() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+    let service = register(&server, "confidential", &["users:read"]).await;
+    let inactive = json!({ "active": false });
+
+    let grant = [("grant_type", "client_credentials")];
+    let (_, tokens) = token(&server, &service, &grant).await;
+    let access_token = tokens["access_token"].as_str().unwrap();
+    let (status, active) = lookup(&server, &service, "introspect", access_token).await;
+    assert_eq!(status, Status::Ok, "{active}");
+    assert_eq!(active["active"], json!(true));
+    assert_eq!(active["client_id"], json!(service.id));
+    assert_eq!(active["token_type"], json!("access_token"));
+    assert_eq!(active["scope"], json!("users:read"));
+
+    let (status, _) = lookup(&server, &service, "revoke", access_token).await;
+    assert_eq!(status, Status::Ok);
+    let (_, revoked) = lookup(&server, &service, "introspect", access_token).await;
+    assert_eq!(revoked, inactive);
+
+    let (_, tokens) = token(&server, &service, &grant).await;
+    let access_token = tokens["access_token"].as_str().unwrap();
+    sqlx::query("UPDATE oauth_tokens SET expires_at = datetime('now', '-1 second')")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    let (_, expired) = lookup(&server, &service, "introspect", access_token).await;
+    assert_eq!(expired, inactive);
+
+    // revoking a refresh token ends the access tokens issued with it
+    let code = authorize(&server, &service, "alice@example.com").await;
+    let tokens = redeem(&server, &service, &code).await;
+    let refresh_token = tokens["refresh_token"].as_str().unwrap();
+    let access_token = tokens["access_token"].as_str().unwrap();
+    let (_, active) = lookup(&server, &service, "introspect", refresh_token).await;
+    assert_eq!(active["token_type"], json!("refresh_token"));
+    assert_eq!(active["username"], json!("alice@example.com"));
+    let (status, _) = lookup(&server, &service, "revoke", refresh_token).await;
+    assert_eq!(status, Status::Ok);
+    let (_, revoked) = lookup(&server, &service, "introspect", access_token).await;
+    assert_eq!(revoked, inactive);
+
+    // only confidential clients may ask
+    let app = register(&server, "public", &[]).await;
+    let (status, error) = lookup(&server, &app, "introspect", refresh_token).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("unauthorized_client"));
+}
+
+/// Starts a device authorization for `app`, returning its codes.
+async fn device_authorization(server: &TestServer, app: &App) -> Value {
+    let response = server
+        .client
+        .post("/oauth/device_authorization")
+        .header(ContentType::Form)
+        .body(form(&[("client_id", &app.id), ("scope", "openid")]))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    response.into_json().await.unwrap()
+}
+
+/// Approves or denies the request showing `user_code` as `email`.
+async fn decide(server: &TestServer, user_code: &str, email: &str, decision: &str) -> String {
+    let response = server
+        .client
+        .post("/oauth/device")
+        .header(ContentType::Form)
+        .body(form(&[
+            ("user_code", user_code),
+            ("email", email),
+            ("password", PASSWORD),
+            ("decision", decision),
+        ]))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    response.into_string().await.unwrap()
+}
+
+/// Polls the token endpoint as a device would, returning the error or the tokens.
+async fn poll(server: &TestServer, app: &App, codes: &Value) -> (Status, Value) {
+    let device_code = codes["device_code"].as_str().unwrap();
+    token(
+        server,
+        app,
+        &[
+            ("grant_type", DEVICE_CODE_GRANT_TYPE),
+            ("device_code", device_code),
+        ],
+    )
+    .await
+}
+
+/// Lets devices poll again without waiting for the interval.
+async fn wait_interval(server: &TestServer) {
+    sqlx::query("UPDATE oauth_device_codes SET polled_at = datetime('now', '-1 hour')")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+}
+
+#[rocket::async_test]
+async fn devices_keep_polling_until_the_user_decides() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+    let app = register(&server, "public", &[]).await;
+    let codes = device_authorization(&server, &app).await;
+    let interval = codes["interval"].as_u64().unwrap();
+
+    let (status, error) = poll(&server, &app, &codes).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("authorization_pending"));
+
+    // polling within the interval makes it longer
+    let (status, error) = poll(&server, &app, &codes).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("slow_down"));
+    let raised: i64 = sqlx::query_scalar("SELECT poll_interval FROM oauth_device_codes")
+        .fetch_one(&server.pool)
+        .await
+        .unwrap();
+    assert!(raised as u64 > interval, "{raised}");
+    wait_interval(&server).await;
+    let (_, error) = poll(&server, &app, &codes).await;
+    assert_eq!(error["error"], json!("authorization_pending"));
+
+    let user_code = codes["user_code"].as_str().unwrap();
+    let page = decide(&server, user_code, "alice@example.com", "allow").await;
+    assert!(page.contains("Device connected"), "{page}");
+    let (status, tokens) = poll(&server, &app, &codes).await;
+    assert_eq!(status, Status::Ok, "{tokens}");
+    let access_token = tokens["access_token"].as_str().unwrap();
+    let (_, user) = userinfo(&server, access_token).await;
+    let subject = oidc::subject(&TenantId::default(), "alice@example.com");
+    assert_eq!(user["sub"], json!(subject));
+
+    // the device code is used up
+    let (_, error) = poll(&server, &app, &codes).await;
+    assert_eq!(error["error"], json!("invalid_grant"));
+}
+
+#[rocket::async_test]
+async fn devices_stop_polling_once_denied_or_expired() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+    let app = register(&server, "public", &[]).await;
+
+    let codes = device_authorization(&server, &app).await;
+    let user_code = codes["user_code"].as_str().unwrap();
+    let page = decide(&server, user_code, "alice@example.com", "deny").await;
+    assert!(page.contains("Request denied"), "{page}");
+    let (status, error) = poll(&server, &app, &codes).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("access_denied"));
+
+    let codes = device_authorization(&server, &app).await;
+    sqlx::query("UPDATE oauth_device_codes SET expires_at = datetime('now', '-1 second')")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    let (status, error) = poll(&server, &app, &codes).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["error"], json!("expired_token"));
+}
@@ -0,0 +1,129 @@
+//! Passwordless sign-in with one-time codes.
+
+mod common;
+
+use common::TestServer;
+use rocket::http::Status;
+use serde_json::json;
+
+async fn request_code(server: &TestServer, email: &str) {
+    let request = json!({ "email": email, "channel": "email" });
+    let (status, _) = server.operator("POST", "/api/user/otp", request).await;
+    assert_eq!(status, Status::Ok);
+}
+
+async fn verify(server: &TestServer, email: &str, code: &str) -> Status {
+    let login = json!({ "email": email, "code": code });
+    let (status, _) = server.operator("POST", "/api/user/otp/verify", login).await;
+    status
+}
+
+/// A six-digit code other than `code`.
+fn wrong(code: &str) -> String {
+    format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000)
+}
+
+#[rocket::async_test]
+async fn codes_work_once_until_they_expire() {
+    let server = TestServer::start().await;
+    server.create_user("alice@example.com").await;
+
+    request_code(&server, "alice@example.com").await;
+    let code = server.last_code();
+    let login = json!({ "email": "alice@example.com", "code": code });
+    let (status, issued) = server.operator("POST", "/api/user/otp/verify", login).await;
+    assert_eq!(status, Status::Ok, "{issued}");
+    assert_eq!(issued["email"], "alice@example.com");
+    assert_eq!(
+        verify(&server, "alice@example.com", &code).await,
+        Status::NotFound
+    );
+
+    // only the latest code is accepted
+    request_code(&server, "alice@example.com").await;
+    let replaced = server.last_code();
+    request_code(&server, "alice@example.com").await;
+    let code = server.last_code();
+    if replaced != code {
+        assert_eq!(
+            verify(&server, "alice@example.com", &replaced).await,
+            Status::NotFound
+        );
+    }
+
+    sqlx::query("UPDATE otp_codes SET expires_at = datetime('now', '-1 minute')")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        verify(&server, "alice@example.com", &code).await,
+        Status::NotFound
+    );
+}
+
+#[rocket::async_test]
+async fn new_codes_do_not_reset_the_attempt_limit() {
+    let server = TestServer::start().await;
+    server.create_user("bob@example.com").await;
+
+    request_code(&server, "bob@example.com").await;
+    let code = server.last_code();
+    for _ in 0..4 {
+        assert_eq!(
+            verify(&server, "bob@example.com", &wrong(&code)).await,
+            Status::NotFound
+        );
+    }
+    request_code(&server, "bob@example.com").await;
+    let code = server.last_code();
+    assert_eq!(
+        verify(&server, "bob@example.com", &wrong(&code)).await,
+        Status::NotFound
+    );
+
+    // five wrong guesses between the codes: even the right one is refused now
+    assert_eq!(
+        verify(&server, "bob@example.com", &code).await,
+        Status::NotFound
+    );
+    request_code(&server, "bob@example.com").await;
+    assert_eq!(
+        verify(&server, "bob@example.com", &server.last_code()).await,
+        Status::NotFound
+    );
+
+    // until the failed attempts are an hour old
+    sqlx::query("UPDATE otp_codes SET created_at = datetime('now', '-2 hours')")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+    request_code(&server, "bob@example.com").await;
+    assert_eq!(
+        verify(&server, "bob@example.com", &server.last_code()).await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn limits_how_often_codes_are_sent() {
+    let server = TestServer::start().await;
+    server.create_user("carol@example.com").await;
+
+    for _ in 0..5 {
+        request_code(&server, "carol@example.com").await;
+    }
+    assert_eq!(server.messages().len(), 5);
+    let code = server.last_code();
+
+    // refused without saying so, leaving the last code usable
+    request_code(&server, "carol@example.com").await;
+    assert_eq!(server.messages().len(), 5);
+    let (_, events) = server
+        .operator("GET", "/api/audit/?action=otp.request", json!({}))
+        .await;
+    assert_eq!(events["events"][0]["detail"]["sent"], json!(false));
+    assert_eq!(
+        verify(&server, "carol@example.com", &code).await,
+        Status::Ok
+    );
+}
@@ -0,0 +1,164 @@
+//! SCIM 2.0 provisioning of users and groups under `/scim/v2`.
+
+mod common;
+
+use authy::web::api::API_KEY_HEADER;
+use common::TestServer;
+use rocket::http::{ContentType, Header, Status};
+use serde_json::{json, Value};
+
+async fn scim(
+    server: &TestServer,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> (Status, Value) {
+    let path = format!("/scim/v2{path}");
+    let request = match method {
+        "POST" => server.client.post(path),
+        "PUT" => server.client.put(path),
+        "PATCH" => server.client.patch(path),
+        "DELETE" => server.client.delete(path),
+        _ => server.client.get(path),
+    };
+    let request = request
+        .header(ContentType::new("application", "scim+json"))
+        .header(Header::new(API_KEY_HEADER, server.operator_key.clone()));
+    let request = match body {
+        Some(body) => request.body(body.to_string()),
+        None => request,
+    };
+    let response = request.dispatch().await;
+    let status = response.status();
+    let body = response.into_string().await.unwrap_or_default();
+
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[rocket::async_test]
+async fn provisions_users_and_groups() {
+    let server = TestServer::start().await;
+
+    let (status, config) = scim(&server, "GET", "/ServiceProviderConfig", None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(config["patch"]["supported"], json!(true));
+    let (_, types) = scim(&server, "GET", "/ResourceTypes", None).await;
+    assert_eq!(types["totalResults"], json!(2));
+    let (status, _) = scim(
+        &server,
+        "GET",
+        "/Schemas/urn:ietf:params:scim:schemas:core:2.0:User",
+        None,
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+
+    let alice = json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+        "userName": "alice@example.com",
+        "name": { "givenName": "Alice", "familyName": "Smith" },
+        "active": true,
+    });
+    let (status, user) = scim(&server, "POST", "/Users", Some(alice.clone())).await;
+    assert_eq!(status, Status::Created, "{user}");
+    assert_eq!(user["id"], json!("alice@example.com"));
+    assert_eq!(user["displayName"], json!("Alice Smith"));
+    assert!(user.get("password").is_none());
+    let (status, error) = scim(&server, "POST", "/Users", Some(alice)).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["scimType"], json!("uniqueness"));
+
+    let bob = json!({ "userName": "bob@example.com", "displayName": "Bob", "active": "False" });
+    let (status, user) = scim(&server, "POST", "/Users", Some(bob)).await;
+    assert_eq!(status, Status::Created, "{user}");
+    assert_eq!(user["active"], json!(false));
+
+    let (_, page) = scim(
+        &server,
+        "GET",
+        "/Users?filter=userName%20eq%20%22ALICE@example.com%22",
+        None,
+    )
+    .await;
+    assert_eq!(page["totalResults"], json!(1));
+    assert_eq!(page["Resources"][0]["userName"], json!("alice@example.com"));
+    let (_, page) = scim(&server, "GET", "/Users?startIndex=2&count=1", None).await;
+    assert_eq!(page["totalResults"], json!(2));
+    assert_eq!(page["itemsPerPage"], json!(1));
+    assert_eq!(page["Resources"][0]["userName"], json!("bob@example.com"));
+    let (status, error) = scim(&server, "GET", "/Users?filter=title%20eq%20%22x%22", None).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["scimType"], json!("invalidFilter"));
+
+    let patch = json!({
+        "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+        "Operations": [
+            { "op": "Replace", "path": "active", "value": true },
+            { "op": "replace", "value": { "displayName": "Robert" } },
+        ],
+    });
+    let (status, user) = scim(&server, "PATCH", "/Users/bob@example.com", Some(patch)).await;
+    assert_eq!(status, Status::Ok, "{user}");
+    assert_eq!(user["active"], json!(true));
+    assert_eq!(user["displayName"], json!("Robert"));
+
+    let replace = json!({ "userName": "carol@example.com", "displayName": "Carol" });
+    let (status, error) = scim(&server, "PUT", "/Users/bob@example.com", Some(replace)).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["scimType"], json!("mutability"));
+
+    let group = json!({
+        "displayName": "Engineering Team",
+        "members": [{ "value": "alice@example.com" }],
+    });
+    let (status, group) = scim(&server, "POST", "/Groups", Some(group)).await;
+    assert_eq!(status, Status::Created, "{group}");
+    assert_eq!(group["id"], json!("engineering-team"));
+    assert_eq!(group["members"].as_array().unwrap().len(), 1);
+
+    let patch = json!({
+        "Operations": [
+            { "op": "add", "path": "members", "value": [{ "value": "bob@example.com" }] },
+            { "op": "remove", "path": "members[value eq \"alice@example.com\"]" },
+        ],
+    });
+    let (status, group) = scim(&server, "PATCH", "/Groups/engineering-team", Some(patch)).await;
+    assert_eq!(status, Status::Ok, "{group}");
+    assert_eq!(group["members"][0]["value"], json!("bob@example.com"));
+    assert_eq!(group["members"].as_array().unwrap().len(), 1);
+    let (_, user) = scim(&server, "GET", "/Users/bob@example.com", None).await;
+    assert_eq!(user["groups"][0]["value"], json!("engineering-team"));
+
+    let (_, page) = scim(
+        &server,
+        "GET",
+        "/Groups?filter=displayName%20eq%20%22engineering%20team%22",
+        None,
+    )
+    .await;
+    assert_eq!(page["totalResults"], json!(1));
+
+    let (status, _) = scim(&server, "DELETE", "/Groups/engineering-team", None).await;
+    assert_eq!(status, Status::NoContent);
+    let (status, _) = scim(&server, "DELETE", "/Users/bob@example.com", None).await;
+    assert_eq!(status, Status::NoContent);
+    let (status, error) = scim(&server, "GET", "/Users/bob@example.com", None).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["status"], json!("404"));
+
+    let (status, error) = scim(
+        &server,
+        "POST",
+        "/Users",
+        Some(json!({ "userName": ["x"] })),
+    )
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(error["scimType"], json!("invalidSyntax"));
+    let response = server.client.get("/scim/v2/Users").dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(
+        response.content_type(),
+        Some(ContentType::new("application", "scim+json"))
+    );
+}
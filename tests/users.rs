@@ -0,0 +1,273 @@
+//! Managing users through `/api/user` and `/api/users`.
+
+mod common;
+
+use authy::domain::tenant::TenantId;
+use authy::service::{action, ask, audit};
+use authy::web::api::API_KEY_HEADER;
+use common::{TestServer, PASSWORD};
+use rocket::http::{Header, Status};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// How many keys a user holds or uses to impersonate someone else.
+async fn keys_of(server: &TestServer, email: &str) -> i64 {
+    sqlx::query_scalar("SELECT count(*) FROM api_keys WHERE owner = ? OR impersonator = ?")
+        .bind(email)
+        .bind(email)
+        .fetch_one(&server.pool)
+        .await
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn deleting_a_user_removes_their_sessions() {
+    let server = TestServer::start().await;
+    let alice = server.user_key("alice@example.com").await;
+    let bob = server.user_key("bob@example.com").await;
+    let (status, _) = server
+        .call("GET", "/api/user/sessions", &alice, json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(keys_of(&server, "alice@example.com").await, 1);
+
+    let delete = json!({ "email": "alice@example.com", "permanent": false });
+    let (status, _) = server.operator("DELETE", "/api/user/", delete).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = server
+        .call("GET", "/api/user/sessions", &alice, json!({}))
+        .await;
+    assert_ne!(status, Status::Ok);
+    assert_eq!(keys_of(&server, "alice@example.com").await, 0);
+
+    let delete = json!({ "email": "bob@example.com", "permanent": true });
+    let (status, _) = server.operator("DELETE", "/api/user/", delete).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = server
+        .call("GET", "/api/user/sessions", &bob, json!({}))
+        .await;
+    assert_ne!(status, Status::Ok);
+    assert_eq!(keys_of(&server, "bob@example.com").await, 0);
+
+    // erased once the retention period is over, so the address can sign up again
+    let purged = action::purge_deleted_users(Duration::ZERO, &server.pool)
+        .await
+        .unwrap();
+    assert_eq!(purged, 1);
+    server.user_key("alice@example.com").await;
+}
+
+#[rocket::async_test]
+async fn status_changes_record_who_made_them() {
+    let server = TestServer::start().await;
+    let alice = server.user_key("alice@example.com").await;
+    server.user_key("bob@example.com").await;
+
+    // the actor comes from the key, whatever the request claims
+    let change = json!({
+        "email": "bob@example.com",
+        "status": "disabled",
+        "reason": "left",
+        "actor": "mallory@example.com",
+    });
+    let (status, user) = server.operator("PUT", "/api/user/status", change).await;
+    assert_eq!(status, Status::Ok, "{user}");
+    assert_eq!(user["status"], json!("disabled"));
+    let change = json!({ "email": "alice@example.com", "status": "disabled" });
+    let (status, _) = server.call("PUT", "/api/user/status", &alice, change).await;
+    assert_eq!(status, Status::Forbidden);
+
+    let actors: Vec<String> =
+        sqlx::query_scalar("SELECT actor FROM user_status_changes ORDER BY id")
+            .fetch_all(&server.pool)
+            .await
+            .unwrap();
+    assert_eq!(actors, ["operator"]);
+}
+
+#[rocket::async_test]
+async fn listing_users_leaves_out_passwords() {
+    let server = TestServer::start().await;
+    server.user_key("alice@example.com").await;
+    server.user_key("bob@example.com").await;
+
+    let response = server
+        .client
+        .get("/api/users/?sort=email")
+        .header(Header::new(API_KEY_HEADER, server.operator_key.clone()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().await.unwrap();
+    assert!(!body.contains("password"), "{body}");
+    assert!(!body.contains(PASSWORD), "{body}");
+    let page: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(page["users"][0]["email"], json!("alice@example.com"));
+    assert_eq!(page["users"].as_array().unwrap().len(), 2);
+}
+
+#[rocket::async_test]
+async fn user_responses_leave_out_passwords() {
+    let server = TestServer::start().await;
+    let user = json!({ "email": "alice@example.com", "password": PASSWORD, "name": "Alice" });
+    let (status, created) = server.operator("POST", "/api/user/", user).await;
+    assert_eq!(status, Status::Ok, "{created}");
+    let credentials = json!({ "email": "alice@example.com", "password": PASSWORD });
+    let (status, issued) = server.operator("POST", "/api/user/key", credentials).await;
+    assert_eq!(status, Status::Ok, "{issued}");
+    let alice = issued["api_key"].as_str().unwrap();
+    let rename = json!({ "email": "alice@example.com", "name": "Alice A." });
+    let (status, updated) = server.call("PATCH", "/api/user/", alice, rename).await;
+    assert_eq!(status, Status::Ok, "{updated}");
+    let credentials = json!({ "email": "alice@example.com", "password": PASSWORD });
+    let (status, checked) = server
+        .operator("POST", "/api/user/login", credentials)
+        .await;
+    assert_eq!(status, Status::Ok, "{checked}");
+
+    for user in [created, updated, checked] {
+        assert_eq!(user["email"], json!("alice@example.com"));
+        assert!(user.get("password").is_none(), "{user}");
+        assert!(!user.to_string().contains(PASSWORD), "{user}");
+    }
+}
+
+#[rocket::async_test]
+async fn only_operators_and_user_admins_create_users() {
+    let server = TestServer::start().await;
+    let alice = server.user_key("alice@example.com").await;
+
+    let user = json!({ "email": "bob@example.com", "password": PASSWORD, "name": "Bob" });
+    let (status, _) = server
+        .call("POST", "/api/user/", &alice, user.clone())
+        .await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = server.operator("POST", "/api/user/", user).await;
+    assert_eq!(status, Status::Ok);
+
+    let grant = json!({ "email": "alice@example.com", "role": "admin" });
+    let (status, _) = server.operator("POST", "/api/roles/grant", grant).await;
+    assert_eq!(status, Status::Ok);
+    let user = json!({ "email": "carol@example.com", "password": PASSWORD, "name": "Carol" });
+    let (status, _) = server.call("POST", "/api/user/", &alice, user).await;
+    assert_eq!(status, Status::Ok);
+
+    // checking someone's password is for operators, not for anyone holding a key
+    let credentials = json!({ "email": "bob@example.com", "password": PASSWORD });
+    let (status, _) = server
+        .call("POST", "/api/user/login", &alice, credentials.clone())
+        .await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = server
+        .operator("POST", "/api/user/login", credentials)
+        .await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn concurrent_metadata_patches_are_all_applied() {
+    let server = TestServer::start().await;
+    server.user_key("alice@example.com").await;
+
+    let patches: Vec<_> = (0..8)
+        .map(|i| {
+            let pool = server.pool.clone();
+            rocket::tokio::spawn(async move {
+                let req = ask::UpdateAppMetadata {
+                    email: "alice@example.com".into(),
+                    app_metadata: json!({ format!("key{i}"): i }),
+                };
+                let tenant = TenantId::default();
+                action::update_app_metadata(&tenant, &audit::Context::default(), req, &pool).await
+            })
+        })
+        .collect();
+    for patch in patches {
+        patch.await.unwrap().unwrap();
+    }
+
+    let (_, page) = server.operator("GET", "/api/users/", json!({})).await;
+    let metadata = page["users"][0]["app_metadata"]
+        .as_object()
+        .unwrap()
+        .clone();
+    assert_eq!(metadata.len(), 8, "{metadata:?}");
+}
+
+#[rocket::async_test]
+async fn users_only_update_their_own_account() {
+    let server = TestServer::start().await;
+    let alice = server.user_key("alice@example.com").await;
+    server.user_key("bob@example.com").await;
+
+    let takeover = json!({ "email": "bob@example.com", "password": "Takeover!234" });
+    let (status, _) = server.call("PATCH", "/api/user/", &alice, takeover).await;
+    assert_eq!(status, Status::Forbidden);
+    let credentials = json!({ "email": "bob@example.com", "password": PASSWORD });
+    let (status, _) = server.operator("POST", "/api/user/key", credentials).await;
+    assert_eq!(status, Status::Ok);
+
+    let rename = json!({ "email": "alice@example.com", "name": "Alice" });
+    let (status, user) = server.call("PATCH", "/api/user/", &alice, rename).await;
+    assert_eq!(status, Status::Ok, "{user}");
+    assert_eq!(user["name"], json!("Alice"));
+    let rename = json!({ "email": "bob@example.com", "name": "Bob" });
+    let (status, _) = server.operator("PATCH", "/api/user/", rename).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn invitations_only_grant_roles_the_inviter_could_hold() {
+    let server = TestServer::start().await;
+    let alice = server.user_key("alice@example.com").await;
+    let support = json!({ "name": "support", "permissions": ["users:read", "users:write"] });
+    let (status, _) = server.operator("PUT", "/api/roles/", support).await;
+    assert_eq!(status, Status::Ok);
+    let grant = json!({ "email": "alice@example.com", "role": "support" });
+    let (status, _) = server.operator("POST", "/api/roles/grant", grant).await;
+    assert_eq!(status, Status::Ok);
+
+    let invite = json!({ "email": "carol@example.com", "role": "admin" });
+    let (status, _) = server
+        .call("POST", "/api/invitations/", &alice, invite)
+        .await;
+    assert_eq!(status, Status::Forbidden);
+    let invite = json!({ "email": "carol@example.com", "role": "support" });
+    let (status, invitation) = server
+        .call("POST", "/api/invitations/", &alice, invite)
+        .await;
+    assert_eq!(status, Status::Ok, "{invitation}");
+    let invite = json!({ "email": "dave@example.com", "role": "admin" });
+    let (status, _) = server.operator("POST", "/api/invitations/", invite).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn support_cannot_impersonate_an_admin() {
+    let server = TestServer::start().await;
+    let alice = server.user_key("alice@example.com").await;
+    server.user_key("bob@example.com").await;
+    server.user_key("carol@example.com").await;
+    let support = json!({ "name": "support", "permissions": ["users:read", "users:impersonate"] });
+    let (status, _) = server.operator("PUT", "/api/roles/", support).await;
+    assert_eq!(status, Status::Ok);
+    let grant = json!({ "email": "alice@example.com", "role": "support" });
+    let (status, _) = server.operator("POST", "/api/roles/grant", grant).await;
+    assert_eq!(status, Status::Ok);
+    let grant = json!({ "email": "bob@example.com", "role": "admin" });
+    let (status, _) = server.operator("POST", "/api/roles/grant", grant).await;
+    assert_eq!(status, Status::Ok);
+
+    let target = json!({ "email": "bob@example.com" });
+    let (status, _) = server
+        .call("POST", "/api/user/impersonate", &alice, target)
+        .await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(keys_of(&server, "bob@example.com").await, 1);
+
+    let target = json!({ "email": "carol@example.com" });
+    let (status, issued) = server
+        .call("POST", "/api/user/impersonate", &alice, target)
+        .await;
+    assert_eq!(status, Status::Ok, "{issued}");
+}
@@ -0,0 +1,259 @@
+//! Passkey registration and sign-in, driven by a software authenticator.
+
+mod common;
+
+use common::{SoftwareAuthenticator, TestServer};
+use rocket::http::Status;
+use serde_json::{json, Value};
+
+const RP_ID: &str = "localhost";
+
+async fn login_options(server: &TestServer) -> Value {
+    let (status, options) = server
+        .operator("POST", "/api/user/webauthn/login/start", json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    options
+}
+
+async fn register(
+    server: &TestServer,
+    key: &str,
+    authenticator: &mut SoftwareAuthenticator,
+) -> Value {
+    let (status, options) = server
+        .call("POST", "/api/user/webauthn/register/start", key, json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(options["rp"]["id"], RP_ID);
+
+    let (status, credential) = server
+        .call(
+            "POST",
+            "/api/user/webauthn/register/finish",
+            key,
+            authenticator.create(&options),
+        )
+        .await;
+    assert_eq!(status, Status::Ok, "{credential}");
+    credential
+}
+
+#[rocket::async_test]
+async fn registers_a_passkey_and_signs_in_with_it_first() {
+    let server = TestServer::start().await;
+    let key = server.user_key("alice@example.com").await;
+    let mut authenticator = SoftwareAuthenticator::new();
+
+    let credential = register(&server, &key, &mut authenticator).await;
+    assert_eq!(credential["id"], authenticator.credential_id());
+    assert_eq!(credential["transports"], json!(["internal"]));
+
+    let (_, credentials) = server
+        .call("GET", "/api/user/webauthn/credentials", &key, json!({}))
+        .await;
+    assert_eq!(credentials.as_array().unwrap().len(), 1);
+
+    // passkey-first: no email, the authenticator picks the credential
+    let (status, options) = server
+        .operator("POST", "/api/user/webauthn/login/start", json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(options["allowCredentials"], json!([]));
+
+    let assertion = authenticator.get(&options);
+    let (status, issued) = server
+        .operator("POST", "/api/user/webauthn/login/finish", assertion.clone())
+        .await;
+    assert_eq!(status, Status::Ok, "{issued}");
+    assert_eq!(issued["email"], "alice@example.com");
+
+    // the challenge was used up
+    let (status, _) = server
+        .operator("POST", "/api/user/webauthn/login/finish", assertion)
+        .await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn requires_the_passkey_after_a_password() {
+    let server = TestServer::start().await;
+    let key = server.user_key("bob@example.com").await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&server, &key, &mut authenticator).await;
+
+    let password = json!({ "email": "bob@example.com", "password": "Passw0rd!23" });
+    let (status, _) = server
+        .operator("POST", "/api/user/key", password.clone())
+        .await;
+    assert_eq!(status, Status::Forbidden);
+
+    let wrong = json!({ "email": "bob@example.com", "password": "wrong password" });
+    let (status, _) = server
+        .operator("POST", "/api/user/webauthn/login/start", wrong)
+        .await;
+    assert_eq!(status, Status::NotFound);
+
+    let (status, options) = server
+        .operator("POST", "/api/user/webauthn/login/start", password)
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        options["allowCredentials"][0]["id"],
+        authenticator.credential_id()
+    );
+
+    let (status, issued) = server
+        .operator(
+            "POST",
+            "/api/user/webauthn/login/finish",
+            authenticator.get(&options),
+        )
+        .await;
+    assert_eq!(status, Status::Ok, "{issued}");
+    assert_eq!(issued["email"], "bob@example.com");
+
+    let (_, events) = server
+        .operator("GET", "/api/audit/?action=login", json!({}))
+        .await;
+    assert_eq!(events["events"][0]["detail"]["method"], "password+passkey");
+}
+
+#[rocket::async_test]
+async fn rejects_forged_and_cloned_assertions() {
+    let server = TestServer::start().await;
+    let key = server.user_key("carol@example.com").await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&server, &key, &mut authenticator).await;
+
+    // collected on another site
+    let options = login_options(&server).await;
+    let phished = authenticator.get_from(&options, "https://evil.example");
+    let (status, error) = server
+        .operator("POST", "/api/user/webauthn/login/finish", phished)
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(
+        error,
+        "webauthn error: client data is for another ceremony or origin"
+    );
+
+    // signed by a different key
+    let options = login_options(&server).await;
+    let mut forged = authenticator.get(&options);
+    let mut impostor = SoftwareAuthenticator::new();
+    impostor.credential_id = authenticator.credential_id.clone();
+    forged["response"]["signature"] = impostor.get(&options)["response"]["signature"].clone();
+    let (status, error) = server
+        .operator("POST", "/api/user/webauthn/login/finish", forged)
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error, "webauthn error: invalid signature");
+
+    // a copy of the key whose counter lags behind
+    let options = login_options(&server).await;
+    let (status, _) = server
+        .operator(
+            "POST",
+            "/api/user/webauthn/login/finish",
+            authenticator.get(&options),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    authenticator.sign_count -= 2;
+    let options = login_options(&server).await;
+    let (status, error) = server
+        .operator(
+            "POST",
+            "/api/user/webauthn/login/finish",
+            authenticator.get(&options),
+        )
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(
+        error,
+        "webauthn error: signature counter did not increase, the authenticator may be cloned"
+    );
+}
+
+#[rocket::async_test]
+async fn requires_the_passkey_after_a_magic_link() {
+    let server = TestServer::start().await;
+    let key = server.user_key("dave@example.com").await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&server, &key, &mut authenticator).await;
+
+    let request = json!({ "email": "dave@example.com" });
+    let (status, _) = server
+        .operator("POST", "/api/user/magic-link", request)
+        .await;
+    assert_eq!(status, Status::Ok);
+    let response = server.client.get(server.last_link()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let pending: Value = response.into_json().await.unwrap();
+    assert!(pending.get("api_key").is_none(), "{pending}");
+    let options = &pending["passkey_required"];
+    assert_eq!(
+        options["allowCredentials"][0]["id"],
+        authenticator.credential_id()
+    );
+
+    let (status, issued) = server
+        .operator(
+            "POST",
+            "/api/user/webauthn/login/finish",
+            authenticator.get(options),
+        )
+        .await;
+    assert_eq!(status, Status::Ok, "{issued}");
+    assert_eq!(issued["email"], "dave@example.com");
+
+    let (_, events) = server
+        .operator("GET", "/api/audit/?action=login", json!({}))
+        .await;
+    assert_eq!(
+        events["events"][0]["detail"]["method"],
+        "magic_link+passkey"
+    );
+    assert_eq!(
+        events["events"][1]["detail"]["request"]["method"],
+        "magic_link"
+    );
+    assert_eq!(events["events"][1]["outcome"], "failure");
+}
+
+#[rocket::async_test]
+async fn requires_the_passkey_after_a_one_time_code() {
+    let server = TestServer::start().await;
+    let key = server.user_key("erin@example.com").await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&server, &key, &mut authenticator).await;
+
+    let request = json!({ "email": "erin@example.com", "channel": "email" });
+    let (status, _) = server.operator("POST", "/api/user/otp", request).await;
+    assert_eq!(status, Status::Ok);
+    let code = json!({ "email": "erin@example.com", "code": server.last_code() });
+    let (status, pending) = server
+        .operator("POST", "/api/user/otp/verify", code.clone())
+        .await;
+    assert_eq!(status, Status::Ok, "{pending}");
+    assert!(pending.get("api_key").is_none(), "{pending}");
+
+    // the code is used up; only the passkey finishes the sign-in
+    let (status, _) = server.operator("POST", "/api/user/otp/verify", code).await;
+    assert_eq!(status, Status::NotFound);
+    let (status, issued) = server
+        .operator(
+            "POST",
+            "/api/user/webauthn/login/finish",
+            authenticator.get(&pending["passkey_required"]),
+        )
+        .await;
+    assert_eq!(status, Status::Ok, "{issued}");
+    assert_eq!(issued["email"], "erin@example.com");
+
+    let (_, events) = server
+        .operator("GET", "/api/audit/?action=login", json!({}))
+        .await;
+    assert_eq!(events["events"][0]["detail"]["method"], "otp+passkey");
+}